@@ -8,7 +8,11 @@ extern crate stable_vec;
 compile_error!("`nightly-bench` feature not enabled; use `cargo bench --features nightly-bench`");
 
 use criterion::{black_box, BatchSize, Criterion};
-use stable_vec::StableVec;
+use stable_vec::{
+    core::Core,
+    DenseStableVec, ExternStableVec, FusedStableVec, InterleavedStableVec, StableVec,
+    StableVecFacade,
+};
 use std::{
     iter::FromIterator,
 };
@@ -63,6 +67,16 @@ fn sv_with_prime_holes(size: usize) -> StableVec<u32> {
     sv
 }
 
+/// Converts the given stable vector into one using another core, keeping all
+/// indices.
+fn convert<C: Core<u32> + Default>(sv: StableVec<u32>) -> StableVecFacade<u32, C> {
+    let mut out = StableVecFacade::with_capacity(sv.next_push_index());
+    for (idx, elem) in sv {
+        out.insert(idx, elem);
+    }
+    out
+}
+
 fn two_element_sv(size: usize) -> StableVec<u32> {
     let mut sv = full_sv(size);
     for i in 0..size {
@@ -126,6 +140,54 @@ fn push(c: &mut Criterion) {
     });
 }
 
+/// Compares `BitVecCore` (two allocations) with `FusedBitVecCore` (one
+/// allocation) when growing and reallocating.
+fn layout(c: &mut Criterion) {
+    macro_rules! gen_layout_benches {
+        ($name:literal, $ty:ident) => {
+            c.bench_function_over_inputs(
+                concat!("push_without_reserve_", $name),
+                |b, &len| {
+                    b.iter(|| {
+                        let mut sv = $ty::new();
+                        for i in 0..len as u32 {
+                            sv.push(i);
+                        }
+                        sv
+                    });
+                },
+                vec![10, 1000, 100_000],
+            );
+
+            c.bench_function_over_inputs(
+                concat!("with_capacity_", $name),
+                |b, &len| {
+                    b.iter(|| $ty::<u32>::with_capacity(len));
+                },
+                vec![1, 1000, 100_000],
+            );
+
+            c.bench_function_over_inputs(
+                concat!("realloc_", $name),
+                |b, &len| {
+                    b.iter_batched_ref(
+                        || (0..len as u32).collect::<$ty<_>>(),
+                        |sv| {
+                            sv.reserve_exact(len);
+                            sv.shrink_to_fit();
+                        },
+                        BatchSize::SmallInput,
+                    );
+                },
+                vec![10, 1000, 100_000],
+            );
+        };
+    }
+
+    gen_layout_benches!("bitvec", ExternStableVec);
+    gen_layout_benches!("fused", FusedStableVec);
+}
+
 fn delete_some_elements(c: &mut Criterion) {
     /// Some arbitrary delete condition
     fn should_delete(i: usize) -> bool {
@@ -187,6 +249,36 @@ fn get(c: &mut Criterion) {
     c.bench_function("get_miss_hole_in_middle", move |b| {
         b.iter(|| sv.get(SIZE / 2));
     });
+
+    let sv: InterleavedStableVec<_> = convert(full_sv(SIZE));
+    c.bench_function("get_full_interleaved", move |b| {
+        b.iter(|| sv.get(SIZE / 3));
+    });
+
+    let sv: InterleavedStableVec<_> = convert(sv_with_hole_in_middle(SIZE));
+    c.bench_function("get_hit_hole_in_middle_interleaved", move |b| {
+        b.iter(|| sv.get(3 * SIZE / 4));
+    });
+
+    let sv: InterleavedStableVec<_> = convert(sv_with_hole_in_middle(SIZE));
+    c.bench_function("get_miss_hole_in_middle_interleaved", move |b| {
+        b.iter(|| sv.get(SIZE / 2));
+    });
+
+    let sv: DenseStableVec<_> = convert(full_sv(SIZE));
+    c.bench_function("get_full_dense", move |b| {
+        b.iter(|| sv.get(SIZE / 3));
+    });
+
+    let sv: DenseStableVec<_> = convert(sv_with_hole_in_middle(SIZE));
+    c.bench_function("get_hit_hole_in_middle_dense", move |b| {
+        b.iter(|| sv.get(3 * SIZE / 4));
+    });
+
+    let sv: DenseStableVec<_> = convert(sv_with_hole_in_middle(SIZE));
+    c.bench_function("get_miss_hole_in_middle_dense", move |b| {
+        b.iter(|| sv.get(SIZE / 2));
+    });
 }
 
 fn count(c: &mut Criterion) {
@@ -309,6 +401,24 @@ fn sum(c: &mut Criterion) {
         vec![10, 1000, 100_000],
     );
 
+    c.bench_function_over_inputs(
+        "sum_with_element_every_fifth_dense_slice",
+        move |b, &len| {
+            let sv: DenseStableVec<_> = convert(sv_with_element_every_fifth(len));
+            b.iter(|| sv.as_dense_slice().iter().map(|&e| e as u64).sum::<u64>());
+        },
+        vec![10, 1000, 100_000],
+    );
+
+    c.bench_function_over_inputs(
+        "sum_with_element_every_fifth_dense_values",
+        move |b, &len| {
+            let sv: DenseStableVec<_> = convert(sv_with_element_every_fifth(len));
+            b.iter(|| sv.values().map(|&e| e as u64).sum::<u64>());
+        },
+        vec![10, 1000, 100_000],
+    );
+
     c.bench_function_over_inputs(
         "sum_fully_deleted",
         move |b, &len| {
@@ -325,6 +435,7 @@ criterion_group!(
     clear,
     from_iter,
     push,
+    layout,
     delete_some_elements,
     get,
     count,
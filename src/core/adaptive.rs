@@ -0,0 +1,452 @@
+use std::{
+    prelude::v1::*,
+    collections::BTreeMap,
+    fmt,
+    hint::unreachable_unchecked,
+};
+
+use super::{BitVecCore, Core, MapCore, small::move_all};
+
+
+/// A `Core` implementation that switches between a dense and a sparse
+/// representation, depending on the fraction of filled slots.
+///
+/// In the *dense* representation, a [`BitVecCore`] is used. In the *sparse*
+/// representation, the elements are stored in a `BTreeMap` from index to
+/// element, meaning that memory is only needed for existing elements and that
+/// searching for filled slots (and thus iterating) skips holes quickly. On the
+/// other hand, accessing elements by index is slower (`O(log n)`).
+///
+/// The *density* is `num_elements / next_push_index` (from the perspective of
+/// the stable vector). When it falls below the threshold, all elements are
+/// moved into the sparse representation. When it reaches twice the threshold
+/// (or 1.0 if that's smaller), they are moved back. This gap avoids switching
+/// back and forth repeatedly. Small vectors (with `next_push_index < 64`)
+/// never switch to the sparse representation.
+///
+/// Switching does not change any indices or the capacity. The threshold
+/// defaults to [`DEFAULT_THRESHOLD`][AdaptiveCore::DEFAULT_THRESHOLD] and can
+/// be set via [`with_threshold`][AdaptiveCore::with_threshold] or
+/// [`StableVecFacade::set_density_threshold`][crate::StableVecFacade::set_density_threshold].
+pub struct AdaptiveCore<T> {
+    repr: Repr<T>,
+
+    /// The number of filled slots. We need to track this ourselves as the
+    /// `Core` interface does not expose it.
+    num_elements: usize,
+
+    /// See the type documentation.
+    threshold: f32,
+}
+
+enum Repr<T> {
+    Dense(BitVecCore<T>),
+    Sparse(SparseCore<T>),
+}
+
+/// Dispatches to the core that is currently used. `$core` is bound to the
+/// inner core and `$body` is evaluated with it.
+macro_rules! dispatch {
+    ($self:expr, $core:ident => $body:expr) => {
+        match $self {
+            Repr::Dense($core) => $body,
+            Repr::Sparse($core) => $body,
+        }
+    };
+}
+
+/// Below this `len`, we never switch to the sparse representation.
+const MIN_SPARSE_LEN: usize = 64;
+
+impl<T> AdaptiveCore<T> {
+    /// The default density threshold: if less than 10% of slots are filled,
+    /// the sparse representation is used.
+    pub const DEFAULT_THRESHOLD: f32 = 0.1;
+
+    /// Creates an empty instance with the default threshold. Does not
+    /// allocate memory.
+    pub fn new() -> Self {
+        Self::with_threshold(Self::DEFAULT_THRESHOLD)
+    }
+
+    /// Creates an empty instance with the given density threshold. Does not
+    /// allocate memory.
+    ///
+    /// Panics if `threshold` is not in `0.0..=1.0`.
+    pub fn with_threshold(threshold: f32) -> Self {
+        check_threshold(threshold);
+        Self {
+            repr: Repr::Dense(BitVecCore::new()),
+            num_elements: 0,
+            threshold,
+        }
+    }
+
+    /// Returns the density threshold.
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    /// Sets the density threshold, possibly switching the representation
+    /// immediately.
+    ///
+    /// Panics if `threshold` is not in `0.0..=1.0`.
+    pub fn set_threshold(&mut self, threshold: f32) {
+        check_threshold(threshold);
+        self.threshold = threshold;
+        unsafe {
+            self.adapt();
+        }
+    }
+
+    /// Returns `true` if the sparse representation is currently used.
+    pub fn is_sparse(&self) -> bool {
+        match self.repr {
+            Repr::Dense(_) => false,
+            Repr::Sparse(_) => true,
+        }
+    }
+
+    /// Switches the representation if the density crossed the threshold.
+    ///
+    /// This is only `unsafe` because of the `Core` methods involved. It is
+    /// always safe to call as long as the invariants of `self` hold.
+    unsafe fn adapt(&mut self) {
+        let len = self.len();
+        let density = self.num_elements as f32 / len as f32;
+
+        match &mut self.repr {
+            Repr::Dense(dense) if len >= MIN_SPARSE_LEN && density < self.threshold => {
+                let mut sparse = SparseCore::new();
+                sparse.realloc(dense.cap());
+                move_all(dense, &mut sparse);
+                self.repr = Repr::Sparse(sparse);
+            }
+
+            // If `len == 0`, `density` is NaN, so we explicitly check for
+            // that.
+            Repr::Sparse(sparse)
+                if len == 0 || density >= f32::min(2.0 * self.threshold, 1.0) =>
+            {
+                let mut dense = BitVecCore::new();
+                dense.realloc(sparse.cap());
+                move_all(sparse, &mut dense);
+                self.repr = Repr::Dense(dense);
+            }
+
+            _ => {}
+        }
+    }
+}
+
+fn check_threshold(threshold: f32) {
+    assert!(
+        (0.0..=1.0).contains(&threshold),
+        "density threshold has to be between 0.0 and 1.0, but is {}",
+        threshold,
+    );
+}
+
+impl<T> Core<T> for AdaptiveCore<T> {
+    fn new() -> Self {
+        Self::new()
+    }
+
+    fn len(&self) -> usize {
+        dispatch!(&self.repr, core => core.len())
+    }
+
+    unsafe fn set_len(&mut self, new_len: usize) {
+        dispatch!(&mut self.repr, core => core.set_len(new_len));
+
+        // Changing `len` changes the density. This also covers bulk
+        // operations like `make_compact`, which end with a `set_len`.
+        self.adapt();
+    }
+
+    fn cap(&self) -> usize {
+        dispatch!(&self.repr, core => core.cap())
+    }
+
+    #[inline(never)]
+    #[cold]
+    unsafe fn realloc(&mut self, new_cap: usize) {
+        dispatch!(&mut self.repr, core => core.realloc(new_cap))
+    }
+
+    unsafe fn has_element_at(&self, idx: usize) -> bool {
+        dispatch!(&self.repr, core => core.has_element_at(idx))
+    }
+
+    unsafe fn insert_at(&mut self, idx: usize, elem: T) {
+        dispatch!(&mut self.repr, core => core.insert_at(idx, elem));
+        self.num_elements += 1;
+        if self.is_sparse() {
+            self.adapt();
+        }
+    }
+
+    unsafe fn remove_at(&mut self, idx: usize) -> T {
+        let out = dispatch!(&mut self.repr, core => core.remove_at(idx));
+        self.num_elements -= 1;
+        if !self.is_sparse() {
+            self.adapt();
+        }
+        out
+    }
+
+    unsafe fn get_unchecked(&self, idx: usize) -> &T {
+        dispatch!(&self.repr, core => core.get_unchecked(idx))
+    }
+
+    unsafe fn get_unchecked_mut(&mut self, idx: usize) -> &mut T {
+        dispatch!(&mut self.repr, core => core.get_unchecked_mut(idx))
+    }
+
+    fn clear(&mut self) {
+        dispatch!(&mut self.repr, core => core.clear());
+        self.num_elements = 0;
+        unsafe {
+            self.adapt();
+        }
+    }
+
+    unsafe fn first_filled_slot_from(&self, idx: usize) -> Option<usize> {
+        dispatch!(&self.repr, core => core.first_filled_slot_from(idx))
+    }
+
+    unsafe fn first_filled_slot_below(&self, idx: usize) -> Option<usize> {
+        dispatch!(&self.repr, core => core.first_filled_slot_below(idx))
+    }
+
+    unsafe fn first_empty_slot_from(&self, idx: usize) -> Option<usize> {
+        dispatch!(&self.repr, core => core.first_empty_slot_from(idx))
+    }
+
+    unsafe fn first_empty_slot_below(&self, idx: usize) -> Option<usize> {
+        dispatch!(&self.repr, core => core.first_empty_slot_below(idx))
+    }
+
+    unsafe fn swap(&mut self, a: usize, b: usize) {
+        // Swapping changes neither `len` nor the number of elements, so the
+        // density stays the same and we don't need to adapt.
+        dispatch!(&mut self.repr, core => core.swap(a, b))
+    }
+}
+
+impl<T> Default for AdaptiveCore<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> MapCore<T> for AdaptiveCore<T> {
+    type Mapped<U> = AdaptiveCore<U>;
+
+    /// Always succeeds, so that the threshold is kept. Only the dense
+    /// representation can actually reuse its memory (if `T` and `U` have the
+    /// same layout).
+    fn map_in_place<U, F>(self, f: &mut F) -> Result<AdaptiveCore<U>, Self>
+    where
+        F: FnMut(T) -> U,
+    {
+        let Self { repr, num_elements, threshold } = self;
+
+        let repr = match repr {
+            Repr::Dense(core) => match core.map_in_place(f) {
+                Ok(mapped) => Repr::Dense(mapped),
+
+                // If `f` panics, both cores drop their remaining elements.
+                Err(mut core) => unsafe {
+                    let mut mapped = BitVecCore::new();
+                    mapped.realloc(core.cap());
+                    mapped.set_len(core.len());
+
+                    let mut next = 0;
+                    while let Some(idx) = core.first_filled_slot_from(next) {
+                        mapped.insert_at(idx, f(core.remove_at(idx)));
+                        next = idx + 1;
+                    }
+                    Repr::Dense(mapped)
+                },
+            },
+            Repr::Sparse(core) => Repr::Sparse(SparseCore {
+                elements: core.elements.into_iter().map(|(idx, elem)| (idx, f(elem))).collect(),
+                cap: core.cap,
+                len: core.len,
+            }),
+        };
+
+        Ok(AdaptiveCore { repr, num_elements, threshold })
+    }
+}
+
+impl<T: Clone> Clone for AdaptiveCore<T> {
+    fn clone(&self) -> Self {
+        let repr = match &self.repr {
+            Repr::Dense(core) => Repr::Dense(core.clone()),
+            Repr::Sparse(core) => Repr::Sparse(core.clone()),
+        };
+
+        Self {
+            repr,
+            num_elements: self.num_elements,
+            threshold: self.threshold,
+        }
+    }
+}
+
+// This impl is usually not used. `StableVec` has its own impl which doesn't
+// use this one.
+impl<T> fmt::Debug for AdaptiveCore<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AdaptiveCore")
+            .field("len", &self.len())
+            .field("cap", &self.cap())
+            .field("sparse", &self.is_sparse())
+            .field("threshold", &self.threshold)
+            .finish()
+    }
+}
+
+
+/// The sparse representation of `AdaptiveCore`. The capacity is just a
+/// number: no memory is needed for empty slots.
+#[derive(Clone)]
+struct SparseCore<T> {
+    elements: BTreeMap<usize, T>,
+    cap: usize,
+    len: usize,
+}
+
+impl<T> SparseCore<T> {
+    fn new() -> Self {
+        Self {
+            elements: BTreeMap::new(),
+            cap: 0,
+            len: 0,
+        }
+    }
+}
+
+impl<T> Core<T> for SparseCore<T> {
+    fn new() -> Self {
+        Self::new()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    unsafe fn set_len(&mut self, new_len: usize) {
+        debug_assert!(new_len <= self.cap());
+        self.len = new_len;
+    }
+
+    fn cap(&self) -> usize {
+        self.cap
+    }
+
+    unsafe fn realloc(&mut self, new_cap: usize) {
+        debug_assert!(new_cap >= self.len());
+        self.cap = new_cap;
+    }
+
+    unsafe fn has_element_at(&self, idx: usize) -> bool {
+        debug_assert!(idx < self.cap());
+        self.elements.contains_key(&idx)
+    }
+
+    unsafe fn insert_at(&mut self, idx: usize, elem: T) {
+        debug_assert!(idx < self.cap());
+        debug_assert!(!self.has_element_at(idx));
+        self.elements.insert(idx, elem);
+    }
+
+    unsafe fn remove_at(&mut self, idx: usize) -> T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        match self.elements.remove(&idx) {
+            // The precondition guarantees us that the slot is not empty.
+            None => unreachable_unchecked(),
+            Some(elem) => elem,
+        }
+    }
+
+    unsafe fn get_unchecked(&self, idx: usize) -> &T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        match self.elements.get(&idx) {
+            None => unreachable_unchecked(),
+            Some(elem) => elem,
+        }
+    }
+
+    unsafe fn get_unchecked_mut(&mut self, idx: usize) -> &mut T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        match self.elements.get_mut(&idx) {
+            None => unreachable_unchecked(),
+            Some(elem) => elem,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.elements.clear();
+        self.len = 0;
+    }
+
+    unsafe fn first_filled_slot_from(&self, idx: usize) -> Option<usize> {
+        debug_assert!(idx <= self.cap());
+        self.elements.range(idx..).next().map(|(&i, _)| i)
+    }
+
+    unsafe fn first_filled_slot_below(&self, idx: usize) -> Option<usize> {
+        debug_assert!(idx <= self.cap());
+        self.elements.range(..idx).next_back().map(|(&i, _)| i)
+    }
+
+    unsafe fn first_empty_slot_from(&self, idx: usize) -> Option<usize> {
+        debug_assert!(idx <= self.cap());
+
+        // Walk over the run of filled slots starting at `idx`.
+        let mut candidate = idx;
+        for (&i, _) in self.elements.range(idx..) {
+            if i != candidate {
+                break;
+            }
+            candidate += 1;
+        }
+
+        if candidate < self.cap { Some(candidate) } else { None }
+    }
+
+    unsafe fn first_empty_slot_below(&self, idx: usize) -> Option<usize> {
+        debug_assert!(idx <= self.cap());
+
+        // Walk backwards over the run of filled slots ending at `idx - 1`.
+        let mut candidate = idx;
+        for (&i, _) in self.elements.range(..idx).rev() {
+            if i + 1 != candidate {
+                break;
+            }
+            candidate -= 1;
+        }
+
+        candidate.checked_sub(1)
+    }
+
+    unsafe fn swap(&mut self, a: usize, b: usize) {
+        let elem_a = self.elements.remove(&a);
+        let elem_b = self.elements.remove(&b);
+        if let Some(elem) = elem_a {
+            self.elements.insert(b, elem);
+        }
+        if let Some(elem) = elem_b {
+            self.elements.insert(a, elem);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Abstraction over the memory allocator used by the allocating cores.
+//!
+//! With the `allocator-api2` feature, the `Allocator` trait and the `Global`
+//! allocator from the `allocator-api2` crate are used, meaning that users can
+//! plug in their own allocators. Without that feature, a minimal stand-in
+//! with the same interface is used, which is only implemented by `Global`
+//! (the global allocator), together with a `Vec` wrapper mirroring the
+//! `Vec<T, A>` of `allocator-api2`.
+
+use std::{
+    alloc::{handle_alloc_error, Layout},
+    ptr::NonNull,
+};
+
+#[cfg(feature = "allocator-api2")]
+pub use allocator_api2::{
+    alloc::{Allocator, Global},
+    vec::Vec,
+};
+
+#[cfg(not(feature = "allocator-api2"))]
+pub use self::fallback::{Allocator, Global, Vec};
+
+#[cfg(not(feature = "allocator-api2"))]
+mod fallback {
+    use std::{
+        alloc::{alloc, alloc_zeroed, dealloc, realloc, Layout},
+        ops::{Deref, DerefMut},
+        ptr::NonNull,
+    };
+
+    /// The error type returned by `Allocator` methods.
+    #[derive(Debug)]
+    pub struct AllocError;
+
+    /// A minimal version of the `Allocator` trait from `allocator-api2`. Only
+    /// the methods used in this crate exist. This trait is not nameable
+    /// outside of this crate, so `Global` is the only implementor.
+    pub unsafe trait Allocator {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError>;
+        fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError>;
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+        unsafe fn grow(
+            &self,
+            ptr: NonNull<u8>,
+            old_layout: Layout,
+            new_layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError>;
+        unsafe fn shrink(
+            &self,
+            ptr: NonNull<u8>,
+            old_layout: Layout,
+            new_layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError>;
+    }
+
+    /// The global memory allocator.
+    #[derive(Clone, Copy, Default, Debug)]
+    pub struct Global;
+
+    fn to_block(ptr: *mut u8, size: usize) -> Result<NonNull<[u8]>, AllocError> {
+        NonNull::new(ptr)
+            .map(|ptr| NonNull::slice_from_raw_parts(ptr, size))
+            .ok_or(AllocError)
+    }
+
+    /// A minimal version of `Vec` from `allocator-api2`: a normal `Vec`
+    /// that also stores the (always global) allocator. Only the methods used
+    /// in this crate exist, everything else is available via `Deref`.
+    #[derive(Clone, Debug)]
+    pub struct Vec<T, A> {
+        inner: std::vec::Vec<T>,
+        alloc: A,
+    }
+
+    impl<T, A> Vec<T, A> {
+        pub fn new_in(alloc: A) -> Self {
+            Self { inner: std::vec::Vec::new(), alloc }
+        }
+
+        pub fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+            Self { inner: std::vec::Vec::with_capacity(capacity), alloc }
+        }
+
+        pub fn allocator(&self) -> &A {
+            &self.alloc
+        }
+    }
+
+    impl<T, A> Deref for Vec<T, A> {
+        type Target = std::vec::Vec<T>;
+        fn deref(&self) -> &Self::Target {
+            &self.inner
+        }
+    }
+
+    impl<T, A> DerefMut for Vec<T, A> {
+        fn deref_mut(&mut self) -> &mut Self::Target {
+            &mut self.inner
+        }
+    }
+
+    // All layouts passed to these methods by this crate have a non-zero size,
+    // as required by the global allocator functions.
+    unsafe impl Allocator for Global {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            to_block(unsafe { alloc(layout) }, layout.size())
+        }
+
+        fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            to_block(unsafe { alloc_zeroed(layout) }, layout.size())
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            dealloc(ptr.as_ptr(), layout)
+        }
+
+        unsafe fn grow(
+            &self,
+            ptr: NonNull<u8>,
+            old_layout: Layout,
+            new_layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            to_block(realloc(ptr.as_ptr(), old_layout, new_layout.size()), new_layout.size())
+        }
+
+        unsafe fn shrink(
+            &self,
+            ptr: NonNull<u8>,
+            old_layout: Layout,
+            new_layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            to_block(realloc(ptr.as_ptr(), old_layout, new_layout.size()), new_layout.size())
+        }
+    }
+}
+
+
+/// Allocates memory for `layout` with `alloc`, zeroing it if `zeroed` is
+/// `true`. Quits the program with an OOM error if the allocation fails.
+///
+/// # Formal
+///
+/// **Preconditions**:
+/// - `layout.size() != 0`
+pub(crate) fn allocate<A: Allocator>(alloc: &A, layout: Layout, zeroed: bool) -> NonNull<u8> {
+    let res = if zeroed {
+        alloc.allocate_zeroed(layout)
+    } else {
+        alloc.allocate(layout)
+    };
+
+    match res {
+        Ok(block) => block.cast(),
+        Err(_) => handle_alloc_error(layout),
+    }
+}
+
+/// Grows or shrinks the memory block `ptr` from `old_layout` to `new_layout`.
+/// The contents of the block (up to the smaller of both sizes) are preserved.
+/// Quits the program with an OOM error if the reallocation fails.
+///
+/// # Formal
+///
+/// **Preconditions**:
+/// - `ptr` was allocated by `alloc` with `old_layout`
+/// - `old_layout.align() == new_layout.align()`
+/// - `old_layout.size() != 0` and `new_layout.size() != 0`
+pub(crate) unsafe fn reallocate<A: Allocator>(
+    alloc: &A,
+    ptr: NonNull<u8>,
+    old_layout: Layout,
+    new_layout: Layout,
+) -> NonNull<u8> {
+    let res = if new_layout.size() > old_layout.size() {
+        alloc.grow(ptr, old_layout, new_layout)
+    } else if new_layout.size() < old_layout.size() {
+        alloc.shrink(ptr, old_layout, new_layout)
+    } else {
+        return ptr;
+    };
+
+    match res {
+        Ok(block) => block.cast(),
+        Err(_) => handle_alloc_error(new_layout),
+    }
+}
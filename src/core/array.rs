@@ -0,0 +1,244 @@
+use std::{
+    fmt,
+    mem::MaybeUninit,
+    ptr,
+};
+
+use super::Core;
+
+/// A `Core` implementation with a fixed capacity of `N` slots that never
+/// allocates.
+///
+/// All slots are stored inline in an `[MaybeUninit<T>; N]` array, together
+/// with an `[bool; N]` array storing which slots are filled. This means that
+/// a stable vector using this core can be stored on the stack or in a
+/// `static` (see
+/// [`StableVecFacade::new_const`][crate::StableVecFacade::new_const]) and is
+/// usable without the `alloc` crate. Together with
+/// [`SliceCore`][super::SliceCore], it is the only core available when this
+/// crate is compiled without the `alloc` feature.
+///
+/// The capacity is always exactly `N`, even directly after creation. Trying
+/// to reserve memory for more than `N` slots (e.g. by pushing the `N + 1`th
+/// element) panics. Use
+/// [`StableVecFacade::try_push`][crate::StableVecFacade::try_push] and
+/// [`StableVecFacade::try_reserve`][crate::StableVecFacade::try_reserve] to
+/// handle a full stable vector gracefully.
+///
+/// A packed bitmap would only need `⌈N / usize::BITS⌉` words, but array
+/// lengths computed from a const parameter cannot be expressed on stable Rust
+/// yet. So this core spends one byte per slot on its occupancy flags instead,
+/// which works for any `N`.
+///
+/// ```
+/// use stable_vec::ArrayStableVec;
+///
+/// let mut sv = ArrayStableVec::<u32, 200>::new();
+/// sv.extend(0..200);
+/// assert_eq!(sv.try_push(200), Err(200));
+/// ```
+pub struct ArrayCore<T, const N: usize> {
+    /// The actual slots/elements. If a slot is empty, the memory at that index
+    /// is uninitialized.
+    data: [MaybeUninit<T>; N],
+
+    /// Stores whether slots are filled (`true`) or empty (`false`).
+    filled: [bool; N],
+
+    /// The `len`: corresponse to the `len` of the `Core` definition.
+    len: usize,
+}
+
+impl<T, const N: usize> ArrayCore<T, N> {
+    /// Creates an empty instance. This is the same as `Core::new`, but usable
+    /// in `const` contexts.
+    pub const fn new() -> Self {
+        Self {
+            // An array of `MaybeUninit` does not require initialization.
+            data: unsafe { MaybeUninit::<[MaybeUninit<T>; N]>::uninit().assume_init() },
+            filled: [false; N],
+            len: 0,
+        }
+    }
+}
+
+/// The error returned by
+/// [`StableVecFacade::try_reserve`][crate::StableVecFacade::try_reserve] if
+/// the fixed capacity is too small.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CapacityError {
+    /// The number of slots that would have been required.
+    pub required: usize,
+
+    /// The fixed capacity.
+    pub capacity: usize,
+}
+
+impl fmt::Display for CapacityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} slots required, but the capacity is fixed to {}",
+            self.required,
+            self.capacity,
+        )
+    }
+}
+
+impl<T, const N: usize> Core<T> for ArrayCore<T, N> {
+    fn new() -> Self {
+        Self::new()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    unsafe fn set_len(&mut self, new_len: usize) {
+        debug_assert!(new_len <= self.cap());
+        // Other precondition is too expensive to test, even in debug:
+        // ∀ i in `new_len..self.cap()` ⇒ `self.has_element_at(i) == false`
+
+        self.len = new_len;
+    }
+
+    fn cap(&self) -> usize {
+        N
+    }
+
+    #[inline(never)]
+    #[cold]
+    unsafe fn realloc(&mut self, new_cap: usize) {
+        debug_assert!(new_cap >= self.len());
+        debug_assert!(new_cap <= isize::MAX as usize);
+
+        // We can't change our capacity. If the requested capacity fits into
+        // our fixed capacity, the postcondition `self.cap() >= new_cap` is
+        // already met and we do nothing. Otherwise, we can't fulfill the
+        // request.
+        if new_cap > N {
+            panic!(
+                "capacity overflow in `stable_vec::ArrayCore::realloc` (attempt to \
+                    grow to {} slots, but the capacity is fixed to {})",
+                new_cap,
+                N,
+            );
+        }
+    }
+
+    unsafe fn has_element_at(&self, idx: usize) -> bool {
+        debug_assert!(idx < self.cap());
+
+        *self.filled.get_unchecked(idx)
+    }
+
+    unsafe fn insert_at(&mut self, idx: usize, elem: T) {
+        debug_assert!(idx < self.cap());
+        debug_assert!(!self.has_element_at(idx));
+
+        // We first write the value and then update the flag to avoid
+        // potential double drops if a random panic appears.
+        ptr::write(self.data.get_unchecked_mut(idx).as_mut_ptr(), elem);
+        *self.filled.get_unchecked_mut(idx) = true;
+    }
+
+    unsafe fn remove_at(&mut self, idx: usize) -> T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        // We first mark the value as deleted and then read the value.
+        // Otherwise, a random panic could lead to a double drop.
+        *self.filled.get_unchecked_mut(idx) = false;
+        ptr::read(self.data.get_unchecked(idx).as_ptr())
+    }
+
+    unsafe fn get_unchecked(&self, idx: usize) -> &T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        // The preconditions guarantee us that the slot is initialized.
+        &*self.data.get_unchecked(idx).as_ptr()
+    }
+
+    unsafe fn get_unchecked_mut(&mut self, idx: usize) -> &mut T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        // The preconditions guarantee us that the slot is initialized.
+        &mut *self.data.get_unchecked_mut(idx).as_mut_ptr()
+    }
+
+    fn clear(&mut self) {
+        // We can assume that all existing elements have an index lower than
+        // `len` (this is one of the invariants of the `Core` interface).
+        for idx in 0..self.len {
+            if self.filled[idx] {
+                // We reset the flag first, for the same reason as in
+                // `remove_at`.
+                self.filled[idx] = false;
+                unsafe {
+                    ptr::drop_in_place(self.data[idx].as_mut_ptr());
+                }
+            }
+        }
+        self.len = 0;
+    }
+
+    unsafe fn swap(&mut self, a: usize, b: usize) {
+        debug_assert!(a < self.cap());
+        debug_assert!(b < self.cap());
+
+        self.filled.swap(a, b);
+
+        // Swapping uninitialized memory is fine, as we never read it as `T`.
+        let pa = self.data.as_mut_ptr().add(a);
+        let pb = self.data.as_mut_ptr().add(b);
+        ptr::swap(pa, pb);
+    }
+}
+
+impl<T, const N: usize> Default for ArrayCore<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for ArrayCore<T, N> {
+    fn drop(&mut self) {
+        // We don't own any memory, so dropping all elements is all we need to
+        // do.
+        self.clear();
+    }
+}
+
+impl<T: Clone, const N: usize> Clone for ArrayCore<T, N> {
+    fn clone(&self) -> Self {
+        let mut out = Self::new();
+
+        // We set the length first (all slots of `out` are empty, so that's
+        // fine). That way, if a `clone()` panics, all clones that have been
+        // inserted so far are dropped correctly.
+        unsafe {
+            out.set_len(self.len);
+
+            let mut idx = 0;
+            while let Some(next) = self.first_filled_slot_from(idx) {
+                out.insert_at(next, self.get_unchecked(next).clone());
+                idx = next + 1;
+            }
+        }
+
+        out
+    }
+}
+
+// This impl is usually not used. `StableVec` has its own impl which doesn't
+// use this one.
+impl<T, const N: usize> fmt::Debug for ArrayCore<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ArrayCore")
+            .field("len", &self.len())
+            .field("cap", &self.cap())
+            .finish()
+    }
+}
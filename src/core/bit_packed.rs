@@ -0,0 +1,371 @@
+use std::{
+    prelude::v1::*,
+    fmt, mem,
+    ptr::NonNull,
+};
+
+use super::{
+    Core,
+    bitvec::{num_usizes_for, BITS_PER_USIZE},
+};
+
+
+/// A `Core` implementation for `bool` elements that stores two bits per slot:
+/// one for whether the slot is filled and one for the value.
+///
+/// Compared to `BitVecCore<bool>` (one byte per value plus one bit) or
+/// `OptionCore<bool>` (two bytes per slot), this needs a lot less memory,
+/// making it a good choice for large flag tables. Additionally, operations on
+/// whole words are possible: see
+/// [`count_true`][crate::StableVecFacade::count_true],
+/// [`iter_true_indices`][crate::StableVecFacade::iter_true_indices],
+/// [`and`][crate::StableVecFacade::and] and
+/// [`or`][crate::StableVecFacade::or].
+///
+/// As a single bit cannot be referenced, mutable references to elements
+/// (e.g. via `get_mut` or `iter_mut`) are more expensive: the first one into
+/// a word of `usize::BITS` slots unpacks the values of that word into a
+/// temporary `bool` buffer. All unpacked words are packed again by the next
+/// modifying operation. Prefer [`set`][crate::StableVecFacade::set] to change
+/// values.
+pub struct BitPackedCore {
+    /// Stores whether slots are filled (1) or empty (0). Has
+    /// `num_usizes_for(cap)` entries.
+    occupied: Vec<usize>,
+
+    /// Stores the value of each filled slot. The bits of empty slots are
+    /// unspecified. Has `num_usizes_for(cap)` entries.
+    values: Vec<usize>,
+
+    /// The capacity. Corresponds to the `cap` of the `Core` definition.
+    cap: usize,
+
+    /// The `len`: corresponds to the `len` of the `Core` definition.
+    len: usize,
+
+    /// Either empty or has `num_usizes_for(cap)` entries. If entry `w` is
+    /// `Some`, it points to a buffer which holds the values of the slots in
+    /// word `w` (instead of `values[w]`). These buffers are created by
+    /// `get_unchecked_mut` and packed into `values` again by all other
+    /// modifying methods.
+    unpacked: Vec<Option<NonNull<Word>>>,
+
+    /// The indices of all `Some` entries in `unpacked`.
+    dirty: Vec<usize>,
+}
+
+/// The unpacked values of one word.
+type Word = [bool; BITS_PER_USIZE];
+
+impl BitPackedCore {
+    /// Creates an empty instance. Does not allocate memory.
+    pub fn new() -> Self {
+        Self {
+            occupied: Vec::new(),
+            values: Vec::new(),
+            cap: 0,
+            len: 0,
+            unpacked: Vec::new(),
+            dirty: Vec::new(),
+        }
+    }
+
+    /// Returns the number of filled slots with the value `true`.
+    pub fn count_true(&self) -> usize {
+        (0..num_usizes_for(self.len))
+            .map(|w| self.true_word(w).count_ones() as usize)
+            .sum()
+    }
+
+    /// Returns an iterator over the indices of all filled slots with the
+    /// value `true`, in increasing order.
+    pub fn iter_true_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..num_usizes_for(self.len)).flat_map(move |w| {
+            let mut word = self.true_word(w);
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+                Some(w * BITS_PER_USIZE + bit)
+            })
+        })
+    }
+
+    /// Sets each filled slot to `self[i] && other[i]`, where `other[i]` is
+    /// `false` if `other` has no element at `i`. Does not change which slots
+    /// are filled.
+    pub fn and(&mut self, other: &Self) {
+        self.pack();
+        for w in 0..num_usizes_for(self.len) {
+            self.values[w] &= other.true_word_or_zero(w);
+        }
+    }
+
+    /// Sets each filled slot to `self[i] || other[i]`, where `other[i]` is
+    /// `false` if `other` has no element at `i`. Does not change which slots
+    /// are filled.
+    pub fn or(&mut self, other: &Self) {
+        self.pack();
+        for w in 0..num_usizes_for(self.len) {
+            self.values[w] |= other.true_word_or_zero(w);
+        }
+    }
+
+    /// Sets the value of the filled slot `idx`.
+    ///
+    /// # Formal
+    ///
+    /// **Preconditions**:
+    /// - `idx < self.cap()`
+    /// - `self.has_element_at(idx) == true`
+    pub unsafe fn set_unchecked(&mut self, idx: usize, value: bool) {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        self.pack();
+        set_bit(&mut self.values, idx, value);
+    }
+
+    /// Returns the `w`-th word with all bits set that belong to filled slots
+    /// with the value `true`. `w` has to be smaller than
+    /// `num_usizes_for(self.cap)`.
+    fn true_word(&self, w: usize) -> usize {
+        let values = match self.unpacked_word(w) {
+            None => self.values[w],
+            // Only read through the raw pointer, see `get_unchecked_mut`.
+            Some(ptr) => unsafe { pack_word(ptr) },
+        };
+
+        values & self.occupied[w]
+    }
+
+    /// Like `true_word`, but returns 0 if `w` is out of bounds.
+    fn true_word_or_zero(&self, w: usize) -> usize {
+        if w < self.occupied.len() { self.true_word(w) } else { 0 }
+    }
+
+    /// Returns the buffer holding the values of word `w`, if that word is
+    /// unpacked.
+    fn unpacked_word(&self, w: usize) -> Option<NonNull<Word>> {
+        self.unpacked.get(w).copied().flatten()
+    }
+
+    /// Packs the values of all unpacked words into `values` and frees their
+    /// buffers. Runs in O(number of unpacked words).
+    fn pack(&mut self) {
+        let mut dirty = mem::take(&mut self.dirty);
+        for &w in &dirty {
+            if let Some(ptr) = self.unpacked[w].take() {
+                // The buffer was created via `Box::into_raw` in
+                // `get_unchecked_mut` and is only freed here. As we have
+                // `&mut self`, no references into it are alive anymore.
+                let buf = unsafe { Box::from_raw(ptr.as_ptr()) };
+                self.values[w] = unsafe { pack_word(NonNull::from(&*buf)) };
+            }
+        }
+        dirty.clear();
+        self.dirty = dirty;
+    }
+}
+
+/// Packs the values of an unpacked word into a `usize`.
+unsafe fn pack_word(ptr: NonNull<Word>) -> usize {
+    let base = ptr.as_ptr() as *const bool;
+    (0..BITS_PER_USIZE).fold(0, |word, i| word | ((*base.add(i) as usize) << i))
+}
+
+fn get_bit(words: &[usize], idx: usize) -> bool {
+    (words[idx / BITS_PER_USIZE] >> (idx % BITS_PER_USIZE)) & 1 != 0
+}
+
+fn set_bit(words: &mut [usize], idx: usize, value: bool) {
+    let mask = 1 << (idx % BITS_PER_USIZE);
+    let word = &mut words[idx / BITS_PER_USIZE];
+    if value {
+        *word |= mask;
+    } else {
+        *word &= !mask;
+    }
+}
+
+impl Core<bool> for BitPackedCore {
+    fn new() -> Self {
+        Self::new()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    unsafe fn set_len(&mut self, new_len: usize) {
+        debug_assert!(new_len <= self.cap());
+        // Other precondition is too expensive to test, even in debug:
+        // ∀ i in `new_len..self.cap()` ⇒ `self.has_element_at(i) == false`
+
+        self.len = new_len;
+    }
+
+    fn cap(&self) -> usize {
+        self.cap
+    }
+
+    #[inline(never)]
+    #[cold]
+    unsafe fn realloc(&mut self, new_cap: usize) {
+        debug_assert!(new_cap >= self.len());
+        debug_assert!(new_cap <= isize::MAX as usize);
+
+        // `unpacked` has one entry per word of `cap`, so we get rid of it
+        // first. It is recreated on demand.
+        self.pack();
+        self.unpacked = Vec::new();
+
+        // New slots are empty, so the new bits of `occupied` have to be 0. The
+        // bits of empty slots in `occupied` are always 0, so when shrinking,
+        // the remaining bits in the last word are fine.
+        let words = num_usizes_for(new_cap);
+        self.occupied.resize(words, 0);
+        self.values.resize(words, 0);
+        self.occupied.shrink_to_fit();
+        self.values.shrink_to_fit();
+        self.cap = new_cap;
+    }
+
+    unsafe fn has_element_at(&self, idx: usize) -> bool {
+        debug_assert!(idx < self.cap());
+
+        get_bit(&self.occupied, idx)
+    }
+
+    unsafe fn insert_at(&mut self, idx: usize, elem: bool) {
+        debug_assert!(idx < self.cap());
+        debug_assert!(!self.has_element_at(idx));
+
+        self.pack();
+        set_bit(&mut self.values, idx, elem);
+        set_bit(&mut self.occupied, idx, true);
+    }
+
+    unsafe fn remove_at(&mut self, idx: usize) -> bool {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        self.pack();
+        set_bit(&mut self.occupied, idx, false);
+        get_bit(&self.values, idx)
+    }
+
+    unsafe fn get_unchecked(&self, idx: usize) -> &bool {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        match self.unpacked_word(idx / BITS_PER_USIZE) {
+            Some(ptr) => &*(ptr.as_ptr() as *const bool).add(idx % BITS_PER_USIZE),
+            None if get_bit(&self.values, idx) => &true,
+            None => &false,
+        }
+    }
+
+    unsafe fn get_unchecked_mut(&mut self, idx: usize) -> &mut bool {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        // We must not touch existing word buffers other than through their
+        // raw pointers: references to other slots (e.g. from `IterMut`) might
+        // still be alive. Each buffer is a separate allocation, so modifying
+        // `unpacked` and `dirty` themselves is fine.
+        if self.unpacked.is_empty() {
+            self.unpacked.resize(self.values.len(), None);
+        }
+
+        let w = idx / BITS_PER_USIZE;
+        let ptr = match self.unpacked[w] {
+            Some(ptr) => ptr,
+            None => {
+                let word = self.values[w];
+                let buf: Box<Word> =
+                    Box::new(std::array::from_fn(|i| (word >> i) & 1 != 0));
+                let ptr = NonNull::new_unchecked(Box::into_raw(buf));
+                self.unpacked[w] = Some(ptr);
+                self.dirty.push(w);
+                ptr
+            }
+        };
+
+        &mut *(ptr.as_ptr() as *mut bool).add(idx % BITS_PER_USIZE)
+    }
+
+    fn clear(&mut self) {
+        self.pack();
+        for word in &mut self.occupied[..num_usizes_for(self.len)] {
+            *word = 0;
+        }
+        self.len = 0;
+    }
+
+    fn occupancy_words(&self) -> Option<&[usize]> {
+        Some(&self.occupied[..num_usizes_for(self.len)])
+    }
+
+    unsafe fn swap(&mut self, a: usize, b: usize) {
+        debug_assert!(a < self.cap());
+        debug_assert!(b < self.cap());
+
+        self.pack();
+        for words in [&mut self.occupied, &mut self.values] {
+            let bit_a = get_bit(words, a);
+            let bit_b = get_bit(words, b);
+            set_bit(words, a, bit_b);
+            set_bit(words, b, bit_a);
+        }
+    }
+}
+
+impl Default for BitPackedCore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for BitPackedCore {
+    fn drop(&mut self) {
+        self.pack();
+    }
+}
+
+impl Clone for BitPackedCore {
+    fn clone(&self) -> Self {
+        Self {
+            occupied: self.occupied.clone(),
+            values: (0..self.values.len()).map(|w| self.true_word(w)).collect(),
+            cap: self.cap,
+            len: self.len,
+            unpacked: Vec::new(),
+            dirty: Vec::new(),
+        }
+    }
+}
+
+// The raw pointers in `unpacked` are what prevents the auto impls. The
+// buffers behind them are owned by the core, so sending it to another thread
+// is fine. Sharing is fine as well: the buffers are only ever written through
+// `&mut self` (`pack`) or through the `&mut bool` returned by
+// `get_unchecked_mut`, which borrows `self` mutably. All methods taking
+// `&self` only read them. So, like with `Vec<bool>`, shared references only
+// allow concurrent reads.
+unsafe impl Send for BitPackedCore {}
+unsafe impl Sync for BitPackedCore {}
+
+// This impl is usually not used. `StableVec` has its own impl which doesn't
+// use this one.
+impl fmt::Debug for BitPackedCore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BitPackedCore")
+            .field("len", &self.len())
+            .field("cap", &self.cap())
+            .field("unpacked_words", &self.dirty.len())
+            .finish()
+    }
+}
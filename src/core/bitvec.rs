@@ -1,11 +1,15 @@
 use std::{
-    alloc::{alloc, alloc_zeroed, dealloc, handle_alloc_error, realloc, Layout},
+    alloc::Layout,
     fmt,
-    mem::{align_of, size_of},
+    marker::PhantomData,
+    mem::{self, align_of, size_of, ManuallyDrop},
     ptr::{self, NonNull},
 };
 
-use super::Core;
+use super::{
+    Core, MapCore,
+    allocator::{allocate, reallocate, Allocator, Global},
+};
 
 
 /// A `Core` implementation that is conceptually a `BitVec` and a `Vec<T>`.
@@ -25,10 +29,20 @@ use super::Core;
 /// perform two allocations with the underlying memory allocator. Potentially
 /// more important is the decrease of cache-friendliness when accessing
 /// elements at random. Because in the worst case, this means that each element
-/// access results in two cache-misses instead of only one.
+/// access results in two cache-misses instead of only one. If that's a
+/// problem, have a look at [`InterleavedCore`][super::InterleavedCore].
+///
+/// If the cost of reallocation matters to you, have a look at
+/// [`FusedBitVecCore`][super::FusedBitVecCore], which stores the bit vector
+/// and the elements in a single allocation.
 ///
 /// For most use cases, this is a good choice. That's why it's default.
-pub struct BitVecCore<T> {
+///
+/// Both allocations are performed with the allocator `A`. Unless the
+/// `allocator-api2` feature is enabled, this is always the global allocator.
+/// `Core::new` is only available if `A` implements `Default`; otherwise, use
+/// `new_in`.
+pub struct BitVecCore<T, A: Allocator = Global> {
     /// This is the memory that stores the actual slots/elements. If a slot is
     /// empty, the memory at that index is undefined.
     elem_ptr: NonNull<T>,
@@ -44,11 +58,31 @@ pub struct BitVecCore<T> {
 
     /// The `len`: corresponse to the `len` of the `Core` definition.
     len: usize,
+
+    /// The allocator used for both allocations.
+    alloc: A,
 }
 
-const BITS_PER_USIZE: usize = size_of::<usize>() * 8;
+pub(super) const BITS_PER_USIZE: usize = usize::BITS as usize;
+
+impl<T, A: Allocator> BitVecCore<T, A> {
+    /// Creates an empty instance that will allocate memory with `alloc`.
+    /// Does not allocate memory itself.
+    pub fn new_in(alloc: A) -> Self {
+        Self {
+            elem_ptr: NonNull::dangling(),
+            bit_ptr: NonNull::dangling(),
+            cap: 0,
+            len: 0,
+            alloc,
+        }
+    }
+
+    /// Returns a reference to the underlying allocator.
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
 
-impl<T> BitVecCore<T> {
     /// Deallocates both pointers, sets them to the same value as `new()` does
     /// and sets `cap` to 0.
     ///
@@ -60,10 +94,10 @@ impl<T> BitVecCore<T> {
     unsafe fn dealloc(&mut self) {
         if self.cap != 0 {
             if size_of::<T>() != 0 {
-                dealloc(self.elem_ptr.as_ptr() as *mut _, self.old_elem_layout());
+                self.alloc.deallocate(self.elem_ptr.cast(), self.old_elem_layout());
             }
 
-            dealloc(self.bit_ptr.as_ptr() as *mut _, self.old_bit_layout());
+            self.alloc.deallocate(self.bit_ptr.cast(), self.old_bit_layout());
             self.cap = 0;
         }
     }
@@ -89,14 +123,12 @@ impl<T> BitVecCore<T> {
     }
 }
 
-impl<T> Core<T> for BitVecCore<T> {
-    fn new() -> Self {
-        Self {
-            elem_ptr: NonNull::dangling(),
-            bit_ptr: NonNull::dangling(),
-            cap: 0,
-            len: 0,
-        }
+impl<T, A: Allocator> Core<T> for BitVecCore<T, A> {
+    fn new() -> Self
+    where
+        Self: Default,
+    {
+        Self::default()
     }
 
     fn len(&self) -> usize {
@@ -128,7 +160,7 @@ impl<T> Core<T> for BitVecCore<T> {
     #[cold]
     unsafe fn realloc(&mut self, new_cap: usize) {
         debug_assert!(new_cap >= self.len());
-        debug_assert!(new_cap <= isize::max_value() as usize);
+        debug_assert!(new_cap <= isize::MAX as usize);
 
         #[inline(never)]
         #[cold]
@@ -158,22 +190,17 @@ impl<T> Core<T> for BitVecCore<T> {
                 .unwrap_or_else(|| capacity_overflow());
             let new_elem_layout = Layout::from_size_align_unchecked(size, align_of::<T>());
 
-            // (Re)allocate memory.
+            // (Re)allocate memory. If the allocation fails, the program is
+            // quit with an OOM error.
             let ptr = if self.cap == 0 {
-                alloc(new_elem_layout)
+                allocate(&self.alloc, new_elem_layout, false)
             } else {
-                realloc(self.elem_ptr.as_ptr() as *mut _, self.old_elem_layout(), size)
+                reallocate(&self.alloc, self.elem_ptr.cast(), self.old_elem_layout(), new_elem_layout)
             };
 
-            // If the element allocation failed, we quit the program with an
-            // OOM error.
-            if ptr.is_null() {
-                 handle_alloc_error(new_elem_layout);
-            }
-
             // We already overwrite the pointer here. It is not read/changed
             // anywhere else in this function.
-            self.elem_ptr = NonNull::new_unchecked(ptr as *mut _);
+            self.elem_ptr = ptr.cast();
         };
 
 
@@ -184,19 +211,14 @@ impl<T> Core<T> for BitVecCore<T> {
             let size = size_of::<usize>() * num_usizes_for(new_cap);
             let new_bit_layout = Layout::from_size_align_unchecked(size, align_of::<usize>());
 
-            // (Re)allocate memory.
+            // (Re)allocate memory. If the allocation fails, the program is
+            // quit with an OOM error.
             let ptr = if self.cap == 0 {
-                alloc_zeroed(new_bit_layout)
+                allocate(&self.alloc, new_bit_layout, true)
             } else {
-                realloc(self.bit_ptr.as_ptr() as *mut _, self.old_bit_layout(), size)
+                reallocate(&self.alloc, self.bit_ptr.cast(), self.old_bit_layout(), new_bit_layout)
             };
-            let ptr = ptr as *mut usize;
-
-            // If the element allocation failed, we quit the program with an
-            // OOM error.
-            if ptr.is_null() {
-                 handle_alloc_error(new_bit_layout);
-            }
+            let ptr = ptr.as_ptr() as *mut usize;
 
             // If we reallocated, the new memory is not necessarily zeroed, so
             // we need to do it. TODO: if `alloc` offers a `realloc_zeroed`
@@ -242,7 +264,7 @@ impl<T> Core<T> for BitVecCore<T> {
 
     unsafe fn insert_at(&mut self, idx: usize, elem: T) {
         debug_assert!(idx < self.cap());
-        debug_assert!(self.has_element_at(idx) == false);
+        debug_assert!(!self.has_element_at(idx));
 
         // We first write the value and then update the bitvector to avoid
         // potential double drops if a random panic appears.
@@ -310,6 +332,15 @@ impl<T> Core<T> for BitVecCore<T> {
     // principle we could scan the bitvector very quickly with specialized
     // instructions. Needs benchmarking.
 
+    fn occupancy_words(&self) -> Option<&[usize]> {
+        // The first `num_usizes_for(len) <= num_usizes_for(cap)` words are
+        // allocated and initialized. Bits of slots `≥ len` are 0 as these
+        // slots are empty.
+        unsafe {
+            Some(std::slice::from_raw_parts(self.bit_ptr.as_ptr(), num_usizes_for(self.len)))
+        }
+    }
+
     unsafe fn swap(&mut self, a: usize, b: usize) {
         // Swapping the bits is a bit annoying. To avoid branches we first xor
         // both previous bits.
@@ -342,7 +373,90 @@ impl<T> Core<T> for BitVecCore<T> {
     }
 }
 
-impl<T> Drop for BitVecCore<T> {
+impl<T, A: Allocator + Default> Default for BitVecCore<T, A> {
+    fn default() -> Self {
+        Self::new_in(A::default())
+    }
+}
+
+impl<T> MapCore<T> for BitVecCore<T> {
+    type Mapped<U> = BitVecCore<U>;
+
+    fn map_in_place<U, F>(self, f: &mut F) -> Result<BitVecCore<U>, Self>
+    where
+        F: FnMut(T) -> U,
+    {
+        if size_of::<T>() != size_of::<U>() || align_of::<T>() != align_of::<U>() {
+            return Err(self);
+        }
+
+        /// Cleans up if `f` panics: the filled slots below `idx` already
+        /// contain `U`s, the element in slot `idx` was moved out and all
+        /// filled slots above `idx` still contain `T`s.
+        struct Guard<'a, T, U> {
+            core: &'a mut BitVecCore<T>,
+            idx: usize,
+            _mapped: PhantomData<U>,
+        }
+
+        impl<T, U> Drop for Guard<'_, T, U> {
+            fn drop(&mut self) {
+                unsafe {
+                    let core = &mut *self.core;
+                    for i in 0..core.len {
+                        if core.has_element_at(i) {
+                            let p = core.elem_ptr.as_ptr().add(i);
+                            if i < self.idx {
+                                ptr::drop_in_place(p as *mut U);
+                            } else if i > self.idx {
+                                ptr::drop_in_place(p);
+                            }
+                        }
+                    }
+
+                    // All elements are gone, so we mark all slots as empty.
+                    // The memory is freed when the core itself is dropped.
+                    ptr::write_bytes(core.bit_ptr.as_ptr(), 0, num_usizes_for(core.cap));
+                    core.len = 0;
+                }
+            }
+        }
+
+        let mut this = self;
+        let mut guard = Guard::<T, U> {
+            core: &mut this,
+            idx: 0,
+            _mapped: PhantomData,
+        };
+
+        // `U` has the same layout as `T`, so we can just write each new
+        // element into the memory of the old one.
+        unsafe {
+            let mut next = 0;
+            while let Some(idx) = guard.core.first_filled_slot_from(next) {
+                guard.idx = idx;
+                let p = guard.core.elem_ptr.as_ptr().add(idx);
+                let new = f(ptr::read(p));
+                ptr::write(p as *mut U, new);
+                next = idx + 1;
+            }
+        }
+        mem::forget(guard);
+
+        // All elements are `U`s now. The layouts of both allocations stay
+        // the same, so the new core can take them over.
+        let this = ManuallyDrop::new(this);
+        Ok(BitVecCore {
+            elem_ptr: this.elem_ptr.cast(),
+            bit_ptr: this.bit_ptr,
+            cap: this.cap,
+            len: this.len,
+            alloc: Global,
+        })
+    }
+}
+
+impl<T, A: Allocator> Drop for BitVecCore<T, A> {
     fn drop(&mut self) {
         // Drop all elements
         self.clear();
@@ -355,24 +469,23 @@ impl<T> Drop for BitVecCore<T> {
     }
 }
 
-impl<T: Clone> Clone for BitVecCore<T> {
+impl<T: Clone, A: Allocator + Clone> Clone for BitVecCore<T, A> {
     fn clone(&self) -> Self {
-        let mut out = Self::new();
+        let mut out = Self::new_in(self.alloc.clone());
 
         if self.cap != 0 {
             // All of this is scary
             unsafe {
                 out.realloc(self.cap);
 
-                // Copy element data over
-                if size_of::<T>() != 0 {
-                    let mut idx = 0;
-                    while let Some(next) = self.first_filled_slot_from(idx) {
-                        let clone = self.get_unchecked(next).clone();
-                        ptr::write(out.elem_ptr.as_ptr().add(next), clone);
+                // Copy element data over. We also do this for zero-sized
+                // types, as their `clone()` might have side effects.
+                let mut idx = 0;
+                while let Some(next) = self.first_filled_slot_from(idx) {
+                    let clone = self.get_unchecked(next).clone();
+                    ptr::write(out.elem_ptr.as_ptr().add(next), clone);
 
-                        idx = next + 1;
-                    }
+                    idx = next + 1;
                 }
 
                 // Copy bitvec data over
@@ -392,7 +505,7 @@ impl<T: Clone> Clone for BitVecCore<T> {
 
 // This impl is usually not used. `StableVec` has its own impl which doesn't
 // use this one.
-impl<T> fmt::Debug for BitVecCore<T> {
+impl<T, A: Allocator> fmt::Debug for BitVecCore<T, A> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("BitVecCore")
             .field("len", &self.len())
@@ -402,18 +515,18 @@ impl<T> fmt::Debug for BitVecCore<T> {
 }
 
 // Implement `Send` and `Sync`. These are not automatically implemented as we
-// use raw pointers. But they are safe to implement (given that `T` implements
-// them). We do not have interior mutability, thus we can implement `Sync`. We
-// also do not share any data with other instance of this type, meaning that
-// `Send` can be implemented.
-unsafe impl<T: Send> Send for BitVecCore<T> {}
-unsafe impl<T: Sync> Sync for BitVecCore<T> {}
+// use raw pointers. But they are safe to implement (given that `T` and `A`
+// implement them). We do not have interior mutability, thus we can implement
+// `Sync`. We also do not share any data with other instance of this type,
+// meaning that `Send` can be implemented.
+unsafe impl<T: Send, A: Allocator + Send> Send for BitVecCore<T, A> {}
+unsafe impl<T: Sync, A: Allocator + Sync> Sync for BitVecCore<T, A> {}
 
 #[inline(always)]
-fn num_usizes_for(cap: usize) -> usize {
+pub(super) fn num_usizes_for(cap: usize) -> usize {
     // We need ⌈new_cap / BITS_PER_USIZE⌉ many usizes to store all required
-    // bits. We do rounding up by first adding the (BITS_PER_USIZE - 1).
-    (cap + (BITS_PER_USIZE - 1)) / BITS_PER_USIZE
+    // bits.
+    cap.div_ceil(BITS_PER_USIZE)
 }
 
 #[cfg(test)]
@@ -0,0 +1,555 @@
+use std::{
+    prelude::v1::*,
+    cell::{RefCell, RefMut},
+    fmt,
+};
+
+// `no-std-compat` does not reexport these.
+use ::core::panic::{RefUnwindSafe, UnwindSafe};
+
+use super::{Core, MapCore};
+
+
+/// A `Core` wrapper that checks that the inner core `C` and its users adhere
+/// to the formal requirements of the [`Core`] trait.
+///
+/// This is a debugging tool for people implementing their own core. All
+/// methods are forwarded to `C`, but before and after each call, the
+/// documented preconditions, postconditions and invariants are checked
+/// against a simple reference model (one `bool` per slot plus `len` and
+/// `cap`). Any violation results in a panic that names the method and the
+/// violated condition. That way, a faulty core fails loudly in tests instead
+/// of causing undefined behavior.
+///
+/// ```
+/// use stable_vec::{StableVecFacade, core::{BitVecCore, CheckedCore}};
+///
+/// // Replace `BitVecCore` with your own core.
+/// let mut sv = StableVecFacade::<u32, CheckedCore<BitVecCore<u32>>>::new();
+/// sv.extend(0..10);
+/// sv.remove(3);
+/// sv.make_compact();
+/// assert_eq!(sv.num_elements(), 9);
+/// ```
+///
+/// The requirements on `new()` and `clone()` are checked as well (with
+/// the first method call on the new instance). Elements are never compared
+/// (`T` does not need to implement `PartialEq`), so postconditions about
+/// element values are not checked. Many checks look at all slots, making this
+/// core very slow. Only use it in tests.
+pub struct CheckedCore<C> {
+    inner: C,
+    model: RefCell<State>,
+}
+
+/// The state of the reference model. It is only created lazily, as `T` is
+/// not known in `Core::new`, `default()` and `clone()`.
+#[derive(Clone)]
+enum State {
+    /// Created by `new()`, no method was called yet.
+    New,
+
+    /// Created by `Core::new` or `default()`, no method was called yet.
+    Default,
+
+    /// Created by `clone()` from an instance with the given model, no method
+    /// was called yet.
+    Cloned(Model),
+
+    /// The model is in sync with the inner core.
+    Ready(Model),
+}
+
+#[derive(Clone)]
+struct Model {
+    /// Whether each slot is filled. Its length is the expected `cap`.
+    filled: Vec<bool>,
+
+    /// The expected `len`.
+    len: usize,
+}
+
+impl Model {
+    fn cap(&self) -> usize {
+        self.filled.len()
+    }
+}
+
+/// Panics with a message naming the method and the kind of the violated
+/// condition if `$cond` is `false`.
+macro_rules! check {
+    ($cond:expr, $method:expr, $kind:literal, $($msg:tt)+) => {
+        if !$cond {
+            panic!(
+                "`CheckedCore`: {} of `{}` violated: {}",
+                $kind,
+                $method,
+                format_args!($($msg)+),
+            );
+        }
+    };
+}
+
+fn state_name(filled: bool) -> &'static str {
+    if filled { "filled" } else { "empty" }
+}
+
+impl<C> CheckedCore<C> {
+    /// Wraps `inner`. Its current state is checked against the invariants of
+    /// `Core` (`len ≤ cap` and all slots `≥ len` are empty) with the first
+    /// method call.
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            model: RefCell::new(State::New),
+        }
+    }
+
+    /// Returns a reference to the inner core.
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /// Returns the inner core.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Returns the model, creating it from the inner core first if necessary.
+    /// When creating it, the invariants of `Core` and the postconditions of
+    /// `new()` or `clone()` are checked.
+    fn model<T>(&self, method: &str) -> RefMut<'_, Model>
+    where
+        C: Core<T>,
+    {
+        let mut state = self.model.borrow_mut();
+        if !matches!(*state, State::Ready(_)) {
+            let len = self.inner.len();
+            let cap = self.inner.cap();
+            check!(len <= cap, method, "invariant", "len ({}) > cap ({})", len, cap);
+            check!(cap <= isize::MAX as usize, method, "invariant", "cap ({}) > isize::MAX", cap);
+
+            let filled: Vec<bool> = (0..cap)
+                .map(|i| unsafe { self.inner.has_element_at(i) })
+                .collect();
+            if let Some(i) = (len..cap).find(|&i| filled[i]) {
+                check!(false, method, "invariant", "slot {} ≥ len ({}) is filled", i, len);
+            }
+
+            match &*state {
+                State::Default => {
+                    check!(len == 0, "new", "postcondition", "len is {}", len);
+                    if let Some(i) = filled.iter().position(|&f| f) {
+                        check!(false, "new", "postcondition", "slot {} is filled", i);
+                    }
+                }
+                State::Cloned(original) => {
+                    check!(
+                        len == original.len,
+                        "clone", "postcondition", "len is {}, but should be {}", len, original.len,
+                    );
+                    check!(
+                        cap >= original.cap(),
+                        "clone", "postcondition", "cap ({}) < original cap ({})", cap, original.cap(),
+                    );
+                    if let Some(i) = (0..original.cap()).find(|&i| filled[i] != original.filled[i]) {
+                        check!(
+                            false, "clone", "postcondition", "slot {} is {}, but should be {}",
+                            i,
+                            state_name(filled[i]),
+                            state_name(original.filled[i]),
+                        );
+                    }
+                }
+                State::New | State::Ready(_) => {}
+            }
+
+            *state = State::Ready(Model { filled, len });
+        }
+
+        RefMut::map(state, |state| match state {
+            State::Ready(model) => model,
+            _ => unreachable!(),
+        })
+    }
+
+    /// Checks that `len` and `cap` of the inner core are as expected by the
+    /// model.
+    fn check_len_cap<T>(&self, model: &Model, method: &str)
+    where
+        C: Core<T>,
+    {
+        let (len, cap) = (self.inner.len(), self.inner.cap());
+        check!(
+            len == model.len && cap == model.cap(),
+            method,
+            "invariant",
+            "len and cap should be {} and {}, but are {} and {}",
+            model.len,
+            model.cap(),
+            len,
+            cap,
+        );
+    }
+
+    /// Checks that the states of the slots `0..end` match the model.
+    fn check_slots<T>(&self, model: &Model, method: &str, end: usize)
+    where
+        C: Core<T>,
+    {
+        for (i, &expected) in model.filled[..end].iter().enumerate() {
+            let actual = unsafe { self.inner.has_element_at(i) };
+            check!(
+                actual == expected,
+                method, "invariant", "slot {} should be {}, but is {}",
+                i,
+                state_name(expected),
+                state_name(actual),
+            );
+        }
+    }
+}
+
+/// Checks that `idx` is a valid index (`< cap`).
+fn check_idx(model: &Model, method: &str, idx: usize) {
+    check!(
+        idx < model.cap(),
+        method, "precondition", "index {} ≥ cap ({})", idx, model.cap(),
+    );
+}
+
+/// Checks that `idx` is a valid index and the slot is filled or empty.
+fn check_state(model: &Model, method: &str, idx: usize, filled: bool) {
+    check_idx(model, method, idx);
+    check!(
+        model.filled[idx] == filled,
+        method, "precondition", "slot {} is {}", idx, state_name(!filled),
+    );
+}
+
+/// Checks the result of a search method.
+fn check_search(method: &str, idx: usize, actual: Option<usize>, expected: Option<usize>) {
+    check!(
+        actual == expected,
+        method, "postcondition", "searching from {} returned {:?}, but should return {:?}",
+        idx,
+        actual,
+        expected,
+    );
+}
+
+/// Checks the precondition of a search method.
+fn check_search_start(model: &Model, method: &str, idx: usize) {
+    check!(
+        idx <= model.cap(),
+        method, "precondition", "idx ({}) > cap ({})", idx, model.cap(),
+    );
+}
+
+impl<T, C: Core<T>> Core<T> for CheckedCore<C> {
+    fn new() -> Self
+    where
+        Self: Default,
+    {
+        Self::default()
+    }
+
+    fn len(&self) -> usize {
+        let model = self.model("len");
+        self.check_len_cap(&model, "len");
+        model.len
+    }
+
+    unsafe fn set_len(&mut self, new_len: usize) {
+        let cap = {
+            let model = self.model("set_len");
+            let cap = model.cap();
+            check!(new_len <= cap, "set_len", "precondition", "new_len ({}) > cap ({})", new_len, cap);
+            if let Some(i) = (new_len..cap).find(|&i| model.filled[i]) {
+                check!(false, "set_len", "precondition", "slot {} ≥ new_len ({}) is filled", i, new_len);
+            }
+            cap
+        };
+
+        self.inner.set_len(new_len);
+
+        let mut model = self.model("set_len");
+        model.len = new_len;
+        self.check_len_cap(&model, "set_len");
+        self.check_slots(&model, "set_len", cap);
+    }
+
+    fn cap(&self) -> usize {
+        let model = self.model("cap");
+        self.check_len_cap(&model, "cap");
+        model.cap()
+    }
+
+    unsafe fn realloc(&mut self, new_cap: usize) {
+        let len = self.model("realloc").len;
+        check!(new_cap >= len, "realloc", "precondition", "new_cap ({}) < len ({})", new_cap, len);
+        check!(
+            new_cap <= isize::MAX as usize,
+            "realloc", "precondition", "new_cap ({}) > isize::MAX", new_cap,
+        );
+
+        self.inner.realloc(new_cap);
+
+        let mut model = self.model("realloc");
+        let cap = self.inner.cap();
+        check!(cap >= new_cap, "realloc", "postcondition", "cap ({}) < new_cap ({})", cap, new_cap);
+        check!(cap <= isize::MAX as usize, "realloc", "invariant", "cap ({}) > isize::MAX", cap);
+
+        // Slot data is invariant, new slots have to be empty.
+        model.filled.resize(cap, false);
+        self.check_len_cap(&model, "realloc");
+        self.check_slots(&model, "realloc", cap);
+    }
+
+    unsafe fn has_element_at(&self, idx: usize) -> bool {
+        let model = self.model("has_element_at");
+        check_idx(&model, "has_element_at", idx);
+
+        let out = self.inner.has_element_at(idx);
+        check!(
+            out == model.filled[idx],
+            "has_element_at", "postcondition", "returned {} for slot {}", out, idx,
+        );
+        out
+    }
+
+    unsafe fn insert_at(&mut self, idx: usize, elem: T) {
+        {
+            let model = self.model("insert_at");
+            check_state(&model, "insert_at", idx, false);
+
+            // Not a precondition of `insert_at` itself, but otherwise the
+            // invariant "all slots ≥ len are empty" is violated afterwards.
+            check!(
+                idx < model.len,
+                "insert_at", "precondition", "index {} ≥ len ({})", idx, model.len,
+            );
+        }
+
+        self.inner.insert_at(idx, elem);
+
+        let mut model = self.model("insert_at");
+        model.filled[idx] = true;
+        self.check_len_cap(&model, "insert_at");
+        check!(
+            self.inner.has_element_at(idx),
+            "insert_at", "postcondition", "slot {} is still empty", idx,
+        );
+    }
+
+    unsafe fn remove_at(&mut self, idx: usize) -> T {
+        check_state(&self.model("remove_at"), "remove_at", idx, true);
+
+        let out = self.inner.remove_at(idx);
+
+        let mut model = self.model("remove_at");
+        model.filled[idx] = false;
+        self.check_len_cap(&model, "remove_at");
+        check!(
+            !self.inner.has_element_at(idx),
+            "remove_at", "postcondition", "slot {} is still filled", idx,
+        );
+        drop(model);
+        out
+    }
+
+    unsafe fn get_unchecked(&self, idx: usize) -> &T {
+        check_state(&self.model("get_unchecked"), "get_unchecked", idx, true);
+        self.inner.get_unchecked(idx)
+    }
+
+    unsafe fn get_unchecked_mut(&mut self, idx: usize) -> &mut T {
+        check_state(&self.model("get_unchecked_mut"), "get_unchecked_mut", idx, true);
+        self.inner.get_unchecked_mut(idx)
+    }
+
+    fn clear(&mut self) {
+        let old_len = self.model("clear").len;
+        self.inner.clear();
+
+        let mut model = self.model("clear");
+        for filled in &mut model.filled[..old_len] {
+            *filled = false;
+        }
+        model.len = 0;
+        self.check_len_cap(&model, "clear");
+        self.check_slots(&model, "clear", old_len);
+    }
+
+    unsafe fn first_filled_slot_from(&self, idx: usize) -> Option<usize> {
+        const NAME: &str = "first_filled_slot_from";
+        let model = self.model(NAME);
+        check_search_start(&model, NAME, idx);
+
+        let out = self.inner.first_filled_slot_from(idx);
+        let expected = (idx..model.len).find(|&i| model.filled[i]);
+        check_search(NAME, idx, out, expected);
+        out
+    }
+
+    unsafe fn first_filled_slot_below(&self, idx: usize) -> Option<usize> {
+        const NAME: &str = "first_filled_slot_below";
+        let model = self.model(NAME);
+        check_search_start(&model, NAME, idx);
+
+        let out = self.inner.first_filled_slot_below(idx);
+        let expected = (0..idx).rev().find(|&i| model.filled[i]);
+        check_search(NAME, idx, out, expected);
+        out
+    }
+
+    unsafe fn first_empty_slot_from(&self, idx: usize) -> Option<usize> {
+        const NAME: &str = "first_empty_slot_from";
+        let model = self.model(NAME);
+        check_search_start(&model, NAME, idx);
+
+        let out = self.inner.first_empty_slot_from(idx);
+        let expected = (idx..model.cap()).find(|&i| !model.filled[i]);
+        check_search(NAME, idx, out, expected);
+        out
+    }
+
+    unsafe fn first_empty_slot_below(&self, idx: usize) -> Option<usize> {
+        const NAME: &str = "first_empty_slot_below";
+        let model = self.model(NAME);
+        check_search_start(&model, NAME, idx);
+
+        let out = self.inner.first_empty_slot_below(idx);
+        let expected = (0..idx).rev().find(|&i| !model.filled[i]);
+        check_search(NAME, idx, out, expected);
+        out
+    }
+
+    fn occupancy_words(&self) -> Option<&[usize]> {
+        const NAME: &str = "occupancy_words";
+        let model = self.model(NAME);
+        let out = self.inner.occupancy_words();
+
+        if let Some(words) = out {
+            let expected = model.len.div_ceil(usize::BITS as usize);
+            check!(
+                words.len() == expected,
+                NAME, "postcondition", "returned {} words, but should return {}",
+                words.len(),
+                expected,
+            );
+            for i in 0..words.len() * usize::BITS as usize {
+                let bit = (words[i / usize::BITS as usize] >> (i % usize::BITS as usize)) & 1 != 0;
+                let filled = i < model.len && model.filled[i];
+                check!(
+                    bit == filled,
+                    NAME, "postcondition", "bit {} is {}, but slot {} is {}",
+                    i,
+                    bit as u8,
+                    i,
+                    state_name(filled),
+                );
+            }
+        }
+
+        out
+    }
+
+    unsafe fn swap(&mut self, a: usize, b: usize) {
+        {
+            let model = self.model("swap");
+            check_idx(&model, "swap", a);
+            check_idx(&model, "swap", b);
+        }
+
+        self.inner.swap(a, b);
+
+        let mut model = self.model("swap");
+        model.filled.swap(a, b);
+        self.check_len_cap(&model, "swap");
+        for idx in [a, b] {
+            let actual = self.inner.has_element_at(idx);
+            check!(
+                actual == model.filled[idx],
+                "swap", "postcondition", "slot {} should be {}, but is {}",
+                idx,
+                state_name(model.filled[idx]),
+                state_name(actual),
+            );
+        }
+    }
+}
+
+impl<T, C: MapCore<T>> MapCore<T> for CheckedCore<C> {
+    type Mapped<U> = CheckedCore<C::Mapped<U>>;
+
+    fn map_in_place<U, F>(self, f: &mut F) -> Result<Self::Mapped<U>, Self>
+    where
+        F: FnMut(T) -> U,
+    {
+        const NAME: &str = "map_in_place";
+
+        // Make sure the model exists, as it can't be created from the mapped
+        // core if this was created by `new()`, `default()` or `clone()`.
+        drop(self.model::<T>(NAME));
+
+        let Self { inner, model } = self;
+        match inner.map_in_place(f) {
+            Ok(inner) => {
+                let out = CheckedCore { inner, model };
+                {
+                    let model = out.model::<U>(NAME);
+                    out.check_len_cap::<U>(&model, NAME);
+                    out.check_slots::<U>(&model, NAME, model.cap());
+                }
+                Ok(out)
+            }
+            Err(inner) => {
+                let out = Self { inner, model };
+                {
+                    let model = out.model::<T>(NAME);
+                    out.check_len_cap::<T>(&model, NAME);
+                    out.check_slots::<T>(&model, NAME, model.cap());
+                }
+                Err(out)
+            }
+        }
+    }
+}
+
+impl<C: Default> Default for CheckedCore<C> {
+    fn default() -> Self {
+        Self {
+            inner: C::default(),
+            model: RefCell::new(State::Default),
+        }
+    }
+}
+
+impl<C: Clone> Clone for CheckedCore<C> {
+    fn clone(&self) -> Self {
+        let model = match &*self.model.borrow() {
+            State::Ready(model) => State::Cloned(model.clone()),
+
+            // No method was called yet, so the original was never checked.
+            // We keep the state, so that the clone is checked instead.
+            other => other.clone(),
+        };
+
+        Self {
+            inner: self.inner.clone(),
+            model: RefCell::new(model),
+        }
+    }
+}
+
+// After a panic, the model might be out of sync with the inner core. But
+// panics only happen if a requirement was already violated.
+impl<C: UnwindSafe> UnwindSafe for CheckedCore<C> {}
+impl<C: RefUnwindSafe> RefUnwindSafe for CheckedCore<C> {}
+
+impl<C: fmt::Debug> fmt::Debug for CheckedCore<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("CheckedCore").field(&self.inner).finish()
+    }
+}
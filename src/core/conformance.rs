@@ -0,0 +1,1918 @@
+//! A reusable test suite for `Core` implementations.
+//!
+//! This module is only available with the `test-suite` feature. It contains
+//! the unit tests and randomized tests this crate runs against its own cores,
+//! made available for third-party implementations of [`Core`]. Put this into
+//! a test module of your crate:
+//!
+//! ```ignore
+//! use stable_vec::StableVecFacade;
+//!
+//! type MyStableVec<T> = StableVecFacade<T, MyCore<T>>;
+//! stable_vec::conformance_tests!(MyStableVec);
+//! ```
+//!
+//! The macro [`conformance_tests!`][crate::conformance_tests] generates one
+//! `#[test]` function per test. Apart from the basic operations, these
+//! include tests with zero-sized types, types counting how often they are
+//! dropped and types with a panicking `clone()`. Consider combining this
+//! suite with [`CheckedCore`][super::CheckedCore] (i.e. testing
+//! `StableVecFacade<T, CheckedCore<MyCore<T>>>`) to find violations of the
+//! `Core` contract right where they happen.
+
+use std::{
+    prelude::v1::*,
+    fmt::Debug,
+    format,
+};
+
+use crate::{Core, StableVecFacade};
+
+#[doc(hidden)]
+pub use quickcheck::quickcheck;
+
+
+// Miri does not support unwinding, so we only do this check when Miri is not
+// used.
+#[cfg(not(miri))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __assert_panic {
+    ($($body:tt)*) => {{
+        let res = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+            $($body)*
+        }));
+        if let Ok(x) = res {
+            panic!(
+                "expected panic for '{}', but got '{:?}' ",
+                stringify!($($body)*),
+                x,
+            );
+        }
+    }}
+}
+
+#[cfg(miri)]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __assert_panic {
+    ($($body:tt)*) => {};
+}
+
+#[doc(hidden)]
+pub fn assert_sv_eq_fn<T, C>(
+    sv: &mut StableVecFacade<T, C>,
+    indices: &[usize],
+    values: &mut [T],
+    last_index: usize,
+)
+where
+    T: Debug + PartialEq + Copy,
+    C: Core<T> + Clone,
+{
+    let num_elements = values.len();
+    let combined = indices.iter()
+        .zip(&*values)
+        .map(|(i, e)| (*i, *e))
+        .collect::<Vec<_>>();
+
+    assert_eq!(sv.num_elements(), num_elements, "num_elements check failed");
+    assert_eq!(sv.is_empty(), num_elements == 0, "is_empty check failed");
+    assert_eq!(sv.is_compact(), last_index + 1 == num_elements, "is_compact check failed");
+    assert_eq!(sv.next_push_index(), last_index + 1, "next_push_index check failed");
+    assert!(sv.capacity() > last_index, "capacity check failed");
+
+    macro_rules! test_iter {
+        ($it:expr, $expected:ident, || $($mapping:tt)*) => {
+            assert_eq!($it $($mapping)* .collect::<Vec<_>>(), $expected);
+            assert_eq!(
+                $it $($mapping)* .rev().collect::<Vec<_>>()
+                    .into_iter().rev().collect::<Vec<_>>(),
+                $expected,
+            );
+            assert_eq!($it.len(), num_elements);
+            assert_eq!($it.count(), num_elements);
+            assert_eq!($it.size_hint(), (num_elements, Some(num_elements)));
+        };
+    }
+
+    test_iter!(sv.values(), values, || .cloned());
+    test_iter!(sv.values_mut(), values, || .map(|r| *r));
+    test_iter!(sv.iter(), combined, || .map(|(i, r)| (i, *r)));
+    test_iter!(sv.iter_mut(), combined, || .map(|(i, r)| (i, *r)));
+    test_iter!((&*sv).into_iter(), combined, || .map(|(i, r)| (i, *r)));
+    test_iter!((&mut *sv).into_iter(), combined, || .map(|(i, r)| (i, *r)));
+    test_iter!((*sv).clone().into_iter(), combined, || );
+    test_iter!(sv.indices(), indices, || );
+
+
+    assert_eq!(sv, &*values);
+    assert_eq!(sv, &values.to_vec());
+
+    assert_eq!(format!("{:?}", sv), format!("StableVec {:?}", values));
+
+    for i in 0..last_index {
+        if let Ok(index_index) = indices.binary_search(&i) {
+            assert!(sv.has_element_at(i));
+            assert_eq!(sv.get(i), Some(&values[index_index]));
+            assert_eq!(sv.get_mut(i), Some(&mut values[index_index]));
+            assert_eq!(sv[i], values[index_index]);
+
+            assert_eq!(sv.first_filled_slot_from(i), Some(i));
+            assert_eq!(sv.first_filled_slot_below(i + 1), Some(i));
+        } else {
+            assert!(!sv.has_element_at(i));
+            assert_eq!(sv.get(i), None);
+            assert_eq!(sv.get_mut(i), None);
+            crate::__assert_panic!(sv[i]);
+
+            assert_eq!(sv.first_empty_slot_from(i), Some(i));
+            assert_eq!(sv.first_empty_slot_below(i + 1), Some(i));
+        }
+    }
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __assert_sv_eq {
+    ($left:expr, [$(; $last_index:literal)*]: $ty:ty $(,)*) => {{
+        let sv = &mut $left;
+
+        let last_index = 0 $(+ $last_index)*;
+        let next_index = if last_index == 0 { 0 } else { last_index + 1 };
+
+        assert_eq!(sv.num_elements(), 0, "num_elements check failed");
+        assert!(sv.is_empty(), "is_empty() check failed");
+        assert_eq!(sv.is_compact(), next_index == 0, "is_compact check failed");
+        assert_eq!(sv.next_push_index(), next_index, "next_push_index check failed");
+        assert!(sv.capacity() >= next_index, "capacity check failed");
+
+        assert_eq!(sv.iter().count(), 0);
+        assert_eq!(sv.iter_mut().count(), 0);
+        assert_eq!((&*sv).into_iter().count(), 0);
+        assert_eq!((&mut *sv).into_iter().count(), 0);
+        assert_eq!(sv.into_iter().count(), 0);
+        assert_eq!(sv.indices().count(), 0);
+
+        assert_eq!(sv, &[] as &[$ty]);
+        assert_eq!(sv, &vec![] as &Vec<$ty>);
+
+        assert_eq!(format!("{:?}", sv), "StableVec []");
+    }};
+    ($left:expr, [$( $idx:literal => $val:expr ),* $(; $last_index:literal)*] $(,)*) => {{
+        let indices = [$($idx),*];
+        let mut values = [$($val),*];
+        let last_index = 0 $(+ $last_index)*;
+        let last_index = if last_index == 0 {
+            *indices.last().unwrap()
+        } else {
+            last_index
+        };
+
+        $crate::core::conformance::assert_sv_eq_fn(&mut $left, &indices, &mut values, last_index);
+    }};
+}
+
+/// Generates the full test suite of this crate for the stable vector type
+/// `$ty`.
+///
+/// `$ty` has to be the name of a type alias (or type) with a single type
+/// parameter for the element type, e.g.
+/// `type MyStableVec<T> = StableVecFacade<T, MyCore<T>>`. The tests use
+/// different element types, so the core has to implement `Core<T>` and
+/// `Clone` for all `T`. The tests are generated as `#[test]`
+/// functions in the current module. See [the module
+/// documentation][crate::core::conformance] for more information.
+#[macro_export]
+macro_rules! conformance_tests {
+    ($ty:ident) => {
+        use std::{
+            prelude::v1::*,
+            iter::FromIterator,
+        };
+
+        #[test]
+        fn new() {
+            let mut sv = $ty::<String>::new();
+            $crate::__assert_sv_eq!(sv, []: String);
+        }
+
+        #[test]
+        fn default() {
+            let mut sv: $ty<String> = $ty::default();
+            $crate::__assert_sv_eq!(sv, []: String);
+        }
+
+        #[test]
+        fn with_capacity() {
+            let mut sv: $ty<String> = $ty::with_capacity(3);
+
+            assert!(sv.capacity() >= 3);
+            $crate::__assert_sv_eq!(sv, []: String);
+        }
+
+        #[test]
+        fn reserve() {
+            let mut sv = $ty::<String>::new();
+
+            // Reserve for 5
+            sv.reserve(5);
+            assert!(sv.capacity() >= 5);
+            $crate::__assert_sv_eq!(sv, []: String);
+
+            // Reserve for 2 more
+            sv.reserve(7);
+            assert!(sv.capacity() >= 7);
+            $crate::__assert_sv_eq!(sv, []: String);
+
+            // Reserving for 6 should do nothing because we already have memory for 7
+            // or more!
+            let cap_before = sv.capacity();
+            sv.reserve(6);
+            assert_eq!(sv.capacity(), cap_before);
+            $crate::__assert_sv_eq!(sv, []: String);
+
+            // After pushing 23 elements, we should have at least memory for 23 items.
+            for _ in 0..23 {
+                sv.push("x".into());
+            }
+            assert!(sv.capacity() >= 23);
+
+            // Reserving for 13 more elements
+            sv.reserve(13);
+            assert!(sv.capacity() >= 36);
+
+            // Reserving for 2 more shouldn't do anything because we already reserved
+            // for 13 additional ones.
+            let cap_before = sv.capacity();
+            sv.reserve(2);
+            assert_eq!(sv.capacity(), cap_before);
+        }
+
+        #[test]
+        fn reserve_exact() {
+            let mut sv = $ty::<String>::new();
+
+            // Reserve for 5
+            sv.reserve_exact(5);
+            assert!(sv.capacity() >= 5);
+            $crate::__assert_sv_eq!(sv, []: String);
+
+            // Reserve for 2 more
+            sv.reserve_exact(7);
+            assert!(sv.capacity() >= 7);
+            $crate::__assert_sv_eq!(sv, []: String);
+
+            // Reserving for 6 should do nothing because we already have memory for 7
+            // or more!
+            let cap_before = sv.capacity();
+            sv.reserve_exact(6);
+            assert_eq!(sv.capacity(), cap_before);
+            $crate::__assert_sv_eq!(sv, []: String);
+
+            // After pushing 23 elements, we should have at least memory for 23 items.
+            for _ in 0..23 {
+                sv.push("x".into());
+            }
+            assert!(sv.capacity() >= 23);
+
+            // Reserving for 13 more elements
+            sv.reserve_exact(13);
+            assert!(sv.capacity() >= 36);
+
+            // Reserving for 2 more shouldn't do anything because we already reserved
+            // for 13 additional ones.
+            let cap_before = sv.capacity();
+            sv.reserve_exact(2);
+            assert_eq!(sv.capacity(), cap_before);
+        }
+
+        #[test]
+        fn reserve_for() {
+            let mut sv = $ty::<String>::new();
+
+            // Reserve for index 5
+            sv.reserve_for(5);
+            assert!(sv.capacity() >= 6);
+            $crate::__assert_sv_eq!(sv, []: String);
+
+            // Reserve for index 7
+            sv.reserve_for(7);
+            assert!(sv.capacity() >= 8);
+            $crate::__assert_sv_eq!(sv, []: String);
+
+            // Reserving for index 6 should do nothing because we already have
+            // memory for that index!
+            let cap_before = sv.capacity();
+            sv.reserve_for(6);
+            assert_eq!(sv.capacity(), cap_before);
+            $crate::__assert_sv_eq!(sv, []: String);
+
+            // After pushing 23 elements, we should have at least memory for 23 items.
+            for _ in 0..23 {
+                sv.push("x".into());
+            }
+            assert!(sv.capacity() >= 23);
+
+            // Reserving for index 22 shouldn't do anything because we already
+            // have memory for that index.
+            let cap_before = sv.capacity();
+            sv.reserve_for(22);
+            assert_eq!(sv.capacity(), cap_before);
+
+            // Reserving for new index
+            sv.reserve_for(27);
+            assert!(sv.capacity() >= 28);
+        }
+
+        #[test]
+        fn from() {
+            $crate::__assert_sv_eq!(
+                $ty::<String>::from(&[]),
+                []: String,
+            );
+
+            $crate::__assert_sv_eq!(
+                $ty::from(&[1]),
+                [0 => 1],
+            );
+
+            $crate::__assert_sv_eq!(
+                $ty::from(&[2, 9, 5]),
+                [0 => 2, 1 => 9, 2 => 5],
+            );
+        }
+
+        #[test]
+        fn push_simple() {
+            let mut sv = $ty::new();
+
+            sv.push('a');
+            $crate::__assert_sv_eq!(sv, [0 => 'a']);
+
+            sv.push('b');
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 1 => 'b']);
+
+            sv.push('c');
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 1 => 'b', 2 => 'c']);
+        }
+
+        #[test]
+        fn remove_first() {
+            let mut sv = $ty::from_iter(vec!['a', 'b', 'c']);
+
+            assert_eq!(sv.remove_first(), Some('a'));
+            $crate::__assert_sv_eq!(sv, [1 => 'b', 2 => 'c'; 2]);
+
+            assert_eq!(sv.remove_first(), Some('b'));
+            $crate::__assert_sv_eq!(sv, [2 => 'c'; 2]);
+
+            sv.push('d');
+            $crate::__assert_sv_eq!(sv, [2 => 'c', 3 => 'd']);
+
+            sv.push('e');
+            $crate::__assert_sv_eq!(sv, [2 => 'c', 3 => 'd', 4 => 'e']);
+
+            assert_eq!(sv.remove_first(), Some('c'));
+            $crate::__assert_sv_eq!(sv, [3 => 'd', 4 => 'e'; 4]);
+
+            assert_eq!(sv.remove_first(), Some('d'));
+            $crate::__assert_sv_eq!(sv, [4 => 'e'; 4]);
+
+            assert_eq!(sv.remove_first(), Some('e'));
+            $crate::__assert_sv_eq!(sv, [; 4]: char);
+        }
+
+        #[test]
+        fn remove_last() {
+            let mut sv = $ty::from_iter(vec!['a', 'b', 'c']);
+
+            assert_eq!(sv.remove_last(), Some('c'));
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 1 => 'b'; 2]);
+
+            assert_eq!(sv.remove_last(), Some('b'));
+            $crate::__assert_sv_eq!(sv, [0 => 'a'; 2]);
+
+            sv.push('d');
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 3 => 'd']);
+
+            sv.push('e');
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 3 => 'd', 4 => 'e']);
+
+            assert_eq!(sv.remove_last(), Some('e'));
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 3 => 'd'; 4]);
+
+            assert_eq!(sv.remove_last(), Some('d'));
+            $crate::__assert_sv_eq!(sv, [0 => 'a'; 4]);
+
+            assert_eq!(sv.remove_last(), Some('a'));
+            $crate::__assert_sv_eq!(sv, [; 4]: char);
+        }
+
+        #[test]
+        fn find_first() {
+            let mut sv = $ty::from_iter(vec!['a', 'b']);
+
+            assert_eq!(sv.find_first(), Some(&'a'));
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 1 => 'b'; 1]);
+
+            sv.push('c');
+            assert_eq!(sv.find_first(), Some(&'a'));
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 1 => 'b', 2 => 'c'; 2]);
+
+            sv.remove(1);
+            assert_eq!(sv.find_first(), Some(&'a'));
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 2 => 'c'; 2]);
+
+            sv.remove(0);
+            assert_eq!(sv.find_first(), Some(&'c'));
+            $crate::__assert_sv_eq!(sv, [2 => 'c'; 2]);
+
+            sv.clear();
+            assert_eq!(sv.find_first(), None);
+            $crate::__assert_sv_eq!(sv, []: char);
+        }
+
+        #[test]
+        fn find_first_mut() {
+            let mut sv = $ty::from_iter(vec!['a', 'b']);
+
+            *sv.find_first_mut().unwrap() = 'c';
+            $crate::__assert_sv_eq!(sv, [0 => 'c', 1 => 'b'; 1]);
+
+            sv.remove(0);
+            *sv.find_first_mut().unwrap() = 'd';
+            $crate::__assert_sv_eq!(sv, [1 => 'd'; 1]);
+
+            sv.remove(1);
+            assert_eq!(sv.find_first_mut(), None);
+            $crate::__assert_sv_eq!(sv, [; 1]: char);
+        }
+
+        #[test]
+        fn find_last() {
+            let mut sv = $ty::from_iter(vec!['a', 'b']);
+
+            assert_eq!(sv.find_last(), Some(&'b'));
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 1 => 'b'; 1]);
+
+            sv.push('c');
+            assert_eq!(sv.find_last(), Some(&'c'));
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 1 => 'b', 2 => 'c'; 2]);
+
+            sv.remove(1);
+            assert_eq!(sv.find_last(), Some(&'c'));
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 2 => 'c'; 2]);
+
+            sv.remove(2);
+            assert_eq!(sv.find_last(), Some(&'a'));
+            $crate::__assert_sv_eq!(sv, [0 => 'a'; 2]);
+
+            sv.clear();
+            assert_eq!(sv.find_last(), None);
+            $crate::__assert_sv_eq!(sv, []: char);
+        }
+
+        #[test]
+        fn find_last_mut() {
+            let mut sv = $ty::from_iter(vec!['a', 'b']);
+
+            *sv.find_last_mut().unwrap() = 'c';
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 1 => 'c'; 1]);
+
+            sv.remove(1);
+            *sv.find_last_mut().unwrap() = 'd';
+            $crate::__assert_sv_eq!(sv, [0 => 'd'; 1]);
+
+            sv.remove(0);
+            assert_eq!(sv.find_last_mut(), None);
+            $crate::__assert_sv_eq!(sv, [; 1]: char);
+        }
+
+        #[test]
+        fn find_first_index() {
+            let mut sv = $ty::from_iter(vec!['a', 'b']);
+
+            assert_eq!(sv.find_first_index(), Some(0));
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 1 => 'b'; 1]);
+
+            sv.remove(0);
+            assert_eq!(sv.find_first_index(), Some(1));
+            $crate::__assert_sv_eq!(sv, [1 => 'b'; 1]);
+
+            sv.push('c');
+            assert_eq!(sv.find_first_index(), Some(1));
+            $crate::__assert_sv_eq!(sv, [1 => 'b', 2 => 'c'; 2]);
+
+            sv.remove(1);
+            assert_eq!(sv.find_first_index(), Some(2));
+            $crate::__assert_sv_eq!(sv, [2 => 'c'; 2]);
+
+            sv.remove(2);
+            assert_eq!(sv.find_first_index(), None);
+            $crate::__assert_sv_eq!(sv, [; 2]: char);
+        }
+
+        #[test]
+        fn find_last_index() {
+            let mut sv = $ty::from_iter(vec!['a', 'b']);
+
+            assert_eq!(sv.find_last_index(), Some(1));
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 1 => 'b'; 1]);
+
+            sv.remove(1);
+            assert_eq!(sv.find_last_index(), Some(0));
+            $crate::__assert_sv_eq!(sv, [0 => 'a'; 1]);
+
+            sv.push('c');
+            assert_eq!(sv.find_last_index(), Some(2));
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 2 => 'c'; 2]);
+
+            sv.remove(2);
+            assert_eq!(sv.find_last_index(), Some(0));
+            $crate::__assert_sv_eq!(sv, [0 => 'a'; 2]);
+
+            sv.remove(0);
+            assert_eq!(sv.find_last_index(), None);
+            $crate::__assert_sv_eq!(sv, [; 2]: char);
+        }
+
+        #[test]
+        fn first_filled_slot_from() {
+            let mut sv = $ty::new();
+            sv.reserve(10);
+            assert_eq!(sv.first_filled_slot_from(0), None);
+            assert_eq!(sv.first_filled_slot_from(1), None);
+            assert_eq!(sv.first_filled_slot_from(10), None);
+
+            sv.insert(0, 10u32);
+            assert_eq!(sv.first_filled_slot_from(0), Some(0));
+            assert_eq!(sv.first_filled_slot_from(1), None);
+            assert_eq!(sv.first_filled_slot_from(2), None);
+            assert_eq!(sv.first_filled_slot_from(10), None);
+
+            sv.insert(1, 11u32);
+            assert_eq!(sv.first_filled_slot_from(0), Some(0));
+            assert_eq!(sv.first_filled_slot_from(1), Some(1));
+            assert_eq!(sv.first_filled_slot_from(2), None);
+            assert_eq!(sv.first_filled_slot_from(3), None);
+            assert_eq!(sv.first_filled_slot_from(10), None);
+
+            sv.insert(3, 13u32);
+            assert_eq!(sv.first_filled_slot_from(0), Some(0));
+            assert_eq!(sv.first_filled_slot_from(1), Some(1));
+            assert_eq!(sv.first_filled_slot_from(2), Some(3));
+            assert_eq!(sv.first_filled_slot_from(3), Some(3));
+            assert_eq!(sv.first_filled_slot_from(4), None);
+            assert_eq!(sv.first_filled_slot_from(5), None);
+            assert_eq!(sv.first_filled_slot_from(10), None);
+
+            let mut sv = $ty::new();
+            sv.reserve(10);
+            sv.insert(2, 10u32);
+            assert_eq!(sv.first_filled_slot_from(0), Some(2));
+            assert_eq!(sv.first_filled_slot_from(1), Some(2));
+            assert_eq!(sv.first_filled_slot_from(2), Some(2));
+            assert_eq!(sv.first_filled_slot_from(3), None);
+            assert_eq!(sv.first_filled_slot_from(4), None);
+            assert_eq!(sv.first_filled_slot_from(10), None);
+        }
+
+        #[test]
+        fn first_filled_slot_from_medium() {
+            let mut sv = $ty::new();
+            sv.reserve(200);
+
+            for i in (25..60).chain(62..65).chain(66..70).chain(90..120) {
+                sv.insert(i, 27u32);
+            }
+
+            for i in 0..25 {
+                assert_eq!(sv.first_filled_slot_from(i), Some(25));
+            }
+            for i in 25..60 {
+                assert_eq!(sv.first_filled_slot_from(i), Some(i));
+            }
+            for i in 60..62 {
+                assert_eq!(sv.first_filled_slot_from(i), Some(62));
+            }
+            for i in 62..65 {
+                assert_eq!(sv.first_filled_slot_from(i), Some(i));
+            }
+            for i in 65..66 {
+                assert_eq!(sv.first_filled_slot_from(i), Some(66));
+            }
+            for i in 66..70 {
+                assert_eq!(sv.first_filled_slot_from(i), Some(i));
+            }
+            for i in 70..90 {
+                assert_eq!(sv.first_filled_slot_from(i), Some(90));
+            }
+            for i in 90..120 {
+                assert_eq!(sv.first_filled_slot_from(i), Some(i));
+            }
+            for i in 120..201 {
+                assert_eq!(sv.first_filled_slot_from(i), None);
+            }
+        }
+
+        // This largest test takes a fairly long time with Miri, so it is disabled
+        // by default.
+        #[cfg(not(miri))]
+        #[test]
+        fn first_filled_slot_from_large() {
+            let mut sv = $ty::new();
+            sv.reserve(2000);
+
+            for i in (250..600).chain(620..650).chain(652..700).chain(900..1200) {
+                sv.insert(i, 27u32);
+            }
+
+            for i in 0..250 {
+                assert_eq!(sv.first_filled_slot_from(i), Some(250));
+            }
+            for i in 250..600 {
+                assert_eq!(sv.first_filled_slot_from(i), Some(i));
+            }
+            for i in 600..620 {
+                assert_eq!(sv.first_filled_slot_from(i), Some(620));
+            }
+            for i in 620..650 {
+                assert_eq!(sv.first_filled_slot_from(i), Some(i));
+            }
+            for i in 650..652 {
+                assert_eq!(sv.first_filled_slot_from(i), Some(652));
+            }
+            for i in 652..700 {
+                assert_eq!(sv.first_filled_slot_from(i), Some(i));
+            }
+            for i in 700..900 {
+                assert_eq!(sv.first_filled_slot_from(i), Some(900));
+            }
+            for i in 900..1200 {
+                assert_eq!(sv.first_filled_slot_from(i), Some(i));
+            }
+            for i in 1200..2001 {
+                assert_eq!(sv.first_filled_slot_from(i), None);
+            }
+        }
+
+        #[test]
+        fn first_filled_slot_below_medium() {
+            let mut sv = $ty::new();
+            sv.reserve(200);
+
+            for i in (25..60).chain(62..65).chain(66..70).chain(90..120) {
+                sv.insert(i, 27u32);
+            }
+
+            for i in 0..26 {
+                assert_eq!(sv.first_filled_slot_below(i), None);
+            }
+            for i in 26..61 {
+                assert_eq!(sv.first_filled_slot_below(i), Some(i - 1));
+            }
+            for i in 61..63 {
+                assert_eq!(sv.first_filled_slot_below(i), Some(59));
+            }
+            for i in 63..66 {
+                assert_eq!(sv.first_filled_slot_below(i), Some(i - 1));
+            }
+            for i in 66..67 {
+                assert_eq!(sv.first_filled_slot_below(i), Some(64));
+            }
+            for i in 67..71 {
+                assert_eq!(sv.first_filled_slot_below(i), Some(i - 1));
+            }
+            for i in 71..91 {
+                assert_eq!(sv.first_filled_slot_below(i), Some(69));
+            }
+            for i in 91..121 {
+                assert_eq!(sv.first_filled_slot_below(i), Some(i - 1));
+            }
+            for i in 121..201 {
+                assert_eq!(sv.first_filled_slot_below(i), Some(119));
+            }
+        }
+
+        #[test]
+        fn first_empty_slot_from_medium() {
+            let mut sv = $ty::new();
+            sv.reserve(200);
+
+            for i in (25..60).chain(62..65).chain(66..70).chain(90..120) {
+                sv.insert(i, 27u32);
+            }
+
+            for i in 0..25 {
+                assert_eq!(sv.first_empty_slot_from(i), Some(i));
+            }
+            for i in 25..60 {
+                assert_eq!(sv.first_empty_slot_from(i), Some(60));
+            }
+            for i in 60..62 {
+                assert_eq!(sv.first_empty_slot_from(i), Some(i));
+            }
+            for i in 62..65 {
+                assert_eq!(sv.first_empty_slot_from(i), Some(65));
+            }
+            for i in 65..66 {
+                assert_eq!(sv.first_empty_slot_from(i), Some(i));
+            }
+            for i in 66..70 {
+                assert_eq!(sv.first_empty_slot_from(i), Some(70));
+            }
+            for i in 70..90 {
+                assert_eq!(sv.first_empty_slot_from(i), Some(i));
+            }
+            for i in 90..120 {
+                assert_eq!(sv.first_empty_slot_from(i), Some(120));
+            }
+            for i in 120..200 {
+                assert_eq!(sv.first_empty_slot_from(i), Some(i));
+            }
+            assert_eq!(sv.first_empty_slot_from(200), None);
+        }
+
+        #[test]
+        fn first_empty_slot_below_medium() {
+            let mut sv = $ty::new();
+            sv.reserve(200);
+
+            for i in (25..60).chain(62..65).chain(66..70).chain(90..120) {
+                sv.insert(i, 27u32);
+            }
+
+            assert_eq!(sv.first_empty_slot_below(0), None);
+            for i in 1..26 {
+                assert_eq!(sv.first_empty_slot_below(i), Some(i - 1));
+            }
+            for i in 26..61 {
+                assert_eq!(sv.first_empty_slot_below(i), Some(24));
+            }
+            for i in 61..63 {
+                assert_eq!(sv.first_empty_slot_below(i), Some(i - 1));
+            }
+            for i in 63..66 {
+                assert_eq!(sv.first_empty_slot_below(i), Some(61));
+            }
+            for i in 66..67 {
+                assert_eq!(sv.first_empty_slot_below(i), Some(i - 1));
+            }
+            for i in 67..71 {
+                assert_eq!(sv.first_empty_slot_below(i), Some(65));
+            }
+            for i in 71..91 {
+                assert_eq!(sv.first_empty_slot_below(i), Some(i - 1));
+            }
+            for i in 91..121 {
+                assert_eq!(sv.first_empty_slot_below(i), Some(89));
+            }
+            for i in 121..201 {
+                assert_eq!(sv.first_empty_slot_below(i), Some(i - 1));
+            }
+        }
+
+        #[test]
+        fn retain_indices() {
+            let mut sv = $ty::from_iter(vec!['a', 'b', 'c', 'd', 'e']);
+
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 1 => 'b', 2 => 'c', 3 => 'd', 4 => 'e'; 4]);
+
+            sv.retain_indices(|index| index != 2);
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 1 => 'b', 3 => 'd', 4 => 'e'; 4]);
+
+            sv.retain_indices(|index| index == 0 || index == 3);
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 3 => 'd'; 4]);
+
+            sv.retain_indices(|index| index == 0);
+            $crate::__assert_sv_eq!(sv, [0 => 'a'; 4]);
+
+            sv.retain_indices(|index| index != 4);
+            $crate::__assert_sv_eq!(sv, [0 => 'a'; 4]);
+
+            sv.retain_indices(|_| false);
+            $crate::__assert_sv_eq!(sv, [; 4]: char);
+        }
+
+        #[test]
+        fn shrink_to_fit() {
+            let mut sv = $ty::from_iter(vec!['a', 'b', 'c', 'd', 'e', 'f']);
+            sv.reserve(100);
+            sv.retain_indices(|index| index != 1 && index != 3 && index != 5);
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 2 => 'c', 4 => 'e'; 5]);
+
+            sv.shrink_to_fit();
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 2 => 'c', 4 => 'e'; 5]);
+            assert_eq!(sv.capacity(), 6);
+        }
+
+        #[test]
+        fn remove() {
+            let mut sv = $ty::from_iter(vec!['a', 'b', 'c']);
+
+            assert_eq!(sv.remove(1), Some('b'));
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 2 => 'c']);
+
+            sv.extend_from_slice(&['d', 'e']);
+            assert_eq!(sv.remove(4), Some('e'));
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 2 => 'c', 3 => 'd'; 4]);
+
+            assert_eq!(sv.remove(4), None);
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 2 => 'c', 3 => 'd'; 4]);
+
+            assert_eq!(sv.remove(1), None);
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 2 => 'c', 3 => 'd'; 4]);
+
+            assert_eq!(sv.remove(0), Some('a'));
+            $crate::__assert_sv_eq!(sv, [2 => 'c', 3 => 'd'; 4]);
+        }
+
+        #[test]
+        fn swap() {
+            let mut sv = $ty::from(&['a', 'b', 'c', 'd']);
+            sv.reserve_for(5);
+            assert_eq!(sv.next_push_index(), 4);
+
+            sv.swap(0, 5);
+            $crate::__assert_sv_eq!(sv, [1 => 'b', 2 => 'c', 3 => 'd', 5 => 'a']);
+            assert_eq!(sv.next_push_index(), 6);
+
+            sv.swap(1, 2);
+            $crate::__assert_sv_eq!(sv, [1 => 'c', 2 => 'b', 3 => 'd', 5 => 'a']);
+
+            sv.swap(0, 4);
+            $crate::__assert_sv_eq!(sv, [1 => 'c', 2 => 'b', 3 => 'd', 5 => 'a']);
+
+            $crate::__assert_panic!(sv.swap(0, sv.capacity()));
+            $crate::__assert_panic!(sv.swap(sv.capacity(), 0));
+            $crate::__assert_panic!(sv.swap(0, sv.capacity() + 1));
+            $crate::__assert_panic!(sv.swap(sv.capacity() + 1, 0));
+        }
+
+        // This is a fairly time-consuming test which takes a long time on
+        // Miri, we we do not execute it by default with Miri.
+        #[cfg(not(miri))]
+        #[test]
+        fn large() {
+            let mut sv = $ty::new();
+
+            const LIMIT: usize = 200;
+            sv.extend((0u32..LIMIT as u32).rev());
+            sv.push(2 * LIMIT as u32);
+
+            for i in 0..LIMIT {
+                assert!(sv.has_element_at(i));
+                assert_eq!(sv.get(i).cloned(), Some(LIMIT as u32 - 1 - i as u32));
+            }
+            assert_eq!(sv.num_elements(), LIMIT + 1);
+            assert_eq!(sv.indices().count(), LIMIT + 1);
+            assert_eq!(sv.iter().count(), LIMIT + 1);
+            assert_eq!(sv.iter_mut().count(), LIMIT + 1);
+            assert_eq!(sv.clone().into_iter().count(), LIMIT + 1);
+
+            for hole in 0..LIMIT {
+                let mut clone = sv.clone();
+                clone.remove(hole);
+
+                for i in 0..LIMIT {
+                    if i != hole {
+                        assert!(clone.has_element_at(i));
+                        assert_eq!(clone.get(i).cloned(), Some(LIMIT as u32 - 1 - i as u32));
+                    } else {
+                        assert!(!clone.has_element_at(i));
+                        assert_eq!(clone.get(i), None);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn zero_sized_type() {
+            let mut sv = $ty::<()>::from(&[(), (), ()]);
+
+            assert_eq!(sv.remove(1), Some(()));
+            $crate::__assert_sv_eq!(sv, [0 => (), 2 => ()]);
+
+            sv.extend_from_slice(&[(), ()]);
+            assert_eq!(sv.remove(4), Some(()));
+            $crate::__assert_sv_eq!(sv, [0 => (), 2 => (), 3 => (); 4]);
+
+            assert_eq!(sv.remove(4), None);
+            $crate::__assert_sv_eq!(sv, [0 => (), 2 => (), 3 => (); 4]);
+
+            assert_eq!(sv.remove(1), None);
+            $crate::__assert_sv_eq!(sv, [0 => (), 2 => (), 3 => (); 4]);
+
+            assert_eq!(sv.remove(0), Some(()));
+            $crate::__assert_sv_eq!(sv, [2 => (), 3 => (); 4]);
+        }
+
+        #[test]
+        fn insert() {
+            let mut sv = $ty::from_iter(vec!['a', 'b', 'c']);
+
+            assert_eq!(sv.insert(1, 'x'), Some('b'));
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 1 => 'x', 2 => 'c']);
+
+            $crate::__assert_panic!(sv.insert(3, 'x'));
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 1 => 'x', 2 => 'c']);
+
+            assert_eq!(sv.remove(0), Some('a'));
+            assert_eq!(sv.insert(0, 'd'), None);
+            $crate::__assert_sv_eq!(sv, [0 => 'd', 1 => 'x', 2 => 'c']);
+
+            sv.reserve(2);
+            assert_eq!(sv.insert(4, 'f'), None);
+            $crate::__assert_sv_eq!(sv, [0 => 'd', 1 => 'x', 2 => 'c', 4 => 'f']);
+
+            assert_eq!(sv.insert(3, 'g'), None);
+            $crate::__assert_sv_eq!(sv, [0 => 'd', 1 => 'x', 2 => 'c', 3 => 'g', 4 => 'f']);
+        }
+
+        #[test]
+        fn clear() {
+            let mut sv: $ty<String> = $ty::new();
+            sv.clear();
+            $crate::__assert_sv_eq!(sv, []: String);
+
+            let mut sv = $ty::from_iter(vec![1, 3, 5]);
+            sv.clear();
+            $crate::__assert_sv_eq!(sv, []: u32);
+        }
+
+        #[test]
+        fn extend_from_slice() {
+            let mut sv = $ty::new();
+
+            sv.extend_from_slice(&['a']);
+            $crate::__assert_sv_eq!(sv, [0 => 'a']);
+
+            sv.push('b');
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 1 => 'b']);
+
+            sv.extend_from_slice(&['c', 'd']);
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 1 => 'b', 2 => 'c', 3 => 'd']);
+
+            assert_eq!(sv.remove_last(), Some('d'));
+            sv.extend_from_slice(&['e']);
+            $crate::__assert_sv_eq!(sv, [0 => 'a', 1 => 'b', 2 => 'c', 4 => 'e']);
+        }
+
+        #[test]
+        fn clone() {
+            let sv = $ty::<String>::new();
+            $crate::__assert_sv_eq!(sv.clone(), []: String);
+
+            let sv = $ty::from(&[2, 4]);
+            $crate::__assert_sv_eq!(sv.clone(), [0 => 2, 1 => 4]);
+
+            let mut sv = $ty::from(&[2, 5, 4]);
+            sv.remove(1);
+            $crate::__assert_sv_eq!(sv.clone(), [0 => 2, 2 => 4]);
+        }
+
+        #[test]
+        fn iter_values_mut() {
+            let mut sv = $ty::from(&[2, 5, 4]);
+
+            for (i, x) in &mut sv {
+                *x += i;
+            }
+            $crate::__assert_sv_eq!(sv, [0 => 2, 1 => 6, 2 => 6]);
+
+            for x in sv.values_mut() {
+                *x -= 1;
+            }
+            $crate::__assert_sv_eq!(sv, [0 => 1, 1 => 5, 2 => 5]);
+
+            for (i, x) in sv.iter_mut() {
+                *x *= i;
+            }
+            $crate::__assert_sv_eq!(sv, [0 => 0, 1 => 5, 2 => 10]);
+        }
+
+        #[test]
+        fn iter() {
+            let sv = $ty::from(&[5, 6, 7, 8, 9]);
+            let mut it = sv.iter();
+
+            assert_eq!(it.len(), 5);
+            assert_eq!(it.next(), Some((0, &5)));
+            assert_eq!(it.len(), 4);
+            assert_eq!(it.next_back(), Some((4, &9)));
+            assert_eq!(it.len(), 3);
+            assert_eq!(it.next(), Some((1, &6)));
+            assert_eq!(it.len(), 2);
+            assert_eq!(it.clone().last(), Some((3, &8)));
+            assert_eq!(it.next_back(), Some((3, &8)));
+            assert_eq!(it.len(), 1);
+            assert_eq!(it.next_back(), Some((2, &7)));
+            assert_eq!(it.len(), 0);
+            assert_eq!(it.next(), None);
+        }
+
+        #[test]
+        fn iter_mut() {
+            let mut sv = $ty::from(&[5, 6, 7, 8, 9]);
+            let mut it = sv.iter_mut();
+
+            assert_eq!(it.len(), 5);
+            assert_eq!(it.next(), Some((0, &mut 5)));
+            assert_eq!(it.len(), 4);
+            assert_eq!(it.next_back(), Some((4, &mut 9)));
+            assert_eq!(it.len(), 3);
+            assert_eq!(it.next(), Some((1, &mut 6)));
+            assert_eq!(it.len(), 2);
+            assert_eq!(it.next_back(), Some((3, &mut 8)));
+            assert_eq!(it.len(), 1);
+            assert_eq!(it.next_back(), Some((2, &mut 7)));
+            assert_eq!(it.len(), 0);
+            assert_eq!(it.next(), None);
+        }
+
+        #[test]
+        fn into_iter() {
+            let sv = $ty::from(&[5, 6, 7, 8, 9]);
+            let mut it = sv.into_iter();
+
+            assert_eq!(it.len(), 5);
+            assert_eq!(it.next(), Some((0, 5)));
+            assert_eq!(it.len(), 4);
+            assert_eq!(it.next_back(), Some((4, 9)));
+            assert_eq!(it.len(), 3);
+            assert_eq!(it.next(), Some((1, 6)));
+            assert_eq!(it.len(), 2);
+            assert_eq!(it.clone().last(), Some((3, 8)));
+            assert_eq!(it.next_back(), Some((3, 8)));
+            assert_eq!(it.len(), 1);
+            assert_eq!(it.next_back(), Some((2, 7)));
+            assert_eq!(it.len(), 0);
+            assert_eq!(it.next(), None);
+        }
+
+        #[test]
+        fn index_mut() {
+            let mut sv = $ty::from(&[2, 5, 4]);
+
+            sv[1] = 8;
+            $crate::__assert_sv_eq!(sv, [0 => 2, 1 => 8, 2 => 4]);
+
+            sv[2] = 5;
+            $crate::__assert_sv_eq!(sv, [0 => 2, 1 => 8, 2 => 5]);
+        }
+
+        #[test]
+        fn index_panic() {
+            let mut sv: $ty<_> = $ty::from(&[2, 5, 4]);
+            sv.remove(1);
+
+            $crate::__assert_panic!(sv[1]);
+            $crate::__assert_panic!(sv[3]);
+
+            sv.reserve(10);
+            $crate::__assert_panic!(sv[8]);
+        }
+
+        #[test]
+        fn correct_drop() {
+            use std::sync::atomic::{Ordering, AtomicIsize};
+
+            static ALIVE_COUNT: AtomicIsize = AtomicIsize::new(0);
+
+            struct Dummy(char);
+            impl Dummy {
+                fn new(c: char) -> Self {
+                    ALIVE_COUNT.fetch_add(1, Ordering::SeqCst);
+                    Self(c)
+                }
+            }
+            impl Drop for Dummy {
+                fn drop(&mut self) {
+                    ALIVE_COUNT.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+            impl Clone for Dummy {
+                fn clone(&self) -> Self {
+                    Self::new(self.0)
+                }
+            }
+
+            let mut sv = $ty::new();
+
+            sv.push(Dummy::new('a'));
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 1);
+
+            sv.push(Dummy::new('b'));
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 2);
+
+            sv.push(Dummy::new('c'));
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 3);
+
+            sv.remove(1);
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 2);
+
+            sv.extend_from_slice(&[Dummy::new('d'), Dummy::new('e')]);
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 4);
+
+            sv.remove_first();
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 3);
+
+            sv.retain(|c| c.0 != 'd');
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 2);
+
+            {
+                let mut clone = sv.clone();
+                assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 4);
+
+                clone.reordering_make_compact();
+                assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 4);
+            }
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 2);
+
+
+            sv.make_compact();
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 2);
+
+            sv.clear();
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 0);
+        }
+
+        #[test]
+        fn compact_tiny() {
+            let mut sv = $ty::from(&[1.0, 2.0, 3.0]);
+            sv.remove(1);
+            $crate::__assert_sv_eq!(sv, [0 => 1.0, 2 => 3.0]);
+
+            sv.make_compact();
+            $crate::__assert_sv_eq!(sv, [0 => 1.0, 1 => 3.0]);
+            assert_eq!(sv, vec![1.0, 3.0]);
+        }
+
+        #[test]
+        fn extend_from_iter() {
+            use std::iter::FromIterator;
+
+            let sv = $ty::from_iter(0..0);
+            $crate::__assert_sv_eq!(sv.clone(), []: u32);
+
+            let sv = $ty::from_iter(0..3);
+            $crate::__assert_sv_eq!(sv.clone(), [0 => 0, 1 => 1, 2 => 2]);
+
+            let mut sv = $ty::from_iter((0..3).map(|x| x * 3));
+            $crate::__assert_sv_eq!(sv.clone(), [0 => 0, 1 => 3, 2 => 6]);
+
+            sv.remove(2);
+            sv.extend((7..10).rev());
+            $crate::__assert_sv_eq!(sv.clone(), [0 => 0, 1 => 3, 3 => 9, 4 => 8, 5 => 7]);
+        }
+
+        #[test]
+        fn size_hints() {
+            let mut sv = $ty::<()>::new();
+
+            assert_eq!(sv.iter().size_hint(), (0, Some(0)));
+            assert_eq!(sv.iter_mut().size_hint(), (0, Some(0)));
+            assert_eq!(sv.indices().size_hint(), (0, Some(0)));
+
+
+            let mut sv = $ty::from(&[0, 1, 2, 3, 4]);
+            sv.remove(1);
+
+            macro_rules! check_iter {
+                ($it:expr) => {{
+                    let mut it = $it;
+                    assert_eq!(it.size_hint(), (4, Some(4)));
+                    assert!(it.next().is_some());
+                    assert_eq!(it.size_hint(), (3, Some(3)));
+                    assert!(it.next().is_some());
+                    assert_eq!(it.size_hint(), (2, Some(2)));
+                    assert!(it.next().is_some());
+                    assert_eq!(it.size_hint(), (1, Some(1)));
+                    assert!(it.next().is_some());
+                    assert_eq!(it.size_hint(), (0, Some(0)));
+                }}
+            }
+
+            check_iter!(sv.iter());
+            check_iter!(sv.iter_mut());
+            check_iter!(sv.indices());
+        }
+
+        // Quickcheck tests run far
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn reordering_compact() {
+            fn prop(insertions: u16, to_delete: Vec<u16>) -> bool {
+                let insertions = insertions + 1;
+                // Create stable vector containing `insertions` zeros. Afterwards, we
+                // remove at most half of those elements
+                let mut sv = $ty::from(vec![0; insertions as usize]);
+                for i in to_delete {
+                    let i = (i % insertions) as usize;
+                    if sv.has_element_at(i) {
+                        sv.remove(i);
+                    }
+                }
+
+                // Remember the number of elements before and call compact.
+                let sv_before = sv.clone();
+                let n_before_compact = sv.num_elements();
+                sv.reordering_make_compact();
+
+                n_before_compact == sv.num_elements()
+                    && sv.is_compact()
+                    && (0..n_before_compact).all(|i| sv.get(i).is_some())
+                    && sv_before.values().all(|e| sv.contains(e))
+            }
+
+            $crate::core::conformance::quickcheck(prop as fn(u16, Vec<u16>) -> bool);
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn compact() {
+            fn prop(insertions: u16, to_delete: Vec<u16>) -> bool {
+                let insertions = insertions + 1;
+                // Create stable vector containing `insertions` zeros. Afterwards, we
+                // remove at most half of those elements
+                let mut sv = $ty::from(vec![0; insertions as usize]);
+                for i in to_delete {
+                    let i = (i % insertions) as usize;
+                    if sv.has_element_at(i) {
+                        sv.remove(i);
+                    }
+                }
+
+                // Remember the number of elements before and call compact.
+                let sv_before = sv.clone();
+                let items_before: Vec<_> = sv_before.values().cloned().collect();
+                let n_before_compact = sv.num_elements();
+                sv.make_compact();
+
+
+                n_before_compact == sv.num_elements()
+                    && sv.is_compact()
+                    && (0..n_before_compact).all(|i| sv.get(i).is_some())
+                    && sv == items_before
+            }
+
+            $crate::core::conformance::quickcheck(prop as fn(u16, Vec<u16>) -> bool);
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn from_and_extend_and_from_iter() {
+            fn prop(items: Vec<u8>) -> bool {
+                use std::iter::FromIterator;
+
+                let iter_a = items.iter().cloned();
+                let iter_b = items.iter().cloned();
+
+                let sv_a = $ty::from_iter(iter_a);
+                let sv_b = {
+                    let mut sv = $ty::new();
+                    sv.extend(iter_b);
+                    sv
+                };
+                let sv_c = $ty::from(&items);
+
+                sv_a.num_elements() == items.len()
+                    && sv_a == sv_b
+                    && sv_a == sv_c
+            }
+
+            $crate::core::conformance::quickcheck(prop as fn(Vec<u8>) -> bool);
+        }
+
+
+        #[test]
+        fn map_ref() {
+            let mut sv = $ty::from(&[1, 2, 3, 4]);
+            sv.remove(1);
+            sv.remove(3);
+
+            let mapped: $ty<u64> = sv.map_ref(|&x| x as u64 * 10);
+            $crate::__assert_sv_eq!(mapped.clone(), [0 => 10, 2 => 30; 3]);
+            $crate::__assert_sv_eq!(sv, [0 => 1, 2 => 3; 3]);
+
+            let empty: $ty<u64> = $ty::<u32>::new().map_ref(|&x| x as u64);
+            $crate::__assert_sv_eq!(empty.clone(), []: u64);
+        }
+
+        #[test]
+        fn try_map() {
+            let mut sv = $ty::from(&["1", "2", "x", "4", "y"]);
+            sv.remove(0);
+
+            let res: Result<$ty<u32>, _> = sv.clone().try_map(|s| s.parse::<u32>());
+            assert_eq!(res.unwrap_err().0, 2);
+
+            sv.remove(2);
+            sv.remove(4);
+            let numbers: $ty<u32> = sv.try_map(|s| s.parse::<u32>()).unwrap();
+            $crate::__assert_sv_eq!(numbers.clone(), [1 => 2, 3 => 4; 4]);
+            assert_eq!(numbers.next_push_index(), 5);
+        }
+
+        #[test]
+        fn filter_map() {
+            let mut sv = $ty::from(&[1, 2, 3, 4, 5, 6]);
+            sv.remove(3);
+
+            let even: $ty<i64> = sv.filter_map(|x| if x % 2 == 0 { Some(x as i64 * 10) } else { None });
+            $crate::__assert_sv_eq!(even.clone(), [1 => 20, 5 => 60]);
+
+            let none: $ty<u8> = even.filter_map(|_| None);
+            $crate::__assert_sv_eq!(none.clone(), [; 5]: u8);
+            assert_eq!(none.next_push_index(), 6);
+        }
+
+        #[test]
+        fn join() {
+            let mut a = $ty::from(&[0, 1, 2, 3, 4, 5]);
+            let mut b = $ty::from(&['a', 'b', 'c', 'd']);
+            a.remove(1);
+            a.remove(3);
+            b.remove(2);
+
+            assert_eq!(a.join(&b).collect::<Vec<_>>(), [(0, &0, &'a')]);
+            assert_eq!(
+                a.left_join(&b).collect::<Vec<_>>(),
+                [(0, &0, Some(&'a')), (2, &2, None), (4, &4, None), (5, &5, None)],
+            );
+            assert_eq!(
+                a.outer_join(&b).collect::<Vec<_>>(),
+                [
+                    (0, Some(&0), Some(&'a')),
+                    (1, None, Some(&'b')),
+                    (2, Some(&2), None),
+                    (3, None, Some(&'d')),
+                    (4, Some(&4), None),
+                    (5, Some(&5), None),
+                ],
+            );
+
+            for (i, x, c) in a.join_mut(&mut b) {
+                *x += 10;
+                *c = c.to_ascii_uppercase();
+                assert_eq!(i, 0);
+            }
+            $crate::__assert_sv_eq!(a, [0 => 10, 2 => 2, 4 => 4, 5 => 5]);
+            $crate::__assert_sv_eq!(b, [0 => 'A', 1 => 'b', 3 => 'd']);
+
+            let empty = $ty::<u32>::new();
+            assert_eq!(a.join(&empty).count(), 0);
+            assert_eq!(empty.outer_join(&b).count(), 3);
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn join_random() {
+            fn prop(a: Vec<Option<u8>>, b: Vec<Option<u8>>) -> bool {
+                fn create(slots: &[Option<u8>]) -> $ty<u8> {
+                    let mut sv = $ty::new();
+                    for slot in slots {
+                        let idx = sv.push(slot.unwrap_or(0));
+                        if slot.is_none() {
+                            sv.remove(idx);
+                        }
+                    }
+                    sv
+                }
+
+                let (sa, sb) = (create(&a), create(&b));
+                let get = |slots: &[Option<u8>], i: usize| slots.get(i).copied().flatten();
+                let expected: Vec<_> = (0..a.len().max(b.len()))
+                    .map(|i| (i, get(&a, i), get(&b, i)))
+                    .filter(|&(_, x, y)| x.is_some() || y.is_some())
+                    .collect();
+
+                let outer: Vec<_> = sa.outer_join(&sb)
+                    .map(|(i, x, y)| (i, x.copied(), y.copied()))
+                    .collect();
+                let left: Vec<_> = sa.left_join(&sb)
+                    .map(|(i, x, y)| (i, Some(*x), y.copied()))
+                    .collect();
+                let inner: Vec<_> = sa.join(&sb)
+                    .map(|(i, x, y)| (i, Some(*x), Some(*y)))
+                    .collect();
+
+                outer == expected
+                    && left == expected.iter().copied().filter(|t| t.1.is_some()).collect::<Vec<_>>()
+                    && inner == expected.iter().copied()
+                        .filter(|t| t.1.is_some() && t.2.is_some())
+                        .collect::<Vec<_>>()
+            }
+
+            $crate::core::conformance::quickcheck(
+                prop as fn(Vec<Option<u8>>, Vec<Option<u8>>) -> bool,
+            );
+        }
+
+        #[test]
+        fn partition() {
+            let mut sv = $ty::from(&[0, 1, 2, 3, 4, 5, 6]);
+            sv.reserve(20);
+            sv.remove(2);
+            sv.remove(6);
+            let cap = sv.capacity();
+
+            let (even, odd) = sv.clone().partition_by(|x| x % 2 == 0);
+            assert!(even.capacity() >= cap);
+            assert!(odd.capacity() >= cap);
+            $crate::__assert_sv_eq!(even.clone(), [0 => 0, 4 => 4; 6]);
+            $crate::__assert_sv_eq!(odd.clone(), [1 => 1, 3 => 3, 5 => 5; 6]);
+            assert_eq!(even.next_push_index(), 7);
+            assert_eq!(odd.next_push_index(), 7);
+
+            let (low, high) = sv.partition_indices(|i| i < 4);
+            $crate::__assert_sv_eq!(low.clone(), [0 => 0, 1 => 1, 3 => 3; 6]);
+            $crate::__assert_sv_eq!(high.clone(), [4 => 4, 5 => 5; 6]);
+
+            let (all, none) = $ty::<u32>::new().partition_indices(|_| true);
+            $crate::__assert_sv_eq!(all.clone(), []: u32);
+            $crate::__assert_sv_eq!(none.clone(), []: u32);
+        }
+
+        #[test]
+        fn relocate() {
+            let mut sv = $ty::from(&[0, 1, 2, 3]);
+            sv.remove(1);
+
+            sv.relocate(3, 1);
+            $crate::__assert_sv_eq!(sv, [0 => 0, 1 => 3, 2 => 2; 3]);
+
+            sv.relocate(0, 9);
+            $crate::__assert_sv_eq!(sv, [1 => 3, 2 => 2, 9 => 0]);
+
+            $crate::__assert_panic!(sv.relocate(0, 3));
+            $crate::__assert_panic!(sv.relocate(1, 2));
+            $crate::__assert_sv_eq!(sv, [1 => 3, 2 => 2, 9 => 0]);
+        }
+
+        #[test]
+        fn apply_permutation() {
+            let mut sv = $ty::from(&[0, 1, 2, 3, 4, 5]);
+            sv.remove(2);
+
+            sv.apply_permutation(&[0, 1, 2, 3, 4, 5]);
+            $crate::__assert_sv_eq!(sv, [0 => 0, 1 => 1, 3 => 3, 4 => 4, 5 => 5]);
+
+            sv.apply_permutation(&[5, 0, 1, 4, 3, 2]);
+            $crate::__assert_sv_eq!(sv, [0 => 1, 2 => 5, 3 => 4, 4 => 3, 5 => 0]);
+
+            $crate::__assert_panic!(sv.apply_permutation(&[0, 1, 2]));
+            $crate::__assert_panic!(sv.apply_permutation(&[0, 1, 2, 3, 4, 6]));
+            $crate::__assert_panic!(sv.apply_permutation(&[0, 1, 2, 3, 0, 5]));
+            $crate::__assert_sv_eq!(sv, [0 => 1, 2 => 5, 3 => 4, 4 => 3, 5 => 0]);
+
+            let mut empty = $ty::<u32>::new();
+            empty.apply_permutation(&[]);
+            $crate::__assert_sv_eq!(empty, []: u32);
+        }
+
+        #[test]
+        fn sort_by_key_compact() {
+            let mut sv = $ty::from(&[5, 3, 8, 1, 3, 7]);
+            sv.remove(2);
+            sv.remove(5);
+
+            let remap = sv.sort_by_key_compact(|&x| x);
+            $crate::__assert_sv_eq!(sv, [0 => 1, 1 => 3, 2 => 3, 3 => 5]);
+            assert_eq!(remap, [Some(3), Some(1), None, Some(0), Some(2), None]);
+
+            let remap = sv.sort_by_key_compact(|&x| ::std::cmp::Reverse(x));
+            $crate::__assert_sv_eq!(sv, [0 => 5, 1 => 3, 2 => 3, 3 => 1]);
+            assert_eq!(remap, [Some(3), Some(1), Some(2), Some(0)]);
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn sort_by_key_compact_random() {
+            fn prop(slots: Vec<Option<u8>>) -> bool {
+                let mut sv = $ty::new();
+                for slot in &slots {
+                    let idx = sv.push(slot.unwrap_or(0));
+                    if slot.is_none() {
+                        sv.remove(idx);
+                    }
+                }
+
+                let before = sv.clone();
+                let remap = sv.sort_by_key_compact(|&x| x);
+
+                let mut expected = slots.iter().flatten().copied().collect::<Vec<_>>();
+                expected.sort();
+
+                sv.is_compact()
+                    && sv.values().copied().eq(expected)
+                    && remap.len() == slots.len()
+                    && before.iter().all(|(old, x)| remap[old].map(|new| sv[new]) == Some(*x))
+                    && slots.iter().zip(&remap).all(|(s, r)| s.is_some() == r.is_some())
+            }
+
+            $crate::core::conformance::quickcheck(prop as fn(Vec<Option<u8>>) -> bool);
+        }
+
+        #[test]
+        fn make_compact_remapping() {
+            let mut sv = $ty::from(&[Some(4usize), None, Some(0), Some(9), Some(2)]);
+            sv.remove(1);
+            sv.remove(3);
+
+            let mut clone = sv.clone();
+            let remap = clone.make_compact_remapping();
+            assert_eq!(remap, [Some(0), None, Some(1), None, Some(2)]);
+            $crate::__assert_sv_eq!(clone, [0 => Some(2), 1 => Some(0), 2 => Some(1)]);
+
+            let remap = sv.reordering_make_compact_remapping();
+            assert_eq!(remap, [Some(0), None, Some(2), None, Some(1)]);
+            $crate::__assert_sv_eq!(sv, [0 => Some(1), 1 => Some(2), 2 => Some(0)]);
+
+            // Compact vectors are not changed.
+            let remap = sv.make_compact_remapping();
+            assert_eq!(remap, [Some(0), Some(1), Some(2)]);
+            $crate::__assert_sv_eq!(sv, [0 => Some(1), 1 => Some(2), 2 => Some(0)]);
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn make_compact_remapping_random() {
+            // Every element stores its own index, so after remapping, each
+            // element has to store its new index.
+            fn prop(slots: Vec<bool>, reordering: bool) -> bool {
+                let mut sv = $ty::new();
+                for &filled in &slots {
+                    let idx = sv.push(Some(sv.next_push_index()));
+                    if !filled {
+                        sv.remove(idx);
+                    }
+                }
+
+                let before = sv.clone();
+                let remap = if reordering {
+                    sv.reordering_make_compact_remapping()
+                } else {
+                    sv.make_compact_remapping()
+                };
+
+                sv.is_compact()
+                    && remap.len() == slots.len()
+                    && sv.iter().all(|(idx, elem)| *elem == Some(idx))
+                    && slots.iter().zip(&remap).all(|(&filled, r)| filled == r.is_some())
+                    && (reordering || before.indices().zip(sv.indices()).all(|(old, new)| {
+                        remap[old] == Some(new)
+                    }))
+            }
+
+            $crate::core::conformance::quickcheck(prop as fn(Vec<bool>, bool) -> bool);
+        }
+
+        #[test]
+        fn compact_together() {
+            let mut primary = $ty::from(&[0u32, 1, 2, 3, 4, 5]);
+            let mut same = $ty::from(&["a", "b", "c", "d", "e", "f"]);
+            let mut superset = $ty::from(&[10u64, 11, 12, 13, 14, 15, 16, 17]);
+            for idx in [0, 2, 3] {
+                primary.remove(idx);
+                same.remove(idx);
+            }
+            superset.remove(2);
+            superset.remove(6);
+
+            primary.compact_together(&mut [&mut same, &mut superset]);
+            $crate::__assert_sv_eq!(primary, [0 => 1, 1 => 4, 2 => 5]);
+            assert_eq!(same, &["b", "e", "f"] as &[_]);
+            $crate::__assert_sv_eq!(superset, [0 => 11, 1 => 14, 2 => 15]);
+
+            // A sibling missing an element is not allowed.
+            let mut primary = $ty::from(&[0u32, 1, 2]);
+            let mut subset = $ty::from(&[0u32, 1, 2]);
+            primary.remove(0);
+            subset.remove(2);
+            $crate::__assert_panic!(primary.compact_together(&mut [&mut subset]));
+            $crate::__assert_sv_eq!(primary, [1 => 1, 2 => 2]);
+            $crate::__assert_sv_eq!(subset, [0 => 0, 1 => 1; 2]);
+
+            // Compact primaries still truncate their siblings.
+            let mut primary = $ty::from(&[0u32, 1]);
+            let mut longer = $ty::from(&[0u32, 1, 2, 3]);
+            primary.compact_together(&mut [&mut longer]);
+            $crate::__assert_sv_eq!(primary, [0 => 0, 1 => 1]);
+            $crate::__assert_sv_eq!(longer, [0 => 0, 1 => 1]);
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn observer_mirror() {
+            // Rebuilds the stable vector only from the observer events.
+            #[derive(Default)]
+            struct Mirror(Vec<Option<u16>>);
+
+            impl Mirror {
+                fn slot(&mut self, idx: usize) -> &mut Option<u16> {
+                    if idx >= self.0.len() {
+                        self.0.resize(idx + 1, None);
+                    }
+                    &mut self.0[idx]
+                }
+            }
+
+            impl $crate::StableVecObserver<u16> for Mirror {
+                fn on_insert(&mut self, idx: usize, elem: &u16) {
+                    assert!(self.slot(idx).replace(*elem).is_none());
+                }
+                fn on_remove(&mut self, idx: usize, elem: &u16) {
+                    assert_eq!(self.slot(idx).take(), Some(*elem));
+                }
+                fn on_replace(&mut self, idx: usize, old: &u16, new: &u16) {
+                    assert_eq!(self.slot(idx).replace(*new), Some(*old));
+                }
+                fn on_move(&mut self, from: usize, to: usize) {
+                    let a = self.slot(from).take();
+                    let b = ::std::mem::replace(self.slot(to), a);
+                    *self.slot(from) = b;
+                }
+                fn on_clear(&mut self) {
+                    self.0.clear();
+                }
+            }
+
+            fn prop(ops: Vec<(u8, u16)>) -> bool {
+                let mut sv = $ty::new().with_observer(Mirror::default());
+                for (op, x) in ops {
+                    let idx = x as usize % (sv.next_push_index() + 2);
+                    match op % 10 {
+                        0..=2 => { sv.push(x); }
+                        3 => {
+                            if idx < sv.capacity() {
+                                sv.insert(idx, x);
+                            }
+                        }
+                        4 | 5 => {
+                            if idx < sv.capacity() {
+                                sv.remove(idx);
+                            }
+                        }
+                        6 => {
+                            let other = (x as usize / 7) % (sv.next_push_index() + 1);
+                            if idx < sv.capacity() && other < sv.capacity() {
+                                sv.swap(idx, other);
+                            }
+                        }
+                        7 => sv.retain(|&e| e % 3 != x % 3),
+                        8 => {
+                            if x % 2 == 0 {
+                                sv.make_compact();
+                            } else {
+                                sv.reordering_make_compact();
+                            }
+                        }
+                        _ => {
+                            if x % 8 == 0 {
+                                sv.clear();
+                            }
+                        }
+                    }
+
+                    let mirror = &sv.observer().0;
+                    let slots = ::std::cmp::max(mirror.len(), sv.next_push_index());
+                    let same = (0..slots).all(|i| {
+                        mirror.get(i).copied().flatten() == sv.get(i).copied()
+                    });
+                    if !same {
+                        return false;
+                    }
+                }
+
+                true
+            }
+
+            $crate::core::conformance::quickcheck(prop as fn(Vec<(u8, u16)>) -> bool);
+        }
+
+        #[test]
+        fn diff_apply() {
+            let mut a = $ty::from(&[0, 1, 2, 3, 4]);
+            let mut b = a.clone();
+            a.remove(1);
+            b.remove(2);
+            b.insert(4, 40);
+            b.reserve_for(70);
+            b.insert(70, 70);
+
+            let patch = a.diff(&b);
+            assert_eq!(patch.inserted, [(1, 1), (70, 70)]);
+            assert_eq!(patch.removed, [2]);
+            assert_eq!(patch.changed, [(4, 40)]);
+            assert!(b.diff(&b).is_empty());
+
+            // Invalid patches are rejected without changing anything.
+            let mut invalid = patch.clone();
+            invalid.inserted.push((0, 5));
+            assert_eq!(a.apply(invalid), Err($crate::PatchError::SlotFilled(0)));
+            let mut invalid = patch.clone();
+            invalid.changed.push((1, 5));
+            assert_eq!(a.apply(invalid), Err($crate::PatchError::DuplicateIndex(1)));
+            let mut invalid = patch.clone();
+            invalid.removed.push(60);
+            assert_eq!(a.apply(invalid), Err($crate::PatchError::SlotEmpty(60)));
+            $crate::__assert_sv_eq!(a, [0 => 0, 2 => 2, 3 => 3, 4 => 4]);
+
+            a.apply(patch).unwrap();
+            $crate::__assert_sv_eq!(a, [0 => 0, 1 => 1, 3 => 3, 4 => 40, 70 => 70]);
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn diff_apply_random() {
+            fn create(slots: &[Option<u8>]) -> $ty<u8> {
+                let mut sv = $ty::new();
+                for slot in slots {
+                    let idx = sv.push(slot.unwrap_or(0));
+                    if slot.is_none() {
+                        sv.remove(idx);
+                    }
+                }
+                sv
+            }
+
+            fn same(a: &$ty<u8>, b: &$ty<u8>) -> bool {
+                a.iter().eq(b.iter()) && a.next_push_index() == b.next_push_index()
+            }
+
+            fn prop(a: Vec<Option<u8>>, b: Vec<Option<u8>>, c: Vec<Option<u8>>) -> bool {
+                let (a, b, c) = (create(&a), create(&b), create(&c));
+
+                let mut patched = a.clone();
+                patched.apply(a.diff(&b)).unwrap();
+
+                let composed = a.diff(&b).compose(b.diff(&c)).unwrap();
+                let mut composed_patched = a.clone();
+                composed_patched.apply(composed).unwrap();
+
+                same(&patched, &b) && same(&composed_patched, &c)
+            }
+
+            $crate::core::conformance::quickcheck(
+                prop as fn(Vec<Option<u8>>, Vec<Option<u8>>, Vec<Option<u8>>) -> bool,
+            );
+        }
+
+        #[test]
+        fn zero_sized_type_many() {
+            let mut sv = $ty::<()>::new();
+            for _ in 0..100 {
+                sv.push(());
+            }
+            for i in (0..100).step_by(2) {
+                assert_eq!(sv.remove(i), Some(()));
+            }
+            assert_eq!(sv.num_elements(), 50);
+            assert_eq!(sv.iter().count(), 50);
+            assert_eq!(sv.indices().collect::<Vec<_>>(), (1..100).step_by(2).collect::<Vec<_>>());
+
+            sv.swap(0, 1);
+            assert!(sv.has_element_at(0));
+            assert!(!sv.has_element_at(1));
+
+            let clone = sv.clone();
+            assert_eq!(clone.indices().collect::<Vec<_>>(), sv.indices().collect::<Vec<_>>());
+
+            sv.retain_indices(|i| i % 4 != 3);
+            assert_eq!(sv.num_elements(), 25);
+
+            sv.make_compact();
+            assert!(sv.is_compact());
+            assert_eq!(sv.num_elements(), 25);
+            assert_eq!(sv.iter().count(), 25);
+
+            sv.clear();
+            $crate::__assert_sv_eq!(sv, []: ());
+        }
+
+        #[test]
+        fn drop_counting() {
+            use std::sync::atomic::{Ordering, AtomicIsize};
+
+            static ALIVE_COUNT: AtomicIsize = AtomicIsize::new(0);
+
+            struct Dummy;
+            impl Dummy {
+                fn new() -> Self {
+                    ALIVE_COUNT.fetch_add(1, Ordering::SeqCst);
+                    Self
+                }
+            }
+            impl Drop for Dummy {
+                fn drop(&mut self) {
+                    ALIVE_COUNT.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+            impl Clone for Dummy {
+                fn clone(&self) -> Self {
+                    Self::new()
+                }
+            }
+
+            let mut sv = $ty::new();
+            for _ in 0..20 {
+                sv.push(Dummy::new());
+            }
+            for i in (0..20).step_by(3) {
+                sv.remove(i);
+            }
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 13);
+
+            // Replacing an element returns the old one.
+            let old = sv.insert(1, Dummy::new());
+            assert!(old.is_some());
+            drop(old);
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 13);
+
+            sv.swap(0, 1);
+            sv.reserve(100);
+            sv.shrink_to_fit();
+            sv.reordering_make_compact();
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 13);
+
+            // A partially consumed `IntoIter` drops the remaining elements.
+            {
+                let mut it = sv.clone().into_iter();
+                assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 26);
+
+                it.next();
+                it.next_back();
+                assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 24);
+            }
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 13);
+
+            drop(sv);
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 0);
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn panicking_clone() {
+            use std::sync::atomic::{Ordering, AtomicIsize};
+
+            static ALIVE_COUNT: AtomicIsize = AtomicIsize::new(0);
+
+            // Panics when cloned if the flag is set.
+            struct Bomb(bool);
+            impl Bomb {
+                fn new(explodes: bool) -> Self {
+                    ALIVE_COUNT.fetch_add(1, Ordering::SeqCst);
+                    Self(explodes)
+                }
+            }
+            impl Drop for Bomb {
+                fn drop(&mut self) {
+                    ALIVE_COUNT.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+            impl Clone for Bomb {
+                fn clone(&self) -> Self {
+                    if self.0 {
+                        panic!("bomb exploded");
+                    }
+                    Self::new(false)
+                }
+            }
+
+            let mut sv = $ty::new();
+            for i in 0..10 {
+                sv.push(Bomb::new(i == 6));
+            }
+            sv.remove(2);
+            sv.remove(8);
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 8);
+
+            // The elements cloned before the panic might be leaked, but nothing
+            // must be dropped twice and the original has to stay intact.
+            $crate::__assert_panic!(sv.clone().num_elements());
+            let leaked = ALIVE_COUNT.load(Ordering::SeqCst) - 8;
+            assert!(leaked >= 0);
+            assert_eq!(sv.num_elements(), 8);
+            assert_eq!(sv.indices().collect::<Vec<_>>(), [0, 1, 3, 4, 5, 6, 7, 9]);
+
+            // The elements cloned before the panic stay in the stable vector.
+            $crate::__assert_panic!({
+                let new = [Bomb::new(false), Bomb::new(true), Bomb::new(false)];
+                sv.extend_from_slice(&new);
+            });
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), leaked + 9);
+            assert_eq!(sv.num_elements(), 9);
+            assert_eq!(sv.iter().count(), 9);
+
+            sv.remove(6);
+            let mut clone = sv.clone();
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), leaked + 16);
+            clone.make_compact();
+            drop(clone);
+            drop(sv);
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), leaked);
+        }
+    }
+}
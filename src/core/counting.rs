@@ -0,0 +1,250 @@
+use std::{
+    cell::Cell,
+    fmt,
+    mem::size_of,
+};
+// `no-std-compat` does not reexport these.
+use ::core::panic::{RefUnwindSafe, UnwindSafe};
+
+use super::{Core, MapCore};
+
+
+/// A snapshot of the operation counters of a [`CountingCore`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CoreStats {
+    /// Number of `realloc` calls.
+    pub reallocs: usize,
+
+    /// Sum of the element buffer sizes (in bytes) requested by all `realloc`
+    /// calls, i.e. `new_cap * size_of::<T>()` per call. Memory the core needs
+    /// in addition to the elements (e.g. bit vectors) is not included.
+    /// Saturates at `usize::MAX`.
+    pub realloc_bytes: usize,
+
+    /// Number of `insert_at` calls.
+    pub inserts: usize,
+
+    /// Number of `remove_at` calls.
+    pub removes: usize,
+
+    /// Number of `swap` calls.
+    pub swaps: usize,
+
+    /// Number of calls to `first_filled_slot_from`, `first_filled_slot_below`,
+    /// `first_empty_slot_from` and `first_empty_slot_below`.
+    pub scans: usize,
+
+    /// Sum of the number of slots covered by all scans: from the start index
+    /// up to and including the found slot, or up to the end of the searched
+    /// range if nothing was found. How many slots the core actually had to
+    /// look at depends on its implementation. Saturates at `usize::MAX`.
+    pub scanned_slots: usize,
+}
+
+/// A `Core` wrapper that counts the operations performed on the inner core
+/// `C`.
+///
+/// All methods are forwarded to `C`. Additionally, calls to `realloc`,
+/// `insert_at`, `remove_at`, `swap` and the search methods are counted (see
+/// [`CoreStats`]). This is useful to compare how different cores behave with a
+/// specific workload. The counters can be read via
+/// [`stats`][crate::StableVecFacade::stats] and reset via
+/// [`reset_stats`][crate::StableVecFacade::reset_stats].
+///
+/// ```
+/// # #[cfg(feature = "alloc")] {
+/// use stable_vec::{StableVecFacade, core::{BitVecCore, CountingCore}};
+///
+/// let mut sv = StableVecFacade::<u32, CountingCore<BitVecCore<u32>>>::new();
+/// sv.extend(0..10);
+/// sv.remove(3);
+///
+/// let stats = sv.stats();
+/// assert_eq!(stats.inserts, 10);
+/// assert_eq!(stats.removes, 1);
+/// # }
+/// ```
+///
+/// As the search methods only have `&self` access, the counters are stored in
+/// a `Cell`, so this core is not `Sync`.
+pub struct CountingCore<C> {
+    inner: C,
+    stats: Cell<CoreStats>,
+}
+
+impl<C> CountingCore<C> {
+    /// Wraps `inner`, with all counters set to 0.
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            stats: Cell::new(CoreStats::default()),
+        }
+    }
+
+    /// Returns the current values of all counters.
+    pub fn stats(&self) -> CoreStats {
+        self.stats.get()
+    }
+
+    /// Sets all counters to 0.
+    pub fn reset_stats(&self) {
+        self.stats.set(CoreStats::default());
+    }
+
+    /// Returns a reference to the inner core.
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /// Returns the inner core.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    fn record(&self, f: impl FnOnce(&mut CoreStats)) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+
+    fn record_scan(&self, slots: usize) {
+        self.record(|s| {
+            s.scans += 1;
+            s.scanned_slots = s.scanned_slots.saturating_add(slots);
+        });
+    }
+}
+
+impl<T, C: Core<T>> Core<T> for CountingCore<C> {
+    fn new() -> Self
+    where
+        Self: Default,
+    {
+        Self::default()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    unsafe fn set_len(&mut self, new_len: usize) {
+        self.inner.set_len(new_len)
+    }
+
+    fn cap(&self) -> usize {
+        self.inner.cap()
+    }
+
+    unsafe fn realloc(&mut self, new_cap: usize) {
+        self.record(|s| {
+            s.reallocs += 1;
+            let bytes = new_cap.saturating_mul(size_of::<T>());
+            s.realloc_bytes = s.realloc_bytes.saturating_add(bytes);
+        });
+        self.inner.realloc(new_cap)
+    }
+
+    unsafe fn has_element_at(&self, idx: usize) -> bool {
+        self.inner.has_element_at(idx)
+    }
+
+    unsafe fn insert_at(&mut self, idx: usize, elem: T) {
+        self.record(|s| s.inserts += 1);
+        self.inner.insert_at(idx, elem)
+    }
+
+    unsafe fn remove_at(&mut self, idx: usize) -> T {
+        self.record(|s| s.removes += 1);
+        self.inner.remove_at(idx)
+    }
+
+    unsafe fn get_unchecked(&self, idx: usize) -> &T {
+        self.inner.get_unchecked(idx)
+    }
+
+    unsafe fn get_unchecked_mut(&mut self, idx: usize) -> &mut T {
+        self.inner.get_unchecked_mut(idx)
+    }
+
+    fn clear(&mut self) {
+        self.inner.clear()
+    }
+
+    unsafe fn first_filled_slot_from(&self, idx: usize) -> Option<usize> {
+        let out = self.inner.first_filled_slot_from(idx);
+        let end = out.map_or(self.inner.len(), |j| j + 1);
+        self.record_scan(end.saturating_sub(idx));
+        out
+    }
+
+    unsafe fn first_filled_slot_below(&self, idx: usize) -> Option<usize> {
+        let out = self.inner.first_filled_slot_below(idx);
+        self.record_scan(idx - out.unwrap_or(0));
+        out
+    }
+
+    unsafe fn first_empty_slot_from(&self, idx: usize) -> Option<usize> {
+        let out = self.inner.first_empty_slot_from(idx);
+        let end = out.map_or(self.inner.cap(), |j| j + 1);
+        self.record_scan(end - idx);
+        out
+    }
+
+    unsafe fn first_empty_slot_below(&self, idx: usize) -> Option<usize> {
+        let out = self.inner.first_empty_slot_below(idx);
+        self.record_scan(idx - out.unwrap_or(0));
+        out
+    }
+
+    fn occupancy_words(&self) -> Option<&[usize]> {
+        self.inner.occupancy_words()
+    }
+
+    unsafe fn swap(&mut self, a: usize, b: usize) {
+        self.record(|s| s.swaps += 1);
+        self.inner.swap(a, b)
+    }
+}
+
+impl<T, C: MapCore<T>> MapCore<T> for CountingCore<C> {
+    type Mapped<U> = CountingCore<C::Mapped<U>>;
+
+    fn map_in_place<U, F>(self, f: &mut F) -> Result<Self::Mapped<U>, Self>
+    where
+        F: FnMut(T) -> U,
+    {
+        let Self { inner, stats } = self;
+        match inner.map_in_place(f) {
+            Ok(inner) => Ok(CountingCore { inner, stats }),
+            Err(inner) => Err(Self { inner, stats }),
+        }
+    }
+}
+
+impl<C: Default> Default for CountingCore<C> {
+    fn default() -> Self {
+        Self::new(C::default())
+    }
+}
+
+impl<C: Clone> Clone for CountingCore<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+// A panic can at most leave the counters slightly off, which is harmless.
+impl<C: UnwindSafe> UnwindSafe for CountingCore<C> {}
+impl<C: RefUnwindSafe> RefUnwindSafe for CountingCore<C> {}
+
+impl<C: fmt::Debug> fmt::Debug for CountingCore<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CountingCore")
+            .field("inner", &self.inner)
+            .field("stats", &self.stats.get())
+            .finish()
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    prelude::v1::*,
+    fmt,
+};
+
+use super::Core;
+
+
+/// Marker in `slot_to_dense` for empty slots.
+const EMPTY: usize = usize::MAX;
+
+/// A `Core` implementation that stores all elements contiguously, without
+/// any holes, plus tables to map between indices and positions.
+///
+/// The elements are stored in a `Vec<T>` (the *dense* array) in no particular
+/// order. Two tables map between the stable indices handed out to users and
+/// positions in the dense array: one with an entry per slot (`cap` many) and
+/// one with an entry per element. Removing an element moves the last element
+/// of the dense array into the freed position (like `Vec::swap_remove`) and
+/// updates both tables. Indices seen by users never change.
+///
+/// This has a few advantages:
+///
+/// - All elements can be accessed as one slice via
+///   [`as_dense_slice`][crate::StableVecFacade::as_dense_slice]. Walking over
+///   that slice is as fast as it gets, regardless of how many holes the
+///   stable vector has. Keep in mind that the order of elements in that slice
+///   is unrelated to their indices.
+/// - Memory for the elements is only needed for existing elements, not for
+///   empty slots. Especially with large `T` and many holes, this saves a lot
+///   of memory.
+///
+/// The disadvantages: every access via index goes through the slot table,
+/// meaning an additional indirection and potentially an additional cache
+/// miss. Each slot costs a `usize`, plus another `usize` per element. And
+/// searching for filled or empty slots (and thus `iter()` and `indices()`,
+/// which visit elements in index order) has to look at the slot table entry
+/// by entry.
+///
+/// As long as the dense array happens to be ordered by index, `values()` and
+/// `values_mut()` walk over it directly. This is the case if elements were
+/// only pushed and removed from the end, and stays the case when compacting.
+/// Removing any other element breaks the order (until the core is cleared).
+#[derive(Clone)]
+pub struct DenseCore<T> {
+    /// All elements, without holes.
+    dense: Vec<T>,
+
+    /// For each element in `dense`, the index of its slot. Always has the
+    /// same length as `dense`.
+    dense_to_slot: Vec<usize>,
+
+    /// For each slot, the position of its element in `dense` or `EMPTY` if the
+    /// slot is empty. Its length is the `cap` of the `Core` definition.
+    slot_to_dense: Vec<usize>,
+
+    /// The `len`: corresponds to the `len` of the `Core` definition.
+    len: usize,
+
+    /// Whether `dense_to_slot` is sorted, i.e. `dense` is ordered by index.
+    in_order: bool,
+}
+
+impl<T> DenseCore<T> {
+    /// Creates an empty instance. Does not allocate memory.
+    pub fn new() -> Self {
+        Self {
+            dense: Vec::new(),
+            dense_to_slot: Vec::new(),
+            slot_to_dense: Vec::new(),
+            len: 0,
+            in_order: true,
+        }
+    }
+
+    /// Returns all elements as a slice. The order of elements is unspecified
+    /// and changes when elements are removed.
+    pub fn as_slice(&self) -> &[T] {
+        &self.dense
+    }
+
+    /// Returns all elements as a mutable slice. The order of elements is
+    /// unspecified and changes when elements are removed.
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.dense
+    }
+
+    /// Returns the slot indices of all elements: the `i`-th entry is the
+    /// index of `self.as_slice()[i]`.
+    pub fn indices(&self) -> &[usize] {
+        &self.dense_to_slot
+    }
+
+    /// Returns `true` if the element at position `pos` of the dense array is
+    /// ordered correctly relative to its neighbors.
+    fn is_in_order_at(&self, pos: usize) -> bool {
+        let idx = self.dense_to_slot[pos];
+        (pos == 0 || self.dense_to_slot[pos - 1] < idx)
+            && self.dense_to_slot.get(pos + 1).is_none_or(|&next| idx < next)
+    }
+}
+
+impl<T> Core<T> for DenseCore<T> {
+    fn new() -> Self {
+        Self::new()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    unsafe fn set_len(&mut self, new_len: usize) {
+        debug_assert!(new_len <= self.cap());
+        // Other precondition is too expensive to test, even in debug:
+        // ∀ i in `new_len..self.cap()` ⇒ `self.has_element_at(i) == false`
+
+        self.len = new_len;
+    }
+
+    fn cap(&self) -> usize {
+        self.slot_to_dense.len()
+    }
+
+    #[inline(never)]
+    #[cold]
+    unsafe fn realloc(&mut self, new_cap: usize) {
+        debug_assert!(new_cap >= self.len());
+        debug_assert!(new_cap <= isize::MAX as usize);
+
+        // Due to the preconditions, all slots that are cut off are empty. The
+        // dense arrays are not affected, as they only grow with the number of
+        // elements.
+        if new_cap > self.slot_to_dense.len() {
+            self.slot_to_dense.reserve_exact(new_cap - self.slot_to_dense.len());
+            self.slot_to_dense.resize(new_cap, EMPTY);
+        } else {
+            self.slot_to_dense.truncate(new_cap);
+            self.slot_to_dense.shrink_to_fit();
+        }
+    }
+
+    unsafe fn has_element_at(&self, idx: usize) -> bool {
+        debug_assert!(idx < self.cap());
+
+        *self.slot_to_dense.get_unchecked(idx) != EMPTY
+    }
+
+    unsafe fn insert_at(&mut self, idx: usize, elem: T) {
+        debug_assert!(idx < self.cap());
+        debug_assert!(!self.has_element_at(idx));
+
+        let in_order = self.dense_to_slot.last().is_none_or(|&last| last < idx);
+
+        // We reserve space in both dense arrays first, so that the pushes
+        // below can't fail. If reserving panics, nothing has changed.
+        // Otherwise, the two arrays could get out of sync.
+        self.dense.reserve(1);
+        self.dense_to_slot.reserve(1);
+
+        self.dense.push(elem);
+        self.dense_to_slot.push(idx);
+        *self.slot_to_dense.get_unchecked_mut(idx) = self.dense.len() - 1;
+        self.in_order &= in_order;
+    }
+
+    unsafe fn remove_at(&mut self, idx: usize) -> T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        let pos = *self.slot_to_dense.get_unchecked(idx);
+        *self.slot_to_dense.get_unchecked_mut(idx) = EMPTY;
+
+        // The last element is moved into `pos`, so we have to update its
+        // slot table entry (unless it was the removed element itself).
+        let out = self.dense.swap_remove(pos);
+        self.dense_to_slot.swap_remove(pos);
+        if let Some(&moved) = self.dense_to_slot.get(pos) {
+            *self.slot_to_dense.get_unchecked_mut(moved) = pos;
+            self.in_order = false;
+        }
+        if self.dense.is_empty() {
+            self.in_order = true;
+        }
+
+        out
+    }
+
+    unsafe fn get_unchecked(&self, idx: usize) -> &T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        let pos = *self.slot_to_dense.get_unchecked(idx);
+        self.dense.get_unchecked(pos)
+    }
+
+    unsafe fn get_unchecked_mut(&mut self, idx: usize) -> &mut T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        let pos = *self.slot_to_dense.get_unchecked(idx);
+        self.dense.get_unchecked_mut(pos)
+    }
+
+    fn clear(&mut self) {
+        // All filled slots are listed in `dense_to_slot`, so we don't have to
+        // look at all slots.
+        for &slot in &self.dense_to_slot {
+            self.slot_to_dense[slot] = EMPTY;
+        }
+        self.dense_to_slot.clear();
+        self.dense.clear();
+        self.len = 0;
+        self.in_order = true;
+    }
+
+    unsafe fn swap(&mut self, a: usize, b: usize) {
+        debug_assert!(a < self.cap());
+        debug_assert!(b < self.cap());
+
+        // Elements don't move in the dense array, only the tables change.
+        self.slot_to_dense.swap(a, b);
+        if let Some(&pos) = self.slot_to_dense.get(a).filter(|&&pos| pos != EMPTY) {
+            *self.dense_to_slot.get_unchecked_mut(pos) = a;
+        }
+        if let Some(&pos) = self.slot_to_dense.get(b).filter(|&&pos| pos != EMPTY) {
+            *self.dense_to_slot.get_unchecked_mut(pos) = b;
+        }
+
+        // Only the neighbors of the moved elements have to be checked. Moving
+        // elements into holes in ascending order (as compacting does) keeps
+        // the dense array ordered.
+        if self.in_order {
+            self.in_order = [a, b].iter()
+                .map(|&idx| self.slot_to_dense[idx])
+                .all(|pos| pos == EMPTY || self.is_in_order_at(pos));
+        }
+    }
+
+    fn values_in_order(&self) -> Option<&[T]> {
+        if self.in_order { Some(&self.dense) } else { None }
+    }
+
+    fn values_in_order_mut(&mut self) -> Option<&mut [T]> {
+        if self.in_order { Some(&mut self.dense) } else { None }
+    }
+}
+
+impl<T> Default for DenseCore<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// This impl is usually not used. `StableVec` has its own impl which doesn't
+// use this one.
+impl<T: fmt::Debug> fmt::Debug for DenseCore<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DenseCore")
+            .field("len", &self.len())
+            .field("cap", &self.cap())
+            .field("dense", &self.dense)
+            .finish()
+    }
+}
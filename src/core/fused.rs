@@ -0,0 +1,385 @@
+use std::{
+    alloc::Layout,
+    fmt,
+    ptr::{self, NonNull},
+};
+
+use super::{
+    Core,
+    allocator::{allocate, reallocate, Allocator, Global},
+    bitvec::{num_usizes_for, BITS_PER_USIZE},
+};
+
+
+/// A `Core` implementation like [`BitVecCore`][super::BitVecCore], but with
+/// the bit vector and the elements stored in one allocation.
+///
+/// The memory block starts with the `usize` words of the bit vector, followed
+/// by the element buffer (with padding in between if `T` requires a larger
+/// alignment than `usize`). This means that creating a stable vector with a
+/// given capacity costs only a single allocation and that growing or
+/// shrinking performs one reallocation plus one copy of the element buffer
+/// (as the elements have to move when the number of bit words changes)
+/// instead of two reallocations.
+///
+/// Apart from that, this core behaves exactly like `BitVecCore`: it does not
+/// waste memory due to padding and iterating over indices is just as fast.
+/// As the bit words and the elements are closer together, accessing both
+/// might be a bit more cache-friendly for small vectors.
+///
+/// The memory is allocated with the allocator `A`. Unless the
+/// `allocator-api2` feature is enabled, this is always the global allocator.
+/// `Core::new` is only available if `A` implements `Default`; otherwise, use
+/// `new_in`.
+pub struct FusedBitVecCore<T, A: Allocator = Global> {
+    /// Points to the start of the memory block, which is also the start of
+    /// the bit vector. Stores whether or not slots are filled (1) or empty
+    /// (0), one bit per slot.
+    bit_ptr: NonNull<usize>,
+
+    /// Points to the element buffer inside the memory block. If a slot is
+    /// empty, the memory at that index is undefined.
+    elem_ptr: NonNull<T>,
+
+    /// The capacity: the length of the element buffer. Corresponds to the
+    /// `cap` of the `Core` definition.
+    cap: usize,
+
+    /// The `len`: corresponds to the `len` of the `Core` definition.
+    len: usize,
+
+    /// The allocator used for the memory block.
+    alloc: A,
+}
+
+/// Returns the layout of the memory block for `cap` slots and the offset of
+/// the element buffer within it. Returns `None` if the size overflows.
+fn layout_for<T>(cap: usize) -> Option<(Layout, usize)> {
+    let bits = Layout::array::<usize>(num_usizes_for(cap)).ok()?;
+    let elems = Layout::array::<T>(cap).ok()?;
+    bits.extend(elems).ok()
+}
+
+impl<T, A: Allocator> FusedBitVecCore<T, A> {
+    /// Creates an empty instance that will allocate memory with `alloc`.
+    /// Does not allocate memory itself.
+    pub fn new_in(alloc: A) -> Self {
+        Self {
+            bit_ptr: NonNull::dangling(),
+            elem_ptr: NonNull::dangling(),
+            cap: 0,
+            len: 0,
+            alloc,
+        }
+    }
+
+    /// Returns a reference to the underlying allocator.
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    /// Returns the layout that was used for the last allocation and the
+    /// offset of the element buffer. `self.cap` must not be 0, or else this
+    /// method's behavior is undefined.
+    unsafe fn old_layout(&self) -> (Layout, usize) {
+        match layout_for::<T>(self.cap) {
+            Some(v) => v,
+            // This can't overflow due to being previously allocated.
+            None => std::hint::unreachable_unchecked(),
+        }
+    }
+
+    /// Deallocates the memory block, sets the pointers to the same value as
+    /// `new_in()` does and sets `cap` to 0.
+    ///
+    /// # Formal
+    ///
+    /// **Preconditions**:
+    /// - `self.len == 0`
+    /// - All slots are empty
+    unsafe fn dealloc(&mut self) {
+        if self.cap != 0 {
+            self.alloc.deallocate(self.bit_ptr.cast(), self.old_layout().0);
+            self.bit_ptr = NonNull::dangling();
+            self.elem_ptr = NonNull::dangling();
+            self.cap = 0;
+        }
+    }
+}
+
+impl<T, A: Allocator> Core<T> for FusedBitVecCore<T, A> {
+    fn new() -> Self
+    where
+        Self: Default,
+    {
+        Self::default()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    unsafe fn set_len(&mut self, new_len: usize) {
+        debug_assert!(new_len <= self.cap());
+        // Other precondition is too expensive to test, even in debug:
+        // ∀ i in `new_len..self.cap()` ⇒ `self.has_element_at(i) == false`
+
+        self.len = new_len;
+    }
+
+    fn cap(&self) -> usize {
+        self.cap
+    }
+
+    #[inline(never)]
+    #[cold]
+    unsafe fn realloc(&mut self, new_cap: usize) {
+        debug_assert!(new_cap >= self.len());
+        debug_assert!(new_cap <= isize::MAX as usize);
+
+        #[inline(never)]
+        #[cold]
+        fn capacity_overflow() -> ! {
+            panic!("capacity overflow in `stable_vec::FusedBitVecCore::realloc` \
+                (attempt to allocate more than `isize::MAX` bytes");
+        }
+
+        // Handle special case
+        if new_cap == 0 {
+            // Due to preconditions, we know that `self.len == 0` and that in
+            // turn tells us that there aren't any filled slots. So we can just
+            // deallocate the memory.
+            self.dealloc();
+            return;
+        }
+
+        let (new_layout, new_offset) = layout_for::<T>(new_cap)
+            .unwrap_or_else(|| capacity_overflow());
+
+        let base = if self.cap == 0 {
+            allocate(&self.alloc, new_layout, false)
+        } else {
+            // Both layouts have the same alignment (the larger of `usize` and
+            // `T`), so we can reallocate. As the number of bit words might
+            // change, the element buffer has to be moved within the block. We
+            // only need to move the first `len` slots, as all others are
+            // empty.
+            let (old_layout, old_offset) = self.old_layout();
+
+            // If the buffer moves towards the start, we have to move it
+            // before shrinking the block. This overwrites bit words that only
+            // cover slots ≥ `new_cap`, which are all empty.
+            if new_offset < old_offset {
+                let dst = (self.bit_ptr.as_ptr() as *mut u8).add(new_offset) as *mut T;
+                ptr::copy(self.elem_ptr.as_ptr(), dst, self.len);
+            }
+
+            let base = reallocate(&self.alloc, self.bit_ptr.cast(), old_layout, new_layout);
+
+            // If the buffer moves towards the end, we can only do that after
+            // growing the block.
+            if new_offset > old_offset {
+                let src = base.as_ptr().add(old_offset) as *mut T;
+                let dst = base.as_ptr().add(new_offset) as *mut T;
+                ptr::copy(src, dst, self.len);
+            }
+
+            base
+        };
+
+        // All new bit words are uninitialized, so we zero them.
+        let initialized_usizes = num_usizes_for(self.cap);
+        let new_usizes = num_usizes_for(new_cap);
+        let bit_ptr = base.cast::<usize>();
+        if new_usizes > initialized_usizes {
+            ptr::write_bytes(
+                bit_ptr.as_ptr().add(initialized_usizes),
+                0,
+                new_usizes - initialized_usizes,
+            );
+        }
+
+        self.bit_ptr = bit_ptr;
+        self.elem_ptr = NonNull::new_unchecked(base.as_ptr().add(new_offset) as *mut T);
+        self.cap = new_cap;
+    }
+
+    unsafe fn has_element_at(&self, idx: usize) -> bool {
+        debug_assert!(idx < self.cap());
+
+        let usize_pos = idx / BITS_PER_USIZE;
+        let bit_pos = idx % BITS_PER_USIZE;
+
+        let block = *self.bit_ptr.as_ptr().add(usize_pos);
+        ((block >> bit_pos) & 0b1) != 0
+    }
+
+    unsafe fn insert_at(&mut self, idx: usize, elem: T) {
+        debug_assert!(idx < self.cap());
+        debug_assert!(!self.has_element_at(idx));
+
+        // We first write the value and then update the bitvector to avoid
+        // potential double drops if a random panic appears.
+        ptr::write(self.elem_ptr.as_ptr().add(idx), elem);
+
+        let usize_pos = idx / BITS_PER_USIZE;
+        let bit_pos = idx % BITS_PER_USIZE;
+
+        let mask = 1 << bit_pos;
+        *self.bit_ptr.as_ptr().add(usize_pos) |= mask;
+    }
+
+    unsafe fn remove_at(&mut self, idx: usize) -> T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        // We first mark the value as deleted and then read the value.
+        // Otherwise, a random panic could lead to a double drop.
+        let usize_pos = idx / BITS_PER_USIZE;
+        let bit_pos = idx % BITS_PER_USIZE;
+
+        let mask = !(1 << bit_pos);
+        *self.bit_ptr.as_ptr().add(usize_pos) &= mask;
+
+        ptr::read(self.elem_ptr.as_ptr().add(idx))
+    }
+
+    unsafe fn get_unchecked(&self, idx: usize) -> &T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        &*self.elem_ptr.as_ptr().add(idx)
+    }
+
+    unsafe fn get_unchecked_mut(&mut self, idx: usize) -> &mut T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        &mut *self.elem_ptr.as_ptr().add(idx)
+    }
+
+    fn clear(&mut self) {
+        unsafe {
+            // We can assume that all existing elements have an index lower than
+            // `len` (this is one of the invariants of the `Core` interface).
+            for idx in 0..self.len {
+                if self.has_element_at(idx) {
+                    ptr::drop_in_place(self.get_unchecked_mut(idx));
+                }
+            }
+            for bit_idx in 0..num_usizes_for(self.len) {
+                *self.bit_ptr.as_ptr().add(bit_idx) = 0;
+            }
+            self.len = 0;
+        }
+    }
+
+    fn occupancy_words(&self) -> Option<&[usize]> {
+        // The first `num_usizes_for(len) <= num_usizes_for(cap)` words are
+        // allocated and initialized. Bits of slots `≥ len` are 0 as these
+        // slots are empty.
+        unsafe {
+            Some(std::slice::from_raw_parts(self.bit_ptr.as_ptr(), num_usizes_for(self.len)))
+        }
+    }
+
+    unsafe fn swap(&mut self, a: usize, b: usize) {
+        // See `BitVecCore::swap` for an explanation of this bit trickery.
+        let swap_bit = (self.has_element_at(a) ^ self.has_element_at(b)) as usize;
+
+        let mask = swap_bit << (a % BITS_PER_USIZE);
+        *self.bit_ptr.as_ptr().add(a / BITS_PER_USIZE) ^= mask;
+
+        let mask = swap_bit << (b % BITS_PER_USIZE);
+        *self.bit_ptr.as_ptr().add(b / BITS_PER_USIZE) ^= mask;
+
+        ptr::swap(
+            self.elem_ptr.as_ptr().add(a),
+            self.elem_ptr.as_ptr().add(b),
+        );
+    }
+}
+
+impl<T, A: Allocator + Default> Default for FusedBitVecCore<T, A> {
+    fn default() -> Self {
+        Self::new_in(A::default())
+    }
+}
+
+impl<T, A: Allocator> Drop for FusedBitVecCore<T, A> {
+    fn drop(&mut self) {
+        // Drop all elements
+        self.clear();
+
+        unsafe {
+            // Deallocate the memory. `clear()` sets the length to 0 and drops
+            // all existing elements, so it's fine to call `dealloc`.
+            self.dealloc();
+        }
+    }
+}
+
+impl<T: Clone, A: Allocator + Clone> Clone for FusedBitVecCore<T, A> {
+    fn clone(&self) -> Self {
+        let mut out = Self::new_in(self.alloc.clone());
+
+        if self.cap != 0 {
+            unsafe {
+                out.realloc(self.cap);
+
+                // We set the length first (all slots of `out` are empty, so
+                // that's fine). That way, if a `clone()` panics, all clones
+                // that have been inserted so far are dropped correctly.
+                out.set_len(self.len);
+
+                let mut idx = 0;
+                while let Some(next) = self.first_filled_slot_from(idx) {
+                    out.insert_at(next, self.get_unchecked(next).clone());
+                    idx = next + 1;
+                }
+            }
+        }
+
+        out
+    }
+}
+
+// This impl is usually not used. `StableVec` has its own impl which doesn't
+// use this one.
+impl<T, A: Allocator> fmt::Debug for FusedBitVecCore<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FusedBitVecCore")
+            .field("len", &self.len())
+            .field("cap", &self.cap())
+            .finish()
+    }
+}
+
+// Implement `Send` and `Sync`. These are not automatically implemented as we
+// use raw pointers. But they are safe to implement (given that `T` and `A`
+// implement them), for the same reasons as for `BitVecCore`.
+unsafe impl<T: Send, A: Allocator + Send> Send for FusedBitVecCore<T, A> {}
+unsafe impl<T: Sync, A: Allocator + Sync> Sync for FusedBitVecCore<T, A> {}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::{align_of, size_of};
+    use super::*;
+
+    #[test]
+    fn layout() {
+        let (layout, offset) = layout_for::<u8>(1).unwrap();
+        assert_eq!(offset, size_of::<usize>());
+        assert_eq!(layout.size(), size_of::<usize>() + 1);
+
+        let (layout, offset) = layout_for::<u128>(BITS_PER_USIZE + 1).unwrap();
+        assert_eq!(offset % align_of::<u128>(), 0);
+        assert!(offset >= 2 * size_of::<usize>());
+        assert_eq!(layout.size(), offset + (BITS_PER_USIZE + 1) * 16);
+
+        let (layout, offset) = layout_for::<()>(1000).unwrap();
+        assert_eq!(offset, layout.size());
+
+        assert!(layout_for::<u64>(usize::MAX / 4).is_none());
+    }
+}
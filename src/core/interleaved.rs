@@ -0,0 +1,403 @@
+use std::{
+    alloc::Layout,
+    fmt,
+    mem::MaybeUninit,
+    ptr::{self, NonNull},
+};
+
+use super::{
+    Core,
+    allocator::{allocate, reallocate, Allocator, Global},
+};
+
+
+/// Number of slots per block.
+const BLOCK_SIZE: usize = 64;
+
+/// A `Core` implementation that stores the slots in blocks of 64, each
+/// preceded by the occupancy bits of its slots.
+///
+/// Like [`BitVecCore`][super::BitVecCore], this core only uses one bit per
+/// slot to store whether it is filled. But instead of storing all bits in a
+/// separate allocation, the bits are interleaved with the elements: every
+/// block of 64 slots starts with a `u64` word holding the bits for exactly
+/// those slots. That way, accessing an element at random only touches one
+/// region of memory (and usually only one or two cache lines), instead of
+/// two unrelated ones. This makes `get` and friends faster if the stable
+/// vector does not fit into the cache.
+///
+/// Searching for filled or empty slots (and thus iterating) still works on
+/// whole words and is nearly as fast as with `BitVecCore`, as long as `T` is
+/// small. For large `T`, the words are far apart, making iteration over
+/// indices less cache-friendly. Additionally, if `T` has an alignment larger
+/// than 8 bytes, each block contains some padding.
+///
+/// The memory is allocated with the allocator `A`. Unless the
+/// `allocator-api2` feature is enabled, this is always the global allocator.
+/// `Core::new` is only available if `A` implements `Default`; otherwise, use
+/// `new_in`.
+pub struct InterleavedCore<T, A: Allocator = Global> {
+    /// The blocks. There are `⌈cap / 64⌉` many. If a slot is empty, the memory
+    /// of that slot is undefined.
+    ptr: NonNull<Block<T>>,
+
+    /// The capacity: the number of slots. Corresponds to the `cap` of the
+    /// `Core` definition. Only the last block might contain fewer than 64
+    /// usable slots.
+    cap: usize,
+
+    /// The `len`: corresponds to the `len` of the `Core` definition.
+    len: usize,
+
+    /// The allocator used for the blocks.
+    alloc: A,
+}
+
+#[repr(C)]
+struct Block<T> {
+    /// Stores whether or not slots are filled (1) or empty (0). Bit `i`
+    /// belongs to `slots[i]`.
+    bits: u64,
+    slots: [MaybeUninit<T>; BLOCK_SIZE],
+}
+
+#[inline(always)]
+fn num_blocks_for(cap: usize) -> usize {
+    cap.div_ceil(BLOCK_SIZE)
+}
+
+impl<T, A: Allocator> InterleavedCore<T, A> {
+    /// Creates an empty instance that will allocate memory with `alloc`.
+    /// Does not allocate memory itself.
+    pub fn new_in(alloc: A) -> Self {
+        Self {
+            ptr: NonNull::dangling(),
+            cap: 0,
+            len: 0,
+            alloc,
+        }
+    }
+
+    /// Returns a reference to the underlying allocator.
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    /// Returns a pointer to the occupancy word of block `block`. The block
+    /// has to be `< num_blocks_for(self.cap)`.
+    #[inline(always)]
+    unsafe fn bits(&self, block: usize) -> *mut u64 {
+        ptr::addr_of_mut!((*self.ptr.as_ptr().add(block)).bits)
+    }
+
+    /// Returns a pointer to the slot with index `idx`, which has to be
+    /// `< self.cap`.
+    #[inline(always)]
+    unsafe fn slot(&self, idx: usize) -> *mut T {
+        let block = self.ptr.as_ptr().add(idx / BLOCK_SIZE);
+        let slots = ptr::addr_of_mut!((*block).slots) as *mut T;
+        slots.add(idx % BLOCK_SIZE)
+    }
+
+    /// Returns the layout that was used for the last allocation of `ptr`.
+    /// `self.cap` must not be 0, or else this method's behavior is undefined.
+    unsafe fn old_layout(&self) -> Layout {
+        Layout::from_size_align_unchecked(
+            // This can't overflow due to being previously allocated.
+            num_blocks_for(self.cap) * std::mem::size_of::<Block<T>>(),
+            std::mem::align_of::<Block<T>>(),
+        )
+    }
+}
+
+impl<T, A: Allocator> Core<T> for InterleavedCore<T, A> {
+    fn new() -> Self
+    where
+        Self: Default,
+    {
+        Self::default()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    unsafe fn set_len(&mut self, new_len: usize) {
+        debug_assert!(new_len <= self.cap());
+        // Other precondition is too expensive to test, even in debug:
+        // ∀ i in `new_len..self.cap()` ⇒ `self.has_element_at(i) == false`
+
+        self.len = new_len;
+    }
+
+    fn cap(&self) -> usize {
+        self.cap
+    }
+
+    #[inline(never)]
+    #[cold]
+    unsafe fn realloc(&mut self, new_cap: usize) {
+        debug_assert!(new_cap >= self.len());
+        debug_assert!(new_cap <= isize::MAX as usize);
+
+        #[inline(never)]
+        #[cold]
+        fn capacity_overflow() -> ! {
+            panic!("capacity overflow in `stable_vec::InterleavedCore::realloc` \
+                (attempt to allocate more than `isize::MAX` bytes");
+        }
+
+        let old_blocks = num_blocks_for(self.cap);
+        let new_blocks = num_blocks_for(new_cap);
+
+        // If the number of blocks doesn't change, we only need to adjust
+        // `cap`. Slots ≥ `new_cap` in the last block are all empty due to the
+        // preconditions.
+        if old_blocks != new_blocks {
+            if new_blocks == 0 {
+                // Due to the preconditions, all slots are empty, so there is
+                // nothing to drop.
+                self.alloc.deallocate(self.ptr.cast(), self.old_layout());
+                self.ptr = NonNull::dangling();
+            } else {
+                let new_layout = Layout::array::<Block<T>>(new_blocks)
+                    .unwrap_or_else(|_| capacity_overflow());
+
+                // (Re)allocate memory. Blocks are self-contained, so we don't
+                // need to move anything around. If the allocation fails, the
+                // program is quit with an OOM error.
+                let ptr = if old_blocks == 0 {
+                    allocate(&self.alloc, new_layout, false)
+                } else {
+                    reallocate(&self.alloc, self.ptr.cast(), self.old_layout(), new_layout)
+                };
+                self.ptr = ptr.cast();
+
+                // All slots of new blocks are empty.
+                for block in old_blocks..new_blocks {
+                    ptr::write(self.bits(block), 0);
+                }
+            }
+        }
+
+        self.cap = new_cap;
+    }
+
+    unsafe fn has_element_at(&self, idx: usize) -> bool {
+        debug_assert!(idx < self.cap());
+
+        let block = *self.bits(idx / BLOCK_SIZE);
+        ((block >> (idx % BLOCK_SIZE)) & 0b1) != 0
+    }
+
+    unsafe fn insert_at(&mut self, idx: usize, elem: T) {
+        debug_assert!(idx < self.cap());
+        debug_assert!(!self.has_element_at(idx));
+
+        // We first write the value and then update the bits to avoid
+        // potential double drops if a random panic appears.
+        ptr::write(self.slot(idx), elem);
+        *self.bits(idx / BLOCK_SIZE) |= 1 << (idx % BLOCK_SIZE);
+    }
+
+    unsafe fn remove_at(&mut self, idx: usize) -> T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        // We first mark the value as deleted and then read the value.
+        // Otherwise, a random panic could lead to a double drop.
+        *self.bits(idx / BLOCK_SIZE) &= !(1 << (idx % BLOCK_SIZE));
+        ptr::read(self.slot(idx))
+    }
+
+    unsafe fn get_unchecked(&self, idx: usize) -> &T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        &*self.slot(idx)
+    }
+
+    unsafe fn get_unchecked_mut(&mut self, idx: usize) -> &mut T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        &mut *self.slot(idx)
+    }
+
+    fn clear(&mut self) {
+        unsafe {
+            // We can assume that all existing elements have an index lower than
+            // `len` (this is one of the invariants of the `Core` interface).
+            let mut idx = 0;
+            while let Some(next) = self.first_filled_slot_from(idx) {
+                *self.bits(next / BLOCK_SIZE) &= !(1 << (next % BLOCK_SIZE));
+                ptr::drop_in_place(self.slot(next));
+                idx = next + 1;
+            }
+            self.len = 0;
+        }
+    }
+
+    unsafe fn first_filled_slot_from(&self, idx: usize) -> Option<usize> {
+        debug_assert!(idx <= self.cap());
+
+        if idx >= self.len {
+            return None;
+        }
+
+        let mut block = idx / BLOCK_SIZE;
+        let mut word = *self.bits(block) & (!0 << (idx % BLOCK_SIZE));
+        loop {
+            if word != 0 {
+                let out = block * BLOCK_SIZE + word.trailing_zeros() as usize;
+                return if out < self.len { Some(out) } else { None };
+            }
+
+            block += 1;
+            if block * BLOCK_SIZE >= self.len {
+                return None;
+            }
+            word = *self.bits(block);
+        }
+    }
+
+    unsafe fn first_filled_slot_below(&self, idx: usize) -> Option<usize> {
+        debug_assert!(idx <= self.cap());
+
+        // Slots ≥ `len` are always empty.
+        let idx = std::cmp::min(idx, self.len);
+        if idx == 0 {
+            return None;
+        }
+
+        // We look at all slots ≤ `last`.
+        let last = idx - 1;
+        let mut block = last / BLOCK_SIZE;
+        let mut word = *self.bits(block) & (!0 >> (BLOCK_SIZE - 1 - last % BLOCK_SIZE));
+        loop {
+            if word != 0 {
+                let top = BLOCK_SIZE - 1 - word.leading_zeros() as usize;
+                return Some(block * BLOCK_SIZE + top);
+            }
+
+            if block == 0 {
+                return None;
+            }
+            block -= 1;
+            word = *self.bits(block);
+        }
+    }
+
+    unsafe fn first_empty_slot_from(&self, idx: usize) -> Option<usize> {
+        debug_assert!(idx <= self.cap());
+
+        if idx >= self.cap {
+            return None;
+        }
+
+        let mut block = idx / BLOCK_SIZE;
+        let mut word = !*self.bits(block) & (!0 << (idx % BLOCK_SIZE));
+        loop {
+            if word != 0 {
+                let out = block * BLOCK_SIZE + word.trailing_zeros() as usize;
+                return if out < self.cap { Some(out) } else { None };
+            }
+
+            block += 1;
+            if block * BLOCK_SIZE >= self.cap {
+                return None;
+            }
+            word = !*self.bits(block);
+        }
+    }
+
+    unsafe fn first_empty_slot_below(&self, idx: usize) -> Option<usize> {
+        debug_assert!(idx <= self.cap());
+
+        if idx == 0 {
+            return None;
+        }
+
+        let last = idx - 1;
+        let mut block = last / BLOCK_SIZE;
+        let mut word = !*self.bits(block) & (!0 >> (BLOCK_SIZE - 1 - last % BLOCK_SIZE));
+        loop {
+            if word != 0 {
+                let top = BLOCK_SIZE - 1 - word.leading_zeros() as usize;
+                return Some(block * BLOCK_SIZE + top);
+            }
+
+            if block == 0 {
+                return None;
+            }
+            block -= 1;
+            word = !*self.bits(block);
+        }
+    }
+
+    unsafe fn swap(&mut self, a: usize, b: usize) {
+        // See `BitVecCore::swap` for an explanation of this bit trickery.
+        let swap_bit = (self.has_element_at(a) ^ self.has_element_at(b)) as u64;
+        *self.bits(a / BLOCK_SIZE) ^= swap_bit << (a % BLOCK_SIZE);
+        *self.bits(b / BLOCK_SIZE) ^= swap_bit << (b % BLOCK_SIZE);
+
+        ptr::swap(self.slot(a), self.slot(b));
+    }
+}
+
+impl<T, A: Allocator + Default> Default for InterleavedCore<T, A> {
+    fn default() -> Self {
+        Self::new_in(A::default())
+    }
+}
+
+impl<T, A: Allocator> Drop for InterleavedCore<T, A> {
+    fn drop(&mut self) {
+        // Drop all elements. Afterwards, all slots are empty and `len` is 0,
+        // so we can just deallocate the memory via `realloc`.
+        self.clear();
+        unsafe {
+            self.realloc(0);
+        }
+    }
+}
+
+impl<T: Clone, A: Allocator + Clone> Clone for InterleavedCore<T, A> {
+    fn clone(&self) -> Self {
+        let mut out = Self::new_in(self.alloc.clone());
+
+        unsafe {
+            out.realloc(self.cap);
+
+            // We set the length first (all slots of `out` are empty, so
+            // that's fine). That way, if a `clone()` panics, all clones that
+            // have been inserted so far are dropped correctly.
+            out.set_len(self.len);
+
+            let mut idx = 0;
+            while let Some(next) = self.first_filled_slot_from(idx) {
+                out.insert_at(next, self.get_unchecked(next).clone());
+                idx = next + 1;
+            }
+        }
+
+        out
+    }
+}
+
+// This impl is usually not used. `StableVec` has its own impl which doesn't
+// use this one.
+impl<T, A: Allocator> fmt::Debug for InterleavedCore<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InterleavedCore")
+            .field("len", &self.len())
+            .field("cap", &self.cap())
+            .finish()
+    }
+}
+
+// Implement `Send` and `Sync`. These are not automatically implemented as we
+// use raw pointers. But they are safe to implement (given that `T` and `A`
+// implement them), for the same reasons as for `BitVecCore`.
+unsafe impl<T: Send, A: Allocator + Send> Send for InterleavedCore<T, A> {}
+unsafe impl<T: Sync, A: Allocator + Sync> Sync for InterleavedCore<T, A> {}
@@ -0,0 +1,518 @@
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io,
+    marker::PhantomData,
+    mem::{align_of, size_of},
+    os::unix::io::AsRawFd,
+    path::Path,
+    ptr::{self, NonNull},
+};
+
+use super::Core;
+
+
+/// Types that can be stored in a file and read back as is.
+///
+/// # Safety
+///
+/// Implementing this trait means that every bit pattern of the correct size
+/// is a valid value of the type, that the type does not contain padding bytes
+/// and that it does not contain pointers or references (those would be
+/// meaningless when the file is opened again). This is true for primitive
+/// integers and floats and for arrays and `#[repr(C)]` structs thereof
+/// (without padding).
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {
+        $( unsafe impl Pod for $ty {} )*
+    };
+}
+
+impl_pod!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+
+/// Identifies files created by `MmapCore`.
+const MAGIC: [u8; 8] = *b"SVECMMAP";
+
+/// Version of the file format. Needs to be increased whenever the layout
+/// changes.
+const VERSION: u32 = 1;
+
+/// The header at the very start of the file.
+#[repr(C)]
+struct Header {
+    magic: [u8; 8],
+    version: u32,
+    elem_size: u32,
+    elem_align: u32,
+    _reserved: u32,
+    len: u64,
+    cap: u64,
+}
+
+/// The number of bytes reserved for the header. The remaining bytes are
+/// zero.
+const HEADER_SIZE: usize = 64;
+
+/// The occupancy bitmap always uses 64 bit words, so that files are portable
+/// between 32 and 64 bit platforms.
+const BITS_PER_WORD: usize = 64;
+
+/// A `Core` implementation that stores all slots in a memory-mapped file.
+///
+/// The file consists of a header, the element buffer and the occupancy
+/// bitmap (one bit per slot), in that order. The header stores a magic
+/// number, the format version, size and alignment of `T`, the `len` and the
+/// `cap`. Thus, the file can be opened again later (via
+/// [`StableVecFacade::open_mmap`][crate::StableVecFacade::open_mmap]) with all
+/// indices and holes intact. Opening a file that was not created by this
+/// core or that was created with a different element type fails.
+///
+/// Only [`Pod`] types can be stored, as the file contents are used as is.
+/// Data is written to the file by the operating system at some point; use
+/// [`flush`][MmapCore::flush] to make sure everything has been written.
+///
+/// As the `Core` interface does not allow returning errors, I/O errors while
+/// growing or shrinking the file result in a panic.
+///
+/// `Core::new` (and thus [`StableVecFacade::new`][crate::StableVecFacade::new])
+/// returns a core without a file and with a capacity of 0, which panics when
+/// it has to grow. Use [`create`][MmapCore::create] or
+/// [`open`][MmapCore::open] instead.
+///
+/// This core is only available on Unix platforms with the `mmap` feature.
+pub struct MmapCore<T: Pod> {
+    /// `None` if this core was created via `Core::new`. In that case, nothing
+    /// is mapped and `map_len`, `cap` and `len` are 0.
+    file: Option<File>,
+
+    /// The start of the mapping, which covers the whole file. Dangling if
+    /// `map_len` is 0.
+    base: NonNull<u8>,
+
+    /// The size of the mapping and the file in bytes.
+    map_len: usize,
+
+    /// The capacity: the number of slots in the file. Corresponds to the
+    /// `cap` of the `Core` definition. This is also stored in the header.
+    cap: usize,
+
+    /// The `len`: corresponds to the `len` of the `Core` definition. This is
+    /// also stored in the header.
+    len: usize,
+
+    _dummy: PhantomData<T>,
+}
+
+/// Returns the offset of the element buffer within the file.
+fn elem_offset<T>() -> usize {
+    HEADER_SIZE.next_multiple_of(align_of::<T>())
+}
+
+/// Returns the offset of the bitmap within the file for `cap` slots.
+fn bits_offset<T>(cap: usize) -> usize {
+    (elem_offset::<T>() + cap * size_of::<T>()).next_multiple_of(size_of::<u64>())
+}
+
+/// Returns the size of the file for `cap` slots or `None` if it overflows.
+fn file_size<T>(cap: usize) -> Option<usize> {
+    let elems_end = cap.checked_mul(size_of::<T>())?.checked_add(elem_offset::<T>())?;
+    elems_end
+        .checked_next_multiple_of(size_of::<u64>())?
+        .checked_add(cap.div_ceil(BITS_PER_WORD) * size_of::<u64>())
+        .filter(|&size| size <= isize::MAX as usize)
+}
+
+/// Maps `len` bytes of `file` into memory.
+fn map(file: &File, len: usize) -> io::Result<NonNull<u8>> {
+    let ptr = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        )
+    };
+
+    if ptr == libc::MAP_FAILED {
+        Err(io::Error::last_os_error())
+    } else {
+        // `mmap` never returns null on success when the first argument is
+        // null.
+        Ok(NonNull::new(ptr as *mut u8).expect("`mmap` returned null"))
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl<T: Pod> MmapCore<T> {
+    /// Creates a new file at `path` (truncating it if it already exists) and
+    /// maps it into memory. The core is empty and has a capacity of 0.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let map_len = HEADER_SIZE;
+        file.set_len(map_len as u64)?;
+
+        let out = Self {
+            base: map(&file, map_len)?,
+            file: Some(file),
+            map_len,
+            cap: 0,
+            len: 0,
+            _dummy: PhantomData,
+        };
+
+        unsafe {
+            ptr::write(out.header(), Header {
+                magic: MAGIC,
+                version: VERSION,
+                elem_size: size_of::<T>() as u32,
+                elem_align: align_of::<T>() as u32,
+                _reserved: 0,
+                len: 0,
+                cap: 0,
+            });
+        }
+
+        Ok(out)
+    }
+
+    /// Opens a file previously created by [`create`][MmapCore::create] and
+    /// maps it into memory. All slots are exactly as they were when the file
+    /// was last written.
+    ///
+    /// Returns an error with kind `InvalidData` if the file was not created
+    /// by this core, has an incompatible version, was created for an element
+    /// type with a different size or alignment, or has an unexpected size.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let map_len = file.metadata()?.len() as usize;
+        if map_len < HEADER_SIZE {
+            return Err(invalid_data("file too small to contain a stable vector header"));
+        }
+
+        let mut out = Self {
+            base: map(&file, map_len)?,
+            file: Some(file),
+            map_len,
+            cap: 0,
+            len: 0,
+            _dummy: PhantomData,
+        };
+
+        // If we return early, `out` is dropped and unmaps the file. As `cap`
+        // and `len` are 0, nothing else happens.
+        let header = unsafe { &*out.header() };
+        if header.magic != MAGIC {
+            return Err(invalid_data("not a stable vector file (magic number mismatch)"));
+        }
+        if header.version != VERSION {
+            return Err(invalid_data("unsupported stable vector file version"));
+        }
+        if header.elem_size as usize != size_of::<T>()
+            || header.elem_align as usize != align_of::<T>()
+        {
+            return Err(invalid_data("stable vector file was created for a different element type"));
+        }
+
+        let (len, cap) = (header.len as usize, header.cap as usize);
+        if len > cap || file_size::<T>(cap) != Some(map_len) {
+            return Err(invalid_data("corrupt stable vector file (inconsistent size)"));
+        }
+
+        // All slots ≥ `len` have to be empty, otherwise the invariants of
+        // `Core` would be violated.
+        out.cap = cap;
+        let first_word = len / BITS_PER_WORD;
+        let tail_is_empty = (first_word..cap.div_ceil(BITS_PER_WORD)).all(|i| {
+            let mut word = unsafe { *out.word(i) };
+            if i == first_word {
+                word &= !0 << (len % BITS_PER_WORD);
+            }
+            word == 0
+        });
+        if !tail_is_empty {
+            out.cap = 0;
+            return Err(invalid_data("corrupt stable vector file (filled slots beyond `len`)"));
+        }
+
+        out.len = len;
+        Ok(out)
+    }
+
+    /// Writes all changes to the file, blocking until that's done.
+    pub fn flush(&self) -> io::Result<()> {
+        if self.file.is_none() {
+            return Ok(());
+        }
+
+        let res = unsafe {
+            libc::msync(self.base.as_ptr() as *mut libc::c_void, self.map_len, libc::MS_SYNC)
+        };
+        if res == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    /// Returns the number of filled slots by counting the bits in the bitmap.
+    pub fn count_filled(&self) -> usize {
+        (0..self.cap.div_ceil(BITS_PER_WORD))
+            .map(|i| unsafe { (*self.word(i)).count_ones() as usize })
+            .sum()
+    }
+
+    fn header(&self) -> *mut Header {
+        self.base.as_ptr() as *mut Header
+    }
+
+    /// Returns a pointer to the `i`-th bitmap word, which has to exist.
+    fn word(&self, i: usize) -> *mut u64 {
+        unsafe {
+            self.base.as_ptr().add(bits_offset::<T>(self.cap)).cast::<u64>().add(i)
+        }
+    }
+
+    /// Returns a pointer to the slot `idx`, which has to be `< cap`.
+    fn slot(&self, idx: usize) -> *mut T {
+        unsafe {
+            self.base.as_ptr().add(elem_offset::<T>()).cast::<T>().add(idx)
+        }
+    }
+
+    fn unmap(&self) {
+        if self.map_len == 0 {
+            return;
+        }
+
+        unsafe {
+            libc::munmap(self.base.as_ptr() as *mut libc::c_void, self.map_len);
+        }
+    }
+}
+
+impl<T: Pod> Core<T> for MmapCore<T> {
+    fn new() -> Self
+    where
+        Self: Default,
+    {
+        Self::default()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    unsafe fn set_len(&mut self, new_len: usize) {
+        debug_assert!(new_len <= self.cap());
+        // Other precondition is too expensive to test, even in debug:
+        // ∀ i in `new_len..self.cap()` ⇒ `self.has_element_at(i) == false`
+
+        self.len = new_len;
+        if self.file.is_some() {
+            (*self.header()).len = new_len as u64;
+        }
+    }
+
+    fn cap(&self) -> usize {
+        self.cap
+    }
+
+    #[inline(never)]
+    #[cold]
+    unsafe fn realloc(&mut self, new_cap: usize) {
+        debug_assert!(new_cap >= self.len());
+        debug_assert!(new_cap <= isize::MAX as usize);
+
+        fn io_fail(e: io::Error) -> ! {
+            panic!("I/O error in `stable_vec::MmapCore::realloc`: {}", e);
+        }
+
+        let file = match &self.file {
+            Some(file) => file,
+            None => panic!(
+                "`stable_vec::MmapCore` created via `Core::new` has no file and can't \
+                    grow (use `MmapCore::create` or `MmapCore::open`)",
+            ),
+        };
+
+        let old_words = self.cap.div_ceil(BITS_PER_WORD);
+        let new_words = new_cap.div_ceil(BITS_PER_WORD);
+        let old_bits = bits_offset::<T>(self.cap);
+        let new_len = file_size::<T>(new_cap)
+            .unwrap_or_else(|| panic!("capacity overflow in `stable_vec::MmapCore::realloc`"));
+        let new_bits = bits_offset::<T>(new_cap);
+
+        // In both cases, all fallible steps happen before the bitmap is
+        // moved, so that a panic leaves this core (and the file) unchanged.
+        // Both mappings show the same file, so changes made via one of them
+        // are visible via the other one.
+        if new_cap > self.cap {
+            if let Err(e) = file.set_len(new_len as u64) {
+                io_fail(e);
+            }
+            let new_base = match map(file, new_len) {
+                Ok(base) => base,
+                Err(e) => {
+                    // Try to restore the old size, so that the file can still
+                    // be opened. There is nothing we can do if that fails.
+                    let _ = file.set_len(self.map_len as u64);
+                    io_fail(e);
+                }
+            };
+
+            // The bitmap moves towards the end. The new words are zeroed
+            // afterwards, as they might overlap with the old bitmap.
+            let base = new_base.as_ptr();
+            ptr::copy(base.add(old_bits), base.add(new_bits), old_words * size_of::<u64>());
+            ptr::write_bytes(
+                base.add(new_bits).cast::<u64>().add(old_words),
+                0,
+                new_words - old_words,
+            );
+
+            self.unmap();
+            self.base = new_base;
+            self.map_len = new_len;
+            self.cap = new_cap;
+            (*self.header()).cap = new_cap as u64;
+        } else if new_cap < self.cap {
+            let new_base = map(file, new_len).unwrap_or_else(|e| io_fail(e));
+
+            // The bitmap moves towards the start of the file. Only the old
+            // mapping covers the whole old bitmap. All words that are cut off
+            // are zero, as those slots are empty. The moved words only
+            // overwrite empty slots and the old bitmap itself.
+            let base = self.base.as_ptr();
+            let bitmap_size = new_words * size_of::<u64>();
+            ptr::copy(base.add(old_bits), base.add(new_bits), bitmap_size);
+
+            // The new capacity is only committed once the file has the
+            // matching size. If truncating fails, we move the bitmap back, so
+            // that this core, the header and the file size stay unchanged.
+            if let Err(e) = file.set_len(new_len as u64) {
+                ptr::copy(base.add(new_bits), base.add(old_bits), bitmap_size);
+                libc::munmap(new_base.as_ptr() as *mut libc::c_void, new_len);
+                io_fail(e);
+            }
+
+            self.unmap();
+            self.base = new_base;
+            self.map_len = new_len;
+            self.cap = new_cap;
+            (*self.header()).cap = new_cap as u64;
+        }
+    }
+
+    unsafe fn has_element_at(&self, idx: usize) -> bool {
+        debug_assert!(idx < self.cap());
+
+        let word = *self.word(idx / BITS_PER_WORD);
+        ((word >> (idx % BITS_PER_WORD)) & 0b1) != 0
+    }
+
+    unsafe fn insert_at(&mut self, idx: usize, elem: T) {
+        debug_assert!(idx < self.cap());
+        debug_assert!(!self.has_element_at(idx));
+
+        ptr::write(self.slot(idx), elem);
+        *self.word(idx / BITS_PER_WORD) |= 1 << (idx % BITS_PER_WORD);
+    }
+
+    unsafe fn remove_at(&mut self, idx: usize) -> T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        *self.word(idx / BITS_PER_WORD) &= !(1 << (idx % BITS_PER_WORD));
+        ptr::read(self.slot(idx))
+    }
+
+    unsafe fn get_unchecked(&self, idx: usize) -> &T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        &*self.slot(idx)
+    }
+
+    unsafe fn get_unchecked_mut(&mut self, idx: usize) -> &mut T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        &mut *self.slot(idx)
+    }
+
+    fn clear(&mut self) {
+        // Without any slots, there might not even be a mapping.
+        if self.len == 0 {
+            return;
+        }
+
+        // `T: Copy`, so there is nothing to drop. We only need to reset the
+        // bits.
+        unsafe {
+            ptr::write_bytes(self.word(0), 0, self.len.div_ceil(BITS_PER_WORD));
+            self.set_len(0);
+        }
+    }
+
+    unsafe fn swap(&mut self, a: usize, b: usize) {
+        // See `BitVecCore::swap` for an explanation of this bit trickery.
+        let swap_bit = (self.has_element_at(a) ^ self.has_element_at(b)) as u64;
+        *self.word(a / BITS_PER_WORD) ^= swap_bit << (a % BITS_PER_WORD);
+        *self.word(b / BITS_PER_WORD) ^= swap_bit << (b % BITS_PER_WORD);
+
+        ptr::swap(self.slot(a), self.slot(b));
+    }
+}
+
+impl<T: Pod> Default for MmapCore<T> {
+    fn default() -> Self {
+        Self {
+            file: None,
+            base: NonNull::dangling(),
+            map_len: 0,
+            cap: 0,
+            len: 0,
+            _dummy: PhantomData,
+        }
+    }
+}
+
+impl<T: Pod> Drop for MmapCore<T> {
+    fn drop(&mut self) {
+        // The elements don't need to be dropped. Unmapping does not discard
+        // any changes: they are written to the file by the OS eventually.
+        self.unmap();
+    }
+}
+
+// This impl is usually not used. `StableVec` has its own impl which doesn't
+// use this one.
+impl<T: Pod> fmt::Debug for MmapCore<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MmapCore")
+            .field("len", &self.len())
+            .field("cap", &self.cap())
+            .field("file", &self.file)
+            .finish()
+    }
+}
+
+// Implement `Send` and `Sync`. These are not automatically implemented as we
+// use raw pointers. But they are safe to implement, for the same reasons as
+// for `BitVecCore`: the mapping is owned exclusively by this instance.
+unsafe impl<T: Pod + Send> Send for MmapCore<T> {}
+unsafe impl<T: Pod + Sync> Sync for MmapCore<T> {}
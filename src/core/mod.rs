@@ -6,21 +6,81 @@
 //! more information.
 
 use std::{
-    prelude::v1::*,
     fmt,
     marker::PhantomData,
     ops::{Deref, DerefMut},
 };
 
+pub use self::array::{ArrayCore, CapacityError};
+#[cfg(feature = "alloc")]
+pub use self::adaptive::AdaptiveCore;
+#[cfg(feature = "allocator-api2")]
+pub use self::allocator::{Allocator, Global};
+#[cfg(feature = "alloc")]
 pub use self::option::OptionCore;
+#[cfg(feature = "alloc")]
 pub use self::bitvec::BitVecCore;
+#[cfg(feature = "alloc")]
+pub use self::bit_packed::BitPackedCore;
+#[cfg(feature = "alloc")]
+pub use self::checked::CheckedCore;
+pub use self::counting::{CoreStats, CountingCore};
+#[cfg(feature = "alloc")]
+pub use self::dense::DenseCore;
+#[cfg(feature = "alloc")]
+pub use self::fused::FusedBitVecCore;
+#[cfg(feature = "alloc")]
+pub use self::interleaved::InterleavedCore;
+#[cfg(all(feature = "mmap", unix))]
+pub use self::mmap::{MmapCore, Pod};
+#[cfg(feature = "alloc")]
+pub use self::range_set::RangeSetCore;
+pub use self::slice::SliceCore;
+#[cfg(feature = "alloc")]
+pub use self::small::SmallCore;
+#[cfg(feature = "alloc")]
+pub use self::soft_remove::SoftRemoveCore;
+#[cfg(feature = "alloc")]
+pub use self::tracking::{Changes, TrackingCore};
 
+mod array;
+#[cfg(feature = "alloc")]
+mod adaptive;
+#[cfg(feature = "alloc")]
+pub(crate) mod allocator;
+#[cfg(feature = "alloc")]
 mod option;
+#[cfg(feature = "alloc")]
 mod bitvec;
+#[cfg(feature = "alloc")]
+mod bit_packed;
+#[cfg(feature = "alloc")]
+mod checked;
+#[cfg(any(all(test, feature = "alloc"), feature = "test-suite"))]
+pub mod conformance;
+mod counting;
+#[cfg(feature = "alloc")]
+mod dense;
+#[cfg(feature = "alloc")]
+mod fused;
+#[cfg(feature = "alloc")]
+mod interleaved;
+#[cfg(all(feature = "mmap", unix))]
+mod mmap;
+#[cfg(feature = "alloc")]
+mod range_set;
+mod slice;
+#[cfg(feature = "alloc")]
+mod small;
+#[cfg(feature = "alloc")]
+mod soft_remove;
+#[cfg(feature = "alloc")]
+mod tracking;
 
 
 /// The default core implementation of the stable vector. Fine in most
 /// situations.
+#[cfg(feature = "alloc")]
 pub type DefaultCore<T> = BitVecCore<T>;
 
 /// The core of a stable vector.
@@ -75,16 +135,28 @@ pub type DefaultCore<T> = BitVecCore<T>;
 /// Cloning a core must clone everything, including all empty slots. This means
 /// that the capacity of the clone must be at least the capacity of the
 /// original value.
+#[allow(clippy::len_without_is_empty)]
 pub trait Core<T> {
     /// Creates an empty instance without any elements. Must not allocate
     /// memory.
     ///
+    /// Cores that store their slots inline (i.e. without allocating) may
+    /// start with a capacity greater than 0.
+    ///
     /// # Formal
     ///
     /// **Postconditons** (of returned instance `out`):
     /// - `out.len() == 0`
-    /// - `out.cap() == 0`
-    fn new() -> Self;
+    /// - all slots are empty
+    /// - `out.cap() == 0`, unless the core does not allocate for its initial
+    ///   capacity
+    ///
+    /// Only callable if the core implements `Default`, which lets cores that
+    /// need some state to be created (e.g. an allocator) still implement this
+    /// trait. Implementations can drop that bound if they don't need it.
+    fn new() -> Self
+    where
+        Self: Default;
 
     /// Returns the length of this core (the `len`). See the trait docs for
     /// more information.
@@ -297,6 +369,57 @@ pub trait Core<T> {
         (0..idx).rev().find(|&idx| !self.has_element_at(idx))
     }
 
+    /// Returns the bit vector storing which slots are filled, if this core
+    /// has one: bit `i % usize::BITS` of word `i / usize::BITS` is 1 if and
+    /// only if the slot `i` is filled. Stable vector operations involving
+    /// several stable vectors (like
+    /// [`StableVecFacade::join`][crate::StableVecFacade::join]) can then work
+    /// on whole words at once.
+    ///
+    /// The default implementation returns `None`.
+    ///
+    /// # Formal
+    ///
+    /// **Postconditons** (for return value `out`):
+    /// - if `out == Some(words)`:
+    ///     - `words.len() == ⌈self.len() / usize::BITS⌉`
+    ///     - ∀ i in `0..self.len()` ⇒ bit `i` of `words` is 1 if and only if
+    ///       `self.has_element_at(i) == true`
+    ///     - ∀ i in `self.len()..words.len() * usize::BITS` ⇒ bit `i` of
+    ///       `words` is 0
+    fn occupancy_words(&self) -> Option<&[usize]> {
+        None
+    }
+
+    /// Returns all elements as one slice ordered by increasing index, if this
+    /// core stores them like that. [`StableVecFacade::values`] and
+    /// [`StableVecFacade::values_mut`] then simply walk over that slice
+    /// instead of searching for filled slots.
+    ///
+    /// The default implementation returns `None`.
+    ///
+    /// [`StableVecFacade::values`]: crate::StableVecFacade::values
+    /// [`StableVecFacade::values_mut`]: crate::StableVecFacade::values_mut
+    ///
+    /// # Formal
+    ///
+    /// **Postconditons** (for return value `out`):
+    /// - if `out == Some(values)`:
+    ///     - `values.len()` is the number of filled slots
+    ///     - ∀ k in `0..values.len()` ⇒ `values[k]` is the element in the
+    ///       `k`-th filled slot (in order of increasing indices)
+    fn values_in_order(&self) -> Option<&[T]> {
+        None
+    }
+
+    /// Mutable version of [`values_in_order`][Core::values_in_order]. Has to
+    /// return `Some` if and only if `values_in_order` does.
+    ///
+    /// The default implementation returns `None`.
+    fn values_in_order_mut(&mut self) -> Option<&mut [T]> {
+        None
+    }
+
     /// Swaps the two slots with indices `a` and `b`. That is: the element
     /// *and* the "filled/empty" status are swapped. The slots at indices `a`
     /// and `b` can be empty or filled.
@@ -322,6 +445,151 @@ pub trait Core<T> {
 }
 
 
+/// A core that allocates its memory with a user-provided allocator.
+///
+/// Only available with the `allocator-api2` feature. See
+/// [`StableVecFacade::new_in`][crate::StableVecFacade::new_in].
+#[cfg(feature = "allocator-api2")]
+pub trait CoreWithAllocator<T>: Core<T> {
+    /// The allocator type.
+    type Allocator: Allocator;
+
+    /// Creates an empty instance that will allocate memory with `alloc`. Must
+    /// not allocate memory itself. The same postconditions as for
+    /// [`Core::new`] apply.
+    fn new_in(alloc: Self::Allocator) -> Self;
+
+    /// Returns a reference to the allocator.
+    fn allocator(&self) -> &Self::Allocator;
+}
+
+#[cfg(feature = "allocator-api2")]
+impl<T, A: Allocator> CoreWithAllocator<T> for BitVecCore<T, A> {
+    type Allocator = A;
+
+    fn new_in(alloc: A) -> Self {
+        Self::new_in(alloc)
+    }
+
+    fn allocator(&self) -> &A {
+        self.allocator()
+    }
+}
+
+#[cfg(feature = "allocator-api2")]
+impl<T, A: Allocator> CoreWithAllocator<T> for FusedBitVecCore<T, A> {
+    type Allocator = A;
+
+    fn new_in(alloc: A) -> Self {
+        Self::new_in(alloc)
+    }
+
+    fn allocator(&self) -> &A {
+        self.allocator()
+    }
+}
+
+#[cfg(feature = "allocator-api2")]
+impl<T, A: Allocator> CoreWithAllocator<T> for InterleavedCore<T, A> {
+    type Allocator = A;
+
+    fn new_in(alloc: A) -> Self {
+        Self::new_in(alloc)
+    }
+
+    fn allocator(&self) -> &A {
+        self.allocator()
+    }
+}
+
+#[cfg(feature = "allocator-api2")]
+impl<T, A: Allocator> CoreWithAllocator<T> for OptionCore<T, A> {
+    type Allocator = A;
+
+    fn new_in(alloc: A) -> Self {
+        Self::new_in(alloc)
+    }
+
+    fn allocator(&self) -> &A {
+        self.allocator()
+    }
+}
+
+
+/// A core that can be turned into the same kind of core for another element
+/// type. Used by [`StableVecFacade::map`][crate::StableVecFacade::map].
+pub trait MapCore<T>: Core<T> {
+    /// The same kind of core, storing elements of type `U`. Has to implement
+    /// `Default`, as it is created empty if mapping in place fails.
+    type Mapped<U>: Core<U> + Default;
+
+    /// Replaces each element `e` by `f(e)`, reusing the memory of this core.
+    /// Returns `Err(self)` without calling `f` if that is not possible (e.g.
+    /// because `T` and `U` have different layouts). The default
+    /// implementation always does that.
+    ///
+    /// If `f` panics, the remaining elements have to be dropped without
+    /// causing memory unsafety (e.g. a double drop). Leaking memory is allowed.
+    ///
+    /// # Formal
+    ///
+    /// **Postconditons** (for return value `out`, with `before` being `self`
+    /// before the call):
+    /// - if `out == Ok(mapped)`:
+    ///     - `mapped.len() == before.len()`
+    ///     - `mapped.cap() == before.cap()`
+    ///     - ∀ i in `0..before.cap()` ⇒
+    ///       `mapped.has_element_at(i) == before.has_element_at(i)`
+    ///     - ∀ i in `0..before.cap()` where `before.has_element_at(i)` ⇒
+    ///       `mapped.get_unchecked(i) == f(before.get_unchecked(i))`
+    /// - if `out == Err(same)`: `same` is unchanged and `f` was not called
+    fn map_in_place<U, F>(self, f: &mut F) -> Result<Self::Mapped<U>, Self>
+    where
+        Self: Sized,
+        F: FnMut(T) -> U,
+    {
+        let _ = f;
+        Err(self)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> MapCore<T> for OptionCore<T> {
+    // `Option<T>` and `Option<U>` can have different layouts even if `T` and
+    // `U` have the same one, so we don't map in place.
+    type Mapped<U> = OptionCore<U>;
+}
+
+#[cfg(feature = "alloc")]
+impl<T> MapCore<T> for FusedBitVecCore<T> {
+    type Mapped<U> = FusedBitVecCore<U>;
+}
+
+#[cfg(feature = "alloc")]
+impl<T> MapCore<T> for InterleavedCore<T> {
+    type Mapped<U> = InterleavedCore<U>;
+}
+
+#[cfg(feature = "alloc")]
+impl<T> MapCore<T> for DenseCore<T> {
+    type Mapped<U> = DenseCore<U>;
+}
+
+#[cfg(feature = "alloc")]
+impl<T> MapCore<T> for RangeSetCore<T> {
+    type Mapped<U> = RangeSetCore<U>;
+}
+
+#[cfg(feature = "alloc")]
+impl<T, const N: usize> MapCore<T> for SmallCore<T, N> {
+    type Mapped<U> = SmallCore<U, N>;
+}
+
+impl<T, const N: usize> MapCore<T> for ArrayCore<T, N> {
+    type Mapped<U> = ArrayCore<U, N>;
+}
+
+
 /// Just a wrapper around a core with a `PhantomData<T>` field to signal
 /// ownership of `T` (for variance and for the drop checker).
 ///
@@ -336,12 +604,16 @@ pub(crate) struct OwningCore<T, C: Core<T>> {
 }
 
 impl<T, C: Core<T>> OwningCore<T, C> {
-    pub(crate) fn new(core: C) -> Self {
+    pub(crate) const fn new(core: C) -> Self {
         Self {
             core,
             _dummy: PhantomData,
         }
     }
+
+    pub(crate) fn into_inner(self) -> C {
+        self.core
+    }
 }
 
 impl<T, C: Core<T> + fmt::Debug> fmt::Debug for OwningCore<T, C> {
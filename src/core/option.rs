@@ -1,11 +1,14 @@
 use std::{
-    prelude::v1::*,
     fmt,
     hint::unreachable_unchecked,
     ptr,
+    slice,
 };
 
-use super::Core;
+use super::{
+    Core,
+    allocator::{Allocator, Global, Vec},
+};
 
 /// A `Core` implementation that is essentially a `Vec<Option<T>>`.
 ///
@@ -35,7 +38,12 @@ use super::Core;
 /// In both cases, switching the implementation from default to this only makes
 /// sense after you measured that you actually gain performance from it. The
 /// interface of both implementations is exactly the same.
-pub struct OptionCore<T> {
+///
+/// The memory is allocated with the allocator `A`. Unless the
+/// `allocator-api2` feature is enabled, this is always the global allocator.
+/// `Core::new` is only available if `A` implements `Default`; otherwise, use
+/// `new_in`.
+pub struct OptionCore<T, A: Allocator = Global> {
     /// The data and deleted information in one.
     ///
     /// The `len` and `capacity` properties of the vector directly correspond
@@ -62,16 +70,57 @@ pub struct OptionCore<T> {
     /// do not use any methods that would benefit in any way from touching that
     /// memory. Therefore we assume that all slots with indices > len stay
     /// initialized to `None`. A couple of methods rely on that assumption.
-    data: Vec<Option<T>>,
+    data: Vec<Option<T>, A>,
 }
 
-impl<T> Core<T> for OptionCore<T> {
-    fn new() -> Self {
+impl<T, A: Allocator> OptionCore<T, A> {
+    /// Creates an empty instance that will allocate memory with `alloc`.
+    /// Does not allocate memory itself.
+    pub fn new_in(alloc: A) -> Self {
         Self {
-            data: Vec::new(),
+            data: Vec::new_in(alloc),
         }
     }
 
+    /// Returns a reference to the underlying allocator.
+    pub fn allocator(&self) -> &A {
+        self.data.allocator()
+    }
+
+    /// Returns a reference to the slot at index `idx`.
+    ///
+    /// We can't use `Vec::get_unchecked` here, as that requires `idx < len`,
+    /// but we also need to access slots in `len..cap`. Those are always
+    /// initialized to `None` (see the documentation of `data`).
+    ///
+    /// # Formal
+    ///
+    /// **Preconditions**:
+    /// - `idx < self.cap()`
+    unsafe fn slot(&self, idx: usize) -> &Option<T> {
+        &*self.data.as_ptr().add(idx)
+    }
+
+    /// Returns a mutable reference to the slot at index `idx`. See
+    /// [`slot`][OptionCore::slot] for more information.
+    ///
+    /// # Formal
+    ///
+    /// **Preconditions**:
+    /// - `idx < self.cap()`
+    unsafe fn slot_mut(&mut self, idx: usize) -> &mut Option<T> {
+        &mut *self.data.as_mut_ptr().add(idx)
+    }
+}
+
+impl<T, A: Allocator> Core<T> for OptionCore<T, A> {
+    fn new() -> Self
+    where
+        Self: Default,
+    {
+        Self::default()
+    }
+
     fn len(&self) -> usize {
         self.data.len()
     }
@@ -101,7 +150,7 @@ impl<T> Core<T> for OptionCore<T> {
     #[cold]
     unsafe fn realloc(&mut self, new_cap: usize) {
         debug_assert!(new_cap >= self.len());
-        debug_assert!(new_cap <= isize::max_value() as usize);
+        debug_assert!(new_cap <= isize::MAX as usize);
 
         // Do different things depending on whether we shrink or grow.
         let old_cap = self.cap();
@@ -162,26 +211,26 @@ impl<T> Core<T> for OptionCore<T> {
     unsafe fn has_element_at(&self, idx: usize) -> bool {
         debug_assert!(idx < self.cap());
 
-        self.data.get_unchecked(idx).is_some()
+        self.slot(idx).is_some()
     }
 
     unsafe fn insert_at(&mut self, idx: usize, elem: T) {
         debug_assert!(idx < self.cap());
-        debug_assert!(self.has_element_at(idx) == false);
+        debug_assert!(!self.has_element_at(idx));
 
         // We use `ptr::write` instead of a simple assignment here for
         // performance reason. An assignment would try to drop the value on the
         // left hand side. Since we know from our preconditions that this value
         // is in fact `None` and we thus never need to drop it, `ptr::write` is
         // faster.
-        ptr::write(self.data.get_unchecked_mut(idx), Some(elem));
+        ptr::write(self.slot_mut(idx), Some(elem));
     }
 
     unsafe fn remove_at(&mut self, idx: usize) -> T {
         debug_assert!(idx < self.cap());
         debug_assert!(self.has_element_at(idx));
 
-        match self.data.get_unchecked_mut(idx).take() {
+        match self.slot_mut(idx).take() {
             // The precondition guarantees us that the slot is not empty, thus
             // we use this unsafe `unreachable_unchecked` to omit the branch.
             None => unreachable_unchecked(),
@@ -193,7 +242,7 @@ impl<T> Core<T> for OptionCore<T> {
         debug_assert!(idx < self.cap());
         debug_assert!(self.has_element_at(idx));
 
-        match self.data.get_unchecked(idx) {
+        match self.slot(idx) {
             // The precondition guarantees us that the slot is not empty, thus
             // we use this unsafe `unreachable_unchecked` to omit the branch.
             None => unreachable_unchecked(),
@@ -205,7 +254,7 @@ impl<T> Core<T> for OptionCore<T> {
         debug_assert!(idx < self.cap());
         debug_assert!(self.has_element_at(idx));
 
-        match self.data.get_unchecked_mut(idx) {
+        match self.slot_mut(idx) {
             // The precondition guarantees us that the slot is not empty, thus
             // we use this unsafe `unreachable_unchecked` to omit the branch.
             None => unreachable_unchecked(),
@@ -215,23 +264,35 @@ impl<T> Core<T> for OptionCore<T> {
 
     fn clear(&mut self) {
         // We can assume that all existing elements have an index lower than
-        // `len` (this is one of the invariants of the `Core` interface).
-        // Calling `clear` on the `Vec` will drop all remaining elements and
-        // sets the length to 0.
-        self.data.clear();
+        // `len` (this is one of the invariants of the `Core` interface). We
+        // can't just call `clear` on the `Vec`: that would drop the elements
+        // but leave them in the memory, so the slots would not be `None`
+        // anymore. `take` writes `None` before dropping the element.
+        for slot in self.data.iter_mut() {
+            drop(slot.take());
+        }
+        unsafe {
+            self.data.set_len(0);
+        }
     }
 
     unsafe fn swap(&mut self, a: usize, b: usize) {
         // We can't just have two mutable references, so we use `ptr::swap`
         // instead of `mem::swap`. We do not use the slice's `swap` method as
         // that performs bound checks.
-        let pa: *mut _ = self.data.get_unchecked_mut(a);
-        let pb: *mut _ = self.data.get_unchecked_mut(b);
+        let pa = self.data.as_mut_ptr().add(a);
+        let pb = self.data.as_mut_ptr().add(b);
         ptr::swap(pa, pb);
     }
 }
 
-impl<T: Clone> Clone for OptionCore<T> {
+impl<T, A: Allocator + Default> Default for OptionCore<T, A> {
+    fn default() -> Self {
+        Self::new_in(A::default())
+    }
+}
+
+impl<T: Clone, A: Allocator + Clone> Clone for OptionCore<T, A> {
     fn clone(&self) -> Self {
         // Cloning the vector is safe: the `Vec` implementation won't access
         // uninitialized memory. However, simply cloning it would be wrong for
@@ -246,7 +307,9 @@ impl<T: Clone> Clone for OptionCore<T> {
         // to the old value. Both is safe as all the elements that are included
         // and excluded by the "fake length" are `None`.
         let data = unsafe {
-            let mut data_clone = self.data.get_unchecked(0..self.data.capacity()).to_vec();
+            let all_slots = slice::from_raw_parts(self.data.as_ptr(), self.data.capacity());
+            let mut data_clone = Vec::with_capacity_in(all_slots.len(), self.allocator().clone());
+            data_clone.extend_from_slice(all_slots);
             data_clone.set_len(self.data.len());
             data_clone
         };
@@ -255,7 +318,7 @@ impl<T: Clone> Clone for OptionCore<T> {
     }
 }
 
-impl<T> Drop for OptionCore<T> {
+impl<T, A: Allocator> Drop for OptionCore<T, A> {
     fn drop(&mut self) {
         // We don't need to anything! The `Vec` will be dropped which is
         // correct: that will drop all remaining elements but won't touch
@@ -267,10 +330,10 @@ impl<T> Drop for OptionCore<T> {
 
 // This impl is usually not used. `StableVec` has its own impl which doesn't
 // use this one.
-impl<T: fmt::Debug> fmt::Debug for OptionCore<T> {
+impl<T: fmt::Debug, A: Allocator> fmt::Debug for OptionCore<T, A> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_tuple("OptionCore")
-            .field(&self.data)
+            .field(&&self.data[..])
             .finish()
     }
 }
@@ -0,0 +1,340 @@
+use std::{
+    prelude::v1::*,
+    fmt,
+    mem::{self, MaybeUninit},
+    ops::Range,
+    ptr,
+};
+
+use super::Core;
+
+
+/// A `Core` implementation that stores the empty slots as a sorted list of
+/// ranges.
+///
+/// The elements are stored in one buffer like with
+/// [`BitVecCore`][super::BitVecCore]. But instead of one bit per slot, only
+/// the *holes* are recorded: each maximal run of empty slots is stored as one
+/// `Range<usize>`. With `h` being the number of such runs:
+///
+/// - `has_element_at` and all searches for filled or empty slots are
+///   `O(log h)`. In particular, iterating over long runs of filled slots is
+///   very fast.
+/// - Removing or inserting an element might split or merge ranges, which is
+///   `O(h)` in the worst case.
+/// - `clear` is `O(h)` if `T` does not need to be dropped.
+///
+/// This core is a good choice for stable vectors where deletions are rare or
+/// clustered, such that `h` stays small. If elements are deleted at random,
+/// the list of ranges can become a lot larger than a bit vector.
+pub struct RangeSetCore<T> {
+    /// The slots. Its length is the `cap` of the `Core` definition. If a slot
+    /// is empty, the memory at that index is uninitialized.
+    data: Vec<MaybeUninit<T>>,
+
+    /// All empty slots in `0..cap`. The ranges are non-empty, sorted,
+    /// disjoint and never adjacent (adjacent ranges are merged). As all slots
+    /// ≥ `len` are empty, the last range always ends at `cap` if `len < cap`.
+    holes: Vec<Range<usize>>,
+
+    /// The `len`: corresponds to the `len` of the `Core` definition.
+    len: usize,
+}
+
+impl<T> RangeSetCore<T> {
+    /// Creates an empty instance. Does not allocate memory.
+    pub fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            holes: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Returns the ranges of empty slots below `len`, sorted by index.
+    ///
+    /// This is mostly useful to find out how fragmented the stable vector is.
+    pub fn holes(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        let len = self.len;
+        self.holes.iter()
+            .take_while(move |r| r.start < len)
+            .map(move |r| r.start..r.end.min(len))
+    }
+
+    /// Returns the position in `holes` of the first range that ends after
+    /// `idx`. If `idx` is empty, this is the range containing it.
+    fn hole_pos(&self, idx: usize) -> usize {
+        self.holes.partition_point(|r| r.end <= idx)
+    }
+
+    /// Returns the range containing `idx` (if `idx` is empty).
+    fn hole_at(&self, idx: usize) -> Option<&Range<usize>> {
+        self.holes.get(self.hole_pos(idx)).filter(|r| r.start <= idx)
+    }
+
+    /// Marks the slot `idx` as empty, merging with neighboring ranges.
+    /// The slot has to be filled.
+    fn add_hole(&mut self, idx: usize) {
+        // The first range ending at or after `idx`. As `idx` is filled, this
+        // range does not contain `idx`.
+        let pos = self.holes.partition_point(|r| r.end < idx);
+        let joins_left = self.holes.get(pos).is_some_and(|r| r.end == idx);
+        let right = if joins_left { pos + 1 } else { pos };
+        let joins_right = self.holes.get(right).is_some_and(|r| r.start == idx + 1);
+
+        match (joins_left, joins_right) {
+            (true, true) => {
+                self.holes[pos].end = self.holes[right].end;
+                self.holes.remove(right);
+            }
+            (true, false) => self.holes[pos].end = idx + 1,
+            (false, true) => self.holes[right].start = idx,
+            (false, false) => self.holes.insert(pos, idx..idx + 1),
+        }
+    }
+
+    /// Marks the slot `idx` as filled, splitting the range containing it. The
+    /// slot has to be empty.
+    fn remove_hole(&mut self, idx: usize) {
+        let pos = self.hole_pos(idx);
+        let hole = self.holes[pos].clone();
+        debug_assert!(hole.contains(&idx));
+
+        if hole.start == idx && hole.end == idx + 1 {
+            self.holes.remove(pos);
+        } else if hole.start == idx {
+            self.holes[pos].start += 1;
+        } else if hole.end == idx + 1 {
+            self.holes[pos].end -= 1;
+        } else {
+            self.holes[pos].end = idx;
+            self.holes.insert(pos + 1, idx + 1..hole.end);
+        }
+    }
+}
+
+impl<T> Core<T> for RangeSetCore<T> {
+    fn new() -> Self {
+        Self::new()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    unsafe fn set_len(&mut self, new_len: usize) {
+        debug_assert!(new_len <= self.cap());
+        // Other precondition is too expensive to test, even in debug:
+        // ∀ i in `new_len..self.cap()` ⇒ `self.has_element_at(i) == false`
+
+        self.len = new_len;
+    }
+
+    fn cap(&self) -> usize {
+        self.data.len()
+    }
+
+    #[inline(never)]
+    #[cold]
+    unsafe fn realloc(&mut self, new_cap: usize) {
+        debug_assert!(new_cap >= self.len());
+        debug_assert!(new_cap <= isize::MAX as usize);
+
+        let old_cap = self.cap();
+        if new_cap > old_cap {
+            self.data.reserve_exact(new_cap - old_cap);
+            self.data.resize_with(new_cap, MaybeUninit::uninit);
+
+            // All new slots are empty.
+            match self.holes.last_mut() {
+                Some(last) if last.end == old_cap => last.end = new_cap,
+                _ => self.holes.push(old_cap..new_cap),
+            }
+        } else if new_cap < old_cap {
+            // Due to the preconditions, all slots ≥ `new_cap` are empty, so
+            // they are all part of the last range.
+            self.data.truncate(new_cap);
+            self.data.shrink_to_fit();
+
+            let last = self.holes.len() - 1;
+            if self.holes[last].start >= new_cap {
+                self.holes.pop();
+            } else {
+                self.holes[last].end = new_cap;
+            }
+        }
+    }
+
+    unsafe fn has_element_at(&self, idx: usize) -> bool {
+        debug_assert!(idx < self.cap());
+
+        self.hole_at(idx).is_none()
+    }
+
+    unsafe fn insert_at(&mut self, idx: usize, elem: T) {
+        debug_assert!(idx < self.cap());
+        debug_assert!(!self.has_element_at(idx));
+
+        // We first write the value and then update the ranges. If updating
+        // panics (due to OOM), we leak the element, but don't double drop.
+        ptr::write(self.data.get_unchecked_mut(idx).as_mut_ptr(), elem);
+        self.remove_hole(idx);
+    }
+
+    unsafe fn remove_at(&mut self, idx: usize) -> T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        // We first mark the value as deleted and then read the value.
+        // Otherwise, a random panic could lead to a double drop.
+        self.add_hole(idx);
+        ptr::read(self.data.get_unchecked(idx).as_ptr())
+    }
+
+    unsafe fn get_unchecked(&self, idx: usize) -> &T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        &*self.data.get_unchecked(idx).as_ptr()
+    }
+
+    unsafe fn get_unchecked_mut(&mut self, idx: usize) -> &mut T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        &mut *self.data.get_unchecked_mut(idx).as_mut_ptr()
+    }
+
+    fn clear(&mut self) {
+        // Drop all elements run by run. Each run of filled slots lies between
+        // two holes.
+        if mem::needs_drop::<T>() {
+            let mut start = 0;
+            for hole in self.holes.iter().chain(Some(&(self.len..self.len))) {
+                for idx in start..hole.start.min(self.len) {
+                    unsafe {
+                        ptr::drop_in_place(self.data[idx].as_mut_ptr());
+                    }
+                }
+                start = hole.end;
+                if start >= self.len {
+                    break;
+                }
+            }
+        }
+
+        self.holes.clear();
+        if self.cap() > 0 {
+            self.holes.push(0..self.cap());
+        }
+        self.len = 0;
+    }
+
+    unsafe fn first_filled_slot_from(&self, idx: usize) -> Option<usize> {
+        debug_assert!(idx <= self.cap());
+
+        // As ranges are never adjacent, the slot after a range is filled (or
+        // it is `cap`).
+        let out = self.hole_at(idx).map_or(idx, |r| r.end);
+        if out < self.len { Some(out) } else { None }
+    }
+
+    unsafe fn first_filled_slot_below(&self, idx: usize) -> Option<usize> {
+        debug_assert!(idx <= self.cap());
+
+        let last = std::cmp::min(idx, self.len).checked_sub(1)?;
+        match self.hole_at(last) {
+            None => Some(last),
+            Some(r) => r.start.checked_sub(1),
+        }
+    }
+
+    unsafe fn first_empty_slot_from(&self, idx: usize) -> Option<usize> {
+        debug_assert!(idx <= self.cap());
+
+        self.holes.get(self.hole_pos(idx)).map(|r| std::cmp::max(r.start, idx))
+    }
+
+    unsafe fn first_empty_slot_below(&self, idx: usize) -> Option<usize> {
+        debug_assert!(idx <= self.cap());
+
+        let last = idx.checked_sub(1)?;
+        let pos = self.hole_pos(last);
+        match self.holes.get(pos) {
+            Some(r) if r.start <= last => Some(last),
+            // The range at `pos` (if any) starts after `last`, so we look at
+            // the one before it.
+            _ => pos.checked_sub(1).map(|pos| self.holes[pos].end - 1),
+        }
+    }
+
+    unsafe fn swap(&mut self, a: usize, b: usize) {
+        debug_assert!(a < self.cap());
+        debug_assert!(b < self.cap());
+
+        match (self.has_element_at(a), self.has_element_at(b)) {
+            (true, true) => {
+                let pa = self.data.as_mut_ptr().add(a);
+                let pb = self.data.as_mut_ptr().add(b);
+                ptr::swap(pa, pb);
+            }
+            (true, false) => {
+                let elem = self.remove_at(a);
+                self.insert_at(b, elem);
+            }
+            (false, true) => {
+                let elem = self.remove_at(b);
+                self.insert_at(a, elem);
+            }
+            (false, false) => {}
+        }
+    }
+}
+
+impl<T> Default for RangeSetCore<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for RangeSetCore<T> {
+    fn drop(&mut self) {
+        // `data` only contains `MaybeUninit`, so we have to drop the elements
+        // ourselves.
+        self.clear();
+    }
+}
+
+impl<T: Clone> Clone for RangeSetCore<T> {
+    fn clone(&self) -> Self {
+        let mut out = Self::new();
+
+        // We set the length first (all slots of `out` are empty, so that's
+        // fine). That way, if a `clone()` panics, all clones that have been
+        // inserted so far are dropped correctly.
+        unsafe {
+            out.realloc(self.cap());
+            out.set_len(self.len);
+
+            let mut idx = 0;
+            while let Some(next) = self.first_filled_slot_from(idx) {
+                out.insert_at(next, self.get_unchecked(next).clone());
+                idx = next + 1;
+            }
+        }
+
+        out
+    }
+}
+
+// This impl is usually not used. `StableVec` has its own impl which doesn't
+// use this one.
+impl<T> fmt::Debug for RangeSetCore<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RangeSetCore")
+            .field("len", &self.len())
+            .field("cap", &self.cap())
+            .field("holes", &self.holes)
+            .finish()
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    fmt,
+    mem::MaybeUninit,
+    ptr,
+};
+
+use super::Core;
+
+
+const BITS_PER_USIZE: usize = usize::BITS as usize;
+
+/// A `Core` implementation that stores its slots in memory provided by the
+/// caller.
+///
+/// The core is created from two borrowed buffers: one for the elements and
+/// one bitmap (one bit per slot) storing which slots are filled. This is
+/// useful if the stable vector has to live in specific memory, e.g. a DMA
+/// buffer or a slice of a pre-allocated pool. Like
+/// [`ArrayCore`][super::ArrayCore], this core never allocates and is
+/// available without the `alloc` feature.
+///
+/// The capacity is fixed to the length of the element buffer. Trying to
+/// reserve memory for more slots panics; use
+/// [`StableVecFacade::try_push`][crate::StableVecFacade::try_push] to handle
+/// a full buffer gracefully. Dropping the core drops all remaining elements,
+/// but the buffers themselves are never freed (they are only borrowed).
+///
+/// This core does not implement `Default` or `Clone`. Create stable vectors
+/// with it via
+/// [`StableVecFacade::from_slices`][crate::StableVecFacade::from_slices].
+/// `Core::new` returns a core with two empty buffers, i.e. with a capacity
+/// of 0.
+pub struct SliceCore<'a, T> {
+    /// The slots. Its length is the `cap` of the `Core` definition. If a slot
+    /// is empty, the memory at that index is uninitialized.
+    data: &'a mut [MaybeUninit<T>],
+
+    /// Stores whether slots are filled (1) or empty (0). Only the first
+    /// `⌈cap / usize::BITS⌉` words are used.
+    bits: &'a mut [usize],
+
+    /// The `len`: corresponds to the `len` of the `Core` definition.
+    len: usize,
+}
+
+impl<'a, T> SliceCore<'a, T> {
+    /// Creates an empty core that stores its elements in `data` and its
+    /// occupancy bits in `bitmap`. The capacity is `data.len()`.
+    ///
+    /// The existing content of both buffers is ignored: all slots are empty
+    /// afterwards. `bitmap` needs at least `⌈data.len() / usize::BITS⌉`
+    /// words, otherwise this function panics.
+    pub fn new(data: &'a mut [MaybeUninit<T>], bitmap: &'a mut [usize]) -> Self {
+        let words = data.len().div_ceil(BITS_PER_USIZE);
+        assert!(
+            bitmap.len() >= words,
+            "bitmap of length {} too short for {} slots in `SliceCore::new` (needs {} words)",
+            bitmap.len(),
+            data.len(),
+            words,
+        );
+
+        for word in &mut bitmap[..words] {
+            *word = 0;
+        }
+
+        Self {
+            data,
+            bits: bitmap,
+            len: 0,
+        }
+    }
+
+    /// Returns the word index and the mask for the bit of slot `idx`.
+    fn bit_pos(idx: usize) -> (usize, usize) {
+        (idx / BITS_PER_USIZE, 1 << (idx % BITS_PER_USIZE))
+    }
+}
+
+impl<T> Core<T> for SliceCore<'_, T> {
+    fn new() -> Self
+    where
+        Self: Default,
+    {
+        Self::default()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    unsafe fn set_len(&mut self, new_len: usize) {
+        debug_assert!(new_len <= self.cap());
+        // Other precondition is too expensive to test, even in debug:
+        // ∀ i in `new_len..self.cap()` ⇒ `self.has_element_at(i) == false`
+
+        self.len = new_len;
+    }
+
+    fn cap(&self) -> usize {
+        self.data.len()
+    }
+
+    #[inline(never)]
+    #[cold]
+    unsafe fn realloc(&mut self, new_cap: usize) {
+        debug_assert!(new_cap >= self.len());
+        debug_assert!(new_cap <= isize::MAX as usize);
+
+        // Same as `ArrayCore`: we can't change our capacity, so we can only
+        // fulfill requests that already fit.
+        if new_cap > self.cap() {
+            panic!(
+                "capacity overflow in `stable_vec::SliceCore::realloc` (attempt to \
+                    grow to {} slots, but the buffer only has {})",
+                new_cap,
+                self.cap(),
+            );
+        }
+    }
+
+    unsafe fn has_element_at(&self, idx: usize) -> bool {
+        debug_assert!(idx < self.cap());
+
+        let (word, mask) = Self::bit_pos(idx);
+        *self.bits.get_unchecked(word) & mask != 0
+    }
+
+    unsafe fn insert_at(&mut self, idx: usize, elem: T) {
+        debug_assert!(idx < self.cap());
+        debug_assert!(!self.has_element_at(idx));
+
+        // We first write the value and then update the bit to avoid potential
+        // double drops if a random panic appears.
+        ptr::write(self.data.get_unchecked_mut(idx).as_mut_ptr(), elem);
+        let (word, mask) = Self::bit_pos(idx);
+        *self.bits.get_unchecked_mut(word) |= mask;
+    }
+
+    unsafe fn remove_at(&mut self, idx: usize) -> T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        // We first mark the value as deleted and then read the value.
+        // Otherwise, a random panic could lead to a double drop.
+        let (word, mask) = Self::bit_pos(idx);
+        *self.bits.get_unchecked_mut(word) &= !mask;
+        ptr::read(self.data.get_unchecked(idx).as_ptr())
+    }
+
+    unsafe fn get_unchecked(&self, idx: usize) -> &T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        &*self.data.get_unchecked(idx).as_ptr()
+    }
+
+    unsafe fn get_unchecked_mut(&mut self, idx: usize) -> &mut T {
+        debug_assert!(idx < self.cap());
+        debug_assert!(self.has_element_at(idx));
+
+        &mut *self.data.get_unchecked_mut(idx).as_mut_ptr()
+    }
+
+    fn clear(&mut self) {
+        // We can assume that all existing elements have an index lower than
+        // `len` (this is one of the invariants of the `Core` interface).
+        for idx in 0..self.len {
+            unsafe {
+                if self.has_element_at(idx) {
+                    // We reset the bit first, for the same reason as in
+                    // `remove_at`.
+                    drop(self.remove_at(idx));
+                }
+            }
+        }
+        self.len = 0;
+    }
+
+    fn occupancy_words(&self) -> Option<&[usize]> {
+        Some(&self.bits[..self.len.div_ceil(BITS_PER_USIZE)])
+    }
+
+    unsafe fn swap(&mut self, a: usize, b: usize) {
+        debug_assert!(a < self.cap());
+        debug_assert!(b < self.cap());
+
+        match (self.has_element_at(a), self.has_element_at(b)) {
+            (true, true) => {
+                let pa = self.data.as_mut_ptr().add(a);
+                let pb = self.data.as_mut_ptr().add(b);
+                ptr::swap(pa, pb);
+            }
+            (true, false) => {
+                let elem = self.remove_at(a);
+                self.insert_at(b, elem);
+            }
+            (false, true) => {
+                let elem = self.remove_at(b);
+                self.insert_at(a, elem);
+            }
+            (false, false) => {}
+        }
+    }
+}
+
+impl<T> Default for SliceCore<'_, T> {
+    fn default() -> Self {
+        Self {
+            data: &mut [],
+            bits: &mut [],
+            len: 0,
+        }
+    }
+}
+
+impl<T> Drop for SliceCore<'_, T> {
+    fn drop(&mut self) {
+        // The buffers are only borrowed, so dropping all elements is all we
+        // need to do.
+        self.clear();
+    }
+}
+
+// This impl is usually not used. `StableVec` has its own impl which doesn't
+// use this one.
+impl<T> fmt::Debug for SliceCore<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SliceCore")
+            .field("len", &self.len())
+            .field("cap", &self.cap())
+            .finish()
+    }
+}
@@ -0,0 +1,209 @@
+use std::fmt;
+
+use super::{ArrayCore, BitVecCore, Core};
+
+
+/// A `Core` implementation that stores up to `N` slots inline and moves to the
+/// heap when more slots are needed.
+///
+/// As long as the capacity does not exceed `N`, this behaves like
+/// [`ArrayCore`]: no memory is allocated at all. Once more capacity is
+/// requested, all elements are moved into a [`BitVecCore`]; the core is then
+/// said to be *spilled*. Shrinking the capacity to `N` or less (e.g. via
+/// `shrink_to_fit`) moves the elements back into the inline storage.
+///
+/// This is useful if you have many stable vectors which usually only hold a
+/// few elements, as those don't cost any allocation. The price is a branch on
+/// every access and the inline storage, which is part of the core even when
+/// spilled.
+///
+/// Moving between inline and heap storage does not change any indices.
+pub struct SmallCore<T, const N: usize> {
+    repr: Repr<T, N>,
+}
+
+enum Repr<T, const N: usize> {
+    Inline(ArrayCore<T, N>),
+    Heap(BitVecCore<T>),
+}
+
+/// Dispatches to the core that is currently used. `$core` is bound to the
+/// inner core and `$body` is evaluated with it.
+macro_rules! dispatch {
+    ($self:expr, $core:ident => $body:expr) => {
+        match $self {
+            Repr::Inline($core) => $body,
+            Repr::Heap($core) => $body,
+        }
+    };
+}
+
+impl<T, const N: usize> SmallCore<T, N> {
+    /// Creates an empty instance using the inline storage. This is the same as
+    /// `Core::new`, but usable in `const` contexts.
+    pub const fn new() -> Self {
+        Self {
+            repr: Repr::Inline(ArrayCore::new()),
+        }
+    }
+
+    /// Returns `true` if the elements are stored on the heap and `false` if
+    /// they are stored inline.
+    pub fn spilled(&self) -> bool {
+        match self.repr {
+            Repr::Inline(_) => false,
+            Repr::Heap(_) => true,
+        }
+    }
+}
+
+/// Moves all elements from `src` to `dst` (keeping their indices) and sets the
+/// length of `dst` to the one of `src`. Afterwards, `src` is empty and has a
+/// length of 0.
+///
+/// # Formal
+///
+/// **Preconditions**:
+/// - `dst.cap() >= src.len()`
+/// - all slots of `dst` are empty
+pub(super) unsafe fn move_all<T, S: Core<T>, D: Core<T>>(src: &mut S, dst: &mut D) {
+    debug_assert!(dst.cap() >= src.len());
+
+    let len = src.len();
+    dst.set_len(len);
+
+    let mut idx = 0;
+    while let Some(next) = src.first_filled_slot_from(idx) {
+        dst.insert_at(next, src.remove_at(next));
+        idx = next + 1;
+    }
+
+    src.set_len(0);
+}
+
+impl<T, const N: usize> Core<T> for SmallCore<T, N> {
+    fn new() -> Self {
+        Self::new()
+    }
+
+    fn len(&self) -> usize {
+        dispatch!(&self.repr, core => core.len())
+    }
+
+    unsafe fn set_len(&mut self, new_len: usize) {
+        dispatch!(&mut self.repr, core => core.set_len(new_len))
+    }
+
+    fn cap(&self) -> usize {
+        dispatch!(&self.repr, core => core.cap())
+    }
+
+    #[inline(never)]
+    #[cold]
+    unsafe fn realloc(&mut self, new_cap: usize) {
+        debug_assert!(new_cap >= self.len());
+        debug_assert!(new_cap <= isize::MAX as usize);
+
+        match &mut self.repr {
+            // The inline storage is large enough: nothing to do.
+            Repr::Inline(_) if new_cap <= N => {}
+
+            // We have to spill onto the heap.
+            Repr::Inline(inline) => {
+                let mut heap = BitVecCore::new();
+                heap.realloc(new_cap);
+
+                // The `heap` core has at least `new_cap ≥ len` slots, all of
+                // which are empty.
+                move_all(inline, &mut heap);
+                self.repr = Repr::Heap(heap);
+            }
+
+            // The elements fit into the inline storage again, so we move them
+            // back.
+            Repr::Heap(heap) if new_cap <= N => {
+                let mut inline = ArrayCore::new();
+
+                // `inline` has `N ≥ new_cap ≥ len` slots, all of which are
+                // empty.
+                move_all(heap, &mut inline);
+                self.repr = Repr::Inline(inline);
+            }
+
+            Repr::Heap(heap) => heap.realloc(new_cap),
+        }
+    }
+
+    unsafe fn has_element_at(&self, idx: usize) -> bool {
+        dispatch!(&self.repr, core => core.has_element_at(idx))
+    }
+
+    unsafe fn insert_at(&mut self, idx: usize, elem: T) {
+        dispatch!(&mut self.repr, core => core.insert_at(idx, elem))
+    }
+
+    unsafe fn remove_at(&mut self, idx: usize) -> T {
+        dispatch!(&mut self.repr, core => core.remove_at(idx))
+    }
+
+    unsafe fn get_unchecked(&self, idx: usize) -> &T {
+        dispatch!(&self.repr, core => core.get_unchecked(idx))
+    }
+
+    unsafe fn get_unchecked_mut(&mut self, idx: usize) -> &mut T {
+        dispatch!(&mut self.repr, core => core.get_unchecked_mut(idx))
+    }
+
+    fn clear(&mut self) {
+        dispatch!(&mut self.repr, core => core.clear())
+    }
+
+    unsafe fn first_filled_slot_from(&self, idx: usize) -> Option<usize> {
+        dispatch!(&self.repr, core => core.first_filled_slot_from(idx))
+    }
+
+    unsafe fn first_filled_slot_below(&self, idx: usize) -> Option<usize> {
+        dispatch!(&self.repr, core => core.first_filled_slot_below(idx))
+    }
+
+    unsafe fn first_empty_slot_from(&self, idx: usize) -> Option<usize> {
+        dispatch!(&self.repr, core => core.first_empty_slot_from(idx))
+    }
+
+    unsafe fn first_empty_slot_below(&self, idx: usize) -> Option<usize> {
+        dispatch!(&self.repr, core => core.first_empty_slot_below(idx))
+    }
+
+    unsafe fn swap(&mut self, a: usize, b: usize) {
+        dispatch!(&mut self.repr, core => core.swap(a, b))
+    }
+}
+
+impl<T, const N: usize> Default for SmallCore<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone, const N: usize> Clone for SmallCore<T, N> {
+    fn clone(&self) -> Self {
+        let repr = match &self.repr {
+            Repr::Inline(core) => Repr::Inline(core.clone()),
+            Repr::Heap(core) => Repr::Heap(core.clone()),
+        };
+
+        Self { repr }
+    }
+}
+
+// This impl is usually not used. `StableVec` has its own impl which doesn't
+// use this one.
+impl<T, const N: usize> fmt::Debug for SmallCore<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SmallCore")
+            .field("len", &self.len())
+            .field("cap", &self.cap())
+            .field("spilled", &self.spilled())
+            .finish()
+    }
+}
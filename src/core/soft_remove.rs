@@ -0,0 +1,326 @@
+use std::{
+    prelude::v1::*,
+    fmt,
+};
+
+use super::{Core, MapCore};
+
+
+const BITS: usize = usize::BITS as usize;
+
+/// A `Core` wrapper that can hide elements of the inner core `C` without
+/// dropping them ("soft remove").
+///
+/// A soft-removed element stays in its slot of the inner core, but this core
+/// reports that slot as empty. That way, the element is not visible via
+/// `get`, `iter`, `has_element_at` or `num_elements` anymore, but can be
+/// brought back later. Soft-removed elements are tracked with a second bit
+/// per slot. The stable vector methods for this are
+/// [`soft_remove`][crate::StableVecFacade::soft_remove],
+/// [`restore`][crate::StableVecFacade::restore],
+/// [`purge`][crate::StableVecFacade::purge],
+/// [`purge_all`][crate::StableVecFacade::purge_all] and
+/// [`iter_removed`][crate::StableVecFacade::iter_removed].
+///
+/// As a soft-removed slot looks empty, inserting into it drops the
+/// soft-removed element. Compacting the stable vector does the same with all
+/// soft-removed elements. They are also dropped together with the core.
+///
+/// ```
+/// use stable_vec::SoftRemoveStableVec;
+///
+/// let mut sv = SoftRemoveStableVec::from(&['a', 'b', 'c']);
+/// sv.soft_remove(1);
+/// assert_eq!(sv, &['a', 'c'] as &[_]);
+///
+/// sv.restore(1);
+/// assert_eq!(sv, &['a', 'b', 'c'] as &[_]);
+/// ```
+pub struct SoftRemoveCore<C> {
+    inner: C,
+
+    /// One bit per slot up to the capacity of `inner`. A set bit means that
+    /// the slot contains a soft-removed element. Such a slot is always filled
+    /// in `inner`.
+    removed: Vec<usize>,
+
+    /// Number of set bits in `removed`.
+    num_removed: usize,
+}
+
+impl<C> SoftRemoveCore<C> {
+    /// Wraps `inner`. All elements of `inner` are visible.
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            removed: Vec::new(),
+            num_removed: 0,
+        }
+    }
+
+    /// Returns the number of soft-removed elements.
+    pub fn num_removed(&self) -> usize {
+        self.num_removed
+    }
+
+    /// Returns `true` if there is a soft-removed element at index `idx`.
+    pub fn is_removed(&self, idx: usize) -> bool {
+        self.removed.get(idx / BITS).is_some_and(|w| w & (1 << (idx % BITS)) != 0)
+    }
+
+    /// Returns the indices of all soft-removed elements in ascending order.
+    pub fn removed_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.removed.iter().enumerate().flat_map(|(word_idx, &word)| {
+            let mut word = word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+                Some(word_idx * BITS + bit)
+            })
+        })
+    }
+
+    /// Returns a reference to the inner core.
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    fn set_removed(&mut self, idx: usize, removed: bool) {
+        let mask = 1 << (idx % BITS);
+        if removed {
+            // `inner` might have had a capacity before it was wrapped.
+            if idx / BITS >= self.removed.len() {
+                self.removed.resize(idx / BITS + 1, 0);
+            }
+            self.removed[idx / BITS] |= mask;
+            self.num_removed += 1;
+        } else {
+            self.removed[idx / BITS] &= !mask;
+            self.num_removed -= 1;
+        }
+    }
+
+    /// Marks the element at `idx` as soft-removed. Returns `false` if there
+    /// is no visible element at `idx`.
+    pub fn soft_remove<T>(&mut self, idx: usize) -> bool
+    where
+        C: Core<T>,
+    {
+        // `idx < len <= cap` is checked before calling `has_element_at`.
+        if idx >= self.inner.len() || !unsafe { <Self as Core<T>>::has_element_at(self, idx) } {
+            return false;
+        }
+
+        self.set_removed(idx, true);
+        true
+    }
+
+    /// Makes the soft-removed element at `idx` visible again. Returns `false`
+    /// if there is no soft-removed element at `idx`.
+    pub fn restore(&mut self, idx: usize) -> bool {
+        if !self.is_removed(idx) {
+            return false;
+        }
+
+        self.set_removed(idx, false);
+        true
+    }
+
+    /// Removes the soft-removed element at `idx` from the inner core and
+    /// returns it, or returns `None` if there is no soft-removed element at
+    /// `idx`.
+    pub fn purge<T>(&mut self, idx: usize) -> Option<T>
+    where
+        C: Core<T>,
+    {
+        if !self.is_removed(idx) {
+            return None;
+        }
+
+        self.set_removed(idx, false);
+
+        // Soft-removed slots are always filled in `inner`.
+        Some(unsafe { self.inner.remove_at(idx) })
+    }
+
+    /// Drops all soft-removed elements with an index `>= start`.
+    fn purge_from<T>(&mut self, start: usize)
+    where
+        C: Core<T>,
+    {
+        if self.num_removed == 0 {
+            return;
+        }
+
+        let removed = self.removed_indices()
+            .skip_while(|&idx| idx < start)
+            .collect::<Vec<_>>();
+        for idx in removed {
+            drop(self.purge(idx));
+        }
+    }
+
+    /// Drops all soft-removed elements.
+    pub fn purge_all<T>(&mut self)
+    where
+        C: Core<T>,
+    {
+        self.purge_from(0);
+    }
+}
+
+impl<T, C: Core<T>> Core<T> for SoftRemoveCore<C> {
+    fn new() -> Self
+    where
+        Self: Default,
+    {
+        Self::default()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    unsafe fn set_len(&mut self, new_len: usize) {
+        // Soft-removed slots look empty, so there might be some at or after
+        // `new_len`. They have to be dropped to keep `inner` valid.
+        self.purge_from(new_len);
+        self.inner.set_len(new_len)
+    }
+
+    fn cap(&self) -> usize {
+        self.inner.cap()
+    }
+
+    unsafe fn realloc(&mut self, new_cap: usize) {
+        self.inner.realloc(new_cap);
+
+        // Slots `>= len` are never soft-removed, so shrinking only cuts off 0
+        // bits.
+        self.removed.resize(new_cap.div_ceil(BITS), 0);
+    }
+
+    unsafe fn has_element_at(&self, idx: usize) -> bool {
+        self.inner.has_element_at(idx) && !self.is_removed(idx)
+    }
+
+    unsafe fn insert_at(&mut self, idx: usize, elem: T) {
+        // The slot might only look empty.
+        drop(self.purge(idx));
+        self.inner.insert_at(idx, elem)
+    }
+
+    unsafe fn remove_at(&mut self, idx: usize) -> T {
+        self.inner.remove_at(idx)
+    }
+
+    unsafe fn get_unchecked(&self, idx: usize) -> &T {
+        self.inner.get_unchecked(idx)
+    }
+
+    unsafe fn get_unchecked_mut(&mut self, idx: usize) -> &mut T {
+        self.inner.get_unchecked_mut(idx)
+    }
+
+    fn clear(&mut self) {
+        self.inner.clear();
+        self.removed.iter_mut().for_each(|w| *w = 0);
+        self.num_removed = 0;
+    }
+
+    unsafe fn first_filled_slot_from(&self, idx: usize) -> Option<usize> {
+        if self.num_removed == 0 {
+            return self.inner.first_filled_slot_from(idx);
+        }
+
+        let mut idx = idx;
+        while let Some(j) = self.inner.first_filled_slot_from(idx) {
+            if !self.is_removed(j) {
+                return Some(j);
+            }
+            idx = j + 1;
+        }
+        None
+    }
+
+    unsafe fn first_filled_slot_below(&self, idx: usize) -> Option<usize> {
+        if self.num_removed == 0 {
+            return self.inner.first_filled_slot_below(idx);
+        }
+
+        let mut idx = idx;
+        while let Some(j) = self.inner.first_filled_slot_below(idx) {
+            if !self.is_removed(j) {
+                return Some(j);
+            }
+            idx = j;
+        }
+        None
+    }
+
+    unsafe fn first_empty_slot_from(&self, idx: usize) -> Option<usize> {
+        if self.num_removed == 0 {
+            return self.inner.first_empty_slot_from(idx);
+        }
+
+        (idx..self.cap()).find(|&i| !self.has_element_at(i))
+    }
+
+    unsafe fn first_empty_slot_below(&self, idx: usize) -> Option<usize> {
+        if self.num_removed == 0 {
+            return self.inner.first_empty_slot_below(idx);
+        }
+
+        (0..idx).rev().find(|&i| !self.has_element_at(i))
+    }
+
+    fn occupancy_words(&self) -> Option<&[usize]> {
+        if self.num_removed == 0 {
+            self.inner.occupancy_words()
+        } else {
+            None
+        }
+    }
+
+    unsafe fn swap(&mut self, a: usize, b: usize) {
+        self.inner.swap(a, b);
+
+        let (removed_a, removed_b) = (self.is_removed(a), self.is_removed(b));
+        if removed_a != removed_b {
+            self.set_removed(a, removed_b);
+            self.set_removed(b, removed_a);
+        }
+    }
+}
+
+impl<T, C: MapCore<T>> MapCore<T> for SoftRemoveCore<C> {
+    type Mapped<U> = SoftRemoveCore<C::Mapped<U>>;
+}
+
+impl<C: Default> Default for SoftRemoveCore<C> {
+    fn default() -> Self {
+        Self::new(C::default())
+    }
+}
+
+impl<C: Clone> Clone for SoftRemoveCore<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            removed: self.removed.clone(),
+            num_removed: self.num_removed,
+        }
+    }
+}
+
+impl<C: fmt::Debug> fmt::Debug for SoftRemoveCore<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SoftRemoveCore")
+            .field("inner", &self.inner)
+            .field("num_removed", &self.num_removed)
+            .finish()
+    }
+}
@@ -0,0 +1,317 @@
+use std::{
+    prelude::v1::*,
+    fmt,
+};
+
+use super::{Core, MapCore};
+
+
+const BITS: usize = usize::BITS as usize;
+
+/// The changes of a stable vector since the last sync, as returned by
+/// [`take_changes`][crate::StableVecFacade::take_changes]. All index lists
+/// are sorted in ascending order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    /// Indices that were empty at the last sync and contain an element now.
+    pub inserted: Vec<usize>,
+
+    /// Indices that contained an element at the last sync and are empty now.
+    pub removed: Vec<usize>,
+
+    /// Indices that contained an element at the last sync and still do, but
+    /// were mutably accessed or swapped in the meantime. The element might
+    /// not actually be different.
+    pub modified: Vec<usize>,
+}
+
+impl Changes {
+    /// Returns `true` if there are no changes.
+    pub fn is_empty(&self) -> bool {
+        self.inserted.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// A `Core` wrapper that tracks which slots of the inner core `C` changed
+/// ("dirty tracking").
+///
+/// A slot is marked as dirty whenever an element is inserted into or removed
+/// from it, when it is swapped with another slot and when its element is
+/// accessed mutably (e.g. via `get_mut`, `iter_mut`, `values_mut` or
+/// `IndexMut`). In addition to the dirty bit, this core remembers for each
+/// slot whether it was filled at the last sync, so that
+/// [`take_changes`][crate::StableVecFacade::take_changes] can tell apart
+/// inserted, removed and modified slots. The other stable vector methods for
+/// this are [`dirty_indices`][crate::StableVecFacade::dirty_indices] and
+/// [`clear_dirty`][crate::StableVecFacade::clear_dirty].
+///
+/// ```
+/// use stable_vec::{TrackedStableVec, core::Changes};
+///
+/// let mut sv = TrackedStableVec::from(&[1, 2, 3]);
+/// sv.clear_dirty();
+///
+/// sv.remove(0);
+/// sv[1] = 20;
+/// sv.push(4);
+///
+/// assert_eq!(sv.take_changes(), Changes {
+///     inserted: vec![3],
+///     removed: vec![0],
+///     modified: vec![1],
+/// });
+/// assert!(sv.take_changes().is_empty());
+/// ```
+pub struct TrackingCore<C> {
+    inner: C,
+
+    /// One bit per slot. A set bit means that the slot changed since the last
+    /// sync.
+    dirty: Vec<usize>,
+
+    /// One bit per slot. A set bit means that the slot was filled at the last
+    /// sync. Only meaningful for dirty slots.
+    synced: Vec<usize>,
+}
+
+impl<C> TrackingCore<C> {
+    /// Wraps `inner`. The current state of `inner` counts as synced, i.e. no
+    /// slots are dirty.
+    pub fn new<T>(inner: C) -> Self
+    where
+        C: Core<T>,
+    {
+        let mut out = Self {
+            dirty: vec![0; inner.cap().div_ceil(BITS)],
+            synced: vec![0; inner.cap().div_ceil(BITS)],
+            inner,
+        };
+
+        // All indices are `< len <= cap`.
+        for idx in 0..out.inner.len() {
+            if unsafe { out.inner.has_element_at(idx) } {
+                set_bit(&mut out.synced, idx, true);
+            }
+        }
+        out
+    }
+
+    /// Returns `true` if the slot at index `idx` changed since the last sync.
+    pub fn is_dirty(&self, idx: usize) -> bool {
+        get_bit(&self.dirty, idx)
+    }
+
+    /// Returns the indices of all dirty slots in ascending order. This can
+    /// include indices `>= len` and even `>= cap` of elements that were
+    /// removed since the last sync.
+    pub fn dirty_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.dirty.iter().enumerate().flat_map(|(word_idx, &word)| {
+            let mut word = word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+                Some(word_idx * BITS + bit)
+            })
+        })
+    }
+
+    /// Returns all changes since the last sync and marks the current state as
+    /// synced.
+    pub fn take_changes<T>(&mut self) -> Changes
+    where
+        C: Core<T>,
+    {
+        let mut changes = Changes::default();
+        self.sync(|idx, was_filled, is_filled| {
+            match (was_filled, is_filled) {
+                (false, true) => changes.inserted.push(idx),
+                (true, false) => changes.removed.push(idx),
+                (true, true) => changes.modified.push(idx),
+                (false, false) => {}
+            }
+        });
+        changes
+    }
+
+    /// Marks the current state as synced without collecting the changes.
+    pub fn clear_dirty<T>(&mut self)
+    where
+        C: Core<T>,
+    {
+        self.sync(|_, _, _| {});
+    }
+
+    /// Returns a reference to the inner core.
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /// Calls `f(idx, was_filled, is_filled)` for each dirty slot, clears all
+    /// dirty bits and updates the synced state.
+    fn sync<T>(&mut self, mut f: impl FnMut(usize, bool, bool))
+    where
+        C: Core<T>,
+    {
+        for word_idx in 0..self.dirty.len() {
+            let mut word = self.dirty[word_idx];
+            while word != 0 {
+                let idx = word_idx * BITS + word.trailing_zeros() as usize;
+                word &= word - 1;
+
+                // `has_element_at` requires `idx < cap`.
+                let is_filled = idx < self.inner.cap() && unsafe { self.inner.has_element_at(idx) };
+                f(idx, get_bit(&self.synced, idx), is_filled);
+                set_bit(&mut self.synced, idx, is_filled);
+            }
+            self.dirty[word_idx] = 0;
+        }
+    }
+
+    fn mark(&mut self, idx: usize) {
+        set_bit(&mut self.dirty, idx, true);
+    }
+}
+
+fn get_bit(words: &[usize], idx: usize) -> bool {
+    words.get(idx / BITS).is_some_and(|w| w & (1 << (idx % BITS)) != 0)
+}
+
+fn set_bit(words: &mut Vec<usize>, idx: usize, value: bool) {
+    if idx / BITS >= words.len() {
+        words.resize(idx / BITS + 1, 0);
+    }
+    if value {
+        words[idx / BITS] |= 1 << (idx % BITS);
+    } else {
+        words[idx / BITS] &= !(1 << (idx % BITS));
+    }
+}
+
+impl<T, C: Core<T>> Core<T> for TrackingCore<C> {
+    fn new() -> Self
+    where
+        Self: Default,
+    {
+        Self::default()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    unsafe fn set_len(&mut self, new_len: usize) {
+        self.inner.set_len(new_len)
+    }
+
+    fn cap(&self) -> usize {
+        self.inner.cap()
+    }
+
+    unsafe fn realloc(&mut self, new_cap: usize) {
+        self.inner.realloc(new_cap);
+
+        // The bit vectors are never shrunk: slots beyond the new capacity can
+        // still have to be reported as removed.
+        let words = new_cap.div_ceil(BITS);
+        if words > self.dirty.len() {
+            self.dirty.resize(words, 0);
+            self.synced.resize(words, 0);
+        }
+    }
+
+    unsafe fn has_element_at(&self, idx: usize) -> bool {
+        self.inner.has_element_at(idx)
+    }
+
+    unsafe fn insert_at(&mut self, idx: usize, elem: T) {
+        self.mark(idx);
+        self.inner.insert_at(idx, elem)
+    }
+
+    unsafe fn remove_at(&mut self, idx: usize) -> T {
+        self.mark(idx);
+        self.inner.remove_at(idx)
+    }
+
+    unsafe fn get_unchecked(&self, idx: usize) -> &T {
+        self.inner.get_unchecked(idx)
+    }
+
+    unsafe fn get_unchecked_mut(&mut self, idx: usize) -> &mut T {
+        self.mark(idx);
+        self.inner.get_unchecked_mut(idx)
+    }
+
+    fn clear(&mut self) {
+        // All indices are `< len <= cap`.
+        for idx in 0..self.inner.len() {
+            if unsafe { self.inner.has_element_at(idx) } {
+                self.mark(idx);
+            }
+        }
+        self.inner.clear()
+    }
+
+    unsafe fn first_filled_slot_from(&self, idx: usize) -> Option<usize> {
+        self.inner.first_filled_slot_from(idx)
+    }
+
+    unsafe fn first_filled_slot_below(&self, idx: usize) -> Option<usize> {
+        self.inner.first_filled_slot_below(idx)
+    }
+
+    unsafe fn first_empty_slot_from(&self, idx: usize) -> Option<usize> {
+        self.inner.first_empty_slot_from(idx)
+    }
+
+    unsafe fn first_empty_slot_below(&self, idx: usize) -> Option<usize> {
+        self.inner.first_empty_slot_below(idx)
+    }
+
+    fn occupancy_words(&self) -> Option<&[usize]> {
+        self.inner.occupancy_words()
+    }
+
+    unsafe fn swap(&mut self, a: usize, b: usize) {
+        if a != b && (self.inner.has_element_at(a) || self.inner.has_element_at(b)) {
+            self.mark(a);
+            self.mark(b);
+        }
+        self.inner.swap(a, b)
+    }
+}
+
+impl<T, C: MapCore<T>> MapCore<T> for TrackingCore<C> {
+    type Mapped<U> = TrackingCore<C::Mapped<U>>;
+}
+
+impl<C: Default> Default for TrackingCore<C> {
+    fn default() -> Self {
+        Self {
+            inner: C::default(),
+            dirty: Vec::new(),
+            synced: Vec::new(),
+        }
+    }
+}
+
+impl<C: Clone> Clone for TrackingCore<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            dirty: self.dirty.clone(),
+            synced: self.synced.clone(),
+        }
+    }
+}
+
+impl<C: fmt::Debug> fmt::Debug for TrackingCore<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TrackingCore")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
@@ -3,8 +3,10 @@
 //! This is in its own module to not pollute the top-level namespace.
 
 use std::{
+    cmp,
     iter::FusedIterator,
     ops::Range,
+    slice,
 };
 
 use crate::{
@@ -136,26 +138,45 @@ impl<T, C: Core<T>> FusedIterator for IterMut<'_, T, C> {}
 /// Use the method [`StableVecFacade::values`] to obtain an iterator of this
 /// kind.
 #[derive(Clone, Debug)]
-pub struct Values<'a, T, C: Core<T>>(Indices<'a, T, C>);
+pub struct Values<'a, T, C: Core<T>>(ValuesRepr<'a, T, C>);
+
+#[derive(Clone, Debug)]
+enum ValuesRepr<'a, T, C: Core<T>> {
+    Indices(Indices<'a, T, C>),
+
+    /// The core returned `Some` from `values_in_order`.
+    Slice(slice::Iter<'a, T>),
+}
 
 impl<'a, T, C: Core<T>> Values<'a, T, C> {
     pub(crate) fn new(sv: &'a StableVecFacade<T, C>) -> Self {
-        Self(Indices::new(sv))
+        match sv.core.values_in_order() {
+            Some(values) => Self(ValuesRepr::Slice(values.iter())),
+            None => Self(ValuesRepr::Indices(Indices::new(sv))),
+        }
     }
 }
 
 impl<'a, T, C: Core<T>> Iterator for Values<'a, T, C> {
     type Item = &'a T;
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next().map(|idx| unsafe { self.0.core.get_unchecked(idx) })
+        match &mut self.0 {
+            ValuesRepr::Indices(it) => {
+                it.next().map(|idx| unsafe { it.core.get_unchecked(idx) })
+            }
+            ValuesRepr::Slice(it) => it.next(),
+        }
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.0.size_hint()
+        match &self.0 {
+            ValuesRepr::Indices(it) => it.size_hint(),
+            ValuesRepr::Slice(it) => it.size_hint(),
+        }
     }
 
     fn count(self) -> usize {
-        self.0.count()
+        self.len()
     }
 
     fn last(mut self) -> Option<Self::Item> {
@@ -165,13 +186,21 @@ impl<'a, T, C: Core<T>> Iterator for Values<'a, T, C> {
 
 impl<T, C: Core<T>> DoubleEndedIterator for Values<'_, T, C> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        self.0.next_back().map(|idx| unsafe { self.0.core.get_unchecked(idx) })
+        match &mut self.0 {
+            ValuesRepr::Indices(it) => {
+                it.next_back().map(|idx| unsafe { it.core.get_unchecked(idx) })
+            }
+            ValuesRepr::Slice(it) => it.next_back(),
+        }
     }
 }
 
 impl<T, C: Core<T>> ExactSizeIterator for Values<'_, T, C> {
     fn len(&self) -> usize {
-        self.0.len()
+        match &self.0 {
+            ValuesRepr::Indices(it) => it.len(),
+            ValuesRepr::Slice(it) => it.len(),
+        }
     }
 }
 
@@ -183,26 +212,47 @@ impl<T, C: Core<T>> FusedIterator for Values<'_, T, C> {}
 /// Use the method [`StableVecFacade::values_mut`] to obtain an iterator of
 /// this kind.
 #[derive(Debug)]
-pub struct ValuesMut<'a, T, C: Core<T>>(IterMut<'a, T, C>);
+pub struct ValuesMut<'a, T, C: Core<T>>(ValuesMutRepr<'a, T, C>);
+
+#[derive(Debug)]
+enum ValuesMutRepr<'a, T, C: Core<T>> {
+    IterMut(IterMut<'a, T, C>),
+
+    /// The core returned `Some` from `values_in_order_mut`.
+    Slice(slice::IterMut<'a, T>),
+}
 
 impl<'a, T, C: Core<T>> ValuesMut<'a, T, C> {
     pub(crate) fn new(sv: &'a mut StableVecFacade<T, C>) -> Self {
-        Self(IterMut::new(sv))
+        // We can't return the slice from within the `match`, as the borrow
+        // checker would then consider `sv` borrowed in the `None` arm, too.
+        if sv.core.values_in_order_mut().is_some() {
+            let values = sv.core.values_in_order_mut().unwrap();
+            Self(ValuesMutRepr::Slice(values.iter_mut()))
+        } else {
+            Self(ValuesMutRepr::IterMut(IterMut::new(sv)))
+        }
     }
 }
 
 impl<'a, T, C: Core<T>> Iterator for ValuesMut<'a, T, C> {
     type Item = &'a mut T;
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next().map(|(_, r)| r)
+        match &mut self.0 {
+            ValuesMutRepr::IterMut(it) => it.next().map(|(_, r)| r),
+            ValuesMutRepr::Slice(it) => it.next(),
+        }
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.0.size_hint()
+        match &self.0 {
+            ValuesMutRepr::IterMut(it) => it.size_hint(),
+            ValuesMutRepr::Slice(it) => it.size_hint(),
+        }
     }
 
     fn count(self) -> usize {
-        self.0.count()
+        self.len()
     }
 
     fn last(mut self) -> Option<Self::Item> {
@@ -212,13 +262,19 @@ impl<'a, T, C: Core<T>> Iterator for ValuesMut<'a, T, C> {
 
 impl<T, C: Core<T>> DoubleEndedIterator for ValuesMut<'_, T, C> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        self.0.next_back().map(|(_, r)| r)
+        match &mut self.0 {
+            ValuesMutRepr::IterMut(it) => it.next_back().map(|(_, r)| r),
+            ValuesMutRepr::Slice(it) => it.next_back(),
+        }
     }
 }
 
 impl<T, C: Core<T>> ExactSizeIterator for ValuesMut<'_, T, C> {
     fn len(&self) -> usize {
-        self.0.len()
+        match &self.0 {
+            ValuesMutRepr::IterMut(it) => it.len(),
+            ValuesMutRepr::Slice(it) => it.len(),
+        }
     }
 }
 
@@ -340,6 +396,281 @@ impl<T, C: Core<T>> ExactSizeIterator for Indices<'_, T, C> {
 impl<T, C: Core<T>> FusedIterator for Indices<'_, T, C> {}
 
 
+/// Which slots a join iterator visits.
+#[derive(Clone, Copy, Debug)]
+enum JoinKind {
+    /// Slots filled in both stable vectors.
+    Inner,
+    /// Slots filled in the left stable vector.
+    Left,
+    /// Slots filled in at least one of the stable vectors.
+    Outer,
+}
+
+/// The position of a join iterator and the logic to find the next index.
+#[derive(Clone, Debug)]
+struct JoinCursor {
+    kind: JoinKind,
+    pos: usize,
+    end: usize,
+}
+
+impl JoinCursor {
+    fn new<A, B, Ca: Core<A>, Cb: Core<B>>(kind: JoinKind, a: &Ca, b: &Cb) -> Self {
+        let end = match kind {
+            JoinKind::Inner => cmp::min(a.len(), b.len()),
+            JoinKind::Left => a.len(),
+            JoinKind::Outer => cmp::max(a.len(), b.len()),
+        };
+
+        Self { kind, pos: 0, end }
+    }
+
+    /// Returns the next index `≥ self.pos` that should be visited and
+    /// advances `self.pos` past it.
+    fn next<A, B, Ca: Core<A>, Cb: Core<B>>(&mut self, a: &Ca, b: &Cb) -> Option<usize> {
+        let out = match (a.occupancy_words(), b.occupancy_words()) {
+            (Some(wa), Some(wb)) => self.next_by_words(wa, wb),
+            _ => self.next_by_slots(a, b),
+        };
+
+        if let Some(idx) = out {
+            self.pos = idx + 1;
+        }
+        out
+    }
+
+    /// Finds the next index by combining whole words of both bit vectors.
+    fn next_by_words(&mut self, wa: &[usize], wb: &[usize]) -> Option<usize> {
+        const BITS: usize = usize::BITS as usize;
+
+        while self.pos < self.end {
+            let w = self.pos / BITS;
+            let a = wa.get(w).copied().unwrap_or(0);
+            let b = wb.get(w).copied().unwrap_or(0);
+            let word = match self.kind {
+                JoinKind::Inner => a & b,
+                JoinKind::Left => a,
+                JoinKind::Outer => a | b,
+            };
+
+            let word = word >> (self.pos % BITS);
+            if word != 0 {
+                let idx = self.pos + word.trailing_zeros() as usize;
+                return if idx < self.end { Some(idx) } else { None };
+            }
+
+            self.pos = (w + 1) * BITS;
+        }
+
+        None
+    }
+
+    /// Finds the next index with the search methods of both cores.
+    fn next_by_slots<A, B, Ca: Core<A>, Cb: Core<B>>(&mut self, a: &Ca, b: &Cb) -> Option<usize> {
+        // Searches for the next filled slot `≥ pos` below `len` in `core`.
+        // This is fine: `pos < len <= cap` when calling the search method.
+        fn filled_from<T, C: Core<T>>(core: &C, pos: usize) -> Option<usize> {
+            if pos < core.len() {
+                unsafe { core.first_filled_slot_from(pos) }.filter(|&idx| idx < core.len())
+            } else {
+                None
+            }
+        }
+
+        if self.pos >= self.end {
+            return None;
+        }
+
+        match self.kind {
+            JoinKind::Inner => {
+                // Leapfrog: jump to the next filled slot in `a`, then to the
+                // next filled one in `b` starting there and so on, until both
+                // agree.
+                let mut pos = self.pos;
+                loop {
+                    let i = filled_from(a, pos).filter(|&i| i < self.end)?;
+                    let j = filled_from(b, i).filter(|&j| j < self.end)?;
+                    if i == j {
+                        return Some(i);
+                    }
+                    pos = j;
+                }
+            }
+            JoinKind::Left => filled_from(a, self.pos),
+            JoinKind::Outer => {
+                match (filled_from(a, self.pos), filled_from(b, self.pos)) {
+                    (Some(i), Some(j)) => Some(cmp::min(i, j)),
+                    (i, j) => i.or(j),
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the elements of two stable vectors at indices where both
+/// have an element.
+///
+/// Use the method [`StableVecFacade::join`] to obtain an iterator of this
+/// kind.
+#[derive(Clone, Debug)]
+pub struct Join<'a, A, B, Ca: Core<A>, Cb: Core<B>> {
+    a: &'a OwningCore<A, Ca>,
+    b: &'a OwningCore<B, Cb>,
+    cursor: JoinCursor,
+}
+
+impl<'a, A, B, Ca: Core<A>, Cb: Core<B>> Join<'a, A, B, Ca, Cb> {
+    pub(crate) fn new(a: &'a StableVecFacade<A, Ca>, b: &'a StableVecFacade<B, Cb>) -> Self {
+        Self {
+            cursor: JoinCursor::new(JoinKind::Inner, &*a.core, &*b.core),
+            a: &a.core,
+            b: &b.core,
+        }
+    }
+}
+
+impl<'a, A, B, Ca: Core<A>, Cb: Core<B>> Iterator for Join<'a, A, B, Ca, Cb> {
+    type Item = (usize, &'a A, &'a B);
+    fn next(&mut self) -> Option<Self::Item> {
+        let (a, b) = (&**self.a, &**self.b);
+
+        // The cursor only returns indices of slots filled in both cores.
+        self.cursor.next(a, b)
+            .map(|idx| unsafe { (idx, a.get_unchecked(idx), b.get_unchecked(idx)) })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.cursor.end.saturating_sub(self.cursor.pos)))
+    }
+}
+
+impl<A, B, Ca: Core<A>, Cb: Core<B>> FusedIterator for Join<'_, A, B, Ca, Cb> {}
+
+
+/// Iterator over mutable references to the elements of two stable vectors at
+/// indices where both have an element.
+///
+/// Use the method [`StableVecFacade::join_mut`] to obtain an iterator of this
+/// kind.
+#[derive(Debug)]
+pub struct JoinMut<'a, A, B, Ca: Core<A>, Cb: Core<B>> {
+    a: &'a mut OwningCore<A, Ca>,
+    b: &'a mut OwningCore<B, Cb>,
+    cursor: JoinCursor,
+}
+
+impl<'a, A, B, Ca: Core<A>, Cb: Core<B>> JoinMut<'a, A, B, Ca, Cb> {
+    pub(crate) fn new(
+        a: &'a mut StableVecFacade<A, Ca>,
+        b: &'a mut StableVecFacade<B, Cb>,
+    ) -> Self {
+        Self {
+            cursor: JoinCursor::new(JoinKind::Inner, &*a.core, &*b.core),
+            a: &mut a.core,
+            b: &mut b.core,
+        }
+    }
+}
+
+impl<'a, A, B, Ca: Core<A>, Cb: Core<B>> Iterator for JoinMut<'a, A, B, Ca, Cb> {
+    type Item = (usize, &'a mut A, &'a mut B);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.next(&**self.a, &**self.b).map(|idx| {
+            // Same as in `IterMut::next`: the cursor never returns the same
+            // index twice, so extending the lifetimes does not lead to
+            // mutable aliases.
+            let a = unsafe { &mut *(self.a.get_unchecked_mut(idx) as *mut A) };
+            let b = unsafe { &mut *(self.b.get_unchecked_mut(idx) as *mut B) };
+            (idx, a, b)
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.cursor.end.saturating_sub(self.cursor.pos)))
+    }
+}
+
+impl<A, B, Ca: Core<A>, Cb: Core<B>> FusedIterator for JoinMut<'_, A, B, Ca, Cb> {}
+
+
+/// Iterator over the elements of a stable vector together with the elements
+/// at the same indices in another stable vector, if any.
+///
+/// Use the method [`StableVecFacade::left_join`] to obtain an iterator of
+/// this kind.
+#[derive(Clone, Debug)]
+pub struct LeftJoin<'a, A, B, Ca: Core<A>, Cb: Core<B>> {
+    a: &'a OwningCore<A, Ca>,
+    b: &'a StableVecFacade<B, Cb>,
+    cursor: JoinCursor,
+}
+
+impl<'a, A, B, Ca: Core<A>, Cb: Core<B>> LeftJoin<'a, A, B, Ca, Cb> {
+    pub(crate) fn new(a: &'a StableVecFacade<A, Ca>, b: &'a StableVecFacade<B, Cb>) -> Self {
+        Self {
+            cursor: JoinCursor::new(JoinKind::Left, &*a.core, &*b.core),
+            a: &a.core,
+            b,
+        }
+    }
+}
+
+impl<'a, A, B, Ca: Core<A>, Cb: Core<B>> Iterator for LeftJoin<'a, A, B, Ca, Cb> {
+    type Item = (usize, &'a A, Option<&'a B>);
+    fn next(&mut self) -> Option<Self::Item> {
+        let (a, b) = (&**self.a, self.b);
+
+        // The cursor only returns indices of filled slots in `a`.
+        self.cursor.next(a, &*b.core)
+            .map(|idx| (idx, unsafe { a.get_unchecked(idx) }, b.get(idx)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.cursor.end.saturating_sub(self.cursor.pos)))
+    }
+}
+
+impl<A, B, Ca: Core<A>, Cb: Core<B>> FusedIterator for LeftJoin<'_, A, B, Ca, Cb> {}
+
+
+/// Iterator over the elements of two stable vectors at indices where at
+/// least one of them has an element.
+///
+/// Use the method [`StableVecFacade::outer_join`] to obtain an iterator of
+/// this kind.
+#[derive(Clone, Debug)]
+pub struct OuterJoin<'a, A, B, Ca: Core<A>, Cb: Core<B>> {
+    a: &'a StableVecFacade<A, Ca>,
+    b: &'a StableVecFacade<B, Cb>,
+    cursor: JoinCursor,
+}
+
+impl<'a, A, B, Ca: Core<A>, Cb: Core<B>> OuterJoin<'a, A, B, Ca, Cb> {
+    pub(crate) fn new(a: &'a StableVecFacade<A, Ca>, b: &'a StableVecFacade<B, Cb>) -> Self {
+        Self {
+            cursor: JoinCursor::new(JoinKind::Outer, &*a.core, &*b.core),
+            a,
+            b,
+        }
+    }
+}
+
+impl<'a, A, B, Ca: Core<A>, Cb: Core<B>> Iterator for OuterJoin<'a, A, B, Ca, Cb> {
+    type Item = (usize, Option<&'a A>, Option<&'a B>);
+    fn next(&mut self) -> Option<Self::Item> {
+        let (a, b) = (self.a, self.b);
+        self.cursor.next(&*a.core, &*b.core).map(|idx| (idx, a.get(idx), b.get(idx)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.cursor.end.saturating_sub(self.cursor.pos)))
+    }
+}
+
+impl<A, B, Ca: Core<A>, Cb: Core<B>> FusedIterator for OuterJoin<'_, A, B, Ca, Cb> {}
+
+
 /// The actual logic for all `next()` iterator methods.
 fn next<T, C: Core<T>>(
     count: &mut usize,
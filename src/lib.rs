@@ -6,7 +6,18 @@
 //! core implementation. To use a pre-configured stable vector, use
 //! [`StableVec`].
 //!
-//! This crate uses `#![no_std]` but requires the `alloc` crate.
+//! This crate uses `#![no_std]`. By default, it requires the `alloc` crate,
+//! but that can be disabled by turning off the `alloc` feature (which is
+//! enabled by default). In that case, only the allocation-free
+//! [`ArrayCore`] and [`SliceCore`] are available.
+//!
+//! Further optional features:
+//! - `allocator-api2`: lets the allocating cores use custom allocators (see
+//!   `StableVecFacade::new_in`).
+//! - `mmap`: adds `MmapCore`, which stores slots in a memory-mapped file
+//!   (Unix only, requires `std`).
+//! - `test-suite`: adds the `core::conformance` module with a test suite for
+//!   your own `Core` implementations (requires `std`).
 //!
 //!
 //! # Why?
@@ -96,14 +107,31 @@
 //!
 
 #![deny(missing_debug_implementations)]
-#![deny(broken_intra_doc_links)]
+#![deny(rustdoc::broken_intra_doc_links)]
+
+// Many docs link to the default stable vector and the allocating cores, which
+// don't exist without the `alloc` feature.
+#![cfg_attr(not(feature = "alloc"), allow(rustdoc::broken_intra_doc_links))]
+
+// Unsafe methods in this crate document their requirements in a "Formal"
+// section (preconditions, invariants and postconditions) instead.
+#![allow(clippy::missing_safety_doc)]
 
 // ----- Deal with `no_std` stuff --------------------------------------------
 #![no_std]
 
+// In tests (and when `no-std-compat` reexports the real `std`, as with the
+// `mmap` and `test-suite` features), the `std::prelude::v1::*` imports also
+// bring the `std` `panic` macro into scope, which clashes with the one from
+// the `core` prelude. Both are fine for our purposes.
+#![cfg_attr(
+    any(test, feature = "mmap", feature = "test-suite"),
+    allow(ambiguous_panic_imports),
+)]
+
 // Import the real `std` for tests.
 #[cfg(test)]
-#[macro_use]
+#[macro_use(vec, format)]
 extern crate std;
 
 // When compiling in a normal way, we use this compatibility layer that
@@ -123,18 +151,45 @@ use std::{
     ops::{Index, IndexMut},
 };
 use crate::{
-    core::{Core, DefaultCore, OwningCore, OptionCore, BitVecCore},
-    iter::{Indices, Iter, IterMut, IntoIter, Values, ValuesMut},
+    core::{ArrayCore, CapacityError, Core, CoreStats, CountingCore, MapCore, OwningCore, SliceCore},
+    iter::{
+        Indices, Iter, IterMut, IntoIter, Join, JoinMut, LeftJoin, OuterJoin, Values,
+        ValuesMut,
+    },
 };
+#[cfg(feature = "alloc")]
+use crate::core::{
+    AdaptiveCore, DefaultCore, OptionCore, BitPackedCore, BitVecCore, DenseCore,
+    Changes, FusedBitVecCore, InterleavedCore, RangeSetCore, SmallCore, SoftRemoveCore,
+    TrackingCore,
+};
+#[cfg(feature = "allocator-api2")]
+use crate::core::CoreWithAllocator;
+#[cfg(all(feature = "mmap", unix))]
+use crate::core::{MmapCore, Pod};
 
-#[cfg(test)]
+#[cfg(all(test, feature = "alloc"))]
 mod tests;
 pub mod core;
 pub mod iter;
+pub mod observer;
+#[cfg(feature = "alloc")]
+pub mod patch;
+pub mod remap;
+#[cfg(feature = "alloc")]
+pub mod soa;
+
+pub use crate::observer::{ObservedStableVec, StableVecObserver};
+#[cfg(feature = "alloc")]
+pub use crate::patch::{Patch, PatchError};
+pub use crate::remap::{CompactSibling, RemapIndices};
+#[cfg(feature = "alloc")]
+pub use crate::soa::StableSoa;
 
 
 
 /// A stable vector with the default core implementation.
+#[cfg(feature = "alloc")]
 pub type StableVec<T> = StableVecFacade<T, DefaultCore<T>>;
 
 /// A stable vector which stores the "deleted information" inline. This is very
@@ -142,12 +197,91 @@ pub type StableVec<T> = StableVecFacade<T, DefaultCore<T>>;
 ///
 /// This is particularly useful if `T` benefits from "null optimization", i.e.
 /// if `size_of::<T>() == size_of::<Option<T>>()`.
+#[cfg(feature = "alloc")]
 pub type InlineStableVec<T> = StableVecFacade<T, OptionCore<T>>;
 
 /// A stable vector which stores the "deleted information" externally in a bit
 /// vector.
+#[cfg(feature = "alloc")]
 pub type ExternStableVec<T> = StableVecFacade<T, BitVecCore<T>>;
 
+/// A stable vector which stores the "deleted information" in a bit vector
+/// that shares one allocation with the elements.
+///
+/// See [`FusedBitVecCore`] for more information.
+#[cfg(feature = "alloc")]
+pub type FusedStableVec<T> = StableVecFacade<T, FusedBitVecCore<T>>;
+
+/// A stable vector which stores the "deleted information" in blocks
+/// interleaved with the elements.
+///
+/// See [`InterleavedCore`] for more information.
+#[cfg(feature = "alloc")]
+pub type InterleavedStableVec<T> = StableVecFacade<T, InterleavedCore<T>>;
+
+/// A stable vector which stores all elements contiguously and maps indices to
+/// them via a table.
+///
+/// See [`DenseCore`] for more information.
+#[cfg(feature = "alloc")]
+pub type DenseStableVec<T> = StableVecFacade<T, DenseCore<T>>;
+
+/// A stable vector which switches between a dense and a sparse representation
+/// depending on the fraction of filled slots.
+///
+/// See [`AdaptiveCore`] for more information.
+#[cfg(feature = "alloc")]
+pub type AdaptiveStableVec<T> = StableVecFacade<T, AdaptiveCore<T>>;
+
+/// A stable vector which stores ranges of empty slots instead of one bit per
+/// slot.
+///
+/// See [`RangeSetCore`] for more information.
+#[cfg(feature = "alloc")]
+pub type RangeSetStableVec<T> = StableVecFacade<T, RangeSetCore<T>>;
+
+/// A stable vector of `bool`s which stores two bits per slot.
+///
+/// See [`BitPackedCore`] for more information.
+#[cfg(feature = "alloc")]
+pub type BitPackedStableVec = StableVecFacade<bool, BitPackedCore>;
+
+/// A stable vector which stores its slots in a memory-mapped file.
+///
+/// See [`MmapCore`] for more information.
+#[cfg(all(feature = "mmap", unix))]
+pub type MmapStableVec<T> = StableVecFacade<T, MmapCore<T>>;
+
+/// A stable vector which stores up to `N` slots inline and only allocates
+/// when it grows beyond that.
+///
+/// See [`SmallCore`] for more information.
+#[cfg(feature = "alloc")]
+pub type SmallStableVec<T, const N: usize> = StableVecFacade<T, SmallCore<T, N>>;
+
+/// A stable vector whose elements can be removed without dropping them, so
+/// that they can be restored later.
+///
+/// See [`SoftRemoveCore`] for more information.
+#[cfg(feature = "alloc")]
+pub type SoftRemoveStableVec<T> = StableVecFacade<T, SoftRemoveCore<DefaultCore<T>>>;
+
+/// A stable vector which tracks which slots changed since the last sync.
+///
+/// See [`TrackingCore`] for more information.
+#[cfg(feature = "alloc")]
+pub type TrackedStableVec<T> = StableVecFacade<T, TrackingCore<DefaultCore<T>>>;
+
+/// A stable vector with a fixed capacity of `N` slots which never allocates.
+///
+/// See [`ArrayCore`] for more information.
+pub type ArrayStableVec<T, const N: usize> = StableVecFacade<T, ArrayCore<T, N>>;
+
+/// A stable vector which stores its slots in buffers provided by the caller.
+///
+/// See [`SliceCore`] for more information.
+pub type SliceStableVec<'a, T> = StableVecFacade<T, SliceCore<'a, T>>;
+
 
 /// A `Vec<T>`-like collection which guarantees stable indices and features
 /// O(1) deletion of elements.
@@ -254,7 +388,10 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// Constructs a new, empty stable vector.
     ///
     /// The stable-vector will not allocate until elements are pushed onto it.
-    pub fn new() -> Self {
+    pub fn new() -> Self
+    where
+        C: Default,
+    {
         Self {
             core: OwningCore::new(C::new()),
             num_elements: 0,
@@ -267,7 +404,10 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// without reallocating. If `capacity` is 0, the stable-vector will not
     /// allocate any memory. See [`reserve`][StableVecFacade::reserve] for more
     /// information.
-    pub fn with_capacity(capacity: usize) -> Self {
+    pub fn with_capacity(capacity: usize) -> Self
+    where
+        C: Default,
+    {
         let mut out = Self::new();
         out.reserve_exact(capacity);
         out
@@ -284,6 +424,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::new();
     /// let star_idx = sv.push('★');
@@ -295,6 +436,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// // the element!
     /// sv.remove(star_idx);
     /// assert_eq!(sv.get(heart_idx), Some(&'♥'));
+    /// # }
     /// ```
     pub fn push(&mut self, elem: T) -> usize {
         let index = self.core.len();
@@ -329,6 +471,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::new();
     /// let star_idx = sv.push('★');
@@ -353,6 +496,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// // value.
     /// assert_eq!(sv.insert(heart_idx, 'z'), Some('♥'));
     /// assert_eq!(sv[heart_idx], 'z');
+    /// # }
     /// ```
     pub fn insert(&mut self, index: usize, mut elem: T) -> Option<T> {
         // If the index is out of bounds, we cannot insert the new element.
@@ -407,6 +551,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::new();
     /// let star_idx = sv.push('★');
@@ -419,6 +564,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// // the removal of the star.
     /// assert_eq!(sv.remove(heart_idx), Some('♥'));
     /// assert_eq!(sv.remove(heart_idx), None); // the heart was already removed
+    /// # }
     /// ```
     pub fn remove(&mut self, index: usize) -> Option<T> {
         // If the index is out of bounds, we cannot insert the new element.
@@ -453,12 +599,14 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&['a', 'b']);
     ///
     /// sv.clear();
     /// assert_eq!(sv.num_elements(), 0);
     /// assert!(sv.capacity() >= 2);
+    /// # }
     /// ```
     pub fn clear(&mut self) {
         self.core.clear();
@@ -536,6 +684,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::new();
     /// assert!(!sv.has_element_at(3));         // no: index out of bounds
@@ -545,6 +694,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     ///
     /// sv.remove(heart_idx);
     /// assert!(!sv.has_element_at(heart_idx)); // no: was removed
+    /// # }
     /// ```
     pub fn has_element_at(&self, index: usize) -> bool {
         if index >= self.core.cap() {
@@ -568,6 +718,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::new();
     /// assert_eq!(sv.num_elements(), 0);
@@ -577,6 +728,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     ///
     /// sv.remove(heart_idx);
     /// assert_eq!(sv.num_elements(), 0);
+    /// # }
     /// ```
     pub fn num_elements(&self) -> usize {
         self.num_elements
@@ -589,6 +741,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&['a', 'b', 'c']);
     ///
@@ -596,6 +749,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// let index_of_d = sv.push('d');
     ///
     /// assert_eq!(next_push_index, index_of_d);
+    /// # }
     /// ```
     pub fn next_push_index(&self) -> usize {
         self.core.len()
@@ -615,6 +769,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::new();
     /// assert!(sv.is_empty());
@@ -624,6 +779,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     ///
     /// sv.remove(heart_idx);
     /// assert!(sv.is_empty());
+    /// # }
     /// ```
     pub fn is_empty(&self) -> bool {
         self.num_elements == 0
@@ -636,12 +792,14 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[0, 1, 2, 3, 4]);
     /// assert!(sv.is_compact());
     ///
     /// sv.remove(1);
     /// assert!(!sv.is_compact());
+    /// # }
     /// ```
     pub fn is_compact(&self) -> bool {
         self.num_elements == self.core.len()
@@ -657,6 +815,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[10, 11, 12, 13, 14]);
     /// sv.remove(1);
@@ -666,6 +825,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// assert_eq!(it.next(), Some((2, &12)));
     /// assert_eq!(it.next(), Some((3, &13)));
     /// assert_eq!(it.next(), None);
+    /// # }
     /// ```
     pub fn iter(&self) -> Iter<'_, T, C> {
         Iter::new(self)
@@ -681,6 +841,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[10, 11, 12, 13, 14]);
     /// sv.remove(1);
@@ -692,6 +853,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// }
     ///
     /// assert_eq!(sv, vec![20, 24, 13, 28]);
+    /// # }
     /// ```
     pub fn iter_mut(&mut self) -> IterMut<'_, T, C> {
         IterMut::new(self)
@@ -704,6 +866,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[0, 1, 2, 3, 4]);
     /// sv.remove(1);
@@ -713,6 +876,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// assert_eq!(it.next(), Some(&2));
     /// assert_eq!(it.next(), Some(&3));
     /// assert_eq!(it.next(), None);
+    /// # }
     /// ```
     pub fn values(&self) -> Values<'_, T, C> {
         Values::new(self)
@@ -728,6 +892,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Examples
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[1.0, 2.0, 3.0]);
     ///
@@ -736,8 +901,9 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// }
     ///
     /// assert_eq!(sv, &[2.0, 4.0, 6.0] as &[_]);
+    /// # }
     /// ```
-    pub fn values_mut(&mut self) -> ValuesMut<T, C> {
+    pub fn values_mut(&mut self) -> ValuesMut<'_, T, C> {
         ValuesMut::new(self)
     }
 
@@ -747,6 +913,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&['a', 'b', 'c', 'd']);
     /// sv.remove(1);
@@ -756,22 +923,134 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// assert_eq!(it.next(), Some(2));
     /// assert_eq!(it.next(), Some(3));
     /// assert_eq!(it.next(), None);
+    /// # }
     /// ```
     ///
     /// Simply using the `for`-loop:
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&['a', 'b', 'c', 'd']);
     ///
     /// for index in sv.indices() {
     ///     println!("index: {}", index);
     /// }
+    /// # }
     /// ```
     pub fn indices(&self) -> Indices<'_, T, C> {
         Indices::new(self)
     }
 
+    /// Returns an iterator over all indices at which both `self` and `other`
+    /// have an element, together with these elements. This is useful for
+    /// parallel stable vectors sharing an index space.
+    ///
+    /// If both cores expose their bit vectors (see
+    /// [`Core::occupancy_words`]), e.g. with [`BitVecCore`], the filled
+    /// slots are found by ANDing whole words.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")] {
+    /// # use stable_vec::StableVec;
+    /// let mut positions = StableVec::from(&[1.0, 2.0, 3.0]);
+    /// let mut names = StableVec::from(&["a", "b", "c", "d"]);
+    /// positions.remove(0);
+    /// names.remove(2);
+    ///
+    /// let joined: Vec<_> = positions.join(&names).collect();
+    /// assert_eq!(joined, [(1, &2.0, &"b")]);
+    /// # }
+    /// ```
+    pub fn join<'a, B, Cb: Core<B>>(
+        &'a self,
+        other: &'a StableVecFacade<B, Cb>,
+    ) -> Join<'a, T, B, C, Cb> {
+        Join::new(self, other)
+    }
+
+    /// Like [`join`][StableVecFacade::join], but yields mutable references.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")] {
+    /// # use stable_vec::StableVec;
+    /// let mut positions = StableVec::from(&[1.0, 2.0, 3.0]);
+    /// let mut velocities = StableVec::from(&[0.5, 0.5]);
+    /// velocities.remove(0);
+    ///
+    /// for (_, pos, vel) in positions.join_mut(&mut velocities) {
+    ///     *pos += *vel;
+    ///     *vel = 0.0;
+    /// }
+    /// assert_eq!(positions, &[1.0, 2.5, 3.0] as &[_]);
+    /// assert_eq!(velocities, &[0.0] as &[_]);
+    /// # }
+    /// ```
+    pub fn join_mut<'a, B, Cb: Core<B>>(
+        &'a mut self,
+        other: &'a mut StableVecFacade<B, Cb>,
+    ) -> JoinMut<'a, T, B, C, Cb> {
+        JoinMut::new(self, other)
+    }
+
+    /// Returns an iterator over all elements of `self` and their indices,
+    /// together with the element at the same index in `other` (if any).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")] {
+    /// # use stable_vec::StableVec;
+    /// let positions = StableVec::from(&[1.0, 2.0, 3.0]);
+    /// let mut names = StableVec::from(&["a", "b"]);
+    /// names.remove(0);
+    ///
+    /// let joined: Vec<_> = positions.left_join(&names).collect();
+    /// assert_eq!(joined, [(0, &1.0, None), (1, &2.0, Some(&"b")), (2, &3.0, None)]);
+    /// # }
+    /// ```
+    pub fn left_join<'a, B, Cb: Core<B>>(
+        &'a self,
+        other: &'a StableVecFacade<B, Cb>,
+    ) -> LeftJoin<'a, T, B, C, Cb> {
+        LeftJoin::new(self, other)
+    }
+
+    /// Returns an iterator over all indices at which `self` or `other` (or
+    /// both) have an element, together with the elements at that index.
+    ///
+    /// Like with [`join`][StableVecFacade::join], whole words are ORed if
+    /// both cores expose their bit vectors.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")] {
+    /// # use stable_vec::StableVec;
+    /// let mut positions = StableVec::from(&[1.0, 2.0]);
+    /// let mut names = StableVec::from(&["a", "b", "c"]);
+    /// positions.remove(0);
+    /// names.remove(1);
+    ///
+    /// let joined: Vec<_> = positions.outer_join(&names).collect();
+    /// assert_eq!(joined, [
+    ///     (0, None, Some(&"a")),
+    ///     (1, Some(&2.0), None),
+    ///     (2, None, Some(&"c")),
+    /// ]);
+    /// # }
+    /// ```
+    pub fn outer_join<'a, B, Cb: Core<B>>(
+        &'a self,
+        other: &'a StableVecFacade<B, Cb>,
+    ) -> OuterJoin<'a, T, B, C, Cb> {
+        OuterJoin::new(self, other)
+    }
+
     /// Reserves memory for at least `additional` more elements to be inserted
     /// at indices `>= self.next_push_index()`.
     ///
@@ -788,6 +1067,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::new();
     /// let star_idx = sv.push('★');
@@ -809,6 +1089,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// // These values get adjusted accordingly.
     /// assert_eq!(sv.num_elements(), 2);
     /// assert_eq!(sv.next_push_index(), 3);
+    /// # }
     /// ```
     pub fn reserve(&mut self, additional: usize) {
         #[inline(never)]
@@ -838,7 +1119,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
             //: => new_cap >= len
             let new_cap = cmp::max(new_cap, 2 * self.core.cap());
 
-            if new_cap > isize::max_value() as usize {
+            if new_cap > isize::MAX as usize {
                 capacity_overflow();
             }
 
@@ -862,6 +1143,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::new();
     /// let star_idx = sv.push('★');
@@ -878,6 +1160,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// let capacity_before = sv.capacity();
     /// sv.reserve_for(3);
     /// assert_eq!(sv.capacity(), capacity_before);
+    /// # }
     /// ```
     pub fn reserve_for(&mut self, index: usize) {
         if index >= self.capacity() {
@@ -908,7 +1191,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
         };
 
         if self.core.cap() < new_cap {
-            if new_cap > isize::max_value() as usize {
+            if new_cap > isize::MAX as usize {
                 capacity_overflow();
             }
 
@@ -931,10 +1214,12 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[1, 2, 3]);
     /// assert_eq!(sv.remove_first(), Some(1));
     /// assert_eq!(sv, vec![2, 3]);
+    /// # }
     /// ```
     ///
     /// # Note
@@ -955,10 +1240,12 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[1, 2, 3]);
     /// assert_eq!(sv.remove_last(), Some(3));
     /// assert_eq!(sv, vec![1, 2]);
+    /// # }
     /// ```
     ///
     /// # Note
@@ -978,10 +1265,12 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[1, 2]);
     /// sv.remove(0);
     /// assert_eq!(sv.find_first(), Some(&2));
+    /// # }
     /// ```
     pub fn find_first(&self) -> Option<&T> {
         self.find_first_index().map(|index| unsafe { self.core.get_unchecked(index) })
@@ -995,6 +1284,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[1, 2]);
     /// {
@@ -1004,6 +1294,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     ///     *first = 3;
     /// }
     /// assert_eq!(sv, vec![3, 2]);
+    /// # }
     /// ```
     pub fn find_first_mut(&mut self) -> Option<&mut T> {
         self.find_first_index().map(move |index| unsafe { self.core.get_unchecked_mut(index) })
@@ -1017,10 +1308,12 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[1, 2]);
     /// sv.remove(1);
     /// assert_eq!(sv.find_last(), Some(&1));
+    /// # }
     /// ```
     pub fn find_last(&self) -> Option<&T> {
         self.find_last_index().map(|index| unsafe { self.core.get_unchecked(index) })
@@ -1034,6 +1327,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[1, 2]);
     /// {
@@ -1043,6 +1337,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     ///     *last = 3;
     /// }
     /// assert_eq!(sv, vec![1, 3]);
+    /// # }
     /// ```
     pub fn find_last_mut(&mut self) -> Option<&mut T> {
         self.find_last_index().map(move |index| unsafe { self.core.get_unchecked_mut(index) })
@@ -1067,6 +1362,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[0, 1, 2, 3, 4]);
     /// sv.remove(1);
@@ -1079,6 +1375,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// assert_eq!(sv.first_filled_slot_from(3), Some(3));
     /// assert_eq!(sv.first_filled_slot_from(4), None);
     /// assert_eq!(sv.first_filled_slot_from(5), None);
+    /// # }
     /// ```
     pub fn first_filled_slot_from(&self, start: usize) -> Option<usize> {
         if start > self.core.cap() {
@@ -1108,6 +1405,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[0, 1, 2, 3, 4]);
     /// sv.remove(0);
@@ -1120,6 +1418,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// assert_eq!(sv.first_filled_slot_below(3), Some(1));
     /// assert_eq!(sv.first_filled_slot_below(4), Some(1));
     /// assert_eq!(sv.first_filled_slot_below(5), Some(4));
+    /// # }
     /// ```
     pub fn first_filled_slot_below(&self, start: usize) -> Option<usize> {
         if start > self.core.cap() {
@@ -1150,6 +1449,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[0, 1, 2, 3, 4, 5]);
     /// sv.remove(1);
@@ -1166,6 +1466,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// sv.reserve_for(6);
     /// assert_eq!(sv.first_empty_slot_from(5), Some(6));
     /// assert_eq!(sv.first_empty_slot_from(6), Some(6));
+    /// # }
     /// ```
     pub fn first_empty_slot_from(&self, start: usize) -> Option<usize> {
         if start > self.core.cap() {
@@ -1188,6 +1489,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[0, 1, 2, 3, 4, 5]);
     /// sv.remove(1);
@@ -1201,6 +1503,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// assert_eq!(sv.first_empty_slot_below(4), Some(2));
     /// assert_eq!(sv.first_empty_slot_below(5), Some(4));
     /// assert_eq!(sv.first_empty_slot_below(6), Some(4));
+    /// # }
     /// ```
     pub fn first_empty_slot_below(&self, start: usize) -> Option<usize> {
         if start > self.core.cap() {
@@ -1223,10 +1526,12 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[1, 2]);
     /// sv.remove(0);
     /// assert_eq!(sv.find_first_index(), Some(1));
+    /// # }
     /// ```
     pub fn find_first_index(&self) -> Option<usize> {
         // `0 <= self.core.cap()` is always true
@@ -1243,10 +1548,12 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[1, 2]);
     /// sv.remove(1);
     /// assert_eq!(sv.find_last_index(), Some(0));
+    /// # }
     /// ```
     pub fn find_last_index(&self) -> Option<usize> {
         // `self.core.len() <= self.core.cap()` is always true
@@ -1302,6 +1609,12 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// This method invalidates the indices of all elements that are stored
     /// after the first empty slot in the stable vector!
     pub fn make_compact(&mut self) {
+        self.make_compact_with(|_, _| {});
+    }
+
+    /// Implementation of `make_compact`. `on_move(from, to)` is called for
+    /// each element moved from index `from` to index `to`.
+    fn make_compact_with(&mut self, mut on_move: impl FnMut(usize, usize)) {
         if self.is_compact() {
             return;
         }
@@ -1332,6 +1645,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
                     // So at this point `hole_index` points to a valid hole and
                     // `element_index` points to a valid element. Time to swap!
                     self.core.swap(hole_index, element_index);
+                    on_move(element_index, hole_index);
                 }
             }
         }
@@ -1364,6 +1678,12 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// This method invalidates the indices of all elements that are stored
     /// after the first hole and it does not preserve the order of elements!
     pub fn reordering_make_compact(&mut self) {
+        self.reordering_make_compact_with(|_, _| {});
+    }
+
+    /// Implementation of `reordering_make_compact`. `on_move(from, to)` is
+    /// called for each element moved from index `from` to index `to`.
+    fn reordering_make_compact_with(&mut self, mut on_move: impl FnMut(usize, usize)) {
         if self.is_compact() {
             return;
         }
@@ -1394,6 +1714,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
                     // We found an element and a hole left of the element. That
                     // means that we can swap.
                     self.core.swap(hole_index, element_index);
+                    on_move(element_index, hole_index);
                 }
             }
         }
@@ -1405,16 +1726,188 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
         }
     }
 
+    /// Like [`make_compact()`][StableVecFacade::make_compact], but afterwards
+    /// updates the indices stored in the elements. **Invalidates indices!**
+    ///
+    /// After compacting, [`RemapIndices::remap`] is called on every element
+    /// with a function mapping each old index to the new index of the element
+    /// that was stored there, or to `None` if there was no element at that
+    /// index. This mapping is also returned: the returned vector has the
+    /// length of the old `next_push_index()` and `remap[old]` is the new index
+    /// of the element at `old`.
+    ///
+    /// Before compacting, `remap` is also called once with a mapping that
+    /// keeps all indices of existing elements and maps all others to `None`.
+    /// That way, dangling indices are already dropped (or cause a panic)
+    /// before anything is moved.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an element stores a plain `usize` index that does not point
+    /// to an element (see [`RemapIndices`]). In that case, no element has
+    /// been moved and all indices pointing to elements are unchanged.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::StableVec;
+    /// // Each element stores the index of its successor.
+    /// let mut sv = StableVec::from(&[2usize, 2, 3, 0]);
+    /// sv.remove(1);
+    ///
+    /// let remap = sv.make_compact_remapping();
+    /// assert_eq!(sv, &[1usize, 2, 0] as &[_]);
+    /// assert_eq!(remap, [Some(0), None, Some(1), Some(2)]);
+    /// ```
+    #[cfg(feature = "alloc")]
+    pub fn make_compact_remapping(&mut self) -> Vec<Option<usize>>
+    where
+        T: RemapIndices,
+    {
+        self.make_compact_remapping_with(false, |_, _| {})
+    }
+
+    /// Like [`reordering_make_compact()`][StableVecFacade::reordering_make_compact],
+    /// but afterwards updates the indices stored in the elements. **Invalidates
+    /// indices and changes the order of the elements!**
+    ///
+    /// See [`make_compact_remapping()`][StableVecFacade::make_compact_remapping]
+    /// for details on the remapping, the return value and when this panics.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::StableVec;
+    /// // Each element optionally stores the index of another element.
+    /// let mut sv = StableVec::from(&[Some(3usize), None, Some(0), Some(2)]);
+    /// sv.remove(1);
+    ///
+    /// let remap = sv.reordering_make_compact_remapping();
+    /// assert_eq!(sv, &[Some(1usize), Some(2), Some(0)] as &[_]);
+    /// assert_eq!(remap, [Some(0), None, Some(2), Some(1)]);
+    /// ```
+    #[cfg(feature = "alloc")]
+    pub fn reordering_make_compact_remapping(&mut self) -> Vec<Option<usize>>
+    where
+        T: RemapIndices,
+    {
+        self.make_compact_remapping_with(true, |_, _| {})
+    }
+
+    /// Implementation of `make_compact_remapping` (or of
+    /// `reordering_make_compact_remapping` if `reordering` is `true`).
+    /// `on_move(from, to)` is called for each element moved from index `from`
+    /// to index `to`.
+    #[cfg(feature = "alloc")]
+    fn make_compact_remapping_with(
+        &mut self,
+        reordering: bool,
+        mut on_move: impl FnMut(usize, usize),
+    ) -> Vec<Option<usize>>
+    where
+        T: RemapIndices,
+    {
+        let mut remap = self.identity_remap();
+
+        // Dangling `usize` indices panic here, before anything is moved.
+        self.remap_elements(&remap);
+        let record = |from: usize, to: usize| {
+            remap[from] = Some(to);
+            on_move(from, to);
+        };
+        if reordering {
+            self.reordering_make_compact_with(record);
+        } else {
+            self.make_compact_with(record);
+        }
+        self.remap_elements(&remap);
+        remap
+    }
+
+    /// Like [`make_compact()`][StableVecFacade::make_compact], but applies
+    /// every move to all `siblings` as well. **Invalidates indices!**
+    ///
+    /// This keeps stable vectors that are indexed like `self` in sync without
+    /// building a remapping table. Each sibling needs to have an element at
+    /// every index at which `self` has one. Elements of siblings at indices
+    /// without an element in `self` are dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a sibling is missing an element at an index at which `self`
+    /// has one. In that case, nothing is modified.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")] {
+    /// # use stable_vec::{ExternStableVec, StableVec};
+    /// let mut names = StableVec::from(&["a", "b", "c"]);
+    /// let mut ages = ExternStableVec::from(&[30, 40, 50]);
+    /// names.remove(1);
+    ///
+    /// names.compact_together(&mut [&mut ages]);
+    /// assert_eq!(names, &["a", "c"] as &[_]);
+    /// assert_eq!(ages, &[30, 50] as &[_]);
+    /// # }
+    /// ```
+    pub fn compact_together(&mut self, siblings: &mut [&mut dyn CompactSibling]) {
+        for (i, sibling) in siblings.iter().enumerate() {
+            if let Some(idx) = self.indices().find(|&idx| !sibling.has_element_at(idx)) {
+                panic!(
+                    "sibling {} has no element at index {}, but the primary stable vector has",
+                    i,
+                    idx,
+                );
+            }
+        }
+
+        self.make_compact_with(|from, to| {
+            for sibling in siblings.iter_mut() {
+                sibling.swap(from, to);
+            }
+        });
+
+        for sibling in siblings.iter_mut() {
+            sibling.truncate(self.num_elements);
+        }
+    }
+
+    /// Returns a vector of length `next_push_index()` that maps each index of
+    /// an element to itself and all other indices to `None`.
+    #[cfg(feature = "alloc")]
+    fn identity_remap(&self) -> Vec<Option<usize>> {
+        let mut remap = vec![None; self.core.len()];
+        for idx in self.indices() {
+            remap[idx] = Some(idx);
+        }
+        remap
+    }
+
+    /// Calls `RemapIndices::remap` on all elements with the given mapping.
+    #[cfg(feature = "alloc")]
+    fn remap_elements(&mut self, remap: &[Option<usize>])
+    where
+        T: RemapIndices,
+    {
+        let f = |old: usize| remap.get(old).copied().flatten();
+        for elem in self.values_mut() {
+            elem.remap(&f);
+        }
+    }
+
     /// Returns `true` if the stable vector contains an element with the given
     /// value, `false` otherwise.
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&['a', 'b', 'c']);
     /// assert!(sv.contains(&'b'));
     ///
     /// sv.remove(1);   // 'b' is stored at index 1
     /// assert!(!sv.contains(&'b'));
+    /// # }
     /// ```
     pub fn contains<U>(&self, item: &U) -> bool
     where
@@ -1437,6 +1930,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&['a', 'b', 'c', 'd']);
     /// sv.reserve_for(5);
@@ -1469,6 +1963,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// assert_eq!(sv.get(3), Some(&'d'));
     /// assert_eq!(sv.get(4), None);
     /// assert_eq!(sv.get(5), Some(&'a'));
+    /// # }
     /// ```
     pub fn swap(&mut self, a: usize, b: usize) {
         assert!(a < self.core.cap());
@@ -1493,6 +1988,191 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
 
     }
 
+    /// Moves the element at index `from` into the empty slot at index `to`.
+    ///
+    /// Afterwards, the slot `from` is empty and the element can only be
+    /// accessed via `to`. Memory is reserved if `to` is not smaller than
+    /// `self.capacity()`, and `next_push_index` grows if necessary. All other
+    /// indices are not affected.
+    ///
+    /// # Panics
+    ///
+    /// This panics if there is no element at index `from` or if the slot at
+    /// index `to` is not empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")] {
+    /// # use stable_vec::StableVec;
+    /// let mut sv = StableVec::from(&['a', 'b', 'c']);
+    /// sv.remove(0);
+    ///
+    /// sv.relocate(2, 0);
+    /// assert_eq!(sv.get(0), Some(&'c'));
+    /// assert_eq!(sv.get(2), None);
+    ///
+    /// sv.relocate(1, 5);
+    /// assert_eq!(sv.get(5), Some(&'b'));
+    /// assert_eq!(sv.next_push_index(), 6);
+    /// # }
+    /// ```
+    pub fn relocate(&mut self, from: usize, to: usize) {
+        assert!(
+            self.has_element_at(from),
+            "attempt to relocate from index {}, but no element exists at that index",
+            from,
+        );
+        assert!(
+            !self.has_element_at(to),
+            "attempt to relocate to index {}, but that slot is not empty",
+            to,
+        );
+
+        self.reserve_for(to);
+
+        // `to < cap` after `reserve_for` and all slots >= `len` are empty.
+        // `from < len` as it contains an element.
+        unsafe {
+            if to >= self.core.len() {
+                self.core.set_len(to + 1);
+            }
+            self.core.swap(from, to);
+        }
+    }
+
+    /// Reorders all slots according to the given permutation: the slot at
+    /// index `i` is moved to index `perm[i]`.
+    ///
+    /// `perm` has to contain every index in `0..self.next_push_index()`
+    /// exactly once. Empty slots are moved like filled ones, so
+    /// `next_push_index` and `num_elements` do not change.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `perm.len() != self.next_push_index()` or if `perm` is
+    /// not a permutation. In that case, the stable vector is not modified.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::StableVec;
+    /// let mut sv = StableVec::from(&['a', 'b', 'c', 'd']);
+    /// sv.remove(1);
+    ///
+    /// sv.apply_permutation(&[3, 0, 1, 2]);
+    /// assert_eq!(sv.get(0), None);
+    /// assert_eq!(sv.get(1), Some(&'c'));
+    /// assert_eq!(sv.get(2), Some(&'d'));
+    /// assert_eq!(sv.get(3), Some(&'a'));
+    /// ```
+    #[cfg(feature = "alloc")]
+    pub fn apply_permutation(&mut self, perm: &[usize]) {
+        self.apply_permutation_with(perm, |_, _| {});
+    }
+
+    /// Implementation of `apply_permutation`. `on_move(from, to)` is called
+    /// after each swap that moves an element from index `from` to index `to`
+    /// (exchanging it with the element at `to`, if any).
+    #[cfg(feature = "alloc")]
+    fn apply_permutation_with(&mut self, perm: &[usize], mut on_move: impl FnMut(usize, usize)) {
+        let len = self.core.len();
+        assert_eq!(
+            perm.len(),
+            len,
+            "permutation has length {}, but `next_push_index` is {}",
+            perm.len(),
+            len,
+        );
+
+        // Check that `perm` is a permutation before moving anything. Afterwards,
+        // `pending` is `true` for all slots and is reset once a slot holds its
+        // final element.
+        let mut pending = vec![false; len];
+        for &target in perm {
+            assert!(
+                target < len && !pending[target],
+                "invalid permutation: index {} out of bounds or duplicated",
+                target,
+            );
+            pending[target] = true;
+        }
+
+        // Each cycle of the permutation is rotated by repeatedly swapping its
+        // first slot with the target of the slot that currently sits there.
+        // All indices are < `len` <= `cap`, as checked above.
+        for start in 0..len {
+            if !pending[start] {
+                continue;
+            }
+
+            let mut target = perm[start];
+            while target != start {
+                unsafe {
+                    self.core.swap(start, target);
+                    if self.core.has_element_at(target) {
+                        on_move(start, target);
+                    } else if self.core.has_element_at(start) {
+                        on_move(target, start);
+                    }
+                }
+                pending[target] = false;
+                target = perm[target];
+            }
+            pending[start] = false;
+        }
+    }
+
+    /// Sorts all elements by the key returned by `f` and moves them to the
+    /// front of the stable vector, making it compact. **Invalidates indices!**
+    ///
+    /// The sort is stable. Returns the mapping from old to new indices: the
+    /// returned vector has the length of the old `next_push_index()` and
+    /// `remap[old]` is `Some(new)` if there was an element at `old` and `None`
+    /// otherwise.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::StableVec;
+    /// let mut sv = StableVec::from(&[30, 10, 40, 20]);
+    /// sv.remove(2);
+    ///
+    /// let remap = sv.sort_by_key_compact(|&x| x);
+    /// assert_eq!(sv, &[10, 20, 30] as &[_]);
+    /// assert_eq!(remap, [Some(2), Some(0), None, Some(1)]);
+    /// ```
+    #[cfg(feature = "alloc")]
+    pub fn sort_by_key_compact<K, F>(&mut self, mut f: F) -> Vec<Option<usize>>
+    where
+        K: Ord,
+        F: FnMut(&T) -> K,
+    {
+        let mut keys = self.iter().map(|(idx, elem)| (f(elem), idx)).collect::<Vec<_>>();
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let len = self.core.len();
+        let mut remap = vec![None; len];
+        for (new, &(_, old)) in keys.iter().enumerate() {
+            remap[old] = Some(new);
+        }
+
+        // Empty slots fill up the indices after all elements, in order.
+        let mut next_hole = keys.len();
+        let perm = remap.iter()
+            .map(|new| new.unwrap_or_else(|| {
+                next_hole += 1;
+                next_hole - 1
+            }))
+            .collect::<Vec<_>>();
+        self.apply_permutation(&perm);
+
+        // All elements are in the range `0..self.num_elements` now.
+        unsafe { self.core.set_len(self.num_elements) };
+
+        remap
+    }
+
     /// Retains only the elements specified by the given predicate.
     ///
     /// Each element `e` for which `should_be_kept(&e)` returns `false` is
@@ -1501,11 +2181,13 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::from(&[1, 2, 3, 4, 5]);
     /// sv.retain(|&e| e % 2 == 0);
     ///
     /// assert_eq!(sv, &[2, 4] as &[_]);
+    /// # }
     /// ```
     pub fn retain<P>(&mut self, mut should_be_kept: P)
     where
@@ -1538,6 +2220,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")] {
     /// # use stable_vec::StableVec;
     /// let mut sv = StableVec::new();
     /// sv.push(1);
@@ -1546,6 +2229,7 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
     /// sv.retain_indices(|i| i == two);
     ///
     /// assert_eq!(sv, &[2] as &[_]);
+    /// # }
     /// ```
     pub fn retain_indices<P>(&mut self, mut should_be_kept: P)
     where
@@ -1578,7 +2262,6 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
         let len = new_elements.len();
 
         self.reserve(len);
-        self.num_elements += len;
 
         // It's important that a panic in `clone()` does not lead to memory
         // unsafety! The only way that could happen is if some uninitialized
@@ -1586,17 +2269,1014 @@ impl<T, C: Core<T>> StableVecFacade<T, C> {
         // happen: the core won't ever drop uninitialized elements.
         //
         // So that's good. But we also would like to drop all elements that
-        // have already been inserted. That's why we set the length first.
+        // have already been inserted. That's why we set the length first and
+        // count the elements one by one.
         unsafe {
-            let mut i = self.core.len();
-            let new_len = self.core.len() + len;
-            self.core.set_len(new_len);
+            let old_len = self.core.len();
+            self.core.set_len(old_len + len);
 
-            for elem in new_elements {
+            for (i, elem) in (old_len..).zip(new_elements) {
                 self.core.insert_at(i, elem.clone());
-                i += 1;
+                self.num_elements += 1;
+            }
+        }
+    }
+
+    /// Creates a new stable vector by applying `f` to each element. Indices,
+    /// empty slots and `next_push_index()` stay the same.
+    ///
+    /// The new stable vector uses the same kind of core (see [`MapCore`]). If
+    /// `T` and `B` have the same size and alignment and the core supports it
+    /// (like [`BitVecCore`] does), the memory of `self` is reused and nothing
+    /// is allocated. Otherwise, this is equivalent to
+    /// `self.filter_map(|e| Some(f(e)))`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")] {
+    /// # use stable_vec::StableVec;
+    /// let mut sv = StableVec::from(&[1u32, 2, 3]);
+    /// sv.remove(1);
+    ///
+    /// let sv = sv.map(|x| x as f32 * 1.5);
+    /// assert_eq!(sv.indices().collect::<Vec<_>>(), [0, 2]);
+    /// assert_eq!(sv, &[1.5f32, 4.5] as &[_]);
+    /// # }
+    /// ```
+    pub fn map<B, F>(self, mut f: F) -> StableVecFacade<B, C::Mapped<B>>
+    where
+        C: MapCore<T>,
+        F: FnMut(T) -> B,
+    {
+        let num_elements = self.num_elements;
+        match self.core.into_inner().map_in_place(&mut f) {
+            Ok(core) => StableVecFacade {
+                core: OwningCore::new(core),
+                num_elements,
+            },
+            Err(core) => {
+                let this = Self {
+                    core: OwningCore::new(core),
+                    num_elements,
+                };
+                this.filter_map(|elem| Some(f(elem)))
+            }
+        }
+    }
+
+    /// Creates a new stable vector with the core `C2` by applying `f` to
+    /// references of all elements. Indices, empty slots and
+    /// `next_push_index()` stay the same.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")] {
+    /// # use stable_vec::StableVec;
+    /// let mut sv = StableVec::from(&["a", "bb", "ccc"]);
+    /// sv.remove(0);
+    ///
+    /// let lengths: StableVec<usize> = sv.map_ref(|s| s.len());
+    /// assert_eq!(lengths.indices().collect::<Vec<_>>(), [1, 2]);
+    /// assert_eq!(lengths, &[2usize, 3] as &[_]);
+    /// # }
+    /// ```
+    pub fn map_ref<B, C2, F>(&self, mut f: F) -> StableVecFacade<B, C2>
+    where
+        C2: Core<B> + Default,
+        F: FnMut(&T) -> B,
+    {
+        let mut out = StableVecFacade::<B, C2>::with_empty_slots(self.core.len(), self.core.len());
+
+        // Indices returned by `first_filled_slot_from` are always valid and
+        // point to an existing element. `out` has the same `len` and only
+        // empty slots, so these indices can be filled there.
+        unsafe {
+            let mut next = 0;
+            while let Some(idx) = self.core.first_filled_slot_from(next) {
+                out.core.insert_at(idx, f(self.core.get_unchecked(idx)));
+                out.num_elements += 1;
+                next = idx + 1;
+            }
+        }
+
+        out
+    }
+
+    /// Creates a new stable vector with the core `C2` by applying the
+    /// fallible `f` to each element. Indices, empty slots and
+    /// `next_push_index()` stay the same.
+    ///
+    /// Stops at the first error, returning it together with the index of the
+    /// element for which `f` failed. All other elements are dropped in that
+    /// case.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")] {
+    /// # use stable_vec::StableVec;
+    /// let mut sv = StableVec::from(&["1", "x", "3"]);
+    ///
+    /// let res: Result<StableVec<u32>, _> = sv.clone().try_map(|s| s.parse::<u32>());
+    /// assert_eq!(res.unwrap_err().0, 1);
+    ///
+    /// sv.remove(1);
+    /// let numbers: StableVec<u32> = sv.try_map(|s| s.parse()).unwrap();
+    /// assert_eq!(numbers.indices().collect::<Vec<_>>(), [0, 2]);
+    /// assert_eq!(numbers, &[1u32, 3] as &[_]);
+    /// # }
+    /// ```
+    pub fn try_map<B, E, C2, F>(mut self, mut f: F) -> Result<StableVecFacade<B, C2>, (usize, E)>
+    where
+        C2: Core<B> + Default,
+        F: FnMut(T) -> Result<B, E>,
+    {
+        let mut out = StableVecFacade::<B, C2>::with_empty_slots(self.core.len(), self.core.len());
+
+        // Same as in `map_ref`. We remove each element from `self` before
+        // calling `f`, so that a panic in `f` can't lead to a double drop.
+        unsafe {
+            let mut next = 0;
+            while let Some(idx) = self.core.first_filled_slot_from(next) {
+                let elem = self.core.remove_at(idx);
+                self.num_elements -= 1;
+                out.core.insert_at(idx, f(elem).map_err(|e| (idx, e))?);
+                out.num_elements += 1;
+                next = idx + 1;
             }
         }
+
+        Ok(out)
+    }
+
+    /// Creates a new stable vector with the core `C2` by applying `f` to each
+    /// element. Slots for which `f` returns `None` are empty in the new stable
+    /// vector. Apart from that, indices, empty slots and `next_push_index()`
+    /// stay the same.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")] {
+    /// # use stable_vec::StableVec;
+    /// let sv = StableVec::from(&[1, 2, 3, 4]);
+    ///
+    /// let halves: StableVec<i32> = sv.filter_map(|x| if x % 2 == 0 { Some(x / 2) } else { None });
+    /// assert_eq!(halves.indices().collect::<Vec<_>>(), [1, 3]);
+    /// assert_eq!(halves, &[1, 2] as &[_]);
+    /// assert_eq!(halves.next_push_index(), 4);
+    /// # }
+    /// ```
+    pub fn filter_map<B, C2, F>(mut self, mut f: F) -> StableVecFacade<B, C2>
+    where
+        C2: Core<B> + Default,
+        F: FnMut(T) -> Option<B>,
+    {
+        let mut out = StableVecFacade::<B, C2>::with_empty_slots(self.core.len(), self.core.len());
+
+        // Same as in `try_map`.
+        unsafe {
+            let mut next = 0;
+            while let Some(idx) = self.core.first_filled_slot_from(next) {
+                let elem = self.core.remove_at(idx);
+                self.num_elements -= 1;
+                if let Some(new) = f(elem) {
+                    out.core.insert_at(idx, new);
+                    out.num_elements += 1;
+                }
+                next = idx + 1;
+            }
+        }
+
+        out
+    }
+
+    /// Returns the patch that turns `self` into `other`: it lists all indices
+    /// at which `other` has an element but `self` has none (with the element
+    /// of `other`), all indices at which only `self` has an element and all
+    /// indices at which both have different elements (with the element of
+    /// `other`). If the `next_push_index` of both differs, the patch also
+    /// contains the one of `other`.
+    ///
+    /// If both cores expose their occupancy bit vectors (e.g. `BitVecCore`),
+    /// inserted and removed indices are found by XOR-ing whole words and
+    /// only elements present in both stable vectors are compared.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::StableVec;
+    /// let a = StableVec::from(&[1, 2, 3]);
+    /// let mut b = a.clone();
+    /// b.remove(0);
+    /// b[2] = 30;
+    ///
+    /// let patch = a.diff(&b);
+    /// assert!(patch.inserted.is_empty());
+    /// assert_eq!(patch.removed, [0]);
+    /// assert_eq!(patch.changed, [(2, 30)]);
+    /// ```
+    #[cfg(feature = "alloc")]
+    pub fn diff<C2: Core<T>>(&self, other: &StableVecFacade<T, C2>) -> Patch<T>
+    where
+        T: Clone + PartialEq,
+    {
+        patch::diff(self, other)
+    }
+
+    /// Applies the given patch, usually created by
+    /// [`diff`][StableVecFacade::diff], to this stable vector.
+    ///
+    /// The patch is checked before anything is modified: all inserted
+    /// indices have to be empty, all removed and changed ones filled, and no
+    /// index may appear twice. If the patch sets the `next_push_index`, all
+    /// slots at and above it have to be empty afterwards. Otherwise, an error
+    /// is returned and this stable vector is not changed. Memory is reserved
+    /// as needed for inserted indices and the new `next_push_index`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::{StableVec, PatchError};
+    /// let mut a = StableVec::from(&[1, 2, 3]);
+    /// let mut b = a.clone();
+    /// b.remove(1);
+    /// b.insert(1, 20);
+    ///
+    /// let patch = a.diff(&b);
+    /// a.apply(patch.clone()).unwrap();
+    /// assert_eq!(a, b);
+    ///
+    /// a.remove(1);
+    /// assert_eq!(a.apply(patch), Err(PatchError::SlotEmpty(1)));
+    /// ```
+    #[cfg(feature = "alloc")]
+    pub fn apply(&mut self, patch: Patch<T>) -> Result<(), PatchError> {
+        patch::apply(self, patch)
+    }
+
+    /// Wraps this stable vector so that all changes are reported to
+    /// `observer`. See [`ObservedStableVec`] for more information.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")] {
+    /// # use stable_vec::{StableVec, StableVecObserver};
+    /// struct CountRemovals(usize);
+    ///
+    /// impl<T> StableVecObserver<T> for CountRemovals {
+    ///     fn on_remove(&mut self, _: usize, _: &T) {
+    ///         self.0 += 1;
+    ///     }
+    /// }
+    ///
+    /// let mut sv = StableVec::from(&[1, 2, 3, 4]).with_observer(CountRemovals(0));
+    /// sv.remove(0);
+    /// sv.retain(|&x| x != 3);
+    /// assert_eq!(sv.observer().0, 2);
+    /// # }
+    /// ```
+    pub fn with_observer<O: StableVecObserver<T>>(self, observer: O) -> ObservedStableVec<T, C, O> {
+        ObservedStableVec::new(self, observer)
+    }
+
+    /// Splits this stable vector into two: the first one contains all elements
+    /// for which `pred` returns `true`, the second one all others. Indices
+    /// don't change, so each slot is filled in at most one of the results.
+    ///
+    /// Both stable vectors have the same `next_push_index()` as `self`. The
+    /// first one reuses the memory of `self`, the second one is allocated
+    /// once with (at least) the same capacity.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")] {
+    /// # use stable_vec::StableVec;
+    /// let sv = StableVec::from(&[1, 2, 3, 4, 5]);
+    ///
+    /// let (even, odd) = sv.partition_by(|x| x % 2 == 0);
+    /// assert_eq!(even.indices().collect::<Vec<_>>(), [1, 3]);
+    /// assert_eq!(odd.indices().collect::<Vec<_>>(), [0, 2, 4]);
+    /// assert_eq!(even.next_push_index(), 5);
+    /// # }
+    /// ```
+    pub fn partition_by<P>(self, mut pred: P) -> (Self, Self)
+    where
+        C: Default,
+        P: FnMut(&T) -> bool,
+    {
+        self.partition_impl(|_, elem| pred(elem))
+    }
+
+    /// Like [`partition_by`][StableVecFacade::partition_by], but `pred` gets
+    /// the index of each element instead of the element.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")] {
+    /// # use stable_vec::StableVec;
+    /// let sv = StableVec::from(&['a', 'b', 'c', 'd']);
+    ///
+    /// let (low, high) = sv.partition_indices(|i| i < 2);
+    /// assert_eq!(low, &['a', 'b'] as &[_]);
+    /// assert_eq!(high.iter().collect::<Vec<_>>(), [(2, &'c'), (3, &'d')]);
+    /// # }
+    /// ```
+    pub fn partition_indices<P>(self, mut pred: P) -> (Self, Self)
+    where
+        C: Default,
+        P: FnMut(usize) -> bool,
+    {
+        self.partition_impl(|idx, _| pred(idx))
+    }
+
+    /// Moves all elements for which `keep` returns `false` into a new stable
+    /// vector with the same `len` and capacity.
+    fn partition_impl<P>(mut self, mut keep: P) -> (Self, Self)
+    where
+        C: Default,
+        P: FnMut(usize, &T) -> bool,
+    {
+        let mut other = Self::with_empty_slots(self.core.len(), self.core.cap());
+
+        // Indices returned by `first_filled_slot_from` are always valid and
+        // point to an existing element. `other` has the same `len` as `self`
+        // and only empty slots, so every index can be filled there. Each
+        // element is moved immediately, so a panic in `keep` leaves both
+        // stable vectors in a consistent state.
+        unsafe {
+            let mut next = 0;
+            while let Some(idx) = self.core.first_filled_slot_from(next) {
+                if !keep(idx, self.core.get_unchecked(idx)) {
+                    let elem = self.core.remove_at(idx);
+                    self.num_elements -= 1;
+                    other.core.insert_at(idx, elem);
+                    other.num_elements += 1;
+                }
+                next = idx + 1;
+            }
+        }
+
+        (self, other)
+    }
+
+    /// Creates an empty stable vector with `next_push_index() == len`, i.e.
+    /// with `len` empty slots, and a capacity of at least `cap >= len`.
+    fn with_empty_slots(len: usize, cap: usize) -> Self
+    where
+        C: Default,
+    {
+        let mut out = Self::new();
+        out.reserve_exact(cap);
+
+        // After reserving, `len <= cap` holds and all slots are empty.
+        unsafe {
+            out.core.set_len(len);
+        }
+
+        out
+    }
+}
+
+impl<T, const N: usize> StableVecFacade<T, ArrayCore<T, N>> {
+    /// Constructs a new, empty stable vector with a fixed capacity of `N`.
+    ///
+    /// This is the same as [`new`][StableVecFacade::new], but can be used in
+    /// `const` contexts, e.g. to initialize a `static`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::ArrayStableVec;
+    /// static EMPTY: ArrayStableVec<u32, 16> = ArrayStableVec::new_const();
+    /// assert_eq!(EMPTY.num_elements(), 0);
+    /// assert_eq!(EMPTY.capacity(), 16);
+    /// ```
+    pub const fn new_const() -> Self {
+        Self {
+            core: OwningCore::new(ArrayCore::new()),
+            num_elements: 0,
+        }
+    }
+
+    /// Appends `elem` like [`push`][StableVecFacade::push], but returns it
+    /// back as `Err` instead of panicking if the capacity `N` is exhausted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::ArrayStableVec;
+    /// let mut sv = ArrayStableVec::<char, 2>::new();
+    /// assert_eq!(sv.try_push('a'), Ok(0));
+    /// assert_eq!(sv.try_push('b'), Ok(1));
+    /// assert_eq!(sv.try_push('c'), Err('c'));
+    /// ```
+    pub fn try_push(&mut self, elem: T) -> Result<usize, T> {
+        if self.next_push_index() < N {
+            Ok(self.push(elem))
+        } else {
+            Err(elem)
+        }
+    }
+
+    /// Checks that `additional` more elements can be pushed, like
+    /// [`reserve`][StableVecFacade::reserve] does, but returns an error
+    /// instead of panicking if they don't fit into the capacity `N`.
+    ///
+    /// As the capacity is fixed, this never changes the stable vector.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::{ArrayStableVec, core::CapacityError};
+    /// let mut sv = ArrayStableVec::<char, 4>::from(&['a', 'b']);
+    /// assert_eq!(sv.try_reserve(2), Ok(()));
+    /// assert_eq!(sv.try_reserve(3), Err(CapacityError { required: 5, capacity: 4 }));
+    /// ```
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), CapacityError> {
+        match self.next_push_index().checked_add(additional) {
+            Some(required) if required <= N => Ok(()),
+            required => Err(CapacityError {
+                required: required.unwrap_or(usize::MAX),
+                capacity: N,
+            }),
+        }
+    }
+}
+
+impl<T, C: Core<T>> StableVecFacade<T, CountingCore<C>> {
+    /// Returns the operation counters of the core. See [`CountingCore`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")] {
+    /// # use stable_vec::{StableVecFacade, core::{CountingCore, OptionCore}};
+    /// let mut sv = StableVecFacade::<u32, CountingCore<OptionCore<u32>>>::new();
+    /// sv.push(1);
+    /// sv.push(2);
+    /// sv.make_compact();
+    /// assert_eq!(sv.stats().inserts, 2);
+    /// # }
+    /// ```
+    pub fn stats(&self) -> CoreStats {
+        self.core.stats()
+    }
+
+    /// Sets all operation counters of the core to 0. See [`CountingCore`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")] {
+    /// # use stable_vec::{StableVecFacade, core::{CountingCore, OptionCore}};
+    /// let mut sv = StableVecFacade::<u32, CountingCore<OptionCore<u32>>>::new();
+    /// sv.push(1);
+    /// sv.reset_stats();
+    /// sv.push(2);
+    /// assert_eq!(sv.stats().inserts, 1);
+    /// # }
+    /// ```
+    pub fn reset_stats(&self) {
+        self.core.reset_stats();
+    }
+}
+
+impl<'a, T> StableVecFacade<T, SliceCore<'a, T>> {
+    /// Constructs a new, empty stable vector that stores its elements in
+    /// `data` and its occupancy bits in `bitmap`. The capacity is fixed to
+    /// `data.len()`.
+    ///
+    /// `bitmap` needs at least `⌈data.len() / usize::BITS⌉` words, otherwise
+    /// this function panics. See [`SliceCore`] for more information.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::mem::MaybeUninit;
+    /// # use stable_vec::SliceStableVec;
+    /// let mut data = [const { MaybeUninit::uninit() }; 100];
+    /// let mut bitmap = [0; 2];
+    ///
+    /// let mut sv = SliceStableVec::from_slices(&mut data, &mut bitmap);
+    /// sv.push("hello".to_string());
+    /// assert_eq!(sv.capacity(), 100);
+    /// assert_eq!(sv[0], "hello");
+    /// ```
+    pub fn from_slices(data: &'a mut [std::mem::MaybeUninit<T>], bitmap: &'a mut [usize]) -> Self {
+        Self {
+            core: OwningCore::new(SliceCore::new(data, bitmap)),
+            num_elements: 0,
+        }
+    }
+
+    /// Appends `elem` like [`push`][StableVecFacade::push], but returns it
+    /// back as `Err` instead of panicking if the buffer is full.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::mem::MaybeUninit;
+    /// # use stable_vec::SliceStableVec;
+    /// let mut data = [MaybeUninit::uninit(); 2];
+    /// let mut bitmap = [0];
+    ///
+    /// let mut sv = SliceStableVec::from_slices(&mut data, &mut bitmap);
+    /// assert_eq!(sv.try_push('a'), Ok(0));
+    /// assert_eq!(sv.try_push('b'), Ok(1));
+    /// assert_eq!(sv.try_push('c'), Err('c'));
+    /// ```
+    pub fn try_push(&mut self, elem: T) -> Result<usize, T> {
+        if self.next_push_index() < self.capacity() {
+            Ok(self.push(elem))
+        } else {
+            Err(elem)
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T, const N: usize> StableVecFacade<T, SmallCore<T, N>> {
+    /// Returns `true` if the slots of this stable vector are stored on the
+    /// heap and `false` if they are stored inline.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::SmallStableVec;
+    /// let mut sv = SmallStableVec::<u32, 2>::new();
+    /// sv.push(1);
+    /// sv.push(2);
+    /// assert!(!sv.spilled());
+    ///
+    /// sv.push(3);
+    /// assert!(sv.spilled());
+    ///
+    /// // Moving back to the inline storage requires `next_push_index() <= 2`.
+    /// sv.remove(2);
+    /// sv.make_compact();
+    /// sv.shrink_to_fit();
+    /// assert!(!sv.spilled());
+    /// assert_eq!(sv, vec![1, 2]);
+    /// ```
+    pub fn spilled(&self) -> bool {
+        self.core.spilled()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T, C: Core<T>> StableVecFacade<T, TrackingCore<C>> {
+    /// Returns the indices of all slots that changed since the last sync, in
+    /// ascending order. See [`TrackingCore`] for what counts as a change.
+    ///
+    /// Indices of removed elements can be `>= next_push_index()`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::TrackedStableVec;
+    /// let mut sv = TrackedStableVec::from(&['a', 'b', 'c']);
+    /// sv.clear_dirty();
+    ///
+    /// sv.swap(0, 2);
+    /// sv[1] = 'x';
+    /// assert_eq!(sv.dirty_indices().collect::<Vec<_>>(), [0, 1, 2]);
+    /// ```
+    pub fn dirty_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.core.dirty_indices()
+    }
+
+    /// Returns all changes since the last sync, split into inserted, removed
+    /// and modified slots, and marks the current state as synced.
+    ///
+    /// Only the state at the last sync is compared with the current one: a
+    /// slot that was filled and emptied again in between is not reported,
+    /// while a slot that was emptied and filled again counts as modified.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::TrackedStableVec;
+    /// let mut sv = TrackedStableVec::from(&[1, 2]);
+    /// let changes = sv.take_changes();
+    /// assert_eq!(changes.inserted, [0, 1]);
+    ///
+    /// sv.push(3);
+    /// sv.remove(2);
+    /// sv.remove(1);
+    /// sv.values_mut().for_each(|x| *x *= 10);
+    ///
+    /// let changes = sv.take_changes();
+    /// assert_eq!(changes.inserted, []);
+    /// assert_eq!(changes.removed, [1]);
+    /// assert_eq!(changes.modified, [0]);
+    /// ```
+    pub fn take_changes(&mut self) -> Changes {
+        self.core.take_changes()
+    }
+
+    /// Marks the current state as synced, i.e. forgets all changes.
+    pub fn clear_dirty(&mut self) {
+        self.core.clear_dirty();
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T, C: Core<T>> StableVecFacade<T, SoftRemoveCore<C>> {
+    /// Hides the element at the given index without dropping it. Returns
+    /// `true` if there was an element at that index.
+    ///
+    /// Afterwards, the slot looks empty: `get` returns `None`, the element is
+    /// not included in `iter` or `num_elements` and `remove` returns `None`.
+    /// The element can be brought back with
+    /// [`restore`][StableVecFacade::restore] or dropped with
+    /// [`purge`][StableVecFacade::purge]. Inserting a new element at that
+    /// index or compacting the stable vector also drops it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::SoftRemoveStableVec;
+    /// let mut sv = SoftRemoveStableVec::from(&['a', 'b', 'c']);
+    /// assert!(sv.soft_remove(1));
+    /// assert!(!sv.soft_remove(1));
+    ///
+    /// assert_eq!(sv.get(1), None);
+    /// assert_eq!(sv.num_elements(), 2);
+    /// assert_eq!(sv.iter_removed().collect::<Vec<_>>(), [(1, &'b')]);
+    /// ```
+    pub fn soft_remove(&mut self, index: usize) -> bool {
+        let removed = self.core.soft_remove(index);
+        if removed {
+            self.num_elements -= 1;
+        }
+        removed
+    }
+
+    /// Makes the soft-removed element at the given index visible again.
+    /// Returns `true` if there was a soft-removed element at that index.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::SoftRemoveStableVec;
+    /// let mut sv = SoftRemoveStableVec::from(&['a', 'b', 'c']);
+    /// sv.soft_remove(0);
+    ///
+    /// assert!(sv.restore(0));
+    /// assert!(!sv.restore(1));
+    /// assert_eq!(sv, &['a', 'b', 'c'] as &[_]);
+    /// ```
+    pub fn restore(&mut self, index: usize) -> bool {
+        let restored = self.core.restore(index);
+        if restored {
+            self.num_elements += 1;
+        }
+        restored
+    }
+
+    /// Removes the soft-removed element at the given index for good and
+    /// returns it. Returns `None` if there is no soft-removed element at that
+    /// index.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::SoftRemoveStableVec;
+    /// let mut sv = SoftRemoveStableVec::from(&['a', 'b', 'c']);
+    /// sv.soft_remove(2);
+    ///
+    /// assert_eq!(sv.purge(2), Some('c'));
+    /// assert_eq!(sv.purge(2), None);
+    /// assert!(!sv.restore(2));
+    /// ```
+    pub fn purge(&mut self, index: usize) -> Option<T> {
+        self.core.purge(index)
+    }
+
+    /// Drops all soft-removed elements.
+    pub fn purge_all(&mut self) {
+        self.core.purge_all();
+    }
+
+    /// Returns an iterator over the indices and references of all
+    /// soft-removed elements, in ascending index order.
+    pub fn iter_removed(&self) -> impl Iterator<Item = (usize, &T)> + '_ {
+        // Soft-removed slots are always filled in the inner core.
+        self.core.removed_indices()
+            .map(move |idx| (idx, unsafe { self.core.inner().get_unchecked(idx) }))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> StableVecFacade<T, DenseCore<T>> {
+    /// Returns all elements as a slice, without any holes.
+    ///
+    /// The order of elements in the slice is unspecified; in particular, it
+    /// is not the order of indices and changes when elements are removed.
+    /// Use [`dense_indices`][StableVecFacade::dense_indices] to find out the
+    /// index of an element.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::DenseStableVec;
+    /// let mut sv = DenseStableVec::from(&['a', 'b', 'c', 'd']);
+    /// sv.remove(1);
+    ///
+    /// let mut values = sv.as_dense_slice().to_vec();
+    /// values.sort();
+    /// assert_eq!(values, ['a', 'c', 'd']);
+    /// ```
+    pub fn as_dense_slice(&self) -> &[T] {
+        self.core.as_slice()
+    }
+
+    /// Returns all elements as a mutable slice, without any holes. See
+    /// [`as_dense_slice`][StableVecFacade::as_dense_slice] for more
+    /// information.
+    pub fn as_dense_slice_mut(&mut self) -> &mut [T] {
+        self.core.as_mut_slice()
+    }
+
+    /// Returns the indices of all elements in the same order as
+    /// [`as_dense_slice`][StableVecFacade::as_dense_slice]: the `i`-th entry
+    /// is the index of `self.as_dense_slice()[i]`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::DenseStableVec;
+    /// let mut sv = DenseStableVec::from(&['a', 'b', 'c', 'd']);
+    /// sv.remove(1);
+    ///
+    /// for (&idx, &c) in sv.dense_indices().iter().zip(sv.as_dense_slice()) {
+    ///     assert_eq!(sv[idx], c);
+    /// }
+    /// ```
+    pub fn dense_indices(&self) -> &[usize] {
+        self.core.indices()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> StableVecFacade<T, AdaptiveCore<T>> {
+    /// Constructs a new, empty stable vector which switches to the sparse
+    /// representation when less than `threshold` of its slots are filled.
+    ///
+    /// Panics if `threshold` is not in `0.0..=1.0`. See [`AdaptiveCore`] for
+    /// more information.
+    pub fn with_density_threshold(threshold: f32) -> Self {
+        Self {
+            core: OwningCore::new(AdaptiveCore::with_threshold(threshold)),
+            num_elements: 0,
+        }
+    }
+
+    /// Returns the density threshold of this stable vector.
+    pub fn density_threshold(&self) -> f32 {
+        self.core.threshold()
+    }
+
+    /// Sets the density threshold, possibly switching the representation
+    /// immediately. Indices are not changed by this.
+    ///
+    /// Panics if `threshold` is not in `0.0..=1.0`.
+    pub fn set_density_threshold(&mut self, threshold: f32) {
+        self.core.set_threshold(threshold);
+    }
+
+    /// Returns `true` if the sparse representation is currently used.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::AdaptiveStableVec;
+    /// let mut sv = AdaptiveStableVec::with_density_threshold(0.2);
+    /// sv.extend(0..100);
+    /// assert!(!sv.is_sparse());
+    ///
+    /// for i in 0..90 {
+    ///     sv.remove(i);
+    /// }
+    /// assert!(sv.is_sparse());
+    /// assert_eq!(sv.get(95), Some(&95));
+    ///
+    /// sv.extend(100..200);
+    /// assert!(!sv.is_sparse());
+    /// assert_eq!(sv.get(95), Some(&95));
+    /// ```
+    pub fn is_sparse(&self) -> bool {
+        self.core.is_sparse()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> StableVecFacade<T, RangeSetCore<T>> {
+    /// Returns all maximal ranges of empty slots below
+    /// [`next_push_index`][StableVecFacade::next_push_index], sorted by index.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::RangeSetStableVec;
+    /// let mut sv = RangeSetStableVec::from(&[0, 1, 2, 3, 4, 5]);
+    /// sv.remove(1);
+    /// sv.remove(3);
+    /// sv.remove(2);
+    /// sv.remove(5);
+    ///
+    /// assert_eq!(sv.holes().collect::<Vec<_>>(), [1..4, 5..6]);
+    /// ```
+    pub fn holes(&self) -> impl Iterator<Item = std::ops::Range<usize>> + '_ {
+        self.core.holes()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl StableVecFacade<bool, BitPackedCore> {
+    /// Sets the element at `index` to `value` and returns the old value, or
+    /// returns `None` (without changing anything) if there is no element at
+    /// `index`.
+    ///
+    /// Unlike going through `get_mut`, this works on the packed bits
+    /// directly and is therefore cheap. See [`BitPackedCore`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::BitPackedStableVec;
+    /// let mut sv = BitPackedStableVec::from(&[true, false]);
+    /// assert_eq!(sv.set(1, true), Some(false));
+    /// assert_eq!(sv.set(2, true), None);
+    /// assert_eq!(sv, vec![true, true]);
+    /// ```
+    pub fn set(&mut self, index: usize, value: bool) -> Option<bool> {
+        let old = self.get(index).copied()?;
+        unsafe {
+            // We just checked that there is an element at `index`.
+            self.core.set_unchecked(index, value);
+        }
+        Some(old)
+    }
+
+    /// Returns the number of elements that are `true`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::BitPackedStableVec;
+    /// let mut sv = BitPackedStableVec::from(&[true, false, true, true]);
+    /// sv.remove(0);
+    /// assert_eq!(sv.count_true(), 2);
+    /// ```
+    pub fn count_true(&self) -> usize {
+        self.core.count_true()
+    }
+
+    /// Returns an iterator over the indices of all elements that are `true`,
+    /// in increasing order.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::BitPackedStableVec;
+    /// let mut sv = BitPackedStableVec::from(&[true, false, true, true]);
+    /// sv.remove(2);
+    /// assert_eq!(sv.iter_true_indices().collect::<Vec<_>>(), [0, 3]);
+    /// ```
+    pub fn iter_true_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.core.iter_true_indices()
+    }
+
+    /// Sets each element to `self[i] && other[i]`, treating missing elements
+    /// in `other` as `false`. Which slots are filled in `self` does not
+    /// change. Works on whole words at a time.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::BitPackedStableVec;
+    /// let mut a = BitPackedStableVec::from(&[true, true, false, true]);
+    /// let mut b = BitPackedStableVec::from(&[true, false, true]);
+    /// a.and(&b);
+    /// assert_eq!(a, vec![true, false, false, false]);
+    /// ```
+    pub fn and(&mut self, other: &Self) {
+        self.core.and(&other.core);
+    }
+
+    /// Sets each element to `self[i] || other[i]`, treating missing elements
+    /// in `other` as `false`. Which slots are filled in `self` does not
+    /// change. Works on whole words at a time.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::BitPackedStableVec;
+    /// let mut a = BitPackedStableVec::from(&[false, true, false]);
+    /// let mut b = BitPackedStableVec::from(&[true, false, false, true]);
+    /// b.remove(0);
+    /// a.or(&b);
+    /// assert_eq!(a, vec![false, true, false]);
+    /// ```
+    pub fn or(&mut self, other: &Self) {
+        self.core.or(&other.core);
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl<T: Pod> StableVecFacade<T, MmapCore<T>> {
+    /// Creates an empty stable vector backed by a new file at `path`. If the
+    /// file already exists, it is truncated.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stable_vec::MmapStableVec;
+    /// let path = std::env::temp_dir().join("stable-vec-doc-create-mmap");
+    /// {
+    ///     let mut sv = MmapStableVec::<u64>::create_mmap(&path)?;
+    ///     sv.extend([10, 11, 12]);
+    ///     sv.remove(1);
+    /// }
+    ///
+    /// let sv = MmapStableVec::<u64>::open_mmap(&path)?;
+    /// assert_eq!(sv.iter().collect::<Vec<_>>(), [(0, &10), (2, &12)]);
+    /// # std::fs::remove_file(&path)?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn create_mmap(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        Ok(Self {
+            core: OwningCore::new(MmapCore::create(path)?),
+            num_elements: 0,
+        })
+    }
+
+    /// Opens a stable vector from a file previously created by
+    /// [`create_mmap`][StableVecFacade::create_mmap]. All indices and holes
+    /// are as they were when the file was last written.
+    ///
+    /// See [`MmapCore::open`] for possible errors.
+    pub fn open_mmap(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let core = MmapCore::open(path)?;
+        Ok(Self {
+            num_elements: core.count_filled(),
+            core: OwningCore::new(core),
+        })
+    }
+
+    /// Writes all changes to the file, blocking until that's done.
+    pub fn flush(&self) -> std::io::Result<()> {
+        self.core.flush()
+    }
+}
+
+#[cfg(feature = "allocator-api2")]
+impl<T, C: CoreWithAllocator<T>> StableVecFacade<T, C> {
+    /// Constructs a new, empty stable vector that allocates its memory with
+    /// `alloc`.
+    ///
+    /// Like [`new`][StableVecFacade::new], this does not allocate until
+    /// elements are pushed. Unlike `new`, this does not require the allocator
+    /// to implement `Default`, so `alloc` can e.g. be a reference to an arena.
+    /// Only available with the `allocator-api2` feature.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use stable_vec::{StableVecFacade, core::{BitVecCore, Global}};
+    ///
+    /// let mut sv = StableVecFacade::<u32, BitVecCore<u32, Global>>::new_in(Global);
+    /// sv.push(3);
+    /// assert_eq!(sv, vec![3]);
+    /// ```
+    pub fn new_in(alloc: C::Allocator) -> Self {
+        Self {
+            core: OwningCore::new(C::new_in(alloc)),
+            num_elements: 0,
+        }
+    }
+
+    /// Constructs a new, empty stable vector with the specified capacity that
+    /// allocates its memory with `alloc`.
+    ///
+    /// See [`with_capacity`][StableVecFacade::with_capacity] for more
+    /// information.
+    pub fn with_capacity_in(capacity: usize, alloc: C::Allocator) -> Self {
+        let mut out = Self::new_in(alloc);
+        out.reserve_exact(capacity);
+        out
+    }
+
+    /// Returns a reference to the allocator used by this stable vector.
+    pub fn allocator(&self) -> &C::Allocator {
+        self.core.allocator()
     }
 }
 
@@ -1627,13 +3307,13 @@ impl<T, C: Core<T>> IndexMut<usize> for StableVecFacade<T, C> {
     }
 }
 
-impl<T, C: Core<T>> Default for StableVecFacade<T, C> {
+impl<T, C: Core<T> + Default> Default for StableVecFacade<T, C> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<T, S, C: Core<T>> From<S> for StableVecFacade<T, C>
+impl<T, S, C: Core<T> + Default> From<S> for StableVecFacade<T, C>
 where
     S: AsRef<[T]>,
     T: Clone,
@@ -1645,7 +3325,7 @@ where
     }
 }
 
-impl<T, C: Core<T>> FromIterator<T> for StableVecFacade<T, C> {
+impl<T, C: Core<T> + Default> FromIterator<T> for StableVecFacade<T, C> {
     fn from_iter<I>(iter: I) -> Self
     where
         I: IntoIterator<Item = T>,
@@ -1741,6 +3421,7 @@ where
     }
 }
 
+#[cfg(feature = "alloc")]
 impl<A, B, C: Core<A>> PartialEq<Vec<B>> for StableVecFacade<A, C>
 where
     A: PartialEq<B>,
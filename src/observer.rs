@@ -0,0 +1,352 @@
+//! Getting notified about changes to a stable vector.
+//!
+//! See [`StableVecObserver`] and [`ObservedStableVec`] for more information.
+
+#[cfg(feature = "alloc")]
+use std::prelude::v1::*;
+
+use std::{
+    fmt,
+    ops::Deref,
+};
+
+use crate::{
+    StableVecFacade,
+    core::Core,
+    iter::{IterMut, ValuesMut},
+};
+#[cfg(feature = "alloc")]
+use crate::RemapIndices;
+
+
+/// Receives notifications about changes to an [`ObservedStableVec`].
+///
+/// All methods have an empty default implementation, so you only need to
+/// implement the ones you are interested in. Each method is called right
+/// after the corresponding change was made. `()` implements this trait by
+/// ignoring everything.
+pub trait StableVecObserver<T> {
+    /// The element `elem` was inserted at index `idx`, which was empty
+    /// before.
+    fn on_insert(&mut self, idx: usize, elem: &T) {
+        let _ = (idx, elem);
+    }
+
+    /// The element `elem` was removed from index `idx`. The slot is empty now.
+    fn on_remove(&mut self, idx: usize, elem: &T) {
+        let _ = (idx, elem);
+    }
+
+    /// The element `old` at index `idx` was replaced by `new`.
+    fn on_replace(&mut self, idx: usize, old: &T, new: &T) {
+        let _ = (idx, old, new);
+    }
+
+    /// The contents of the slots `from` and `to` were exchanged: the element
+    /// that was at `from` is now at `to`. If there was an element at `to`,
+    /// it is now at `from`, otherwise `from` is empty now.
+    fn on_move(&mut self, from: usize, to: usize) {
+        let _ = (from, to);
+    }
+
+    /// All elements were removed.
+    fn on_clear(&mut self) {}
+}
+
+impl<T> StableVecObserver<T> for () {}
+
+
+/// A stable vector that notifies an observer `O` about all changes.
+///
+/// Created via [`StableVecFacade::with_observer`]. All methods that change
+/// the stable vector report their changes to the observer, see
+/// [`StableVecObserver`]. Read access to the stable vector is available via
+/// `Deref`. There is no mutable access to the underlying stable vector, as
+/// changes would bypass the observer. Modifying elements through
+/// [`get_mut`][ObservedStableVec::get_mut],
+/// [`iter_mut`][ObservedStableVec::iter_mut],
+/// [`values_mut`][ObservedStableVec::values_mut] or
+/// [`RemapIndices::remap`] is not reported.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(feature = "alloc")] {
+/// use stable_vec::{StableVec, StableVecObserver};
+///
+/// #[derive(Default)]
+/// struct Log(Vec<String>);
+///
+/// impl StableVecObserver<char> for Log {
+///     fn on_insert(&mut self, idx: usize, elem: &char) {
+///         self.0.push(format!("insert {} at {}", elem, idx));
+///     }
+///     fn on_move(&mut self, from: usize, to: usize) {
+///         self.0.push(format!("move {} to {}", from, to));
+///     }
+/// }
+///
+/// let mut sv = StableVec::new().with_observer(Log::default());
+/// sv.push('a');
+/// sv.push('b');
+/// sv.remove(0);
+/// sv.make_compact();
+///
+/// assert_eq!(sv[0], 'b');
+/// assert_eq!(sv.observer().0, ["insert a at 0", "insert b at 1", "move 1 to 0"]);
+/// # }
+/// ```
+pub struct ObservedStableVec<T, C: Core<T>, O> {
+    sv: StableVecFacade<T, C>,
+    observer: O,
+}
+
+impl<T, C: Core<T>, O: StableVecObserver<T>> ObservedStableVec<T, C, O> {
+    pub(crate) fn new(sv: StableVecFacade<T, C>, observer: O) -> Self {
+        Self { sv, observer }
+    }
+
+    /// Returns a reference to the observer.
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    /// Returns a mutable reference to the observer.
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    /// Returns the stable vector and the observer.
+    pub fn into_parts(self) -> (StableVecFacade<T, C>, O) {
+        (self.sv, self.observer)
+    }
+
+    /// Returns a mutable reference to the element at the given index, or
+    /// `None` if there is no element at that index. Changes made via this
+    /// reference are not reported to the observer.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.sv.get_mut(index)
+    }
+
+    /// Like [`StableVecFacade::iter_mut`]. Changes made via the returned
+    /// references are not reported to the observer.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T, C> {
+        self.sv.iter_mut()
+    }
+
+    /// Like [`StableVecFacade::values_mut`]. Changes made via the returned
+    /// references are not reported to the observer.
+    pub fn values_mut(&mut self) -> ValuesMut<'_, T, C> {
+        self.sv.values_mut()
+    }
+
+    /// Like [`StableVecFacade::reserve`]. Does not change any slot, so the
+    /// observer is not called.
+    pub fn reserve(&mut self, additional: usize) {
+        self.sv.reserve(additional);
+    }
+
+    /// Like [`StableVecFacade::reserve_exact`]. Does not change any slot, so
+    /// the observer is not called.
+    pub fn reserve_exact(&mut self, additional: usize) {
+        self.sv.reserve_exact(additional);
+    }
+
+    /// Like [`StableVecFacade::shrink_to_fit`]. Does not change any slot, so
+    /// the observer is not called.
+    pub fn shrink_to_fit(&mut self) {
+        self.sv.shrink_to_fit();
+    }
+
+    /// Like [`StableVecFacade::push`]. Calls `on_insert`.
+    pub fn push(&mut self, elem: T) -> usize {
+        let idx = self.sv.push(elem);
+        self.observer.on_insert(idx, &self.sv[idx]);
+        idx
+    }
+
+    /// Like [`StableVecFacade::extend_from_slice`]. Calls `on_insert` for each
+    /// element.
+    pub fn extend_from_slice(&mut self, new_elements: &[T])
+    where
+        T: Clone,
+    {
+        self.sv.reserve(new_elements.len());
+        for elem in new_elements {
+            self.push(elem.clone());
+        }
+    }
+
+    /// Like [`StableVecFacade::insert`]. Calls `on_replace` if there was an
+    /// element at `index` and `on_insert` otherwise.
+    pub fn insert(&mut self, index: usize, elem: T) -> Option<T> {
+        let old = self.sv.insert(index, elem);
+        match &old {
+            Some(old) => self.observer.on_replace(index, old, &self.sv[index]),
+            None => self.observer.on_insert(index, &self.sv[index]),
+        }
+        old
+    }
+
+    /// Like [`StableVecFacade::remove`]. Calls `on_remove` if there was an
+    /// element at `index`.
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        let elem = self.sv.remove(index);
+        if let Some(elem) = &elem {
+            self.observer.on_remove(index, elem);
+        }
+        elem
+    }
+
+    /// Like [`StableVecFacade::remove_first`]. Calls `on_remove` if there was
+    /// an element.
+    pub fn remove_first(&mut self) -> Option<T> {
+        self.sv.find_first_index().and_then(|index| self.remove(index))
+    }
+
+    /// Like [`StableVecFacade::remove_last`]. Calls `on_remove` if there was
+    /// an element.
+    pub fn remove_last(&mut self) -> Option<T> {
+        self.sv.find_last_index().and_then(|index| self.remove(index))
+    }
+
+    /// Like [`StableVecFacade::retain`]. Calls `on_remove` for each removed
+    /// element.
+    pub fn retain<P>(&mut self, mut should_be_kept: P)
+    where
+        P: FnMut(&T) -> bool,
+    {
+        self.retain_impl(|_, elem| should_be_kept(elem));
+    }
+
+    /// Like [`StableVecFacade::retain_indices`]. Calls `on_remove` for each
+    /// removed element.
+    pub fn retain_indices<P>(&mut self, mut should_be_kept: P)
+    where
+        P: FnMut(usize) -> bool,
+    {
+        self.retain_impl(|idx, _| should_be_kept(idx));
+    }
+
+    fn retain_impl(&mut self, mut should_be_kept: impl FnMut(usize, &T) -> bool) {
+        let mut pos = 0;
+        while let Some(idx) = self.sv.first_filled_slot_from(pos) {
+            if !should_be_kept(idx, &self.sv[idx]) {
+                // There is an element at `idx`, so `remove` returns `Some`.
+                self.remove(idx);
+            }
+            pos = idx + 1;
+        }
+    }
+
+    /// Like [`StableVecFacade::swap`]. Calls `on_move` once if `a != b` and at
+    /// least one of the two slots contains an element.
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.sv.swap(a, b);
+        if a == b {
+            return;
+        }
+
+        if self.sv.has_element_at(b) {
+            self.observer.on_move(a, b);
+        } else if self.sv.has_element_at(a) {
+            self.observer.on_move(b, a);
+        }
+    }
+
+    /// Like [`StableVecFacade::relocate`]. Calls `on_move`.
+    pub fn relocate(&mut self, from: usize, to: usize) {
+        self.sv.relocate(from, to);
+        self.observer.on_move(from, to);
+    }
+
+    /// Like [`StableVecFacade::apply_permutation`]. Calls `on_move` for every
+    /// swap that moves an element. The observer sees the same sequence of
+    /// swaps that is performed on the stable vector.
+    #[cfg(feature = "alloc")]
+    pub fn apply_permutation(&mut self, perm: &[usize]) {
+        let observer = &mut self.observer;
+        self.sv.apply_permutation_with(perm, |from, to| observer.on_move(from, to));
+    }
+
+    /// Like [`StableVecFacade::clear`]. Calls `on_clear`.
+    pub fn clear(&mut self) {
+        self.sv.clear();
+        self.observer.on_clear();
+    }
+
+    /// Like [`StableVecFacade::make_compact`]. Calls `on_move` for every
+    /// moved element.
+    pub fn make_compact(&mut self) {
+        let observer = &mut self.observer;
+        self.sv.make_compact_with(|from, to| observer.on_move(from, to));
+    }
+
+    /// Like [`StableVecFacade::reordering_make_compact`]. Calls `on_move` for
+    /// every moved element.
+    pub fn reordering_make_compact(&mut self) {
+        let observer = &mut self.observer;
+        self.sv.reordering_make_compact_with(|from, to| observer.on_move(from, to));
+    }
+
+    /// Like [`StableVecFacade::make_compact_remapping`]. Calls `on_move` for
+    /// every moved element. Changes made by [`RemapIndices::remap`] are not
+    /// reported.
+    #[cfg(feature = "alloc")]
+    pub fn make_compact_remapping(&mut self) -> Vec<Option<usize>>
+    where
+        T: RemapIndices,
+    {
+        let observer = &mut self.observer;
+        self.sv.make_compact_remapping_with(false, |from, to| observer.on_move(from, to))
+    }
+
+    /// Like [`StableVecFacade::reordering_make_compact_remapping`]. Calls
+    /// `on_move` for every moved element. Changes made by
+    /// [`RemapIndices::remap`] are not reported.
+    #[cfg(feature = "alloc")]
+    pub fn reordering_make_compact_remapping(&mut self) -> Vec<Option<usize>>
+    where
+        T: RemapIndices,
+    {
+        let observer = &mut self.observer;
+        self.sv.make_compact_remapping_with(true, |from, to| observer.on_move(from, to))
+    }
+}
+
+/// Calls `on_insert` for each element.
+impl<T, C: Core<T>, O: StableVecObserver<T>> Extend<T> for ObservedStableVec<T, C, O> {
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let it = iter.into_iter();
+        self.sv.reserve(it.size_hint().0);
+
+        for elem in it {
+            self.push(elem);
+        }
+    }
+}
+
+impl<T, C: Core<T>, O> Deref for ObservedStableVec<T, C, O> {
+    type Target = StableVecFacade<T, C>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.sv
+    }
+}
+
+impl<T, C, O> fmt::Debug for ObservedStableVec<T, C, O>
+where
+    T: fmt::Debug,
+    C: Core<T>,
+    O: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ObservedStableVec")
+            .field("sv", &self.sv)
+            .field("observer", &self.observer)
+            .finish()
+    }
+}
@@ -0,0 +1,344 @@
+//! Differences between two stable vectors.
+//!
+//! See [`Patch`] for more information.
+
+use std::{
+    prelude::v1::*,
+    cmp,
+    fmt,
+};
+
+use crate::{StableVecFacade, core::Core};
+
+
+/// The differences between two stable vectors, as returned by
+/// [`StableVecFacade::diff`] and applied by [`StableVecFacade::apply`].
+///
+/// Each index appears in at most one of the three lists. The lists returned
+/// by `diff` and `compose` are sorted by index, but that is not required for
+/// `apply`. Additionally, a patch can set the
+/// [`next_push_index`][StableVecFacade::next_push_index], as two stable
+/// vectors can differ only in the number of empty slots at the end.
+///
+/// # Example
+///
+/// ```
+/// use stable_vec::StableVec;
+///
+/// let mut client = StableVec::from(&['a', 'b', 'c']);
+/// let mut server = client.clone();
+/// server.remove(0);
+/// server[1] = 'x';
+/// server.push('d');
+///
+/// let patch = client.diff(&server);
+/// assert_eq!(patch.inserted, [(3, 'd')]);
+/// assert_eq!(patch.removed, [0]);
+/// assert_eq!(patch.changed, [(1, 'x')]);
+/// assert_eq!(patch.next_push_index, Some(4));
+///
+/// client.apply(patch).unwrap();
+/// assert_eq!(client, server);
+/// assert_eq!(client.next_push_index(), 4);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Patch<T> {
+    /// Indices of empty slots and the elements to insert there.
+    pub inserted: Vec<(usize, T)>,
+
+    /// Indices of elements to remove.
+    pub removed: Vec<usize>,
+
+    /// Indices of existing elements and the elements to replace them with.
+    pub changed: Vec<(usize, T)>,
+
+    /// The `next_push_index` after applying the patch, or `None` to keep it
+    /// (apart from growing it for inserted indices, like
+    /// [`insert`][StableVecFacade::insert] does). All slots at and above this
+    /// index have to be empty after applying the patch.
+    pub next_push_index: Option<usize>,
+}
+
+/// The error returned when a [`Patch`] does not fit the stable vector it is
+/// applied to, or when two patches cannot be composed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PatchError {
+    /// The patch inserts an element at this index, but the slot is filled.
+    SlotFilled(usize),
+
+    /// The patch removes or changes the element at this index, but the slot
+    /// is empty.
+    SlotEmpty(usize),
+
+    /// The patch contains this index more than once.
+    DuplicateIndex(usize),
+
+    /// The slot at this index would be filled after applying the patch, but
+    /// the index is not below the `next_push_index` of the patch.
+    BeyondEnd(usize),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::SlotFilled(idx) => {
+                write!(f, "patch inserts at index {}, but that slot is filled", idx)
+            }
+            PatchError::SlotEmpty(idx) => {
+                write!(f, "patch removes or changes index {}, but that slot is empty", idx)
+            }
+            PatchError::DuplicateIndex(idx) => {
+                write!(f, "patch contains index {} more than once", idx)
+            }
+            PatchError::BeyondEnd(idx) => {
+                write!(f, "slot {} would be filled, but is beyond the patch's end", idx)
+            }
+        }
+    }
+}
+
+/// A single change to one slot.
+enum Op<T> {
+    Insert(T),
+    Remove,
+    Change(T),
+}
+
+impl<T> Default for Patch<T> {
+    fn default() -> Self {
+        Self {
+            inserted: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+            next_push_index: None,
+        }
+    }
+}
+
+impl<T> Patch<T> {
+    /// Returns `true` if this patch does not change anything.
+    pub fn is_empty(&self) -> bool {
+        self.inserted.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.next_push_index.is_none()
+    }
+
+    /// Combines this patch with `next` into a single patch that has the same
+    /// effect as applying `self` and then `next`.
+    ///
+    /// Returns an error if `next` can't be applied after `self`, e.g. if
+    /// `next` inserts an element at an index `self` inserted one at. Note that
+    /// the composed patch is not checked against any stable vector.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use stable_vec::StableVec;
+    ///
+    /// let v0 = StableVec::from(&[1, 2, 3]);
+    /// let mut v1 = v0.clone();
+    /// v1.remove(1);
+    /// let mut v2 = v1.clone();
+    /// v2.insert(1, 20);
+    /// v2.remove(2);
+    ///
+    /// let patch = v0.diff(&v1).compose(v1.diff(&v2)).unwrap();
+    /// assert_eq!(patch, v0.diff(&v2));
+    /// ```
+    pub fn compose(self, next: Patch<T>) -> Result<Patch<T>, PatchError> {
+        // If `next` keeps the `next_push_index`, it still grows it for its
+        // inserted indices.
+        let next_push_index = match (self.next_push_index, next.next_push_index) {
+            (_, Some(npi)) => Some(npi),
+            (Some(npi), None) => {
+                let end = next.inserted.iter().map(|&(idx, _)| idx + 1).max().unwrap_or(0);
+                Some(cmp::max(npi, end))
+            }
+            (None, None) => None,
+        };
+
+        let mut a = self.into_ops()?.into_iter().peekable();
+        let mut b = next.into_ops()?.into_iter().peekable();
+        let mut out = Patch { next_push_index, ..Patch::default() };
+
+        loop {
+            let idx = match (a.peek(), b.peek()) {
+                (None, None) => break,
+                (Some(&(ia, _)), None) => ia,
+                (None, Some(&(ib, _))) => ib,
+                (Some(&(ia, _)), Some(&(ib, _))) => cmp::min(ia, ib),
+            };
+            let first = a.next_if(|&(i, _)| i == idx).map(|(_, op)| op);
+            let second = b.next_if(|&(i, _)| i == idx).map(|(_, op)| op);
+
+            let op = match (first, second) {
+                (Some(op), None) | (None, Some(op)) => Some(op),
+                (Some(Op::Insert(_)), Some(Op::Remove)) => None,
+                (Some(Op::Insert(_)), Some(Op::Change(v))) => Some(Op::Insert(v)),
+                (Some(Op::Remove), Some(Op::Insert(v))) => Some(Op::Change(v)),
+                (Some(Op::Change(_)), Some(Op::Remove)) => Some(Op::Remove),
+                (Some(Op::Change(_)), Some(Op::Change(v))) => Some(Op::Change(v)),
+                (Some(Op::Insert(_)), Some(Op::Insert(_)))
+                | (Some(Op::Change(_)), Some(Op::Insert(_))) => {
+                    return Err(PatchError::SlotFilled(idx));
+                }
+                (Some(Op::Remove), Some(_)) => return Err(PatchError::SlotEmpty(idx)),
+                (None, None) => unreachable!(),
+            };
+            if let Some(op) = op {
+                out.push(idx, op);
+            }
+        }
+
+        Ok(out)
+    }
+
+    fn push(&mut self, idx: usize, op: Op<T>) {
+        match op {
+            Op::Insert(v) => self.inserted.push((idx, v)),
+            Op::Remove => self.removed.push(idx),
+            Op::Change(v) => self.changed.push((idx, v)),
+        }
+    }
+
+    /// Returns all changes sorted by index, or an error if an index appears
+    /// more than once.
+    fn into_ops(self) -> Result<Vec<(usize, Op<T>)>, PatchError> {
+        let mut ops = self.inserted.into_iter()
+            .map(|(idx, v)| (idx, Op::Insert(v)))
+            .chain(self.removed.into_iter().map(|idx| (idx, Op::Remove)))
+            .chain(self.changed.into_iter().map(|(idx, v)| (idx, Op::Change(v))))
+            .collect::<Vec<_>>();
+        ops.sort_by_key(|&(idx, _)| idx);
+
+        if let Some(w) = ops.windows(2).find(|w| w[0].0 == w[1].0) {
+            return Err(PatchError::DuplicateIndex(w[0].0));
+        }
+        Ok(ops)
+    }
+}
+
+/// Implementation of `StableVecFacade::diff`.
+pub(crate) fn diff<T, Ca, Cb>(a: &StableVecFacade<T, Ca>, b: &StableVecFacade<T, Cb>) -> Patch<T>
+where
+    T: Clone + PartialEq,
+    Ca: Core<T>,
+    Cb: Core<T>,
+{
+    let mut patch = Patch::default();
+    if a.next_push_index() != b.next_push_index() {
+        patch.next_push_index = Some(b.next_push_index());
+    }
+
+    match (a.core.occupancy_words(), b.core.occupancy_words()) {
+        (Some(wa), Some(wb)) => {
+            const BITS: usize = usize::BITS as usize;
+
+            for i in 0..cmp::max(wa.len(), wb.len()) {
+                let x = wa.get(i).copied().unwrap_or(0);
+                let y = wb.get(i).copied().unwrap_or(0);
+
+                // Slots filled in only one of the two stable vectors are
+                // inserted or removed, without looking at any element.
+                let mut differ = x ^ y;
+                while differ != 0 {
+                    let bit = differ.trailing_zeros() as usize;
+                    differ &= differ - 1;
+                    if x & (1 << bit) != 0 {
+                        patch.removed.push(i * BITS + bit);
+                    } else {
+                        patch.inserted.push((i * BITS + bit, b[i * BITS + bit].clone()));
+                    }
+                }
+
+                // Only slots filled in both have to be compared.
+                let mut both = x & y;
+                while both != 0 {
+                    let idx = i * BITS + both.trailing_zeros() as usize;
+                    both &= both - 1;
+                    if a[idx] != b[idx] {
+                        patch.changed.push((idx, b[idx].clone()));
+                    }
+                }
+            }
+        }
+        _ => {
+            for (idx, x, y) in a.outer_join(b) {
+                match (x, y) {
+                    (None, Some(y)) => patch.inserted.push((idx, y.clone())),
+                    (Some(_), None) => patch.removed.push(idx),
+                    (Some(x), Some(y)) if x != y => patch.changed.push((idx, y.clone())),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    patch
+}
+
+/// Implementation of `StableVecFacade::apply`.
+pub(crate) fn apply<T, C: Core<T>>(
+    sv: &mut StableVecFacade<T, C>,
+    patch: Patch<T>,
+) -> Result<(), PatchError> {
+    let next_push_index = patch.next_push_index;
+    let ops = patch.into_ops()?;
+    for (idx, op) in &ops {
+        match op {
+            Op::Insert(_) if sv.has_element_at(*idx) => return Err(PatchError::SlotFilled(*idx)),
+            Op::Remove | Op::Change(_) if !sv.has_element_at(*idx) => {
+                return Err(PatchError::SlotEmpty(*idx));
+            }
+            Op::Insert(_) | Op::Change(_) if next_push_index.is_some_and(|n| *idx >= n) => {
+                return Err(PatchError::BeyondEnd(*idx));
+            }
+            _ => {}
+        }
+    }
+
+    // All existing elements at and above the new `next_push_index` have to
+    // be removed by the patch. `ops` is sorted by index. There are no
+    // elements at and above the current `next_push_index`.
+    if let Some(n) = next_push_index.filter(|&n| n < sv.next_push_index()) {
+        let mut pos = n;
+        while let Some(idx) = sv.first_filled_slot_from(pos) {
+            match ops.binary_search_by_key(&idx, |&(i, _)| i) {
+                Ok(i) if matches!(ops[i].1, Op::Remove) => {}
+                _ => return Err(PatchError::BeyondEnd(idx)),
+            }
+            pos = idx + 1;
+        }
+    }
+
+    for (idx, op) in ops {
+        match op {
+            Op::Insert(v) => {
+                sv.reserve_for(idx);
+                sv.insert(idx, v);
+            }
+            Op::Remove => {
+                sv.remove(idx);
+            }
+            Op::Change(v) => {
+                sv.insert(idx, v);
+            }
+        }
+    }
+
+    if let Some(n) = next_push_index {
+        let len = sv.core.len();
+        if n > sv.core.cap() {
+            sv.reserve(n - len);
+        }
+
+        // We checked above that all slots `>= n` are empty now, and `n` is
+        // `<= cap` after reserving.
+        unsafe {
+            sv.core.set_len(n);
+        }
+    }
+
+    Ok(())
+}
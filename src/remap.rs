@@ -0,0 +1,177 @@
+//! Keeping data in sync with a stable vector when its elements move.
+//!
+//! See [`RemapIndices`] and [`CompactSibling`] for more information.
+
+#[cfg(feature = "alloc")]
+use std::prelude::v1::*;
+
+use std::cmp;
+
+use crate::{StableVecFacade, core::Core};
+
+
+/// Types that store indices into a stable vector and can update them when
+/// the elements of that stable vector are moved.
+///
+/// This is used by
+/// [`make_compact_remapping`][crate::StableVecFacade::make_compact_remapping]
+/// and
+/// [`reordering_make_compact_remapping`][crate::StableVecFacade::reordering_make_compact_remapping]:
+/// after compacting, `remap` is called on every element with a function
+/// mapping old indices to new ones. That function returns `None` for indices
+/// that did not point to an element. Note that the impl for `usize` panics
+/// in that case: store indices which might point to removed elements as
+/// `Option<usize>`.
+///
+/// Before compacting, `remap` is called once more with a function that maps
+/// indices of elements to themselves (and all others to `None`). So an
+/// implementation has to be fine with being called twice and panics (e.g.
+/// of the `usize` impl) happen before any element is moved.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(feature = "alloc")] {
+/// use stable_vec::{RemapIndices, StableVec};
+///
+/// struct Node {
+///     name: char,
+///     parent: Option<usize>,
+/// }
+///
+/// impl RemapIndices for Node {
+///     fn remap(&mut self, f: &dyn Fn(usize) -> Option<usize>) {
+///         self.parent.remap(f);
+///     }
+/// }
+///
+/// let mut sv = StableVec::new();
+/// let root = sv.push(Node { name: 'a', parent: None });
+/// let tmp = sv.push(Node { name: 'b', parent: Some(root) });
+/// let child = sv.push(Node { name: 'c', parent: Some(tmp) });
+/// sv.push(Node { name: 'd', parent: Some(child) });
+/// sv.remove(tmp);
+///
+/// sv.make_compact_remapping();
+/// assert_eq!(sv[1].name, 'c');
+/// assert_eq!(sv[1].parent, None);
+/// assert_eq!(sv[2].parent, Some(1));
+/// # }
+/// ```
+pub trait RemapIndices {
+    /// Replaces all stored indices `i` by `f(i)`.
+    fn remap(&mut self, f: &dyn Fn(usize) -> Option<usize>);
+}
+
+/// A plain `usize` must always point to an element.
+///
+/// # Panics
+///
+/// Panics if `f` returns `None`, i.e. if the index points to a removed
+/// element. There is no sensible value to replace such an index with, and
+/// keeping the old one would silently make it point to a different element
+/// after compacting. When compacting, this panic happens before any element
+/// is moved, so the stable vector stays intact. **Use `Option<usize>` (or
+/// `Vec<usize>`) for indices that might point to removed elements.**
+impl RemapIndices for usize {
+    fn remap(&mut self, f: &dyn Fn(usize) -> Option<usize>) {
+        match f(*self) {
+            Some(new) => *self = new,
+            None => panic!(
+                "index {} points to a removed element (use `Option<usize>` for \
+                    indices that can dangle)",
+                self,
+            ),
+        }
+    }
+}
+
+/// Set to `None` if `f` returns `None`.
+impl RemapIndices for Option<usize> {
+    fn remap(&mut self, f: &dyn Fn(usize) -> Option<usize>) {
+        if let Some(old) = *self {
+            *self = f(old);
+        }
+    }
+}
+
+/// Indices for which `f` returns `None` are removed.
+#[cfg(feature = "alloc")]
+impl RemapIndices for Vec<usize> {
+    fn remap(&mut self, f: &dyn Fn(usize) -> Option<usize>) {
+        self.retain_mut(|idx| match f(*idx) {
+            Some(new) => {
+                *idx = new;
+                true
+            }
+            None => false,
+        });
+    }
+}
+
+macro_rules! impl_for_tuple {
+    ($($name:ident)+) => {
+        impl<$($name: RemapIndices),+> RemapIndices for ($($name,)+) {
+            fn remap(&mut self, f: &dyn Fn(usize) -> Option<usize>) {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = self;
+                $( $name.remap(f); )+
+            }
+        }
+    };
+}
+
+impl_for_tuple!(A);
+impl_for_tuple!(A B);
+impl_for_tuple!(A B C);
+impl_for_tuple!(A B C D);
+impl_for_tuple!(A B C D E);
+impl_for_tuple!(A B C D E F);
+
+
+/// Stable vectors that are indexed like another, primary stable vector and
+/// have to be compacted together with it.
+///
+/// This is used by
+/// [`compact_together`][crate::StableVecFacade::compact_together], which
+/// applies every move of the primary stable vector to all of its siblings.
+/// This trait is object safe and implemented for all stable vectors.
+pub trait CompactSibling {
+    /// Returns `true` if there is an element at index `index`.
+    fn has_element_at(&self, index: usize) -> bool;
+
+    /// Swaps the slots at index `a` and `b`. Both indices are smaller than
+    /// `next_push_index()`.
+    fn swap(&mut self, a: usize, b: usize);
+
+    /// Removes all elements with an index `>= len` and sets
+    /// `next_push_index` to `len` if it is larger.
+    fn truncate(&mut self, len: usize);
+}
+
+impl<T, C: Core<T>> CompactSibling for StableVecFacade<T, C> {
+    fn has_element_at(&self, index: usize) -> bool {
+        StableVecFacade::has_element_at(self, index)
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        StableVecFacade::swap(self, a, b);
+    }
+
+    fn truncate(&mut self, len: usize) {
+        let len = cmp::min(len, self.core.len());
+
+        // Indices returned by `first_filled_slot_from` point to existing
+        // elements. Afterwards, all slots `>= len` are empty and `len` is not
+        // larger than the old `len`, so it is `<= cap`.
+        unsafe {
+            let mut pos = len;
+            while let Some(idx) = self.core.first_filled_slot_from(pos) {
+                self.core.remove_at(idx);
+                self.num_elements -= 1;
+                pos = idx + 1;
+            }
+            self.core.set_len(len);
+        }
+    }
+}
@@ -0,0 +1,680 @@
+//! A stable vector that stores each field of its elements in a separate
+//! column ("structure of arrays").
+//!
+//! See [`StableSoa`] for more information.
+
+use std::{
+    alloc::Layout,
+    any::TypeId,
+    cmp,
+    fmt,
+    iter::FusedIterator,
+    marker::PhantomData,
+    mem::size_of,
+    ptr::{self, NonNull},
+};
+
+use crate::{
+    StableVecFacade,
+    core::{
+        BitVecCore,
+        allocator::{allocate, Allocator, Global},
+    },
+};
+
+
+/// A stable vector of tuples where each tuple field is stored in its own
+/// column.
+///
+/// Conceptually, this is a `StableVec<(A, B, ...)>`. But instead of storing
+/// the tuples next to each other, there is one buffer per tuple field. All
+/// columns share one index space and one occupancy bit vector (the same one
+/// that [`BitVecCore`] uses). This is useful when you often only need some
+/// fields of your elements: iterating over one column only touches the memory
+/// of that column.
+///
+/// `C` has to be a tuple of up to eight `'static` types, e.g.
+/// `StableSoa<(Position, Velocity, Health)>`. Columns are selected by type via
+/// [`column`][StableSoa::column], so the field types should be distinct.
+///
+/// ```
+/// use stable_vec::StableSoa;
+///
+/// #[derive(Debug, PartialEq)]
+/// struct Position(f32);
+/// #[derive(Debug, PartialEq)]
+/// struct Velocity(f32);
+///
+/// let mut soa = StableSoa::<(Position, Velocity)>::new();
+/// let a = soa.push((Position(0.0), Velocity(1.0)));
+/// let b = soa.push((Position(5.0), Velocity(-1.0)));
+/// soa.remove(a);
+///
+/// for (_, (pos, vel)) in soa.iter_mut() {
+///     pos.0 += vel.0;
+/// }
+///
+/// assert_eq!(soa.column::<Position>().get(b), Some(&Position(4.0)));
+/// assert_eq!(soa.column::<Velocity>().count(), 1);
+/// ```
+pub struct StableSoa<C: Columns> {
+    /// Manages the index space and stores which slots are filled. The
+    /// elements are `()`, so this does not allocate element memory. Its
+    /// capacity is never larger than `cap`.
+    slots: StableVecFacade<(), BitVecCore<()>>,
+
+    /// One pointer per column, each to a buffer of `cap` many slots. Empty
+    /// slots are uninitialized.
+    columns: C::Ptrs,
+
+    /// The capacity of all columns.
+    cap: usize,
+
+    _marker: PhantomData<C>,
+}
+
+impl<C: Columns> StableSoa<C> {
+    /// Creates an empty instance. Does not allocate memory.
+    pub fn new() -> Self {
+        Self {
+            slots: StableVecFacade::new(),
+            columns: C::dangling(),
+            cap: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Creates an empty instance with space for at least `capacity` elements
+    /// in each column.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut out = Self::new();
+        out.reserve(capacity);
+        out
+    }
+
+    /// Reserves memory for at least `additional` more elements to be pushed.
+    /// See [`StableVecFacade::reserve`].
+    pub fn reserve(&mut self, additional: usize) {
+        #[inline(never)]
+        #[cold]
+        fn capacity_overflow() -> ! {
+            panic!("capacity overflow in `stable_vec::StableSoa::reserve`");
+        }
+
+        let len = self.slots.next_push_index();
+        let needed = len.checked_add(additional).unwrap_or_else(|| capacity_overflow());
+
+        // The columns are grown first and all at once. Only if that succeeds,
+        // the new capacity is committed and `slots` is grown. If growing
+        // `slots` panics, the columns just have some unused capacity.
+        if needed > self.cap {
+            // Like `StableVecFacade::reserve`, we at least double the
+            // capacity. `self.cap <= isize::MAX`, so this can't overflow.
+            let new_cap = cmp::max(needed, 2 * self.cap);
+            if new_cap > isize::MAX as usize {
+                capacity_overflow();
+            }
+
+            unsafe {
+                C::grow(&mut self.columns, self.cap, new_cap);
+            }
+            self.cap = new_cap;
+        }
+
+        if needed > self.slots.capacity() {
+            self.slots.reserve_exact(self.cap - len);
+        }
+    }
+
+    /// Appends a new element at the back (at index `next_push_index()`) and
+    /// returns its index.
+    pub fn push(&mut self, elem: C) -> usize {
+        self.reserve(1);
+        let index = self.slots.next_push_index();
+
+        unsafe {
+            // Due to `reserve`, `index` is in bounds for all columns. The slot
+            // is empty, so we don't overwrite anything. We write all columns
+            // before marking the slot as filled; writing can't panic.
+            C::write(self.columns, index, elem);
+        }
+        self.slots.push(());
+
+        index
+    }
+
+    /// Removes and returns the element at `index`, clearing the slot in all
+    /// columns. If there is no element at `index`, `None` is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= self.capacity()`.
+    pub fn remove(&mut self, index: usize) -> Option<C> {
+        if index >= self.capacity() {
+            panic!(
+                "`index ({}) >= capacity ({})` in `StableSoa::remove`",
+                index,
+                self.capacity(),
+            );
+        }
+
+        // We first mark the slot as empty and then read the columns.
+        self.slots.remove(index).map(|()| unsafe { C::read(self.columns, index) })
+    }
+
+    /// Returns references to all fields of the element at `index` or `None`
+    /// if there is no element at that index.
+    pub fn get(&self, index: usize) -> Option<C::Refs<'_>> {
+        if self.has_element_at(index) {
+            // The slot is filled, so all columns are initialized at `index`.
+            Some(unsafe { C::get(self.columns, index) })
+        } else {
+            None
+        }
+    }
+
+    /// Returns mutable references to all fields of the element at `index` or
+    /// `None` if there is no element at that index.
+    pub fn get_mut(&mut self, index: usize) -> Option<C::Muts<'_>> {
+        if self.has_element_at(index) {
+            // Same as in `get`; we have `&mut self`, so no other references
+            // into the columns exist.
+            Some(unsafe { C::get_mut(self.columns, index) })
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if there exists an element at `index`.
+    pub fn has_element_at(&self, index: usize) -> bool {
+        self.slots.has_element_at(index)
+    }
+
+    /// Returns the number of elements.
+    pub fn num_elements(&self) -> usize {
+        self.slots.num_elements()
+    }
+
+    /// Returns `true` if there are no elements.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Returns the index that would be returned by calling `push`.
+    pub fn next_push_index(&self) -> usize {
+        self.slots.next_push_index()
+    }
+
+    /// Returns the number of slots each column has memory for.
+    pub fn capacity(&self) -> usize {
+        self.slots.capacity()
+    }
+
+    /// Removes all elements. Does not free any memory.
+    pub fn clear(&mut self) {
+        // We mark each slot as empty before dropping its element. That way,
+        // if a `drop` panics, no element is dropped twice.
+        let mut idx = 0;
+        while let Some(next) = self.slots.first_filled_slot_from(idx) {
+            self.slots.remove(next);
+            unsafe {
+                drop(C::read(self.columns, next));
+            }
+            idx = next + 1;
+        }
+        self.slots.clear();
+    }
+
+    /// Returns an iterator over the indices of all filled slots.
+    pub fn indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.slots.indices()
+    }
+
+    /// Returns an iterator over all elements, yielding the index and
+    /// references to all fields of each element.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, C::Refs<'_>)> + '_ {
+        let columns = self.columns;
+
+        // Each index is filled and we only hand out shared references.
+        self.slots.indices().map(move |i| (i, unsafe { C::get(columns, i) }))
+    }
+
+    /// Returns an iterator over all elements, yielding the index and mutable
+    /// references to all fields of each element.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, C::Muts<'_>)> + '_ {
+        let columns = self.columns;
+
+        // Each index is filled and is yielded only once, so the mutable
+        // references don't alias.
+        self.slots.indices().map(move |i| (i, unsafe { C::get_mut(columns, i) }))
+    }
+
+    /// Returns an iterator over all elements, yielding the index and mutable
+    /// references to the fields with the types in the tuple `S`, e.g.
+    /// `(Position, Velocity)`. Only the memory of those columns is touched.
+    ///
+    /// ```
+    /// use stable_vec::StableSoa;
+    ///
+    /// let mut soa = StableSoa::<(u8, u16, u32)>::new();
+    /// soa.push((1, 2, 3));
+    /// soa.push((4, 5, 6));
+    ///
+    /// for (_, (a, c)) in soa.columns_mut::<(u8, u32)>() {
+    ///     *c += *a as u32;
+    ///     *a = 0;
+    /// }
+    /// assert_eq!(soa.get(1), Some((&0, &5, &10)));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if a type in `S` is not one of the field types or if `S`
+    /// contains a type more than once (as that would result in aliasing
+    /// mutable references). If a type appears several times in `C`, the
+    /// first of those columns is used.
+    pub fn columns_mut<S: Columns>(&mut self) -> impl Iterator<Item = (usize, S::Muts<'_>)> + '_ {
+        assert!(
+            S::distinct(),
+            "`{}` contains a type more than once in `StableSoa::columns_mut`",
+            std::any::type_name::<S>(),
+        );
+        let columns = match S::select(|id| C::column(self.columns, id)) {
+            Some(columns) => columns,
+            None => panic!(
+                "`{}` contains a type that is not a column type in `StableSoa::columns_mut`",
+                std::any::type_name::<S>(),
+            ),
+        };
+
+        // Each index is filled and is yielded only once. All types in `S` are
+        // distinct, so the pointers point to different columns and the
+        // mutable references don't alias.
+        self.slots.indices().map(move |i| (i, unsafe { S::get_mut(columns, i) }))
+    }
+
+    /// Returns a view of the column with type `T`. Iterating over it only
+    /// touches the memory of that column. Several columns can be viewed at
+    /// the same time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` is not one of the field types. If `T` appears several
+    /// times, the first of those columns is returned.
+    pub fn column<T: 'static>(&self) -> Column<'_, T> {
+        Column {
+            slots: &self.slots,
+            ptr: self.column_ptr::<T>(),
+            next: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a mutable view of the column with type `T`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` is not one of the field types. If `T` appears several
+    /// times, the first of those columns is returned.
+    pub fn column_mut<T: 'static>(&mut self) -> ColumnMut<'_, T> {
+        ColumnMut {
+            ptr: self.column_ptr::<T>(),
+            slots: &self.slots,
+            _marker: PhantomData,
+        }
+    }
+
+    fn column_ptr<T: 'static>(&self) -> NonNull<T> {
+        match C::column(self.columns, TypeId::of::<T>()) {
+            Some(ptr) => ptr.cast(),
+            None => panic!(
+                "`{}` is not a column type in `StableSoa::column`",
+                std::any::type_name::<T>(),
+            ),
+        }
+    }
+}
+
+impl<C: Columns> Default for StableSoa<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Columns> Drop for StableSoa<C> {
+    fn drop(&mut self) {
+        self.clear();
+
+        // All slots are empty now.
+        unsafe {
+            C::dealloc(self.columns, self.cap);
+        }
+    }
+}
+
+impl<C: Columns> Extend<C> for StableSoa<C> {
+    fn extend<I: IntoIterator<Item = C>>(&mut self, iter: I) {
+        for elem in iter {
+            self.push(elem);
+        }
+    }
+}
+
+impl<C: Columns> fmt::Debug for StableSoa<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StableSoa")
+            .field("num_elements", &self.num_elements())
+            .field("next_push_index", &self.next_push_index())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+// The columns are owned, so `StableSoa` is `Send`/`Sync` if the columns are.
+unsafe impl<C: Columns + Send> Send for StableSoa<C> {}
+unsafe impl<C: Columns + Sync> Sync for StableSoa<C> {}
+
+
+/// A view of one column of a [`StableSoa`].
+///
+/// Is also an iterator over all filled slots, yielding `(index, &T)`.
+pub struct Column<'a, T> {
+    slots: &'a StableVecFacade<(), BitVecCore<()>>,
+    ptr: NonNull<T>,
+    next: usize,
+    _marker: PhantomData<&'a T>,
+}
+
+impl<'a, T> Column<'a, T> {
+    /// Returns the field at `index` or `None` if there is no element at that
+    /// index.
+    pub fn get(&self, index: usize) -> Option<&'a T> {
+        if self.slots.has_element_at(index) {
+            Some(unsafe { &*self.ptr.as_ptr().add(index) })
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, T> Iterator for Column<'a, T> {
+    type Item = (usize, &'a T);
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.slots.first_filled_slot_from(self.next)?;
+        self.next = index + 1;
+        Some((index, unsafe { &*self.ptr.as_ptr().add(index) }))
+    }
+}
+
+impl<T> FusedIterator for Column<'_, T> {}
+
+impl<T> Clone for Column<'_, T> {
+    fn clone(&self) -> Self {
+        Self { ..*self }
+    }
+}
+
+impl<T> fmt::Debug for Column<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Column").field("next", &self.next).finish()
+    }
+}
+
+
+/// A mutable view of one column of a [`StableSoa`].
+///
+/// Use [`iter_mut`][ColumnMut::iter_mut] or `into_iter()` to iterate over all
+/// filled slots.
+pub struct ColumnMut<'a, T> {
+    slots: &'a StableVecFacade<(), BitVecCore<()>>,
+    ptr: NonNull<T>,
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<'a, T> ColumnMut<'a, T> {
+    /// Returns the field at `index` or `None` if there is no element at that
+    /// index.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if self.slots.has_element_at(index) {
+            Some(unsafe { &*self.ptr.as_ptr().add(index) })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the field at `index` mutably or `None` if there is no element
+    /// at that index.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if self.slots.has_element_at(index) {
+            Some(unsafe { &mut *self.ptr.as_ptr().add(index) })
+        } else {
+            None
+        }
+    }
+
+    /// Returns an iterator over all filled slots, yielding `(index, &mut T)`.
+    pub fn iter_mut(&mut self) -> ColumnIterMut<'_, T> {
+        ColumnIterMut {
+            slots: self.slots,
+            ptr: self.ptr,
+            next: 0,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for ColumnMut<'a, T> {
+    type Item = (usize, &'a mut T);
+    type IntoIter = ColumnIterMut<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        ColumnIterMut {
+            slots: self.slots,
+            ptr: self.ptr,
+            next: 0,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for ColumnMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ColumnMut").finish()
+    }
+}
+
+
+/// Iterator over one column of a [`StableSoa`], yielding `(index, &mut T)`.
+/// See [`ColumnMut::iter_mut`].
+pub struct ColumnIterMut<'a, T> {
+    slots: &'a StableVecFacade<(), BitVecCore<()>>,
+    ptr: NonNull<T>,
+    next: usize,
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<'a, T> Iterator for ColumnIterMut<'a, T> {
+    type Item = (usize, &'a mut T);
+    fn next(&mut self) -> Option<Self::Item> {
+        // Every index is only yielded once, so the references don't alias.
+        let index = self.slots.first_filled_slot_from(self.next)?;
+        self.next = index + 1;
+        Some((index, unsafe { &mut *self.ptr.as_ptr().add(index) }))
+    }
+}
+
+impl<T> FusedIterator for ColumnIterMut<'_, T> {}
+
+impl<T> fmt::Debug for ColumnIterMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ColumnIterMut").field("next", &self.next).finish()
+    }
+}
+
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Tuples that can be stored in a [`StableSoa`]: tuples of one to eight
+/// `'static` types.
+///
+/// This trait is sealed and cannot be implemented outside of this crate. All
+/// methods are implementation details.
+pub unsafe trait Columns: sealed::Sealed + Sized {
+    /// References to all fields, e.g. `(&A, &B)`.
+    type Refs<'a>;
+
+    /// Mutable references to all fields, e.g. `(&mut A, &mut B)`.
+    type Muts<'a>;
+
+    #[doc(hidden)]
+    type Ptrs: Copy + 'static;
+
+    #[doc(hidden)]
+    fn dangling() -> Self::Ptrs;
+
+    /// Grows all columns from `old_cap` to `new_cap` slots. If this panics,
+    /// `ptrs` is unchanged.
+    ///
+    /// **Preconditions**: `old_cap < new_cap <= isize::MAX`.
+    #[doc(hidden)]
+    unsafe fn grow(ptrs: &mut Self::Ptrs, old_cap: usize, new_cap: usize);
+
+    /// Frees all columns, which have `cap` slots.
+    ///
+    /// **Preconditions**: all slots are empty.
+    #[doc(hidden)]
+    unsafe fn dealloc(ptrs: Self::Ptrs, cap: usize);
+
+    /// **Preconditions**: `idx` is in bounds and empty.
+    #[doc(hidden)]
+    unsafe fn write(ptrs: Self::Ptrs, idx: usize, value: Self);
+
+    /// **Preconditions**: `idx` is in bounds and filled. Afterwards, the
+    /// slot has to be treated as empty.
+    #[doc(hidden)]
+    unsafe fn read(ptrs: Self::Ptrs, idx: usize) -> Self;
+
+    /// **Preconditions**: `idx` is in bounds and filled.
+    #[doc(hidden)]
+    unsafe fn get<'a>(ptrs: Self::Ptrs, idx: usize) -> Self::Refs<'a>;
+
+    /// **Preconditions**: `idx` is in bounds and filled and no other
+    /// references to that slot exist.
+    #[doc(hidden)]
+    unsafe fn get_mut<'a>(ptrs: Self::Ptrs, idx: usize) -> Self::Muts<'a>;
+
+    /// Returns the pointer of the first column with the given type.
+    #[doc(hidden)]
+    fn column(ptrs: Self::Ptrs, id: TypeId) -> Option<NonNull<u8>>;
+
+    /// Returns the pointers for the field types of `Self`, looked up via
+    /// `column`, or `None` if `column` returns `None` for one of them.
+    #[doc(hidden)]
+    fn select(column: impl Fn(TypeId) -> Option<NonNull<u8>>) -> Option<Self::Ptrs>;
+
+    /// Returns `true` if all field types are distinct.
+    #[doc(hidden)]
+    fn distinct() -> bool;
+}
+
+/// Returns the layout of a column buffer with `cap` slots.
+fn column_layout<T>(cap: usize) -> Layout {
+    // `cap <= isize::MAX`, but the byte size can still overflow.
+    Layout::array::<T>(cap)
+        .unwrap_or_else(|_| panic!("capacity overflow in `stable_vec::StableSoa`"))
+}
+
+/// Allocates a column buffer with `cap` slots.
+fn alloc_column<T>(cap: usize) -> NonNull<T> {
+    if size_of::<T>() == 0 || cap == 0 {
+        NonNull::dangling()
+    } else {
+        allocate(&Global, column_layout::<T>(cap), false).cast()
+    }
+}
+
+/// Frees a column buffer with `cap` slots that was allocated by
+/// `alloc_column`.
+unsafe fn dealloc_column<T>(ptr: NonNull<T>, cap: usize) {
+    if size_of::<T>() != 0 && cap != 0 {
+        Global.deallocate(ptr.cast(), column_layout::<T>(cap));
+    }
+}
+
+macro_rules! impl_columns {
+    ($($name:ident $idx:tt),+) => {
+        impl<$($name: 'static),+> sealed::Sealed for ($($name,)+) {}
+
+        unsafe impl<$($name: 'static),+> Columns for ($($name,)+) {
+            type Refs<'a> = ($(&'a $name,)+);
+            type Muts<'a> = ($(&'a mut $name,)+);
+            type Ptrs = ($(NonNull<$name>,)+);
+
+            fn dangling() -> Self::Ptrs {
+                ($(NonNull::<$name>::dangling(),)+)
+            }
+
+            unsafe fn grow(ptrs: &mut Self::Ptrs, old_cap: usize, new_cap: usize) {
+                // Check all layouts before allocating anything. Then allocate
+                // all new buffers before touching the old ones: if an
+                // allocation fails, the old buffers are still intact.
+                $( column_layout::<$name>(new_cap); )+
+                let new = ($(alloc_column::<$name>(new_cap),)+);
+
+                $(
+                    ptr::copy_nonoverlapping(ptrs.$idx.as_ptr(), new.$idx.as_ptr(), old_cap);
+                    dealloc_column(ptrs.$idx, old_cap);
+                )+
+                *ptrs = new;
+            }
+
+            unsafe fn dealloc(ptrs: Self::Ptrs, cap: usize) {
+                $( dealloc_column(ptrs.$idx, cap); )+
+            }
+
+            unsafe fn write(ptrs: Self::Ptrs, idx: usize, value: Self) {
+                $( ptr::write(ptrs.$idx.as_ptr().add(idx), value.$idx); )+
+            }
+
+            unsafe fn read(ptrs: Self::Ptrs, idx: usize) -> Self {
+                ($(ptr::read(ptrs.$idx.as_ptr().add(idx)),)+)
+            }
+
+            unsafe fn get<'a>(ptrs: Self::Ptrs, idx: usize) -> Self::Refs<'a> {
+                ($(&*ptrs.$idx.as_ptr().add(idx),)+)
+            }
+
+            unsafe fn get_mut<'a>(ptrs: Self::Ptrs, idx: usize) -> Self::Muts<'a> {
+                ($(&mut *ptrs.$idx.as_ptr().add(idx),)+)
+            }
+
+            fn column(ptrs: Self::Ptrs, id: TypeId) -> Option<NonNull<u8>> {
+                $(
+                    if TypeId::of::<$name>() == id {
+                        return Some(ptrs.$idx.cast());
+                    }
+                )+
+                None
+            }
+
+            fn select(column: impl Fn(TypeId) -> Option<NonNull<u8>>) -> Option<Self::Ptrs> {
+                Some(($(column(TypeId::of::<$name>())?.cast::<$name>(),)+))
+            }
+
+            fn distinct() -> bool {
+                let ids = [$(TypeId::of::<$name>()),+];
+                ids.iter().enumerate().all(|(i, id)| !ids[..i].contains(id))
+            }
+        }
+    };
+}
+
+impl_columns!(A 0);
+impl_columns!(A 0, B 1);
+impl_columns!(A 0, B 1, C 2);
+impl_columns!(A 0, B 1, C 2, D 3);
+impl_columns!(A 0, B 1, C 2, D 3, E 4);
+impl_columns!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_columns!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_columns!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
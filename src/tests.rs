@@ -1,1245 +1,2151 @@
-use std::{
-    prelude::v1::*,
-    fmt::Debug,
-    panic::RefUnwindSafe,
-};
-use crate::{Core, StableVecFacade};
-
-// Miri does not support unwinding, so we only do this check when Miri is not
-// used.
-#[cfg(not(miri))]
+// The shared helpers and the test suite for all cores live in
+// `core::conformance`. These aliases keep the tests in this file short.
 macro_rules! assert_panic {
-    ($($body:tt)*) => {{
-        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-            $($body)*
-        }));
-        if let Ok(x) = res {
-            panic!(
-                "expected panic for '{}', but got '{:?}' ",
-                stringify!($($body)*),
-                x,
-            );
-        }
-    }}
+    ($($body:tt)*) => { crate::__assert_panic!($($body)*) };
 }
 
-#[cfg(miri)]
-macro_rules! assert_panic {
-    ($($body:tt)*) => {};
+macro_rules! assert_sv_eq {
+    ($($args:tt)*) => { crate::__assert_sv_eq!($($args)*) };
 }
 
-fn assert_sv_eq_fn<T, C>(
-    sv: &mut StableVecFacade<T, C>,
-    indices: &[usize],
-    values: &mut [T],
-    last_index: usize,
-)
-where
-    T: Debug + PartialEq + Copy + RefUnwindSafe,
-    C: Core<T> + RefUnwindSafe + Clone,
-{
-    let num_elements = values.len();
-    let combined = indices.iter()
-        .zip(&*values)
-        .map(|(i, e)| (*i, e.clone()))
-        .collect::<Vec<_>>();
-
-    assert_eq!(sv.num_elements(), num_elements, "num_elements check failed");
-    assert_eq!(sv.is_empty(), num_elements == 0, "is_empty check failed");
-    assert_eq!(sv.is_compact(), last_index + 1 == num_elements, "is_compact check failed");
-    assert_eq!(sv.next_push_index(), last_index + 1, "next_push_index check failed");
-    assert!(sv.capacity() >= last_index + 1, "capacity check failed");
-
-    macro_rules! test_iter {
-        ($it:expr, $expected:ident, || $($mapping:tt)*) => {
-            assert_eq!($it $($mapping)* .collect::<Vec<_>>(), $expected);
-            assert_eq!(
-                $it $($mapping)* .rev().collect::<Vec<_>>()
-                    .into_iter().rev().collect::<Vec<_>>(),
-                $expected,
-            );
-            assert_eq!($it.len(), num_elements);
-            assert_eq!($it.count(), num_elements);
-            assert_eq!($it.size_hint(), (num_elements, Some(num_elements)));
-        };
-    }
+macro_rules! gen_tests_for {
+    ($ty:ident) => { crate::conformance_tests!($ty); };
+}
 
-    test_iter!(sv.values(), values, || .cloned());
-    test_iter!(sv.values_mut(), values, || .map(|r| *r));
-    test_iter!(sv.iter(), combined, || .map(|(i, r)| (i, *r)));
-    test_iter!(sv.iter_mut(), combined, || .map(|(i, r)| (i, *r)));
-    test_iter!((&*sv).into_iter(), combined, || .map(|(i, r)| (i, *r)));
-    test_iter!((&mut *sv).into_iter(), combined, || .map(|(i, r)| (i, *r)));
-    test_iter!((*sv).clone().into_iter(), combined, || );
-    test_iter!(sv.indices(), indices, || );
+mod option {
+    use crate::InlineStableVec;
 
+    gen_tests_for!(InlineStableVec);
+}
 
-    assert_eq!(sv, &*values);
-    assert_eq!(sv, &values.to_vec());
+mod bitvec {
+    use std::cell::Cell;
+    use crate::ExternStableVec;
 
-    assert_eq!(format!("{:?}", sv), format!("StableVec {:?}", values));
+    gen_tests_for!(ExternStableVec);
 
-    for i in 0..last_index {
-        if let Ok(index_index) = indices.binary_search(&i) {
-            assert!(sv.has_element_at(i));
-            assert_eq!(sv.get(i), Some(&values[index_index]));
-            assert_eq!(sv.get_mut(i), Some(&mut values[index_index]));
-            assert_eq!(sv[i], values[index_index]);
+    #[test]
+    fn clone_zero_sized_type() {
+        thread_local! {
+            static CLONES: Cell<usize> = const { Cell::new(0) };
+        }
 
-            assert_eq!(sv.first_filled_slot_from(i), Some(i));
-            assert_eq!(sv.first_filled_slot_below(i + 1), Some(i));
-        } else {
-            assert!(!sv.has_element_at(i));
-            assert_eq!(sv.get(i), None);
-            assert_eq!(sv.get_mut(i), None);
-            assert_panic!(sv[i]);
+        // A zero-sized type whose `clone` has a side effect.
+        struct Counted;
+        impl Clone for Counted {
+            fn clone(&self) -> Self {
+                CLONES.with(|c| c.set(c.get() + 1));
+                Counted
+            }
+        }
 
-            assert_eq!(sv.first_empty_slot_from(i), Some(i));
-            assert_eq!(sv.first_empty_slot_below(i + 1), Some(i));
+        let mut sv = ExternStableVec::new();
+        for _ in 0..5 {
+            sv.push(Counted);
         }
+        sv.remove(1);
+
+        let clone = sv.clone();
+        assert_eq!(CLONES.with(Cell::get), 4);
+        assert_eq!(clone.indices().collect::<Vec<_>>(), [0, 2, 3, 4]);
     }
-}
 
-macro_rules! assert_sv_eq {
-    ($left:expr, [$(; $last_index:literal)*]: $ty:ty $(,)*) => {{
-        let sv = &mut $left;
-
-        let last_index = 0 $(+ $last_index)*;
-        let next_index = if last_index == 0 { 0 } else { last_index + 1 };
-
-        assert_eq!(sv.num_elements(), 0, "num_elements check failed");
-        assert!(sv.is_empty(), "is_empty() check failed");
-        assert_eq!(sv.is_compact(), next_index == 0, "is_compact check failed");
-        assert_eq!(sv.next_push_index(), next_index, "next_push_index check failed");
-        assert!(sv.capacity() >= next_index, "capacity check failed");
-
-        assert_eq!(sv.iter().count(), 0);
-        assert_eq!(sv.iter_mut().count(), 0);
-        assert_eq!((&*sv).into_iter().count(), 0);
-        assert_eq!((&mut *sv).into_iter().count(), 0);
-        assert_eq!(sv.into_iter().count(), 0);
-        assert_eq!(sv.indices().count(), 0);
-
-        assert_eq!(sv, &[] as &[$ty]);
-        assert_eq!(sv, &vec![] as &Vec<$ty>);
-
-        assert_eq!(format!("{:?}", sv), "StableVec []");
-    }};
-    ($left:expr, [$( $idx:literal => $val:expr ),* $(; $last_index:literal)*] $(,)*) => {{
-        let indices = [$($idx),*];
-        let mut values = [$($val),*];
-        let last_index = 0 $(+ $last_index)*;
-        let last_index = if last_index == 0 {
-            *indices.last().unwrap()
-        } else {
-            last_index
-        };
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn extend_from_slice_panicking_clone() {
+        // Panics when cloned if it is `true`.
+        struct Bomb(bool);
+        impl Clone for Bomb {
+            fn clone(&self) -> Self {
+                assert!(!self.0, "bomb exploded");
+                Bomb(false)
+            }
+        }
 
-        assert_sv_eq_fn(&mut $left, &indices, &mut values, last_index);
-    }};
+        let mut sv = ExternStableVec::new();
+        sv.push(Bomb(false));
+        assert_panic!(sv.extend_from_slice(&[Bomb(false), Bomb(true), Bomb(false)]));
+
+        // Only the elements cloned before the panic are counted.
+        assert_eq!(sv.num_elements(), 2);
+        assert_eq!(sv.indices().collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(sv.next_push_index(), 4);
+    }
 }
 
-macro_rules! gen_tests_for {
-    ($ty:ident) => {
-        use std::{
-            prelude::v1::*,
-            iter::FromIterator,
-        };
-        use quickcheck_macros::quickcheck;
-        use super::assert_sv_eq_fn;
+mod fused {
+    use crate::FusedStableVec;
+
+    gen_tests_for!(FusedStableVec);
+
+    #[test]
+    fn realloc_moves_elements() {
+        // `u128` has a larger alignment than `usize` on some platforms, which
+        // introduces padding between the bit words and the elements.
+        let mut sv = FusedStableVec::<u128>::new();
+        sv.extend(0..60);
+        sv.remove(7);
+
+        // Grows the bit vector from one to several words, moving the elements.
+        sv.reserve_exact(300);
+        sv.push(1000);
+        assert_eq!(sv.get(6), Some(&6));
+        assert_eq!(sv.get(7), None);
+        assert_eq!(sv.get(60), Some(&1000));
+        assert_eq!(sv.num_elements(), 60);
+
+        // Shrinks the bit vector again, moving the elements back.
+        sv.shrink_to_fit();
+        assert_eq!(sv.capacity(), 61);
+        assert!(sv.values().copied().eq((0..60).filter(|&i| i != 7).chain(Some(1000))));
+    }
+}
 
-        #[test]
-        fn new() {
-            let mut sv = $ty::<String>::new();
-            assert_sv_eq!(sv, []: String);
-        }
+mod interleaved {
+    use crate::InterleavedStableVec;
 
-        #[test]
-        fn default() {
-            let mut sv: $ty<String> = $ty::default();
-            assert_sv_eq!(sv, []: String);
+    gen_tests_for!(InterleavedStableVec);
+
+    #[test]
+    fn search_across_blocks() {
+        let mut sv = InterleavedStableVec::new();
+        sv.extend(0..200u32);
+        for i in (1..70).chain(130..199) {
+            sv.remove(i);
         }
 
-        #[test]
-        fn with_capacity() {
-            let mut sv: $ty<String> = $ty::with_capacity(3);
+        assert_eq!(sv.first_filled_slot_from(1), Some(70));
+        assert_eq!(sv.first_filled_slot_from(130), Some(199));
+        assert_eq!(sv.first_filled_slot_below(130), Some(129));
+        assert_eq!(sv.first_filled_slot_below(70), Some(0));
+        assert_eq!(sv.first_empty_slot_from(70), Some(130));
+        assert_eq!(sv.first_empty_slot_below(199), Some(198));
+        assert_eq!(sv.first_empty_slot_below(129), Some(69));
+        assert!(sv.indices().eq(Some(0).into_iter().chain(70..130).chain(Some(199))));
+
+        // Shrinking within the last block and growing to more blocks.
+        sv.shrink_to_fit();
+        assert_eq!(sv.capacity(), 200);
+        sv.reserve_exact(300);
+        sv.push(500);
+        assert_eq!(sv.first_filled_slot_from(200), Some(200));
+        assert_eq!(sv.first_empty_slot_from(200), Some(201));
+        assert_eq!(sv.num_elements(), 63);
+    }
+}
 
-            assert!(sv.capacity() >= 3);
-            assert_sv_eq!(sv, []: String);
+mod dense {
+    use crate::{DenseStableVec, core::Core};
+
+    gen_tests_for!(DenseStableVec);
+
+    #[test]
+    fn dense_slice() {
+        let mut sv = DenseStableVec::new();
+        sv.extend(0..10u32);
+        sv.remove(2);
+        sv.remove(9);
+        sv.remove(0);
+        sv.swap(1, 2);
+        sv.insert(1, 10);
+
+        let mut pairs: Vec<_> = sv.dense_indices().iter()
+            .copied()
+            .zip(sv.as_dense_slice().iter().copied())
+            .collect();
+        pairs.sort_unstable();
+        assert_eq!(pairs, sv.iter().map(|(i, &e)| (i, e)).collect::<Vec<_>>());
+        assert_sv_eq!(sv, [1 => 10, 2 => 1, 3 => 3, 4 => 4, 5 => 5, 6 => 6, 7 => 7, 8 => 8; 9]);
+
+        for e in sv.as_dense_slice_mut() {
+            *e += 1;
         }
+        assert_eq!(sv[1], 11);
+        assert_eq!(sv[2], 2);
+    }
 
-        #[test]
-        fn reserve() {
-            let mut sv = $ty::<String>::new();
+    #[test]
+    fn values_in_order() {
+        let mut sv = DenseStableVec::new();
+        sv.extend(0..6u32);
+        sv.remove(5);
+        assert!(sv.core.values_in_order().is_some());
+
+        // Removing from the middle moves the last element.
+        sv.remove(1);
+        sv.remove(3);
+        assert!(sv.core.values_in_order().is_none());
+        assert_eq!(sv.values().copied().collect::<Vec<_>>(), [0, 2, 4]);
+
+        // Filling a hole before the last element, too.
+        sv.clear();
+        assert!(sv.core.values_in_order().is_some());
+        sv.reserve_for(9);
+        sv.insert(9, 9);
+        sv.insert(5, 5);
+        assert!(sv.core.values_in_order().is_none());
+        assert_eq!(sv.values().copied().collect::<Vec<_>>(), [5, 9]);
+
+        // Compacting keeps the order.
+        let mut sv = DenseStableVec::new();
+        sv.extend(0..6u32);
+        sv.remove(5);
+        sv.remove(4);
+        sv.reserve_for(9);
+        sv.insert(6, 6);
+        sv.insert(9, 9);
+        sv.make_compact();
+        assert!(sv.core.values_in_order().is_some());
+        assert_eq!(sv.values().copied().collect::<Vec<_>>(), [0, 1, 2, 3, 6, 9]);
+
+        for e in sv.values_mut() {
+            *e *= 2;
+        }
+        assert_eq!(sv.values().rev().copied().collect::<Vec<_>>(), [18, 12, 6, 4, 2, 0]);
+        assert_eq!(sv.values_mut().len(), 6);
 
-            // Reserve for 5
-            sv.reserve(5);
-            assert!(sv.capacity() >= 5);
-            assert_sv_eq!(sv, []: String);
+        sv.swap(0, 1);
+        assert!(sv.core.values_in_order().is_none());
+        assert_eq!(sv.values().copied().collect::<Vec<_>>(), [2, 0, 4, 6, 12, 18]);
+    }
+}
 
-            // Reserve for 2 more
-            sv.reserve(7);
-            assert!(sv.capacity() >= 7);
-            assert_sv_eq!(sv, []: String);
+mod adaptive {
+    use crate::AdaptiveStableVec;
 
-            // Reserving for 6 should do nothing because we already have memory for 7
-            // or more!
-            let cap_before = sv.capacity();
-            sv.reserve(6);
-            assert_eq!(sv.capacity(), cap_before);
-            assert_sv_eq!(sv, []: String);
+    gen_tests_for!(AdaptiveStableVec);
 
-            // After pushing 23 elements, we should have at least memory for 23 items.
-            for _ in 0..23 {
-                sv.push("x".into());
-            }
-            assert!(sv.capacity() >= 23);
-
-            // Reserving for 13 more elements
-            sv.reserve(13);
-            assert!(sv.capacity() >= 36);
-
-            // Reserving for 2 more shouldn't do anything because we already reserved
-            // for 13 additional ones.
-            let cap_before = sv.capacity();
-            sv.reserve(2);
-            assert_eq!(sv.capacity(), cap_before);
-        }
-
-        #[test]
-        fn reserve_exact() {
-            let mut sv = $ty::<String>::new();
-
-            // Reserve for 5
-            sv.reserve_exact(5);
-            assert!(sv.capacity() >= 5);
-            assert_sv_eq!(sv, []: String);
-
-            // Reserve for 2 more
-            sv.reserve_exact(7);
-            assert!(sv.capacity() >= 7);
-            assert_sv_eq!(sv, []: String);
-
-            // Reserving for 6 should do nothing because we already have memory for 7
-            // or more!
-            let cap_before = sv.capacity();
-            sv.reserve_exact(6);
-            assert_eq!(sv.capacity(), cap_before);
-            assert_sv_eq!(sv, []: String);
-
-            // After pushing 23 elements, we should have at least memory for 23 items.
-            for _ in 0..23 {
-                sv.push("x".into());
-            }
-            assert!(sv.capacity() >= 23);
-
-            // Reserving for 13 more elements
-            sv.reserve_exact(13);
-            assert!(sv.capacity() >= 36);
-
-            // Reserving for 2 more shouldn't do anything because we already reserved
-            // for 13 additional ones.
-            let cap_before = sv.capacity();
-            sv.reserve_exact(2);
-            assert_eq!(sv.capacity(), cap_before);
-        }
-
-        #[test]
-        fn reserve_for() {
-            let mut sv = $ty::<String>::new();
-
-            // Reserve for index 5
-            sv.reserve_for(5);
-            assert!(sv.capacity() >= 6);
-            assert_sv_eq!(sv, []: String);
-
-            // Reserve for index 7
-            sv.reserve_for(7);
-            assert!(sv.capacity() >= 8);
-            assert_sv_eq!(sv, []: String);
-
-            // Reserving for index 6 should do nothing because we already have
-            // memory for that index!
-            let cap_before = sv.capacity();
-            sv.reserve_for(6);
-            assert_eq!(sv.capacity(), cap_before);
-            assert_sv_eq!(sv, []: String);
-
-            // After pushing 23 elements, we should have at least memory for 23 items.
-            for _ in 0..23 {
-                sv.push("x".into());
-            }
-            assert!(sv.capacity() >= 23);
+    // The generic tests mostly use small vectors, which never switch to the
+    // sparse representation. So we run them again with a core that is
+    // always sparse (apart from empty vectors).
+    mod always_sparse {
+        use crate::{StableVecFacade, core::AdaptiveCore};
 
-            // Reserving for index 22 shouldn't do anything because we already
-            // have memory for that index.
-            let cap_before = sv.capacity();
-            sv.reserve_for(22);
-            assert_eq!(sv.capacity(), cap_before);
+        #[derive(Clone, Debug)]
+        struct SparseCore<T>(AdaptiveCore<T>);
 
-            // Reserving for new index
-            sv.reserve_for(27);
-            assert!(sv.capacity() >= 28);
+        impl<T> Default for SparseCore<T> {
+            fn default() -> Self { Self(AdaptiveCore::with_threshold(1.0)) }
         }
 
-        #[test]
-        fn from() {
-            assert_sv_eq!(
-                $ty::<String>::from(&[]),
-                []: String,
-            );
-
-            assert_sv_eq!(
-                $ty::from(&[1]),
-                [0 => 1],
-            );
-
-            assert_sv_eq!(
-                $ty::from(&[2, 9, 5]),
-                [0 => 2, 1 => 9, 2 => 5],
-            );
+        // This just forwards everything.
+        impl<T> crate::Core<T> for SparseCore<T> {
+            fn new() -> Self { Self::default() }
+            fn len(&self) -> usize { self.0.len() }
+            unsafe fn set_len(&mut self, new_len: usize) { self.0.set_len(new_len) }
+            fn cap(&self) -> usize { self.0.cap() }
+            unsafe fn realloc(&mut self, new_cap: usize) { self.0.realloc(new_cap) }
+            unsafe fn has_element_at(&self, idx: usize) -> bool { self.0.has_element_at(idx) }
+            unsafe fn insert_at(&mut self, idx: usize, elem: T) { self.0.insert_at(idx, elem) }
+            unsafe fn remove_at(&mut self, idx: usize) -> T { self.0.remove_at(idx) }
+            unsafe fn get_unchecked(&self, idx: usize) -> &T { self.0.get_unchecked(idx) }
+            unsafe fn get_unchecked_mut(&mut self, idx: usize) -> &mut T {
+                self.0.get_unchecked_mut(idx)
+            }
+            fn clear(&mut self) { self.0.clear() }
+            unsafe fn first_filled_slot_from(&self, idx: usize) -> Option<usize> {
+                self.0.first_filled_slot_from(idx)
+            }
+            unsafe fn first_filled_slot_below(&self, idx: usize) -> Option<usize> {
+                self.0.first_filled_slot_below(idx)
+            }
+            unsafe fn first_empty_slot_from(&self, idx: usize) -> Option<usize> {
+                self.0.first_empty_slot_from(idx)
+            }
+            unsafe fn first_empty_slot_below(&self, idx: usize) -> Option<usize> {
+                self.0.first_empty_slot_below(idx)
+            }
+            unsafe fn swap(&mut self, a: usize, b: usize) { self.0.swap(a, b) }
         }
 
-        #[test]
-        fn push_simple() {
-            let mut sv = $ty::new();
+        type SparseStableVec<T> = StableVecFacade<T, SparseCore<T>>;
 
-            sv.push('a');
-            assert_sv_eq!(sv, [0 => 'a']);
+        gen_tests_for!(SparseStableVec);
+    }
 
-            sv.push('b');
-            assert_sv_eq!(sv, [0 => 'a', 1 => 'b']);
+    #[test]
+    fn switch_keeps_indices() {
+        let mut sv = AdaptiveStableVec::with_density_threshold(0.25);
+        sv.extend(0..1000u32);
+        let cap = sv.capacity();
 
-            sv.push('c');
-            assert_sv_eq!(sv, [0 => 'a', 1 => 'b', 2 => 'c']);
+        for i in (0..1000).filter(|i| i % 10 != 0) {
+            sv.remove(i);
         }
+        assert!(sv.is_sparse());
+        assert_eq!(sv.capacity(), cap);
+        assert!(sv.indices().eq((0..1000).step_by(10)));
+        assert!(sv.values().copied().eq((0..1000).step_by(10)));
+        assert_eq!(sv.first_empty_slot_from(0), Some(1));
+        assert_eq!(sv.first_empty_slot_below(11), Some(9));
+
+        // Density needs to reach 0.5 to switch back.
+        for i in 1..400 {
+            sv.insert(i, 1);
+        }
+        assert!(sv.is_sparse());
+        for i in 400..500 {
+            sv.insert(i, 1);
+        }
+        assert!(!sv.is_sparse());
+        assert_eq!(sv[990], 990);
+        assert_eq!(sv[499], 1);
+        assert_eq!(sv.get(501), None);
+
+        // Changing the threshold switches immediately.
+        sv.set_density_threshold(1.0);
+        assert!(sv.is_sparse());
+        assert_eq!(sv.density_threshold(), 1.0);
+        assert_eq!(sv[990], 990);
+    }
 
-        #[test]
-        fn remove_first() {
-            let mut sv = $ty::from_iter(vec!['a', 'b', 'c']);
-
-            assert_eq!(sv.remove_first(), Some('a'));
-            assert_sv_eq!(sv, [1 => 'b', 2 => 'c'; 2]);
-
-            assert_eq!(sv.remove_first(), Some('b'));
-            assert_sv_eq!(sv, [2 => 'c'; 2]);
+    #[test]
+    fn bulk_operations_adapt() {
+        let mut sv = AdaptiveStableVec::with_density_threshold(0.25);
+        sv.extend(0..1000u32);
+        sv.retain(|&x| x % 10 == 0);
+        assert!(sv.is_sparse());
+
+        sv.make_compact();
+        assert!(!sv.is_sparse());
+        assert_eq!(sv.num_elements(), 100);
+        assert_eq!(sv[99], 990);
+
+        sv.retain(|&x| x < 100);
+        assert!(sv.is_sparse());
+        sv.clear();
+        assert!(!sv.is_sparse());
+    }
 
-            sv.push('d');
-            assert_sv_eq!(sv, [2 => 'c', 3 => 'd']);
+    #[test]
+    fn map_keeps_threshold() {
+        for sparse in [false, true] {
+            let mut sv = AdaptiveStableVec::with_density_threshold(0.25);
+            sv.extend(0..1000u32);
+            if sparse {
+                sv.retain(|&x| x % 10 == 0);
+            }
+            assert_eq!(sv.is_sparse(), sparse);
+
+            // Same layout and different layout.
+            let sv = sv.map(|x| x as i32 * 2);
+            assert_eq!(sv.density_threshold(), 0.25);
+            assert_eq!(sv.is_sparse(), sparse);
+            assert_eq!(sv[990], 1980);
+
+            let sv = sv.map(|x| x as u64);
+            assert_eq!(sv.density_threshold(), 0.25);
+            assert_eq!(sv.is_sparse(), sparse);
+            assert_eq!(sv[990], 1980);
+            assert_eq!(sv.num_elements(), if sparse { 100 } else { 1000 });
+        }
+    }
+}
 
-            sv.push('e');
-            assert_sv_eq!(sv, [2 => 'c', 3 => 'd', 4 => 'e']);
+mod range_set {
+    use crate::RangeSetStableVec;
 
-            assert_eq!(sv.remove_first(), Some('c'));
-            assert_sv_eq!(sv, [3 => 'd', 4 => 'e'; 4]);
+    gen_tests_for!(RangeSetStableVec);
 
-            assert_eq!(sv.remove_first(), Some('d'));
-            assert_sv_eq!(sv, [4 => 'e'; 4]);
+    #[test]
+    fn split_and_merge() {
+        let mut sv = RangeSetStableVec::new();
+        sv.extend((0..20).map(|i| i.to_string()));
+        sv.reserve_exact(10);
 
-            assert_eq!(sv.remove_first(), Some('e'));
-            assert_sv_eq!(sv, [; 4]: char);
+        for i in (5..10).chain(12..15) {
+            sv.remove(i);
         }
+        assert_eq!(sv.holes().collect::<Vec<_>>(), [5..10, 12..15]);
+
+        // Splitting a range.
+        sv.insert(7, "x".into());
+        assert_eq!(sv.holes().collect::<Vec<_>>(), [5..7, 8..10, 12..15]);
+
+        // Merging three ranges into one.
+        sv.remove(7);
+        sv.remove(10);
+        sv.remove(11);
+        assert_eq!(sv.holes().collect::<Vec<_>>(), vec![5..15]);
+
+        assert_eq!(sv.first_filled_slot_from(5), Some(15));
+        assert_eq!(sv.first_filled_slot_below(15), Some(4));
+        assert_eq!(sv.first_empty_slot_from(15), Some(20));
+        assert_eq!(sv.first_empty_slot_below(20), Some(14));
+        assert_eq!(sv.next_push_index(), 20);
+        assert_eq!(sv.num_elements(), 10);
+
+        // Moving an element into a hole.
+        sv.swap(0, 9);
+        assert_eq!(sv.holes().collect::<Vec<_>>(), [0..1, 5..9, 10..15]);
+        assert_eq!(sv[9], "0");
+
+        sv.make_compact();
+        sv.shrink_to_fit();
+        assert_eq!(sv.holes().count(), 0);
+        assert_eq!(sv.capacity(), 10);
+        sv.clear();
+        assert!(sv.is_empty());
+    }
+}
 
-        #[test]
-        fn remove_last() {
-            let mut sv = $ty::from_iter(vec!['a', 'b', 'c']);
-
-            assert_eq!(sv.remove_last(), Some('c'));
-            assert_sv_eq!(sv, [0 => 'a', 1 => 'b'; 2]);
+mod array {
+    use std::{
+        prelude::v1::*,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+    use crate::{ArrayStableVec, core::CapacityError};
+
+    #[test]
+    fn new() {
+        let mut sv = ArrayStableVec::<String, 4>::new();
+        assert_eq!(sv.capacity(), 4);
+        assert_sv_eq!(sv, []: String);
+
+        static EMPTY: ArrayStableVec<u32, 8> = ArrayStableVec::new_const();
+        assert_eq!(EMPTY.capacity(), 8);
+        assert_eq!(EMPTY.num_elements(), 0);
+    }
 
-            assert_eq!(sv.remove_last(), Some('b'));
-            assert_sv_eq!(sv, [0 => 'a'; 2]);
+    #[test]
+    fn push_insert_remove() {
+        let mut sv = ArrayStableVec::<char, 4>::new();
+        sv.push('a');
+        sv.push('b');
+        sv.push('c');
+        assert_sv_eq!(sv, [0 => 'a', 1 => 'b', 2 => 'c']);
 
-            sv.push('d');
-            assert_sv_eq!(sv, [0 => 'a', 3 => 'd']);
+        assert_eq!(sv.remove(1), Some('b'));
+        assert_sv_eq!(sv, [0 => 'a', 2 => 'c']);
 
-            sv.push('e');
-            assert_sv_eq!(sv, [0 => 'a', 3 => 'd', 4 => 'e']);
+        assert_eq!(sv.insert(3, 'd'), None);
+        assert_eq!(sv.insert(1, 'x'), None);
+        assert_sv_eq!(sv, [0 => 'a', 1 => 'x', 2 => 'c', 3 => 'd']);
 
-            assert_eq!(sv.remove_last(), Some('e'));
-            assert_sv_eq!(sv, [0 => 'a', 3 => 'd'; 4]);
+        sv.swap(0, 3);
+        sv.remove(1);
+        sv.make_compact();
+        assert_sv_eq!(sv, [0 => 'd', 1 => 'c', 2 => 'a']);
 
-            assert_eq!(sv.remove_last(), Some('d'));
-            assert_sv_eq!(sv, [0 => 'a'; 4]);
+        sv.shrink_to_fit();
+        assert_eq!(sv.capacity(), 4);
+    }
 
-            assert_eq!(sv.remove_last(), Some('a'));
-            assert_sv_eq!(sv, [; 4]: char);
-        }
+    #[test]
+    fn reserve_within_capacity() {
+        let mut sv = ArrayStableVec::<u32, 8>::with_capacity(8);
+        sv.reserve(8);
+        sv.reserve_for(7);
+        assert_eq!(sv.capacity(), 8);
 
-        #[test]
-        fn find_first() {
-            let mut sv = $ty::from_iter(vec!['a', 'b']);
+        sv.extend(0..8);
+        assert_eq!(sv, vec![0, 1, 2, 3, 4, 5, 6, 7]);
+    }
 
-            assert_eq!(sv.find_first(), Some(&'a'));
-            assert_sv_eq!(sv, [0 => 'a', 1 => 'b'; 1]);
+    #[test]
+    fn exceed_capacity() {
+        let mut sv = ArrayStableVec::<u32, 2>::from(&[1, 2]);
+        assert_panic!(sv.push(3));
 
-            sv.push('c');
-            assert_eq!(sv.find_first(), Some(&'a'));
-            assert_sv_eq!(sv, [0 => 'a', 1 => 'b', 2 => 'c'; 2]);
+        let mut sv = ArrayStableVec::<u32, 2>::new();
+        assert_panic!(sv.reserve_for(2));
+    }
 
-            sv.remove(1);
-            assert_eq!(sv.find_first(), Some(&'a'));
-            assert_sv_eq!(sv, [0 => 'a', 2 => 'c'; 2]);
+    #[test]
+    fn try_push_try_reserve() {
+        let mut sv = ArrayStableVec::<u32, 3>::new();
+        assert_eq!(sv.try_reserve(3), Ok(()));
+        assert_eq!(sv.try_reserve(4), Err(CapacityError { required: 4, capacity: 3 }));
+        assert_eq!(sv.try_reserve(usize::MAX), Err(CapacityError {
+            required: usize::MAX,
+            capacity: 3,
+        }));
 
-            sv.remove(0);
-            assert_eq!(sv.find_first(), Some(&'c'));
-            assert_sv_eq!(sv, [2 => 'c'; 2]);
+        assert_eq!(sv.try_push(10), Ok(0));
+        assert_eq!(sv.try_push(11), Ok(1));
+        sv.remove(0);
+        assert_eq!(sv.try_push(12), Ok(2));
+        assert_eq!(sv.try_push(13), Err(13));
+        assert_eq!(sv.try_reserve(0), Ok(()));
+        assert_eq!(sv.try_reserve(1), Err(CapacityError { required: 4, capacity: 3 }));
+        assert_sv_eq!(sv, [1 => 11, 2 => 12]);
+
+        sv.make_compact();
+        assert_eq!(sv.try_push(13), Ok(2));
+        assert_sv_eq!(sv, [0 => 11, 1 => 12, 2 => 13]);
+    }
 
-            sv.clear();
-            assert_eq!(sv.find_first(), None);
-            assert_sv_eq!(sv, []: char);
-        }
+    #[test]
+    fn zero_capacity() {
+        let mut sv = ArrayStableVec::<u32, 0>::new();
+        assert_sv_eq!(sv, []: u32);
+        assert_panic!(sv.push(3));
+        assert_eq!(sv.try_push(3), Err(3));
+    }
 
-        #[test]
-        fn find_first_mut() {
-            let mut sv = $ty::from_iter(vec!['a', 'b']);
+    #[test]
+    fn more_slots_than_usize_bits() {
+        const N: usize = 3 * usize::BITS as usize - 5;
+        let mut sv = ArrayStableVec::<usize, N>::new();
+        sv.extend(0..N);
+        assert_eq!(sv.try_push(0), Err(0));
+
+        sv.retain(|&x| x % 3 != 0);
+        assert_eq!(sv.num_elements(), N - N.div_ceil(3));
+        assert_eq!(sv.first_filled_slot_from(0), Some(1));
+        assert_eq!(sv.first_empty_slot_from(1), Some(3));
+        assert!(sv.values().all(|&x| x % 3 != 0));
+
+        sv.swap(0, N - 2);
+        assert_eq!(sv.get(0), Some(&(N - 2)));
+        assert!(!sv.has_element_at(N - 2));
+
+        sv.make_compact();
+        assert_eq!(sv.next_push_index(), N - N.div_ceil(3));
+        assert!(sv.indices().eq(0..sv.num_elements()));
+    }
 
-            *sv.find_first_mut().unwrap() = 'c';
-            assert_sv_eq!(sv, [0 => 'c', 1 => 'b'; 1]);
+    #[test]
+    fn correct_drop() {
+        static ALIVE_COUNT: AtomicUsize = AtomicUsize::new(0);
 
-            sv.remove(0);
-            *sv.find_first_mut().unwrap() = 'd';
-            assert_sv_eq!(sv, [1 => 'd'; 1]);
+        #[derive(Debug)]
+        struct Dummy;
+        impl Dummy {
+            fn new() -> Self {
+                ALIVE_COUNT.fetch_add(1, Ordering::SeqCst);
+                Self
+            }
+        }
+        impl Drop for Dummy {
+            fn drop(&mut self) {
+                ALIVE_COUNT.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+        impl Clone for Dummy {
+            fn clone(&self) -> Self {
+                Self::new()
+            }
+        }
 
+        {
+            let mut sv = ArrayStableVec::<Dummy, 8>::new();
+            for _ in 0..5 {
+                sv.push(Dummy::new());
+            }
             sv.remove(1);
-            assert_eq!(sv.find_first_mut(), None);
-            assert_sv_eq!(sv, [; 1]: char);
+            sv.remove(3);
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 3);
+
+            let clone = sv.clone();
+            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 6);
+            assert!(clone.has_element_at(4));
+            assert!(!clone.has_element_at(3));
         }
+        assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 0);
+    }
+}
 
-        #[test]
-        fn find_last() {
-            let mut sv = $ty::from_iter(vec!['a', 'b']);
+mod small {
+    use crate::SmallStableVec;
+
+    // Most of the generic tests assume that a new stable vector has a capacity
+    // of 0, so we use a tiny inline capacity for those.
+    type SmallStableVec1<T> = SmallStableVec<T, 1>;
+
+    gen_tests_for!(SmallStableVec1);
+
+    #[test]
+    fn full_inline_bit_word() {
+        const N: usize = usize::BITS as usize;
+        let mut sv = SmallStableVec::<usize, N>::new();
+        sv.extend(0..N);
+        sv.remove(N - 1);
+        assert!(!sv.spilled());
+        assert_eq!(sv.first_empty_slot_from(0), Some(N - 1));
+
+        sv.insert(N - 1, 0);
+        sv.push(N);
+        assert!(sv.spilled());
+        assert_eq!(sv.num_elements(), N + 1);
+        assert_eq!(sv[N - 1], 0);
+    }
 
-            assert_eq!(sv.find_last(), Some(&'b'));
-            assert_sv_eq!(sv, [0 => 'a', 1 => 'b'; 1]);
+    #[test]
+    fn more_inline_slots_than_usize_bits() {
+        const N: usize = 2 * usize::BITS as usize + 3;
+        let mut sv = SmallStableVec::<usize, N>::new();
+        sv.extend(0..N);
+        sv.remove(usize::BITS as usize);
+        assert!(!sv.spilled());
+        assert_eq!(sv.first_empty_slot_from(0), Some(usize::BITS as usize));
+
+        sv.push(N);
+        assert!(sv.spilled());
+        assert_eq!(sv.num_elements(), N);
+        assert!(!sv.has_element_at(usize::BITS as usize));
+        assert_eq!(sv[N - 1], N - 1);
+    }
 
-            sv.push('c');
-            assert_eq!(sv.find_last(), Some(&'c'));
-            assert_sv_eq!(sv, [0 => 'a', 1 => 'b', 2 => 'c'; 2]);
+    #[test]
+    fn spill_and_unspill() {
+        let mut sv = SmallStableVec::<_, 4>::new();
+        sv.extend(0..4);
+        sv.remove(1);
+        assert!(!sv.spilled());
+        assert_sv_eq!(sv, [0 => 0, 2 => 2, 3 => 3]);
+
+        sv.push(4);
+        assert!(sv.spilled());
+        assert_sv_eq!(sv, [0 => 0, 2 => 2, 3 => 3, 4 => 4]);
+
+        // `len` is still 5, so shrinking keeps the elements on the heap.
+        sv.remove(4);
+        sv.shrink_to_fit();
+        assert!(sv.spilled());
+        assert_sv_eq!(sv, [0 => 0, 2 => 2, 3 => 3; 4]);
+
+        sv.make_compact();
+        sv.shrink_to_fit();
+        assert!(!sv.spilled());
+        assert_sv_eq!(sv, [0 => 0, 1 => 2, 2 => 3]);
+    }
+}
 
-            sv.remove(1);
-            assert_eq!(sv.find_last(), Some(&'c'));
-            assert_sv_eq!(sv, [0 => 'a', 2 => 'c'; 2]);
+mod bit_packed {
+    use std::prelude::v1::*;
+    use crate::BitPackedStableVec;
 
-            sv.remove(2);
-            assert_eq!(sv.find_last(), Some(&'a'));
-            assert_sv_eq!(sv, [0 => 'a'; 2]);
+    fn pattern(n: usize) -> BitPackedStableVec {
+        (0..n).map(|i| i % 3 == 0).collect()
+    }
 
-            sv.clear();
-            assert_eq!(sv.find_last(), None);
-            assert_sv_eq!(sv, []: char);
-        }
+    #[test]
+    fn basic() {
+        let mut sv = pattern(200);
+        assert_eq!(sv.num_elements(), 200);
+        assert_eq!(sv.count_true(), 67);
+        assert_eq!(sv.get(3), Some(&true));
+        assert_eq!(sv.get(4), Some(&false));
+
+        assert_eq!(sv.remove(3), Some(true));
+        assert_eq!(sv.remove(4), Some(false));
+        assert_eq!(sv.remove(4), None);
+        assert_eq!(sv.count_true(), 66);
+        assert_eq!(
+            sv.iter_true_indices().take(4).collect::<Vec<_>>(),
+            [0, 6, 9, 12],
+        );
+        assert_eq!(sv.iter_true_indices().last(), Some(198));
+
+        assert_eq!(sv.set(1, true), Some(false));
+        assert_eq!(sv.set(3, true), None);
+        assert_eq!(sv.insert(4, true), None);
+        assert_eq!(sv.iter_true_indices().take(4).collect::<Vec<_>>(), [0, 1, 4, 6]);
+
+        sv.make_compact();
+        assert_eq!(sv.next_push_index(), 199);
+        assert_eq!(sv.count_true(), 68);
+        sv.shrink_to_fit();
+        assert_eq!(sv.capacity(), 199);
+
+        sv.clear();
+        assert_eq!(sv.count_true(), 0);
+        assert_eq!(sv.iter_true_indices().next(), None);
+    }
 
-        #[test]
-        fn find_last_mut() {
-            let mut sv = $ty::from_iter(vec!['a', 'b']);
+    #[test]
+    fn mutable_references() {
+        let mut sv = pattern(130);
+        sv.remove(64);
 
-            *sv.find_last_mut().unwrap() = 'c';
-            assert_sv_eq!(sv, [0 => 'a', 1 => 'c'; 1]);
+        for (i, b) in sv.iter_mut() {
+            *b = i % 2 == 0;
+        }
 
-            sv.remove(1);
-            *sv.find_last_mut().unwrap() = 'd';
-            assert_sv_eq!(sv, [0 => 'd'; 1]);
+        // Reading while the values are unpacked.
+        assert_eq!(sv.count_true(), 64);
+        assert_eq!(sv.iter_true_indices().take(3).collect::<Vec<_>>(), [0, 2, 4]);
+        assert!(sv[128]);
+        let clone = sv.clone();
+        assert_eq!(clone, sv);
+
+        // Modifying packs the values again.
+        *sv.get_mut(1).unwrap() = true;
+        sv.push(true);
+        assert_eq!(sv.count_true(), 66);
+        assert_eq!(sv.iter_true_indices().take(3).collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(clone.count_true(), 64);
+
+        sv.swap(0, 64);
+        assert_eq!(sv.get(0), None);
+        assert_eq!(sv.get(64), Some(&true));
+    }
 
-            sv.remove(0);
-            assert_eq!(sv.find_last_mut(), None);
-            assert_sv_eq!(sv, [; 1]: char);
+    #[test]
+    fn sparse_mutable_references() {
+        // Only the words which are accessed get unpacked, the others have to
+        // keep their values.
+        let mut sv = pattern(10_000);
+        sv[5_000] = true;
+        *sv.get_mut(9_999).unwrap() = true;
+        {
+            let mut it = sv.values_mut();
+            let first = it.next().unwrap();
+            let second = it.next().unwrap();
+            *first = false;
+            *second = true;
         }
 
-        #[test]
-        fn find_first_index() {
-            let mut sv = $ty::from_iter(vec!['a', 'b']);
-
-            assert_eq!(sv.find_first_index(), Some(0));
-            assert_sv_eq!(sv, [0 => 'a', 1 => 'b'; 1]);
+        assert_eq!(sv.count_true(), 3_334 + 1);
+        assert_eq!(sv.iter_true_indices().take(3).collect::<Vec<_>>(), [1, 3, 6]);
+        assert_eq!(sv.iter_true_indices().filter(|&i| i % 3 != 0).collect::<Vec<_>>(), [1, 5_000]);
+        assert_eq!(sv.iter_true_indices().last(), Some(9_999));
+        assert_eq!(sv.clone(), sv);
 
-            sv.remove(0);
-            assert_eq!(sv.find_first_index(), Some(1));
-            assert_sv_eq!(sv, [1 => 'b'; 1]);
+        sv.reserve(100);
+        assert!(sv[1] && !sv[0] && sv[5_000] && sv[9_999] && sv[9_996] && !sv[9_998]);
+        assert_eq!(sv.count_true(), 3_335);
+    }
 
-            sv.push('c');
-            assert_eq!(sv.find_first_index(), Some(1));
-            assert_sv_eq!(sv, [1 => 'b', 2 => 'c'; 2]);
+    #[test]
+    fn and_or() {
+        let mut a = pattern(150);
+        let mut b: BitPackedStableVec = (0..100).map(|i| i % 2 == 0).collect();
+        b.remove(0);
+
+        let mut and = a.clone();
+        and.and(&b);
+        let mut or = a.clone();
+        or.or(&b);
+        for i in 0..150 {
+            let rhs = b.get(i).copied().unwrap_or(false);
+            assert_eq!(and[i], a[i] && rhs);
+            assert_eq!(or[i], a[i] || rhs);
+        }
 
-            sv.remove(1);
-            assert_eq!(sv.find_first_index(), Some(2));
-            assert_sv_eq!(sv, [2 => 'c'; 2]);
+        // Empty slots stay empty.
+        a.remove(6);
+        a.or(&b);
+        assert_eq!(a.get(6), None);
+        assert_eq!(a.num_elements(), 149);
 
-            sv.remove(2);
-            assert_eq!(sv.find_first_index(), None);
-            assert_sv_eq!(sv, [; 2]: char);
+        // `other` with unpacked values.
+        for v in b.values_mut() {
+            *v = true;
         }
+        a.and(&b);
+        assert_eq!(a.count_true(), 65);
+    }
+}
 
-        #[test]
-        fn find_last_index() {
-            let mut sv = $ty::from_iter(vec!['a', 'b']);
-
-            assert_eq!(sv.find_last_index(), Some(1));
-            assert_sv_eq!(sv, [0 => 'a', 1 => 'b'; 1]);
+mod slice {
+    use std::{prelude::v1::*, mem::MaybeUninit, rc::Rc};
+    use crate::{core::{Core, SliceCore}, SliceStableVec};
+
+    #[test]
+    fn fixed_capacity() {
+        let mut data = [MaybeUninit::uninit(); 70];
+        let mut bitmap = [usize::MAX; 3];
+        let mut sv = SliceStableVec::from_slices(&mut data, &mut bitmap);
+        assert_eq!(sv.capacity(), 70);
+        assert!(sv.is_empty());
+        assert_eq!(sv.get(5), None);
+
+        for i in 0..70 {
+            assert_eq!(sv.try_push(i), Ok(i));
+        }
+        assert_eq!(sv.try_push(70), Err(70));
+        assert_panic!(sv.push(70));
+        assert_panic!(sv.reserve(1));
+
+        sv.remove(0);
+        sv.remove(65);
+        assert_eq!(sv.first_filled_slot_from(65), Some(66));
+        sv.make_compact();
+        assert_eq!(sv.next_push_index(), 68);
+        assert_eq!(sv.try_push(100), Ok(68));
+        assert_eq!(sv.iter().last(), Some((68, &100)));
+        let mut values = sv.values().copied().collect::<Vec<_>>();
+        values.sort_unstable();
+        assert_eq!(values, (1..65).chain(66..70).chain(Some(100)).collect::<Vec<_>>());
+
+        // Shrinking does nothing.
+        sv.shrink_to_fit();
+        assert_eq!(sv.capacity(), 70);
+    }
 
-            sv.remove(1);
-            assert_eq!(sv.find_last_index(), Some(0));
-            assert_sv_eq!(sv, [0 => 'a'; 1]);
-
-            sv.push('c');
-            assert_eq!(sv.find_last_index(), Some(2));
-            assert_sv_eq!(sv, [0 => 'a', 2 => 'c'; 2]);
-
-            sv.remove(2);
-            assert_eq!(sv.find_last_index(), Some(0));
-            assert_sv_eq!(sv, [0 => 'a'; 2]);
-
-            sv.remove(0);
-            assert_eq!(sv.find_last_index(), None);
-            assert_sv_eq!(sv, [; 2]: char);
-        }
-
-        #[test]
-        fn first_filled_slot_from() {
-            let mut sv = $ty::new();
-            sv.reserve(10);
-            assert_eq!(sv.first_filled_slot_from(0), None);
-            assert_eq!(sv.first_filled_slot_from(1), None);
-            assert_eq!(sv.first_filled_slot_from(10), None);
-
-            sv.insert(0, 10u32);
-            assert_eq!(sv.first_filled_slot_from(0), Some(0));
-            assert_eq!(sv.first_filled_slot_from(1), None);
-            assert_eq!(sv.first_filled_slot_from(2), None);
-            assert_eq!(sv.first_filled_slot_from(10), None);
-
-            sv.insert(1, 11u32);
-            assert_eq!(sv.first_filled_slot_from(0), Some(0));
-            assert_eq!(sv.first_filled_slot_from(1), Some(1));
-            assert_eq!(sv.first_filled_slot_from(2), None);
-            assert_eq!(sv.first_filled_slot_from(3), None);
-            assert_eq!(sv.first_filled_slot_from(10), None);
-
-            sv.insert(3, 13u32);
-            assert_eq!(sv.first_filled_slot_from(0), Some(0));
-            assert_eq!(sv.first_filled_slot_from(1), Some(1));
-            assert_eq!(sv.first_filled_slot_from(2), Some(3));
-            assert_eq!(sv.first_filled_slot_from(3), Some(3));
-            assert_eq!(sv.first_filled_slot_from(4), None);
-            assert_eq!(sv.first_filled_slot_from(5), None);
-            assert_eq!(sv.first_filled_slot_from(10), None);
-
-            let mut sv = $ty::new();
-            sv.reserve(10);
-            sv.insert(2, 10u32);
-            assert_eq!(sv.first_filled_slot_from(0), Some(2));
-            assert_eq!(sv.first_filled_slot_from(1), Some(2));
-            assert_eq!(sv.first_filled_slot_from(2), Some(2));
-            assert_eq!(sv.first_filled_slot_from(3), None);
-            assert_eq!(sv.first_filled_slot_from(4), None);
-            assert_eq!(sv.first_filled_slot_from(10), None);
-        }
-
-        #[test]
-        fn first_filled_slot_from_medium() {
-            let mut sv = $ty::new();
-            sv.reserve(200);
-
-            for i in (25..60).chain(62..65).chain(66..70).chain(90..120) {
-                sv.insert(i, 27u32);
-            }
+    #[test]
+    fn drops_elements_but_not_buffers() {
+        let counter = Rc::new(());
+        let mut data: Vec<MaybeUninit<Rc<()>>> = (0..10).map(|_| MaybeUninit::uninit()).collect();
+        let mut bitmap = vec![0; 1];
 
-            for i in 0..25 {
-                assert_eq!(sv.first_filled_slot_from(i), Some(25));
-            }
-            for i in 25..60 {
-                assert_eq!(sv.first_filled_slot_from(i), Some(i));
-            }
-            for i in 60..62 {
-                assert_eq!(sv.first_filled_slot_from(i), Some(62));
-            }
-            for i in 62..65 {
-                assert_eq!(sv.first_filled_slot_from(i), Some(i));
-            }
-            for i in 65..66 {
-                assert_eq!(sv.first_filled_slot_from(i), Some(66));
-            }
-            for i in 66..70 {
-                assert_eq!(sv.first_filled_slot_from(i), Some(i));
-            }
-            for i in 70..90 {
-                assert_eq!(sv.first_filled_slot_from(i), Some(90));
-            }
-            for i in 90..120 {
-                assert_eq!(sv.first_filled_slot_from(i), Some(i));
-            }
-            for i in 120..201 {
-                assert_eq!(sv.first_filled_slot_from(i), None);
+        {
+            let mut sv = SliceStableVec::from_slices(&mut data, &mut bitmap);
+            for _ in 0..10 {
+                sv.push(counter.clone());
             }
+            sv.remove(3);
+            assert_eq!(Rc::strong_count(&counter), 10);
         }
+        assert_eq!(Rc::strong_count(&counter), 1);
 
-        // This largest test takes a fairly long time with Miri, so it is disabled
-        // by default.
-        #[cfg(not(miri))]
-        #[test]
-        fn first_filled_slot_from_large() {
-            let mut sv = $ty::new();
-            sv.reserve(2000);
+        // The buffers can be reused.
+        let mut sv = SliceStableVec::from_slices(&mut data, &mut bitmap);
+        sv.push(counter.clone());
+        sv.clear();
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
 
-            for i in (250..600).chain(620..650).chain(652..700).chain(900..1200) {
-                sv.insert(i, 27u32);
-            }
+    #[test]
+    fn short_bitmap() {
+        let mut data = [MaybeUninit::<u8>::uninit(); 65];
+        let mut bitmap = [0; 1];
+        assert_panic!(SliceCore::new(&mut data, &mut bitmap).len());
+    }
+}
 
-            for i in 0..250 {
-                assert_eq!(sv.first_filled_slot_from(i), Some(250));
-            }
-            for i in 250..600 {
-                assert_eq!(sv.first_filled_slot_from(i), Some(i));
-            }
-            for i in 600..620 {
-                assert_eq!(sv.first_filled_slot_from(i), Some(620));
-            }
-            for i in 620..650 {
-                assert_eq!(sv.first_filled_slot_from(i), Some(i));
-            }
-            for i in 650..652 {
-                assert_eq!(sv.first_filled_slot_from(i), Some(652));
-            }
-            for i in 652..700 {
-                assert_eq!(sv.first_filled_slot_from(i), Some(i));
-            }
-            for i in 700..900 {
-                assert_eq!(sv.first_filled_slot_from(i), Some(900));
-            }
-            for i in 900..1200 {
-                assert_eq!(sv.first_filled_slot_from(i), Some(i));
-            }
-            for i in 1200..2001 {
-                assert_eq!(sv.first_filled_slot_from(i), None);
-            }
-        }
+mod checked {
+    use crate::{
+        StableVecFacade,
+        core::{BitVecCore, CheckedCore, Core, OptionCore},
+    };
 
-        #[test]
-        fn first_filled_slot_below_medium() {
-            let mut sv = $ty::new();
-            sv.reserve(200);
+    type CheckedStableVec<T> = StableVecFacade<T, CheckedCore<BitVecCore<T>>>;
 
-            for i in (25..60).chain(62..65).chain(66..70).chain(90..120) {
-                sv.insert(i, 27u32);
-            }
+    gen_tests_for!(CheckedStableVec);
 
-            for i in 0..26 {
-                assert_eq!(sv.first_filled_slot_below(i), None);
-            }
-            for i in 26..61 {
-                assert_eq!(sv.first_filled_slot_below(i), Some(i - 1));
-            }
-            for i in 61..63 {
-                assert_eq!(sv.first_filled_slot_below(i), Some(59));
-            }
-            for i in 63..66 {
-                assert_eq!(sv.first_filled_slot_below(i), Some(i - 1));
-            }
-            for i in 66..67 {
-                assert_eq!(sv.first_filled_slot_below(i), Some(64));
-            }
-            for i in 67..71 {
-                assert_eq!(sv.first_filled_slot_below(i), Some(i - 1));
-            }
-            for i in 71..91 {
-                assert_eq!(sv.first_filled_slot_below(i), Some(69));
+    /// A core that is correct except for the bug `BUG`:
+    /// - `SMALL_REALLOC`: `realloc` does not grow beyond 4 slots.
+    /// - `SKIPPING_SEARCH`: `first_filled_slot_from` skips the slot at `idx`.
+    /// - `LAZY_SWAP`: `swap` only does something if both slots are filled.
+    #[derive(Clone, Default)]
+    struct FaultyCore<const BUG: u8>(OptionCore<u32>);
+
+    const SMALL_REALLOC: u8 = 0;
+    const SKIPPING_SEARCH: u8 = 1;
+    const LAZY_SWAP: u8 = 2;
+
+    impl<const BUG: u8> Core<u32> for FaultyCore<BUG> {
+        fn new() -> Self {
+            Self(OptionCore::new())
+        }
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+        unsafe fn set_len(&mut self, new_len: usize) {
+            self.0.set_len(new_len)
+        }
+        fn cap(&self) -> usize {
+            self.0.cap()
+        }
+        unsafe fn realloc(&mut self, new_cap: usize) {
+            match BUG {
+                SMALL_REALLOC => self.0.realloc(new_cap.min(4)),
+                _ => self.0.realloc(new_cap),
             }
-            for i in 91..121 {
-                assert_eq!(sv.first_filled_slot_below(i), Some(i - 1));
+        }
+        unsafe fn has_element_at(&self, idx: usize) -> bool {
+            self.0.has_element_at(idx)
+        }
+        unsafe fn insert_at(&mut self, idx: usize, elem: u32) {
+            self.0.insert_at(idx, elem)
+        }
+        unsafe fn remove_at(&mut self, idx: usize) -> u32 {
+            self.0.remove_at(idx)
+        }
+        unsafe fn get_unchecked(&self, idx: usize) -> &u32 {
+            self.0.get_unchecked(idx)
+        }
+        unsafe fn get_unchecked_mut(&mut self, idx: usize) -> &mut u32 {
+            self.0.get_unchecked_mut(idx)
+        }
+        fn clear(&mut self) {
+            self.0.clear()
+        }
+        unsafe fn first_filled_slot_from(&self, idx: usize) -> Option<usize> {
+            match BUG {
+                SKIPPING_SEARCH => self.0.first_filled_slot_from(idx + 1),
+                _ => self.0.first_filled_slot_from(idx),
             }
-            for i in 121..201 {
-                assert_eq!(sv.first_filled_slot_below(i), Some(119));
+        }
+        unsafe fn swap(&mut self, a: usize, b: usize) {
+            if BUG != LAZY_SWAP || (self.has_element_at(a) && self.has_element_at(b)) {
+                self.0.swap(a, b);
             }
         }
+    }
 
-        #[test]
-        fn first_empty_slot_from_medium() {
-            let mut sv = $ty::new();
-            sv.reserve(200);
+    type FaultyStableVec<const BUG: u8> = StableVecFacade<u32, CheckedCore<FaultyCore<BUG>>>;
 
-            for i in (25..60).chain(62..65).chain(66..70).chain(90..120) {
-                sv.insert(i, 27u32);
-            }
+    #[test]
+    fn detects_faulty_cores() {
+        assert_panic!(FaultyStableVec::<SMALL_REALLOC>::new().extend(0..10));
 
-            for i in 0..25 {
-                assert_eq!(sv.first_empty_slot_from(i), Some(i));
-            }
-            for i in 25..60 {
-                assert_eq!(sv.first_empty_slot_from(i), Some(60));
-            }
-            for i in 60..62 {
-                assert_eq!(sv.first_empty_slot_from(i), Some(i));
-            }
-            for i in 62..65 {
-                assert_eq!(sv.first_empty_slot_from(i), Some(65));
-            }
-            for i in 65..66 {
-                assert_eq!(sv.first_empty_slot_from(i), Some(i));
-            }
-            for i in 66..70 {
-                assert_eq!(sv.first_empty_slot_from(i), Some(70));
-            }
-            for i in 70..90 {
-                assert_eq!(sv.first_empty_slot_from(i), Some(i));
-            }
-            for i in 90..120 {
-                assert_eq!(sv.first_empty_slot_from(i), Some(120));
-            }
-            for i in 120..200 {
-                assert_eq!(sv.first_empty_slot_from(i), Some(i));
-            }
-            assert_eq!(sv.first_empty_slot_from(200), None);
-        }
+        let mut sv = FaultyStableVec::<SKIPPING_SEARCH>::new();
+        sv.extend(0..10);
+        assert_panic!(sv.first_filled_slot_from(0));
 
-        #[test]
-        fn first_empty_slot_below_medium() {
-            let mut sv = $ty::new();
-            sv.reserve(200);
+        let mut sv = FaultyStableVec::<LAZY_SWAP>::new();
+        sv.extend(0..10);
+        sv.remove(3);
+        assert_panic!(sv.make_compact());
+    }
 
-            for i in (25..60).chain(62..65).chain(66..70).chain(90..120) {
-                sv.insert(i, 27u32);
-            }
+    #[test]
+    fn detects_precondition_violations() {
+        let mut core = CheckedCore::<BitVecCore<u32>>::default();
+        unsafe {
+            core.realloc(4);
+            core.set_len(2);
+            core.insert_at(1, 7);
+            assert_eq!(*core.get_unchecked(1), 7);
+        }
 
-            assert_eq!(sv.first_empty_slot_below(0), None);
-            for i in 1..26 {
-                assert_eq!(sv.first_empty_slot_below(i), Some(i - 1));
-            }
-            for i in 26..61 {
-                assert_eq!(sv.first_empty_slot_below(i), Some(24));
-            }
-            for i in 61..63 {
-                assert_eq!(sv.first_empty_slot_below(i), Some(i - 1));
-            }
-            for i in 63..66 {
-                assert_eq!(sv.first_empty_slot_below(i), Some(61));
-            }
-            for i in 66..67 {
-                assert_eq!(sv.first_empty_slot_below(i), Some(i - 1));
-            }
-            for i in 67..71 {
-                assert_eq!(sv.first_empty_slot_below(i), Some(65));
-            }
-            for i in 71..91 {
-                assert_eq!(sv.first_empty_slot_below(i), Some(i - 1));
-            }
-            for i in 91..121 {
-                assert_eq!(sv.first_empty_slot_below(i), Some(89));
-            }
-            for i in 121..201 {
-                assert_eq!(sv.first_empty_slot_below(i), Some(i - 1));
-            }
+        let mut clone = core.clone();
+        assert_panic!(unsafe { clone.insert_at(3, 0) });
+        assert_panic!(unsafe { clone.remove_at(0) });
+        assert_panic!(unsafe { clone.set_len(1) });
+        assert_panic!(unsafe { clone.realloc(1) });
+        assert_panic!(unsafe { clone.has_element_at(4) });
+        assert_panic!(unsafe { *clone.get_unchecked(0) });
+
+        unsafe {
+            assert_eq!(core.remove_at(1), 7);
         }
+    }
+}
 
-        #[test]
-        fn retain_indices() {
-            let mut sv = $ty::from_iter(vec!['a', 'b', 'c', 'd', 'e']);
+mod counting {
+    use crate::{
+        StableVecFacade,
+        core::{BitVecCore, CoreStats, CountingCore, OptionCore},
+    };
+
+    type CountingStableVec<T> = StableVecFacade<T, CountingCore<BitVecCore<T>>>;
+
+    gen_tests_for!(CountingStableVec);
+
+    #[test]
+    fn stats() {
+        let mut sv = StableVecFacade::<u64, CountingCore<OptionCore<u64>>>::new();
+        sv.reserve_exact(4);
+        sv.extend(0..4);
+        sv.push(4);
+        sv.remove(1);
+        sv.remove(2);
+
+        assert_eq!(sv.stats(), CoreStats {
+            reallocs: 2,
+            realloc_bytes: 4 * 8 + 8 * 8,
+            inserts: 5,
+            removes: 2,
+            ..CoreStats::default()
+        });
+
+        sv.reset_stats();
+        assert_eq!(sv.first_filled_slot_from(1), Some(3));
+        assert_eq!(sv.first_empty_slot_from(5), Some(5));
+        assert_eq!(sv.first_filled_slot_below(3), Some(0));
+        assert_eq!(sv.first_empty_slot_below(1), None);
+        assert_eq!(sv.stats().scans, 4);
+        assert_eq!(sv.stats().scanned_slots, 3 + 1 + 3 + 1);
+
+        sv.reset_stats();
+        sv.make_compact();
+        let stats = sv.stats();
+        assert_eq!(stats.inserts, 0);
+        assert!(stats.swaps > 0 || stats.removes > 0);
+        assert_eq!(sv, vec![0, 3, 4]);
+    }
+}
 
-            assert_sv_eq!(sv, [0 => 'a', 1 => 'b', 2 => 'c', 3 => 'd', 4 => 'e'; 4]);
+mod soft_remove {
+    use std::rc::Rc;
+    use crate::{
+        SoftRemoveStableVec, StableVecFacade,
+        core::{BitVecCore, CheckedCore, SoftRemoveCore},
+    };
+
+    gen_tests_for!(SoftRemoveStableVec);
+
+    type CheckedSoftStableVec<T> = StableVecFacade<T, SoftRemoveCore<CheckedCore<BitVecCore<T>>>>;
+
+    #[test]
+    fn hidden_elements() {
+        let mut sv = CheckedSoftStableVec::from(&[0, 1, 2, 3, 4, 5]);
+        assert!(sv.soft_remove(1));
+        assert!(sv.soft_remove(4));
+        assert!(!sv.soft_remove(4));
+        assert!(!sv.soft_remove(6));
+        sv.remove(2);
+        assert!(!sv.soft_remove(2));
+
+        assert_sv_eq!(sv, [0 => 0, 3 => 3, 5 => 5]);
+        assert_eq!(sv.first_filled_slot_from(1), Some(3));
+        assert_eq!(sv.first_filled_slot_below(5), Some(3));
+        assert_eq!(sv.first_empty_slot_from(3), Some(4));
+        assert_eq!(sv.first_empty_slot_below(4), Some(2));
+        assert_eq!(sv.iter_removed().collect::<Vec<_>>(), [(1, &1), (4, &4)]);
+
+        assert!(sv.restore(4));
+        assert!(!sv.restore(4));
+        assert!(!sv.restore(2));
+        assert_sv_eq!(sv, [0 => 0, 3 => 3, 4 => 4, 5 => 5]);
+
+        // Swapping moves the hidden state with the element.
+        sv.swap(1, 2);
+        assert_eq!(sv.iter_removed().collect::<Vec<_>>(), [(2, &1)]);
+        assert!(sv.restore(2));
+        assert_sv_eq!(sv, [0 => 0, 2 => 1, 3 => 3, 4 => 4, 5 => 5]);
+    }
 
-            sv.retain_indices(|index| index != 2);
-            assert_sv_eq!(sv, [0 => 'a', 1 => 'b', 3 => 'd', 4 => 'e'; 4]);
+    #[test]
+    fn purge() {
+        let mut sv = CheckedSoftStableVec::from(&[0, 1, 2, 3]);
+        sv.soft_remove(0);
+        sv.soft_remove(2);
+        sv.soft_remove(3);
+
+        assert_eq!(sv.purge(0), Some(0));
+        assert_eq!(sv.purge(0), None);
+        assert_eq!(sv.purge(1), None);
+        assert_eq!(sv.iter_removed().count(), 2);
+
+        sv.purge_all();
+        assert_eq!(sv.iter_removed().count(), 0);
+        assert!(!sv.restore(2));
+        assert_sv_eq!(sv, [1 => 1; 3]);
+    }
 
-            sv.retain_indices(|index| index == 0 || index == 3);
-            assert_sv_eq!(sv, [0 => 'a', 3 => 'd'; 4]);
+    #[test]
+    fn drops() {
+        let rc = Rc::new(());
+        let mut sv = CheckedSoftStableVec::new();
+        for _ in 0..200 {
+            sv.push(rc.clone());
+        }
+        for i in (0..200).step_by(2) {
+            sv.soft_remove(i);
+        }
+        assert_eq!(Rc::strong_count(&rc), 201);
+
+        // Inserting into a slot that looks empty drops the hidden element.
+        assert!(sv.insert(0, rc.clone()).is_none());
+        assert_eq!(Rc::strong_count(&rc), 201);
+        assert_eq!(sv.iter_removed().count(), 99);
+
+        let clone = sv.clone();
+        assert_eq!(clone.iter_removed().count(), 99);
+        assert_eq!(Rc::strong_count(&rc), 401);
+        drop(clone);
+
+        // Compacting drops all hidden elements.
+        sv.soft_remove(199);
+        sv.make_compact();
+        assert_eq!(sv.num_elements(), 100);
+        assert_eq!(sv.iter_removed().count(), 0);
+        assert_eq!(Rc::strong_count(&rc), 101);
+
+        sv.soft_remove(3);
+        sv.clear();
+        assert_eq!(Rc::strong_count(&rc), 1);
+
+        for _ in 0..10 {
+            sv.push(rc.clone());
+        }
+        sv.soft_remove(5);
+        sv.soft_remove(9);
+        assert_eq!(sv.clone().into_iter().count(), 8);
+        assert_eq!(Rc::strong_count(&rc), 11);
+        drop(sv);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+}
 
-            sv.retain_indices(|index| index == 0);
-            assert_sv_eq!(sv, [0 => 'a'; 4]);
+mod tracking {
+    use crate::{
+        TrackedStableVec, StableVecFacade,
+        core::{BitVecCore, Changes, CheckedCore, Core, TrackingCore},
+    };
 
-            sv.retain_indices(|index| index != 4);
-            assert_sv_eq!(sv, [0 => 'a'; 4]);
+    gen_tests_for!(TrackedStableVec);
 
-            sv.retain_indices(|_| false);
-            assert_sv_eq!(sv, [; 4]: char);
-        }
+    type CheckedTrackedStableVec<T> = StableVecFacade<T, TrackingCore<CheckedCore<BitVecCore<T>>>>;
 
-        #[test]
-        fn shrink_to_fit() {
-            let mut sv = $ty::from_iter(vec!['a', 'b', 'c', 'd', 'e', 'f']);
-            sv.reserve(100);
-            sv.retain_indices(|index| index != 1 && index != 3 && index != 5);
-            assert_sv_eq!(sv, [0 => 'a', 2 => 'c', 4 => 'e'; 5]);
+    fn changes(inserted: &[usize], removed: &[usize], modified: &[usize]) -> Changes {
+        Changes {
+            inserted: inserted.to_vec(),
+            removed: removed.to_vec(),
+            modified: modified.to_vec(),
+        }
+    }
 
-            sv.shrink_to_fit();
-            assert_sv_eq!(sv, [0 => 'a', 2 => 'c', 4 => 'e'; 5]);
-            assert_eq!(sv.capacity(), 6);
+    #[test]
+    fn marks() {
+        let mut sv = CheckedTrackedStableVec::from(&[0, 1, 2, 3, 4, 5]);
+        assert_eq!(sv.take_changes(), changes(&[0, 1, 2, 3, 4, 5], &[], &[]));
+        assert_eq!(sv.dirty_indices().count(), 0);
+
+        // Read access does not mark anything.
+        assert_eq!(sv.iter().count(), 6);
+        assert_eq!(sv.get(2), Some(&2));
+        assert!(sv.take_changes().is_empty());
+
+        sv.insert(1, 10);
+        sv.remove(2);
+        sv[4] += 1;
+        assert_eq!(sv.take_changes(), changes(&[], &[2], &[1, 4]));
+
+        // Mutable iterators mark each element they yield.
+        for x in sv.values_mut().take(2) {
+            *x += 1;
         }
+        assert_eq!(sv.take_changes(), changes(&[], &[], &[0, 1]));
+        for _ in sv.iter_mut() {}
+        assert_eq!(sv.take_changes(), changes(&[], &[], &[0, 1, 3, 4, 5]));
+
+        // Swapping two empty slots does not change anything.
+        sv.reserve_for(8);
+        sv.swap(7, 8);
+        assert!(sv.take_changes().is_empty());
+
+        sv.swap(0, 8);
+        assert_eq!(sv.take_changes(), changes(&[8], &[0], &[]));
+
+        // Filling and emptying again cancels out, the other way around counts
+        // as modification.
+        sv.insert(0, 0);
+        sv.remove(0);
+        sv.remove(3);
+        sv.insert(3, 30);
+        assert_eq!(sv.take_changes(), changes(&[], &[], &[3]));
+    }
 
-        #[test]
-        fn remove() {
-            let mut sv = $ty::from_iter(vec!['a', 'b', 'c']);
+    #[test]
+    fn clear_and_compact() {
+        let mut sv = CheckedTrackedStableVec::from(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        sv.clear_dirty();
+
+        sv.remove(1);
+        sv.remove(2);
+        sv.make_compact();
+        sv.shrink_to_fit();
+        assert!(sv.capacity() < 8);
+        assert_eq!(sv.take_changes(), changes(&[], &[6, 7], &[1, 2, 3, 4, 5]));
+
+        let clone = sv.clone();
+        sv.clear();
+        assert_eq!(sv.dirty_indices().collect::<Vec<_>>(), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(sv.take_changes(), changes(&[], &[0, 1, 2, 3, 4, 5], &[]));
+
+        let mut clone = clone;
+        assert!(clone.take_changes().is_empty());
+    }
 
-            assert_eq!(sv.remove(1), Some('b'));
-            assert_sv_eq!(sv, [0 => 'a', 2 => 'c']);
+    #[test]
+    fn wrap_existing() {
+        let mut inner = BitVecCore::<u32>::default();
+        unsafe {
+            inner.realloc(4);
+            inner.set_len(3);
+            inner.insert_at(0, 7);
+            inner.insert_at(2, 9);
+        }
 
-            sv.extend_from_slice(&['d', 'e']);
-            assert_eq!(sv.remove(4), Some('e'));
-            assert_sv_eq!(sv, [0 => 'a', 2 => 'c', 3 => 'd'; 4]);
+        let mut core = TrackingCore::new(inner);
+        assert_eq!(core.dirty_indices().count(), 0);
+        unsafe {
+            core.remove_at(0);
+        }
+        assert_eq!(core.take_changes(), changes(&[], &[0], &[]));
+        core.clear();
+    }
+}
 
-            assert_eq!(sv.remove(4), None);
-            assert_sv_eq!(sv, [0 => 'a', 2 => 'c', 3 => 'd'; 4]);
+mod join {
+    use std::{prelude::v1::*, iter::FromIterator};
+    use crate::{
+        BitPackedStableVec, InlineStableVec, StableVec, StableVecFacade,
+        core::{BitVecCore, CheckedCore, Core},
+    };
+
+    /// Creates a stable vector with 300 slots, filling those for which
+    /// `filled` returns `true` with their index.
+    fn create<C: Core<usize> + Default>(filled: impl Fn(usize) -> bool) -> StableVecFacade<usize, C> {
+        let mut sv = StableVecFacade::from_iter(0..300);
+        sv.retain_indices(filled);
+        sv
+    }
 
-            assert_eq!(sv.remove(1), None);
-            assert_sv_eq!(sv, [0 => 'a', 2 => 'c', 3 => 'd'; 4]);
+    fn check<Ca, Cb>(a: &StableVecFacade<usize, Ca>, b: &StableVecFacade<usize, Cb>)
+    where
+        Ca: Core<usize>,
+        Cb: Core<usize>,
+    {
+        let inner: Vec<_> = a.join(b).map(|(i, x, y)| (i, *x, *y)).collect();
+        let expected: Vec<_> = (0..300).filter(|i| i % 3 == 0 && i % 5 == 0).map(|i| (i, i, i)).collect();
+        assert_eq!(inner, expected);
+
+        let left: Vec<_> = a.left_join(b).map(|(i, _, y)| (i, y.is_some())).collect();
+        let expected: Vec<_> = (0..300).filter(|i| i % 3 == 0).map(|i| (i, i % 5 == 0)).collect();
+        assert_eq!(left, expected);
+
+        let outer: Vec<_> = a.outer_join(b).map(|(i, x, y)| (i, x.is_some(), y.is_some())).collect();
+        let expected: Vec<_> = (0..300)
+            .filter(|i| i % 3 == 0 || i % 5 == 0)
+            .map(|i| (i, i % 3 == 0, i % 5 == 0))
+            .collect();
+        assert_eq!(outer, expected);
+    }
 
-            assert_eq!(sv.remove(0), Some('a'));
-            assert_sv_eq!(sv, [2 => 'c', 3 => 'd'; 4]);
-        }
+    #[test]
+    fn mixed_cores() {
+        let bitvec: StableVec<usize> = create(|i| i % 3 == 0);
+        let inline: InlineStableVec<usize> = create(|i| i % 3 == 0);
+        let checked: StableVecFacade<usize, CheckedCore<BitVecCore<usize>>> = create(|i| i % 3 == 0);
+        let other: StableVec<usize> = create(|i| i % 5 == 0);
+        let other_inline: InlineStableVec<usize> = create(|i| i % 5 == 0);
+
+        // Words on both sides.
+        check(&bitvec, &other);
+        check(&checked, &other);
+
+        // Words on one side only and on no side.
+        check(&bitvec, &other_inline);
+        check(&inline, &other);
+        check(&inline, &other_inline);
+    }
 
-        #[test]
-        fn swap() {
-            let mut sv = $ty::from(&['a', 'b', 'c', 'd']);
-            sv.reserve_for(5);
-            assert_eq!(sv.next_push_index(), 4);
+    #[test]
+    fn different_lengths() {
+        let mut a = StableVec::from_iter(0..200);
+        let b = StableVec::from_iter(0..70);
+        a.retain_indices(|i| i >= 60);
 
-            sv.swap(0, 5);
-            assert_sv_eq!(sv, [1 => 'b', 2 => 'c', 3 => 'd', 5 => 'a']);
-            assert_eq!(sv.next_push_index(), 6);
+        assert_eq!(a.join(&b).map(|(i, ..)| i).collect::<Vec<_>>(), (60..70).collect::<Vec<_>>());
+        assert_eq!(a.outer_join(&b).count(), 200);
+        assert_eq!(b.outer_join(&a).count(), 200);
+        assert_eq!(b.left_join(&a).count(), 70);
+    }
 
-            sv.swap(1, 2);
-            assert_sv_eq!(sv, [1 => 'c', 2 => 'b', 3 => 'd', 5 => 'a']);
+    #[test]
+    fn bit_packed() {
+        let flags = BitPackedStableVec::from(&[true, false, true, true]);
+        let mut names = StableVec::from(&["a", "b", "c", "d"]);
+        names.remove(2);
 
-            sv.swap(0, 4);
-            assert_sv_eq!(sv, [1 => 'c', 2 => 'b', 3 => 'd', 5 => 'a']);
+        let joined: Vec<_> = flags.join(&names).map(|(i, f, n)| (i, *f, *n)).collect();
+        assert_eq!(joined, [(0, true, "a"), (1, false, "b"), (3, true, "d")]);
+    }
+}
 
-            assert_panic!(sv.swap(0, sv.capacity()));
-            assert_panic!(sv.swap(sv.capacity(), 0));
-            assert_panic!(sv.swap(0, sv.capacity() + 1));
-            assert_panic!(sv.swap(sv.capacity() + 1, 0));
-        }
+mod map {
+    use std::{prelude::v1::*, rc::Rc};
+    use crate::{
+        InlineStableVec, StableVec, StableVecFacade,
+        core::{BitVecCore, CheckedCore, CountingCore},
+    };
+
+    fn sample() -> StableVec<u32> {
+        let mut sv = StableVec::from(&[1, 2, 3, 4, 5]);
+        sv.remove(1);
+        sv.remove(4);
+        sv
+    }
 
-        // This is a fairly time-consuming test which takes a long time on
-        // Miri, we we do not execute it by default with Miri.
-        #[cfg(not(miri))]
-        #[test]
-        fn large() {
-            let mut sv = $ty::new();
+    #[test]
+    fn in_place() {
+        let sv = sample();
+        let cap = sv.capacity();
+        let ptr = &sv[0] as *const u32 as usize;
 
-            const LIMIT: usize = 200;
-            sv.extend((0u32..LIMIT as u32).rev());
-            sv.push(2 * LIMIT as u32);
+        let sv = sv.map(|x| x as i32 * -2);
+        assert_eq!(&sv[0] as *const i32 as usize, ptr);
+        assert_eq!(sv.capacity(), cap);
+        assert_sv_eq!(sv.clone(), [0 => -2, 2 => -6, 3 => -8; 4]);
+    }
 
-            for i in 0..LIMIT {
-                assert!(sv.has_element_at(i));
-                assert_eq!(sv.get(i).cloned(), Some(LIMIT as u32 - 1 - i as u32));
-            }
-            assert_eq!(sv.num_elements(), LIMIT + 1);
-            assert_eq!(sv.indices().count(), LIMIT + 1);
-            assert_eq!(sv.iter().count(), LIMIT + 1);
-            assert_eq!(sv.iter_mut().count(), LIMIT + 1);
-            assert_eq!(sv.clone().into_iter().count(), LIMIT + 1);
-
-            for hole in 0..LIMIT {
-                let mut clone = sv.clone();
-                clone.remove(hole);
-
-                for i in 0..LIMIT {
-                    if i != hole {
-                        assert!(clone.has_element_at(i));
-                        assert_eq!(clone.get(i).cloned(), Some(LIMIT as u32 - 1 - i as u32));
-                    } else {
-                        assert!(!clone.has_element_at(i));
-                        assert_eq!(clone.get(i), None);
-                    }
-                }
-            }
-        }
+    #[test]
+    fn different_layout() {
+        let sv = sample().map(|x| x as u64 * 1_000_000_000_000);
+        assert_sv_eq!(sv.clone(), [0 => 1_000_000_000_000, 2 => 3_000_000_000_000, 3 => 4_000_000_000_000; 4]);
 
-        #[test]
-        fn zero_sized_type() {
-            let mut sv = $ty::<()>::from(&[(), (), ()]);
+        let sv = sample().map(|x| x as u8);
+        assert_sv_eq!(sv.clone(), [0 => 1, 2 => 3, 3 => 4; 4]);
+    }
 
-            assert_eq!(sv.remove(1), Some(()));
-            assert_sv_eq!(sv, [0 => (), 2 => ()]);
+    #[test]
+    fn zero_sized_type() {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        struct Unit;
 
-            sv.extend_from_slice(&[(), ()]);
-            assert_eq!(sv.remove(4), Some(()));
-            assert_sv_eq!(sv, [0 => (), 2 => (), 3 => (); 4]);
+        let mut sv = StableVec::from(&[(), (), ()]);
+        sv.remove(0);
+        let sv = sv.map(|()| Unit);
+        assert_sv_eq!(sv.clone(), [1 => Unit, 2 => Unit]);
+    }
 
-            assert_eq!(sv.remove(4), None);
-            assert_sv_eq!(sv, [0 => (), 2 => (), 3 => (); 4]);
+    #[test]
+    fn not_in_place() {
+        let mut sv = InlineStableVec::from(&[1u32, 2, 3]);
+        sv.remove(1);
+        let sv = sv.map(|x| x as i32 + 1);
+        assert_sv_eq!(sv.clone(), [0 => 2, 2 => 4]);
+    }
 
-            assert_eq!(sv.remove(1), None);
-            assert_sv_eq!(sv, [0 => (), 2 => (), 3 => (); 4]);
+    #[test]
+    fn wrapper_cores() {
+        let sv = StableVecFacade::<u32, CheckedCore<BitVecCore<u32>>>::from(&[1, 2, 3]);
+        let sv = sv.map(|x| x as f32 / 2.0);
+        assert_sv_eq!(sv.clone(), [0 => 0.5, 1 => 1.0, 2 => 1.5]);
+
+        let sv = StableVecFacade::<u32, CountingCore<BitVecCore<u32>>>::from(&[1, 2, 3]);
+        let inserts = sv.stats().inserts;
+        let sv = sv.map(|x| x as i32);
+        assert_eq!(sv.stats().inserts, inserts);
+        assert_sv_eq!(sv.clone(), [0 => 1, 1 => 2, 2 => 3]);
+    }
 
-            assert_eq!(sv.remove(0), Some(()));
-            assert_sv_eq!(sv, [2 => (), 3 => (); 4]);
+    #[test]
+    fn panic_in_place() {
+        struct A(Rc<()>);
+        struct B(#[allow(dead_code)] Rc<()>);
+
+        let rc = Rc::new(());
+        let mut sv = StableVec::new();
+        for _ in 0..6 {
+            sv.push(A(rc.clone()));
         }
+        sv.remove(1);
+        assert_eq!(Rc::strong_count(&rc), 6);
+
+        let mut calls = 0;
+        assert_panic!({
+            let _ = sv.map(|a| {
+                calls += 1;
+                if calls == 3 {
+                    panic!("boom");
+                }
+                B(a.0)
+            });
+        });
 
-        #[test]
-        fn insert() {
-            let mut sv = $ty::from_iter(vec!['a', 'b', 'c']);
+        // All elements, mapped or not, are dropped.
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+}
 
-            assert_eq!(sv.insert(1, 'x'), Some('b'));
-            assert_sv_eq!(sv, [0 => 'a', 1 => 'x', 2 => 'c']);
+mod observer {
+    use std::prelude::v1::*;
+    use crate::{StableVec, StableVecObserver};
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Insert(usize, char),
+        Remove(usize, char),
+        Replace(usize, char, char),
+        Move(usize, usize),
+        Clear,
+    }
 
-            assert_panic!(sv.insert(3, 'x'));
-            assert_sv_eq!(sv, [0 => 'a', 1 => 'x', 2 => 'c']);
+    #[derive(Default)]
+    struct Log(Vec<Event>);
 
-            assert_eq!(sv.remove(0), Some('a'));
-            assert_eq!(sv.insert(0, 'd'), None);
-            assert_sv_eq!(sv, [0 => 'd', 1 => 'x', 2 => 'c']);
+    impl StableVecObserver<char> for Log {
+        fn on_insert(&mut self, idx: usize, elem: &char) {
+            self.0.push(Event::Insert(idx, *elem));
+        }
+        fn on_remove(&mut self, idx: usize, elem: &char) {
+            self.0.push(Event::Remove(idx, *elem));
+        }
+        fn on_replace(&mut self, idx: usize, old: &char, new: &char) {
+            self.0.push(Event::Replace(idx, *old, *new));
+        }
+        fn on_move(&mut self, from: usize, to: usize) {
+            self.0.push(Event::Move(from, to));
+        }
+        fn on_clear(&mut self) {
+            self.0.push(Event::Clear);
+        }
+    }
 
-            sv.reserve(2);
-            assert_eq!(sv.insert(4, 'f'), None);
-            assert_sv_eq!(sv, [0 => 'd', 1 => 'x', 2 => 'c', 4 => 'f']);
+    #[test]
+    fn events() {
+        use self::Event::*;
+
+        let mut sv = StableVec::from(&['a', 'b']).with_observer(Log::default());
+        sv.push('c');
+        sv.push('d');
+        sv.insert(0, 'x');
+        sv.remove(1);
+        sv.remove(1);
+        sv.retain(|&c| c != 'c');
+        sv.swap(0, 3);
+        sv.swap(1, 3);
+        sv.swap(0, 1);
+        sv.swap(2, 2);
+        sv.reordering_make_compact();
+        sv.clear();
+
+        assert_eq!(sv.observer().0, [
+            Insert(2, 'c'),
+            Insert(3, 'd'),
+            Replace(0, 'a', 'x'),
+            Remove(1, 'b'),
+            Remove(2, 'c'),
+            Move(0, 3),
+            Move(3, 1),
+            Move(0, 1),
+            Clear,
+        ]);
+    }
 
-            assert_eq!(sv.insert(3, 'g'), None);
-            assert_sv_eq!(sv, [0 => 'd', 1 => 'x', 2 => 'c', 3 => 'g', 4 => 'f']);
+    #[test]
+    fn forwarded() {
+        use self::Event::*;
+
+        let mut sv = StableVec::new().with_observer(Log::default());
+        sv.reserve(10);
+        sv.reserve_exact(20);
+        sv.extend(vec!['a', 'b']);
+        sv.extend_from_slice(&['c', 'd', 'e']);
+        assert_eq!(sv.remove_first(), Some('a'));
+        assert_eq!(sv.remove_last(), Some('e'));
+        sv.shrink_to_fit();
+        for (_, c) in sv.iter_mut() {
+            *c = c.to_ascii_uppercase();
         }
 
-        #[test]
-        fn clear() {
-            let mut sv: $ty<String> = $ty::new();
-            sv.clear();
-            assert_sv_eq!(sv, []: String);
+        assert_eq!(sv.capacity(), 5);
+        assert_eq!(sv.iter().map(|(_, &c)| c).collect::<String>(), "BCD");
+        assert_eq!(sv.observer().0, [
+            Insert(0, 'a'),
+            Insert(1, 'b'),
+            Insert(2, 'c'),
+            Insert(3, 'd'),
+            Insert(4, 'e'),
+            Remove(0, 'a'),
+            Remove(4, 'e'),
+        ]);
+    }
+
+    /// Replays all events on a plain vector of slots.
+    #[derive(Default)]
+    struct Mirror(Vec<Option<Option<usize>>>);
 
-            let mut sv = $ty::from_iter(vec![1, 3, 5]);
-            sv.clear();
-            assert_sv_eq!(sv, []: u32);
+    impl Mirror {
+        fn slot(&mut self, idx: usize) -> &mut Option<Option<usize>> {
+            if idx >= self.0.len() {
+                self.0.resize(idx + 1, None);
+            }
+            &mut self.0[idx]
         }
 
-        #[test]
-        fn extend_from_slice() {
-            let mut sv = $ty::new();
+        /// Returns the slots up to the last filled one.
+        fn slots(&self) -> &[Option<Option<usize>>] {
+            let len = self.0.iter().rposition(|s| s.is_some()).map_or(0, |i| i + 1);
+            &self.0[..len]
+        }
+    }
 
-            sv.extend_from_slice(&['a']);
-            assert_sv_eq!(sv, [0 => 'a']);
+    impl StableVecObserver<Option<usize>> for Mirror {
+        fn on_insert(&mut self, idx: usize, elem: &Option<usize>) {
+            *self.slot(idx) = Some(*elem);
+        }
+        fn on_remove(&mut self, idx: usize, _: &Option<usize>) {
+            *self.slot(idx) = None;
+        }
+        fn on_move(&mut self, from: usize, to: usize) {
+            self.slot(from.max(to));
+            self.0.swap(from, to);
+        }
+    }
 
-            sv.push('b');
-            assert_sv_eq!(sv, [0 => 'a', 1 => 'b']);
+    fn slots(sv: &StableVec<Option<usize>>) -> Vec<Option<Option<usize>>> {
+        let len = sv.find_last_index().map_or(0, |i| i + 1);
+        (0..len).map(|i| sv.get(i).copied()).collect()
+    }
 
-            sv.extend_from_slice(&['c', 'd']);
-            assert_sv_eq!(sv, [0 => 'a', 1 => 'b', 2 => 'c', 3 => 'd']);
+    #[test]
+    fn moves() {
+        let mut sv = StableVec::new().with_observer(Mirror::default());
+        sv.extend((0..8).map(Some));
+        sv.remove(1);
+        sv.remove(4);
 
-            assert_eq!(sv.remove_last(), Some('d'));
-            sv.extend_from_slice(&['e']);
-            assert_sv_eq!(sv, [0 => 'a', 1 => 'b', 2 => 'c', 4 => 'e']);
-        }
+        sv.relocate(2, 9);
+        assert_eq!(sv.observer().slots(), slots(&sv));
 
-        #[test]
-        fn clone() {
-            let sv = $ty::<String>::new();
-            assert_sv_eq!(sv.clone(), []: String);
+        sv.apply_permutation(&[3, 9, 0, 7, 1, 8, 2, 5, 6, 4]);
+        assert_eq!(sv.observer().slots(), slots(&sv));
 
-            let sv = $ty::from(&[2, 4]);
-            assert_sv_eq!(sv.clone(), [0 => 2, 1 => 4]);
+        // Changes via `values_mut` are not reported.
+        sv.values_mut().for_each(|v| *v = None);
+        assert!(sv.values().all(Option::is_none));
+        assert!(sv.observer().slots().iter().flatten().all(Option::is_some));
+    }
 
-            let mut sv = $ty::from(&[2, 5, 4]);
+    #[test]
+    fn compact_remapping() {
+        for reordering in [false, true] {
+            // Each element stores its own index, element 7 a removed one.
+            let mut sv = StableVec::new().with_observer(Mirror::default());
+            sv.extend((0..7).map(Some));
+            sv.push(Some(1));
             sv.remove(1);
-            assert_sv_eq!(sv.clone(), [0 => 2, 2 => 4]);
-        }
+            sv.remove(4);
 
-        #[test]
-        fn iter_values_mut() {
-            let mut sv = $ty::from(&[2, 5, 4]);
+            let remap = if reordering {
+                sv.reordering_make_compact_remapping()
+            } else {
+                sv.make_compact_remapping()
+            };
+            assert_eq!(sv.next_push_index(), 6);
 
-            for (i, x) in &mut sv {
-                *x += i;
+            // The observer saw the moves, but not the remapping.
+            let mirrored = sv.observer().slots().to_vec();
+            assert_eq!(mirrored.len(), 6);
+            for (idx, old) in mirrored.into_iter().enumerate() {
+                let old = old.unwrap();
+                if old == Some(1) {
+                    assert_eq!(sv[idx], None);
+                } else {
+                    assert_eq!(remap[old.unwrap()], Some(idx));
+                    assert_eq!(sv[idx], Some(idx));
+                }
             }
-            assert_sv_eq!(sv, [0 => 2, 1 => 6, 2 => 6]);
+        }
+    }
+}
 
-            for x in sv.values_mut() {
-                *x -= 1;
-            }
-            assert_sv_eq!(sv, [0 => 1, 1 => 5, 2 => 5]);
+mod patch {
+    use std::{prelude::v1::*, cell::Cell};
+    use crate::{ExternStableVec, InlineStableVec, Patch, PatchError, StableVec};
+
+    #[test]
+    fn compose_errors() {
+        let insert = Patch { inserted: vec![(0, 'a')], ..Patch::default() };
+        let remove = Patch { removed: vec![0], ..Patch::default() };
+        let change = Patch { changed: vec![(0, 'b')], ..Patch::default() };
+
+        assert_eq!(insert.clone().compose(insert.clone()), Err(PatchError::SlotFilled(0)));
+        assert_eq!(change.clone().compose(insert.clone()), Err(PatchError::SlotFilled(0)));
+        assert_eq!(remove.clone().compose(remove.clone()), Err(PatchError::SlotEmpty(0)));
+        assert_eq!(remove.clone().compose(change.clone()), Err(PatchError::SlotEmpty(0)));
+
+        let duplicate = Patch::<char> { removed: vec![3, 3], ..Patch::default() };
+        assert_eq!(duplicate.compose(Patch::default()), Err(PatchError::DuplicateIndex(3)));
+
+        assert!(insert.clone().compose(remove.clone()).unwrap().is_empty());
+        assert_eq!(
+            remove.compose(insert).unwrap(),
+            Patch { changed: vec![(0, 'a')], ..Patch::default() },
+        );
+    }
 
-            for (i, x) in sv.iter_mut() {
-                *x *= i;
-            }
-            assert_sv_eq!(sv, [0 => 0, 1 => 5, 2 => 10]);
-        }
-
-        #[test]
-        fn iter() {
-            let sv = $ty::from(&[5, 6, 7, 8, 9]);
-            let mut it = sv.iter();
-
-            assert_eq!(it.len(), 5);
-            assert_eq!(it.next(), Some((0, &5)));
-            assert_eq!(it.len(), 4);
-            assert_eq!(it.next_back(), Some((4, &9)));
-            assert_eq!(it.len(), 3);
-            assert_eq!(it.next(), Some((1, &6)));
-            assert_eq!(it.len(), 2);
-            assert_eq!(it.clone().last(), Some((3, &8)));
-            assert_eq!(it.next_back(), Some((3, &8)));
-            assert_eq!(it.len(), 1);
-            assert_eq!(it.next_back(), Some((2, &7)));
-            assert_eq!(it.len(), 0);
-            assert_eq!(it.next(), None);
-        }
-
-        #[test]
-        fn iter_mut() {
-            let mut sv = $ty::from(&[5, 6, 7, 8, 9]);
-            let mut it = sv.iter_mut();
-
-            assert_eq!(it.len(), 5);
-            assert_eq!(it.next(), Some((0, &mut 5)));
-            assert_eq!(it.len(), 4);
-            assert_eq!(it.next_back(), Some((4, &mut 9)));
-            assert_eq!(it.len(), 3);
-            assert_eq!(it.next(), Some((1, &mut 6)));
-            assert_eq!(it.len(), 2);
-            assert_eq!(it.next_back(), Some((3, &mut 8)));
-            assert_eq!(it.len(), 1);
-            assert_eq!(it.next_back(), Some((2, &mut 7)));
-            assert_eq!(it.len(), 0);
-            assert_eq!(it.next(), None);
-        }
-
-        #[test]
-        fn into_iter() {
-            let sv = $ty::from(&[5, 6, 7, 8, 9]);
-            let mut it = sv.into_iter();
-
-            assert_eq!(it.len(), 5);
-            assert_eq!(it.next(), Some((0, 5)));
-            assert_eq!(it.len(), 4);
-            assert_eq!(it.next_back(), Some((4, 9)));
-            assert_eq!(it.len(), 3);
-            assert_eq!(it.next(), Some((1, 6)));
-            assert_eq!(it.len(), 2);
-            assert_eq!(it.clone().last(), Some((3, 8)));
-            assert_eq!(it.next_back(), Some((3, 8)));
-            assert_eq!(it.len(), 1);
-            assert_eq!(it.next_back(), Some((2, 7)));
-            assert_eq!(it.len(), 0);
-            assert_eq!(it.next(), None);
-        }
-
-        #[test]
-        fn index_mut() {
-            let mut sv = $ty::from(&[2, 5, 4]);
-
-            sv[1] = 8;
-            assert_sv_eq!(sv, [0 => 2, 1 => 8, 2 => 4]);
-
-            sv[2] = 5;
-            assert_sv_eq!(sv, [0 => 2, 1 => 8, 2 => 5]);
-        }
-
-        #[test]
-        fn index_panic() {
-            let mut sv: $ty<_> = $ty::from(&[2, 5, 4]);
-            sv.remove(1);
+    #[test]
+    fn only_trailing_holes_differ() {
+        let mut client = StableVec::from(&[1, 2]);
+        let mut server = client.clone();
+        server.push(3);
+        server.remove(2);
+
+        let patch = client.diff(&server);
+        assert_eq!(patch, Patch { next_push_index: Some(3), ..Patch::default() });
+        client.apply(patch).unwrap();
+        assert_eq!(client.next_push_index(), 3);
+        assert_eq!(client, server);
+
+        // And back again.
+        let patch = server.diff(&StableVec::from(&[1, 2]));
+        assert_eq!(patch, Patch { next_push_index: Some(2), ..Patch::default() });
+        server.apply(patch).unwrap();
+        assert_eq!(server.next_push_index(), 2);
+        assert!(server.diff(&StableVec::from(&[1, 2])).is_empty());
+    }
+
+    #[test]
+    fn next_push_index() {
+        let mut sv = StableVec::from(&[1, 2, 3]);
+
+        // Elements at and above the new `next_push_index` have to be removed.
+        let shrink = Patch { next_push_index: Some(1), ..Patch::default() };
+        assert_eq!(sv.apply(shrink.clone()), Err(PatchError::BeyondEnd(1)));
+        let keep = Patch { removed: vec![1], ..shrink.clone() };
+        assert_eq!(sv.apply(keep), Err(PatchError::BeyondEnd(2)));
+        let insert = Patch { inserted: vec![(5, 5)], removed: vec![1, 2], ..shrink };
+        assert_eq!(sv.apply(insert), Err(PatchError::BeyondEnd(5)));
+        assert_eq!(sv.next_push_index(), 3);
+
+        let shrink = Patch { removed: vec![1, 2], next_push_index: Some(1), ..Patch::default() };
+        sv.apply(shrink.clone()).unwrap();
+        assert_eq!(sv, vec![1]);
+        assert_eq!(sv.next_push_index(), 1);
+
+        // Growing reserves memory.
+        let grow = Patch { next_push_index: Some(100), ..Patch::default() };
+        sv.apply(grow.clone()).unwrap();
+        assert_eq!(sv.next_push_index(), 100);
+        assert!(sv.capacity() >= 100);
+        assert_eq!(sv.push(7), 100);
+
+        // If the second patch keeps the `next_push_index`, its inserts still
+        // grow it.
+        let insert = Patch { inserted: vec![(4, 4)], ..Patch::default() };
+        assert_eq!(shrink.clone().compose(insert).unwrap().next_push_index, Some(5));
+        assert_eq!(shrink.compose(grow).unwrap().next_push_index, Some(100));
+    }
 
-            assert_panic!(sv[1]);
-            assert_panic!(sv[3]);
+    #[test]
+    fn mixed_cores() {
+        let a = StableVec::from(&[1, 2, 3]);
+        let mut b = InlineStableVec::from(&[1, 5, 3, 4]);
+        b.remove(0);
+
+        let patch = a.diff(&b);
+        assert_eq!(patch.inserted, [(3, 4)]);
+        assert_eq!(patch.removed, [0]);
+        assert_eq!(patch.changed, [(1, 5)]);
+
+        let back = b.diff(&a);
+        assert_eq!(back.inserted, [(0, 1)]);
+        assert_eq!(back.removed, [3]);
+        assert_eq!(back.changed, [(1, 2)]);
+    }
 
-            sv.reserve(10);
-            assert_panic!(sv[8]);
+    #[test]
+    fn only_common_elements_compared() {
+        thread_local! {
+            static COMPARISONS: Cell<usize> = const { Cell::new(0) };
         }
 
-        #[test]
-        fn correct_drop() {
-            use std::sync::atomic::{Ordering, AtomicIsize};
-
-            static ALIVE_COUNT: AtomicIsize = AtomicIsize::new(0);
+        #[derive(Clone, Debug)]
+        struct Counted(u32);
 
-            struct Dummy(char);
-            impl Dummy {
-                fn new(c: char) -> Self {
-                    ALIVE_COUNT.fetch_add(1, Ordering::SeqCst);
-                    Self(c)
-                }
-            }
-            impl Drop for Dummy {
-                fn drop(&mut self) {
-                    ALIVE_COUNT.fetch_sub(1, Ordering::SeqCst);
-                }
-            }
-            impl Clone for Dummy {
-                fn clone(&self) -> Self {
-                    Self::new(self.0)
-                }
+        impl PartialEq for Counted {
+            fn eq(&self, other: &Self) -> bool {
+                COMPARISONS.with(|c| c.set(c.get() + 1));
+                self.0 == other.0
             }
+        }
 
-            let mut sv = $ty::new();
+        let mut a = ExternStableVec::new();
+        let mut b = ExternStableVec::new();
+        for i in 0..200 {
+            a.push(Counted(i));
+            b.push(Counted(i));
+        }
+        a.retain_indices(|i| i % 2 == 0);
+        b.retain_indices(|i| i % 3 == 0);
+
+        let patch = a.diff(&b);
+        assert_eq!(COMPARISONS.with(|c| c.get()), 34);
+        assert_eq!(patch.inserted.len(), 67 - 34);
+        assert_eq!(patch.removed.len(), 100 - 34);
+        assert!(patch.changed.is_empty());
+    }
+}
 
-            sv.push(Dummy::new('a'));
-            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 1);
+mod remap {
+    use std::prelude::v1::*;
+    use crate::{BitPackedStableVec, InlineStableVec, RemapIndices, StableVec};
 
-            sv.push(Dummy::new('b'));
-            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 2);
+    fn f(old: usize) -> Option<usize> {
+        match old {
+            0 => Some(1),
+            1 => None,
+            n => Some(n - 1),
+        }
+    }
 
-            sv.push(Dummy::new('c'));
-            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 3);
+    #[test]
+    fn impls() {
+        let mut i = 0usize;
+        i.remap(&f);
+        assert_eq!(i, 1);
+        assert_panic!(i.remap(&f));
+
+        let mut o = Some(3usize);
+        o.remap(&f);
+        assert_eq!(o, Some(2));
+        let mut o = Some(1usize);
+        o.remap(&f);
+        assert_eq!(o, None);
+        let mut o: Option<usize> = None;
+        o.remap(&f);
+        assert_eq!(o, None);
+
+        let mut v = vec![5usize, 1, 0, 1, 2];
+        v.remap(&f);
+        assert_eq!(v, [4, 1, 1]);
+
+        let mut t = (0usize, Some(1usize), vec![3usize], (Some(4usize),));
+        t.remap(&f);
+        assert_eq!(t, (1, None, vec![2], (Some(3),)));
+    }
 
-            sv.remove(1);
-            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 2);
+    #[test]
+    fn graph() {
+        // Each node stores the indices of its neighbours.
+        let mut sv: StableVec<Vec<usize>> = StableVec::new();
+        let a = sv.push(vec![]);
+        let b = sv.push(vec![a]);
+        let c = sv.push(vec![a, b]);
+        let d = sv.push(vec![c, b, a]);
+        sv[a].push(d);
+        sv.remove(b);
+
+        let remap = sv.reordering_make_compact_remapping();
+        assert_eq!(remap, [Some(0), None, Some(2), Some(1)]);
+        assert_eq!(sv, &[vec![1usize], vec![2, 0], vec![0]] as &[_]);
+    }
 
-            sv.extend_from_slice(&[Dummy::new('d'), Dummy::new('e')]);
-            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 4);
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn dangling_usize_panics_before_compacting() {
+        // Element 3 stores the index of the removed element 1.
+        let mut sv = StableVec::from(&[(0usize, None), (0, None), (0, Some(1usize)), (1, None)]);
+        sv.remove(1);
+        sv[0].1 = Some(3);
+
+        for reordering in [false, true] {
+            let mut sv = sv.clone();
+            if reordering {
+                assert_panic!(sv.reordering_make_compact_remapping());
+            } else {
+                assert_panic!(sv.make_compact_remapping());
+            }
+
+            // Nothing was moved and only the dangling `Option` was cleared.
+            assert_eq!(sv.indices().collect::<Vec<_>>(), [0, 2, 3]);
+            assert_eq!(sv[0], (0, Some(3)));
+            assert_eq!(sv[2], (0, None));
+            assert_eq!(sv[3], (1, None));
+        }
+    }
 
-            sv.remove_first();
-            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 3);
+    #[test]
+    fn compact_together_mixed_cores() {
+        let mut primary = StableVec::from(&['a', 'b', 'c', 'd']);
+        let mut flags = BitPackedStableVec::from(&[true, false, true, false]);
+        let mut names = InlineStableVec::from(&["a", "b", "c", "d"]);
+        primary.remove(0);
+        primary.remove(2);
+        names.remove(2);
+
+        primary.compact_together(&mut [&mut flags, &mut names]);
+        assert_eq!(primary, &['b', 'd'] as &[_]);
+        assert_eq!(flags, &[false, false] as &[_]);
+        assert_eq!(names, &["b", "d"] as &[_]);
+        assert_eq!(flags.next_push_index(), 2);
+        assert_eq!(names.next_push_index(), 2);
+    }
+}
 
-            sv.retain(|c| c.0 != 'd');
-            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 2);
+mod soa {
+    use std::{prelude::v1::*, rc::Rc};
+    use crate::StableSoa;
 
-            {
-                let mut clone = sv.clone();
-                assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 4);
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Position(i32);
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Velocity(i32);
 
-                clone.reordering_make_compact();
-                assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 4);
-            }
-            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 2);
+    #[test]
+    fn push_remove_get() {
+        let mut soa = StableSoa::<(Position, Velocity, String)>::new();
+        assert!(soa.is_empty());
+        assert_eq!(soa.get(0), None);
+
+        for i in 0..10 {
+            assert_eq!(soa.push((Position(i), Velocity(-i), i.to_string())), i as usize);
+        }
+        assert_eq!(soa.num_elements(), 10);
+
+        assert_eq!(
+            soa.remove(3),
+            Some((Position(3), Velocity(-3), "3".to_string())),
+        );
+        assert_eq!(soa.remove(3), None);
+        assert!(!soa.has_element_at(3));
+        assert_eq!(soa.get(3), None);
+        assert_eq!(soa.get(4), Some((&Position(4), &Velocity(-4), &"4".to_string())));
+        assert_eq!(soa.next_push_index(), 10);
+        assert_eq!(soa.num_elements(), 9);
+
+        if let Some((pos, _, name)) = soa.get_mut(4) {
+            pos.0 = 40;
+            name.push('!');
+        }
+        assert_eq!(soa.get(4), Some((&Position(40), &Velocity(-4), &"4!".to_string())));
 
+        assert_panic!(soa.remove(soa.capacity()));
+    }
 
-            sv.make_compact();
-            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 2);
+    #[test]
+    fn columns() {
+        let mut soa = StableSoa::<(Position, Velocity)>::with_capacity(3);
+        assert!(soa.capacity() >= 3);
+        soa.extend((0..6).map(|i| (Position(i), Velocity(1))));
+        soa.remove(0);
+        soa.remove(4);
+
+        let positions = soa.column::<Position>();
+        let velocities = soa.column::<Velocity>();
+        assert_eq!(
+            positions.map(|(i, p)| (i, p.0)).collect::<Vec<_>>(),
+            [(1, 1), (2, 2), (3, 3), (5, 5)],
+        );
+        assert_eq!(velocities.clone().count(), 4);
+        assert_eq!(velocities.get(0), None);
+        assert_eq!(velocities.get(5), Some(&Velocity(1)));
+
+        let mut velocities = soa.column_mut::<Velocity>();
+        for (i, v) in velocities.iter_mut() {
+            v.0 = i as i32 * 10;
+        }
+        if let Some(v) = velocities.get_mut(2) {
+            v.0 = -1;
+        }
+        assert_eq!(velocities.get(4), None);
 
-            sv.clear();
-            assert_eq!(ALIVE_COUNT.load(Ordering::SeqCst), 0);
+        for (_, (pos, vel)) in soa.iter_mut() {
+            pos.0 += vel.0;
         }
+        assert_eq!(
+            soa.iter().map(|(i, (p, v))| (i, p.0, v.0)).collect::<Vec<_>>(),
+            [(1, 11, 10), (2, 1, -1), (3, 33, 30), (5, 55, 50)],
+        );
+        assert_eq!(soa.indices().collect::<Vec<_>>(), [1, 2, 3, 5]);
 
-        #[test]
-        fn compact_tiny() {
-            let mut sv = $ty::from(&[1.0, 2.0, 3.0]);
-            sv.remove(1);
-            assert_sv_eq!(sv, [0 => 1.0, 2 => 3.0]);
+        assert_panic!(soa.column::<String>());
+    }
+
+    #[test]
+    fn columns_mut() {
+        let mut soa = StableSoa::<(Position, String, Velocity)>::new();
+        soa.extend((0..5).map(|i| (Position(i), i.to_string(), Velocity(i * 10))));
+        soa.remove(2);
+
+        // Any subset in any order.
+        for (i, (vel, pos)) in soa.columns_mut::<(Velocity, Position)>() {
+            pos.0 += vel.0;
+            vel.0 = -(i as i32);
+        }
+        for (_, (name,)) in soa.columns_mut::<(String,)>() {
+            name.push('!');
+        }
+        assert_eq!(
+            soa.iter().map(|(i, (p, n, v))| (i, p.0, n.clone(), v.0)).collect::<Vec<_>>(),
+            [
+                (0, 0, "0!".to_string(), 0),
+                (1, 11, "1!".to_string(), -1),
+                (3, 33, "3!".to_string(), -3),
+                (4, 44, "4!".to_string(), -4),
+            ],
+        );
+
+        assert_panic!(soa.columns_mut::<(Position, u8)>().count());
+        assert_panic!(soa.columns_mut::<(Position, Position)>().count());
+    }
 
-            sv.make_compact();
-            assert_sv_eq!(sv, [0 => 1.0, 1 => 3.0]);
-            assert_eq!(sv, vec![1.0, 3.0]);
+    #[test]
+    fn drops() {
+        let counter = Rc::new(());
+        let mut soa = StableSoa::<(Rc<()>, (), Rc<()>)>::new();
+        for _ in 0..100 {
+            soa.push((counter.clone(), (), counter.clone()));
         }
+        assert_eq!(Rc::strong_count(&counter), 201);
 
-        #[test]
-        fn extend_from_iter() {
-            use std::iter::FromIterator;
+        soa.remove(17);
+        assert_eq!(Rc::strong_count(&counter), 199);
 
-            let sv = $ty::from_iter(0..0);
-            assert_sv_eq!(sv.clone(), []: u32);
+        let cap = soa.capacity();
+        soa.clear();
+        assert_eq!(Rc::strong_count(&counter), 1);
+        assert!(soa.is_empty());
+        assert_eq!(soa.capacity(), cap);
 
-            let sv = $ty::from_iter(0..3);
-            assert_sv_eq!(sv.clone(), [0 => 0, 1 => 1, 2 => 2]);
+        soa.push((counter.clone(), (), counter.clone()));
+        soa.push((counter.clone(), (), counter.clone()));
+        drop(soa);
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
 
-            let mut sv = $ty::from_iter((0..3).map(|x| x * 3));
-            assert_sv_eq!(sv.clone(), [0 => 0, 1 => 3, 2 => 6]);
+    #[test]
+    fn failed_reserve() {
+        let mut soa = StableSoa::<(u8, u64)>::new();
+        soa.push((1, 10));
+        soa.push((2, 20));
+        let cap = soa.capacity();
 
-            sv.remove(2);
-            sv.extend((7..10).rev());
-            assert_sv_eq!(sv.clone(), [0 => 0, 1 => 3, 3 => 9, 4 => 8, 5 => 7]);
+        // The `u8` column would fit, but the `u64` column overflows. Nothing
+        // must have changed.
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            soa.reserve(isize::MAX as usize / 4);
+        }));
+        assert!(res.is_err());
+        assert_eq!(soa.capacity(), cap);
+
+        soa.extend((3..100).map(|i| (i as u8, i * 10)));
+        soa.remove(0);
+        assert_eq!(soa.num_elements(), 98);
+        assert_eq!(soa.get(1), Some((&2, &20)));
+        assert_eq!(soa.get(98), Some((&99, &990)));
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+mod mmap {
+    use std::{
+        prelude::v1::*,
+        fs,
+        io,
+        panic::{self, AssertUnwindSafe},
+        path::PathBuf,
+    };
+    use crate::{
+        MmapStableVec,
+        core::Core,
+    };
+
+    /// A file in the temporary directory that is deleted on drop.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let file = format!("stable-vec-test-{}-{}", std::process::id(), name);
+            Self(std::env::temp_dir().join(file))
         }
+    }
 
-        #[test]
-        fn size_hints() {
-            let mut sv = $ty::<()>::new();
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
 
-            assert_eq!(sv.iter().size_hint(), (0, Some(0)));
-            assert_eq!(sv.iter_mut().size_hint(), (0, Some(0)));
-            assert_eq!(sv.indices().size_hint(), (0, Some(0)));
+    #[test]
+    fn reopen() -> io::Result<()> {
+        let file = TempFile::new("reopen");
+        {
+            let mut sv = MmapStableVec::<u32>::create_mmap(&file.0)?;
+            sv.extend(0..200);
+            for i in (0..200).filter(|i| i % 3 == 0) {
+                sv.remove(i);
+            }
+            sv.reserve_exact(1000);
+            sv.flush()?;
+        }
 
+        let mut sv = MmapStableVec::<u32>::open_mmap(&file.0)?;
+        assert_eq!(sv.num_elements(), 133);
+        assert_eq!(sv.next_push_index(), 200);
+        assert!(sv.capacity() >= 1200);
+        assert!(sv.iter().all(|(i, &e)| i as u32 == e && i % 3 != 0));
+
+        // Shrinking moves the bitmap.
+        sv.shrink_to_fit();
+        assert_eq!(sv.capacity(), 200);
+        sv.push(7);
+        drop(sv);
+
+        let sv = MmapStableVec::<u32>::open_mmap(&file.0)?;
+        assert_eq!(sv.num_elements(), 134);
+        assert_eq!(sv[200], 7);
+        assert_eq!(sv.get(3), None);
+        assert_eq!(sv.get(4), Some(&4));
+
+        Ok(())
+    }
 
-            let mut sv = $ty::from(&[0, 1, 2, 3, 4]);
-            sv.remove(1);
+    #[test]
+    fn mismatched_files() -> io::Result<()> {
+        let file = TempFile::new("mismatch");
+        MmapStableVec::<u32>::create_mmap(&file.0)?.push(1);
 
-            macro_rules! check_iter {
-                ($it:expr) => {{
-                    let mut it = $it;
-                    assert_eq!(it.size_hint(), (4, Some(4)));
-                    assert!(it.next().is_some());
-                    assert_eq!(it.size_hint(), (3, Some(3)));
-                    assert!(it.next().is_some());
-                    assert_eq!(it.size_hint(), (2, Some(2)));
-                    assert!(it.next().is_some());
-                    assert_eq!(it.size_hint(), (1, Some(1)));
-                    assert!(it.next().is_some());
-                    assert_eq!(it.size_hint(), (0, Some(0)));
-                }}
-            }
+        let err = MmapStableVec::<u64>::open_mmap(&file.0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(MmapStableVec::<[u8; 4]>::open_mmap(&file.0).is_err());
+        assert!(MmapStableVec::<i32>::open_mmap(&file.0).is_ok());
 
-            check_iter!(sv.iter());
-            check_iter!(sv.iter_mut());
-            check_iter!(sv.indices());
-        }
-
-        // Quickcheck tests run far
-        #[cfg_attr(miri, ignore)]
-        #[quickcheck]
-        fn reordering_compact(insertions: u16, to_delete: Vec<u16>) -> bool {
-            let insertions = insertions + 1;
-            // Create stable vector containing `insertions` zeros. Afterwards, we
-            // remove at most half of those elements
-            let mut sv = $ty::from(vec![0; insertions as usize]);
-            for i in to_delete {
-                let i = (i % insertions) as usize;
-                if sv.has_element_at(i) {
-                    sv.remove(i);
-                }
-            }
+        fs::write(&file.0, b"definitely not a stable vector, but long enough for a header")?;
+        let err = MmapStableVec::<u32>::open_mmap(&file.0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
 
-            // Remember the number of elements before and call compact.
-            let sv_before = sv.clone();
-            let n_before_compact = sv.num_elements();
-            sv.reordering_make_compact();
-
-            n_before_compact == sv.num_elements()
-                && sv.is_compact()
-                && (0..n_before_compact).all(|i| sv.get(i).is_some())
-                && sv_before.values().all(|e| sv.contains(e))
-        }
-
-        #[cfg_attr(miri, ignore)]
-        #[quickcheck]
-        fn compact(insertions: u16, to_delete: Vec<u16>) -> bool {
-            let insertions = insertions + 1;
-            // Create stable vector containing `insertions` zeros. Afterwards, we
-            // remove at most half of those elements
-            let mut sv = $ty::from(vec![0; insertions as usize]);
-            for i in to_delete {
-                let i = (i % insertions) as usize;
-                if sv.has_element_at(i) {
-                    sv.remove(i);
-                }
-            }
+        fs::write(&file.0, b"short")?;
+        assert!(MmapStableVec::<u32>::open_mmap(&file.0).is_err());
+
+        Ok(())
+    }
 
-            // Remember the number of elements before and call compact.
-            let sv_before = sv.clone();
-            let items_before: Vec<_> = sv_before.values().cloned().collect();
-            let n_before_compact = sv.num_elements();
-            sv.make_compact();
+    #[test]
+    fn clear_and_swap() -> io::Result<()> {
+        let file = TempFile::new("clear-and-swap");
+        let mut sv = MmapStableVec::<f64>::create_mmap(&file.0)?;
+        sv.extend([1.0, 2.0, 3.0]);
+        sv.remove(0);
+        sv.swap(0, 2);
+        assert_eq!(sv.iter().collect::<Vec<_>>(), [(0, &3.0), (1, &2.0)]);
+        assert_eq!(sv.core.count_filled(), 2);
+
+        sv.clear();
+        assert!(sv.is_empty());
+        assert_eq!(sv.core.len(), 0);
+        drop(sv);
+
+        let sv = MmapStableVec::<f64>::open_mmap(&file.0)?;
+        assert!(sv.is_empty());
+        assert_eq!(sv.next_push_index(), 0);
+
+        Ok(())
+    }
 
+    #[test]
+    fn failed_realloc() -> io::Result<()> {
+        let file = TempFile::new("failed-realloc");
+        let mut sv = MmapStableVec::<u32>::create_mmap(&file.0)?;
+        sv.extend(0..100);
+        sv.remove(3);
+
+        // Neither the file system nor the address space is that large, so
+        // either resizing or mapping the file fails.
+        let res = panic::catch_unwind(AssertUnwindSafe(|| sv.reserve_exact(1 << 58)));
+        assert!(res.is_err());
+
+        assert_eq!(sv.num_elements(), 99);
+        assert!(sv.iter().all(|(i, &e)| i as u32 == e && i != 3));
+        sv.push(100);
+        sv.shrink_to_fit();
+        drop(sv);
+
+        let sv = MmapStableVec::<u32>::open_mmap(&file.0)?;
+        assert_eq!(sv.num_elements(), 100);
+        assert_eq!(sv[100], 100);
+
+        Ok(())
+    }
 
-            n_before_compact == sv.num_elements()
-                && sv.is_compact()
-                && (0..n_before_compact).all(|i| sv.get(i).is_some())
-                && sv == items_before
-        }
+    #[test]
+    fn without_file() {
+        let mut sv = MmapStableVec::<u32>::new();
+        assert!(sv.is_empty());
+        assert_eq!(sv.capacity(), 0);
+        sv.clear();
+        sv.flush().unwrap();
+
+        let res = panic::catch_unwind(AssertUnwindSafe(|| sv.push(1)));
+        assert!(res.is_err());
+        assert!(sv.is_empty());
+    }
+}
 
-        #[cfg_attr(miri, ignore)]
-        #[quickcheck]
-        fn from_and_extend_and_from_iter(items: Vec<u8>) -> bool {
-            use std::iter::FromIterator;
+#[cfg(feature = "allocator-api2")]
+mod allocator {
+    use std::{
+        prelude::v1::*,
+        alloc::Layout,
+        cell::Cell,
+        ptr::NonNull,
+    };
+    use allocator_api2::alloc::{AllocError, Allocator, Global};
+    use crate::{
+        StableVecFacade,
+        core::{BitVecCore, FusedBitVecCore, InterleavedCore, OptionCore},
+    };
+
+    thread_local! {
+        static LIVE: Cell<isize> = const { Cell::new(0) };
+        static TOTAL: Cell<usize> = const { Cell::new(0) };
+    }
 
-            let iter_a = items.iter().cloned();
-            let iter_b = items.iter().cloned();
+    /// Forwards to `Global` and counts the number of live allocations of the
+    /// current thread. Zero-sized allocations are not counted, as containers
+    /// are free to never deallocate those.
+    #[derive(Clone, Copy, Default)]
+    struct Counting;
 
-            let sv_a = $ty::from_iter(iter_a);
-            let sv_b = {
-                let mut sv = $ty::new();
-                sv.extend(iter_b);
-                sv
-            };
-            let sv_c = $ty::from(&items);
+    impl Counting {
+        fn live() -> isize {
+            LIVE.with(|c| c.get())
+        }
 
-            sv_a.num_elements() == items.len()
-                && sv_a == sv_b
-                && sv_a == sv_c
+        fn total() -> usize {
+            TOTAL.with(|c| c.get())
         }
     }
-}
 
-mod option {
-    use crate::InlineStableVec;
+    unsafe impl Allocator for Counting {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            if layout.size() != 0 {
+                LIVE.with(|c| c.set(c.get() + 1));
+                TOTAL.with(|c| c.set(c.get() + 1));
+            }
+            Global.allocate(layout)
+        }
 
-    gen_tests_for!(InlineStableVec);
-}
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            if layout.size() != 0 {
+                LIVE.with(|c| c.set(c.get() - 1));
+            }
+            Global.deallocate(ptr, layout)
+        }
+    }
 
-mod bitvec {
-    use crate::ExternStableVec;
+    macro_rules! gen_allocator_test {
+        ($name:ident, $core:ident) => {
+            #[test]
+            fn $name() {
+                {
+                    let mut sv = StableVecFacade::<String, $core<String, Counting>>
+                        ::new_in(Counting);
+                    assert_eq!(Counting::total(), 0);
+
+                    sv.extend((0..100u32).map(|i| i.to_string()));
+                    sv.remove(3);
+                    assert!(Counting::live() > 0);
+
+                    let before = Counting::total();
+                    let clone = sv.clone();
+                    assert_eq!(clone, sv);
+                    assert!(Counting::total() > before);
+
+                    sv.clear();
+                    sv.shrink_to_fit();
+                }
+                assert_eq!(Counting::live(), 0);
+
+                let sv = StableVecFacade::<u8, $core<u8, Counting>>
+                    ::with_capacity_in(10, Counting);
+                assert!(sv.capacity() >= 10);
+                drop(sv);
+                assert_eq!(Counting::live(), 0);
+
+                // `new` uses `Counting::default()`.
+                let mut sv = StableVecFacade::<u8, $core<u8, Counting>>::new();
+                sv.push(1);
+                assert!(Counting::live() > 0);
+                drop(sv);
+                assert_eq!(Counting::live(), 0);
+
+                // Allocators that don't implement `Default` work with `new_in`.
+                let counting = Counting;
+                let mut sv = StableVecFacade::<u8, $core<u8, &Counting>>::new_in(&counting);
+                sv.extend(0..100);
+                let clone = sv.clone();
+                assert_eq!(clone, sv);
+                assert!(Counting::live() > 0);
+                drop((sv, clone));
+                assert_eq!(Counting::live(), 0);
+            }
+        };
+    }
 
-    gen_tests_for!(ExternStableVec);
+    gen_allocator_test!(bitvec, BitVecCore);
+    gen_allocator_test!(fused, FusedBitVecCore);
+    gen_allocator_test!(interleaved, InterleavedCore);
+    gen_allocator_test!(option, OptionCore);
 }